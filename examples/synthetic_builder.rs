@@ -0,0 +1,57 @@
+//! Emits a minidump with a crashed thread, a module and some memory to
+//! specified path, without dumping a process
+
+// usage: `cargo run --example synthetic_builder /tmp/synthetic.dmp`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn main() {
+    use {
+        minidump_writer::{
+            minidump_builder::MinidumpBuilder,
+            minidump_cpu::RawContextCPU,
+            minidump_format::{format::MINIDUMP_STREAM_TYPE, MDException},
+        },
+        std::fs::File,
+    };
+
+    let output_path = std::env::args()
+        .nth(1)
+        .expect("missing argument: output file path");
+
+    let stack_address = 0x7fff_0000;
+    let mut context = RawContextCPU::default();
+    #[cfg(target_arch = "x86_64")]
+    {
+        context.rip = 0x40_1234;
+        context.rsp = stack_address;
+    }
+
+    let mut destination = File::create(output_path).expect("failed to create file");
+    MinidumpBuilder::new()
+        .add_thread(1234, context, stack_address, vec![0xaa; 0x100])
+        .add_module(
+            0x40_0000,
+            0x1_0000,
+            "/usr/bin/synthetic",
+            &[0xde, 0xad, 0xbe, 0xef],
+        )
+        .add_memory(0x60_0000, b"some heap memory".as_slice())
+        .set_exception(
+            1234,
+            MDException {
+                exception_code: libc::SIGSEGV as u32,
+                exception_address: 0x40_1234,
+                ..Default::default()
+            },
+        )
+        .add_stream(
+            MINIDUMP_STREAM_TYPE::LinuxCmdLine as u32,
+            b"synthetic".as_slice(),
+        )
+        .write(&mut destination)
+        .expect("failed to write minidump");
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn main() {
+    eprintln!("the minidump builder is only available on Linux and Android");
+}
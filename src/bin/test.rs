@@ -10,6 +10,8 @@ mod linux {
         super::*,
         error_graph::ErrorList,
         minidump_writer::{
+            annotations::Annotations,
+            crash_generation::CrashGenerationClient,
             minidump_writer::{MinidumpWriter, MinidumpWriterConfig},
             module_reader, LINUX_GATE_LIBRARY_NAME,
         },
@@ -285,6 +287,36 @@ mod linux {
         }
     }
 
+    /// Spawns a thread that is stuck in uninterruptible sleep waiting for a
+    /// `CLONE_VFORK` child, until the child exits after 10 seconds
+    fn spawn_vfork_wait() -> Result<()> {
+        extern "C" fn vfork_child(_: *mut libc::c_void) -> libc::c_int {
+            // SAFETY: Only async-signal-safe functions are called in the child
+            unsafe {
+                libc::write(1, b"1\n".as_ptr().cast(), 2);
+                libc::sleep(10);
+            }
+            0
+        }
+
+        std::thread::spawn(|| {
+            let mut stack = vec![0u8; 64 * 1024];
+            // SAFETY: The child runs on its own stack, in a copy of the memory
+            unsafe {
+                libc::clone(
+                    vfork_child,
+                    stack.as_mut_ptr().add(stack.len()).cast(),
+                    libc::CLONE_VFORK | libc::SIGCHLD,
+                    std::ptr::null_mut(),
+                );
+            }
+        });
+        println!("1");
+        loop {
+            std::thread::park();
+        }
+    }
+
     fn spawn_name_wait(num: usize) -> Result<()> {
         // One less than the requested amount, as the main thread counts as well
         for id in 1..num {
@@ -358,6 +390,37 @@ mod linux {
         }
     }
 
+    fn crash_client(socket: &str) -> Result<()> {
+        let mut client = CrashGenerationClient::connect(socket)?;
+        client.set_annotations(&Annotations {
+            simple: vec![("channel".into(), "nightly".into())],
+            blobs: Vec::new(),
+        });
+        client.install_signal_handlers()?;
+
+        std::thread::spawn(|| loop {
+            std::thread::park();
+        });
+
+        crash()
+    }
+
+    fn spawn_and_crash(num: usize) -> Result<()> {
+        for _ in 1..num {
+            std::thread::spawn(|| loop {
+                std::thread::park();
+            });
+        }
+        crash()
+    }
+
+    fn crash() -> Result<()> {
+        // An unmapped address that is aligned and non-null, so the write
+        // isn't caught by debug assertions first
+        unsafe { (0x10 as *mut u8).write_volatile(1) };
+        Ok(())
+    }
+
     pub(super) fn real_main(args: Vec<String>) -> Result<()> {
         match args.len() {
             1 => match args[0].as_ref() {
@@ -369,6 +432,7 @@ mod linux {
                 "linux_gate_mapping_id" => test_linux_gate_mapping_id(),
                 "spawn_mmap_wait" => spawn_mmap_wait(),
                 "spawn_alloc_wait" => spawn_alloc_wait(),
+                "spawn_vfork_wait" => spawn_vfork_wait(),
                 _ => Err("Len 1: Unknown test option".into()),
             },
             2 => match args[0].as_ref() {
@@ -384,6 +448,11 @@ mod linux {
                     let num_of_files: usize = args[1].parse().unwrap();
                     create_files_wait(num_of_files)
                 }
+                "crash_client" => crash_client(&args[1]),
+                "spawn_and_crash" => {
+                    let num_of_threads: usize = args[1].parse().unwrap();
+                    spawn_and_crash(num_of_threads)
+                }
                 _ => Err(format!("Len 2: Unknown test option: {}", args[0]).into()),
             },
             3 => {
@@ -1,7 +1,7 @@
 use {
     crate::{
         mem_writer::{Buffer, MemoryArrayWriter, MemoryWriterError},
-        minidump_format::MDRawDirectory,
+        minidump_format::{MDLocationDescriptor, MDRawDirectory, MDRVA},
         serializers::*,
     },
     std::io::{Error, Seek, Write},
@@ -19,6 +19,8 @@ pub enum FileWriterError {
     ),
     #[error("Failed to write to memory")]
     MemoryWriterError(#[from] MemoryWriterError),
+    #[error("Trailing data should have been {expected} bytes, but {written} bytes were written")]
+    TrailingDataLength { expected: u64, written: u64 },
 }
 
 /// Utility that wraps writing minidump directory entries to an I/O stream, generally
@@ -62,6 +64,17 @@ where
         self.section.position
     }
 
+    /// The number of directory entries written so far
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.curr_idx
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.curr_idx == 0
+    }
+
     pub fn dump_dir_entry(
         &mut self,
         buffer: &mut DumpBuf,
@@ -70,17 +83,27 @@ where
         self.section.set_value_at(buffer, dirent, self.curr_idx)?;
 
         // Now write it to file
+        let idx_pos = self.section.location_of_index(self.curr_idx);
+        self.curr_idx += 1;
+
+        self.rewrite(buffer, idx_pos)
+    }
 
+    /// Writes the specified part of the buffer to file again, after it has
+    /// been changed
+    pub fn rewrite(
+        &mut self,
+        buffer: &DumpBuf,
+        location: MDLocationDescriptor,
+    ) -> std::result::Result<(), FileWriterError> {
         // First get all the positions
         let curr_file_pos = self.destination.stream_position()?;
-        let idx_pos = self.section.location_of_index(self.curr_idx);
-        self.curr_idx += 1;
 
         self.destination.seek(std::io::SeekFrom::Start(
-            self.destination_start_offset + idx_pos.rva as u64,
+            self.destination_start_offset + location.rva as u64,
         ))?;
-        let start = idx_pos.rva as usize;
-        let end = (idx_pos.rva + idx_pos.data_size) as usize;
+        let start = location.rva as usize;
+        let end = (location.rva + location.data_size) as usize;
         self.destination.write_all(&buffer[start..end])?;
 
         // Reset file-position
@@ -102,9 +125,50 @@ where
             self.dump_dir_entry(buffer, dirent)?;
         }
 
+        // Everything in the buffer is addressed by 32-bit RVAs, so anything
+        // past that would have been truncated
+        if buffer.position() > MDRVA::MAX as u64 {
+            return Err(MemoryWriterError::RvaOverflow(buffer.position()).into());
+        }
+
         let start_pos = self.last_position_written_to_file as usize;
         self.destination.write_all(&buffer[start_pos..])?;
         self.last_position_written_to_file = buffer.position();
         Ok(())
     }
+
+    /// Writes the trailing data of the buffer after everything else, and
+    /// fills in the RVAs referring to it.
+    ///
+    /// This must be called after everything else has been written with
+    /// [`Self::write_to_file`].
+    pub fn write_trailing_data(
+        &mut self,
+        buffer: &mut DumpBuf,
+    ) -> std::result::Result<(), FileWriterError> {
+        let mut trailing = buffer.take_trailing_data();
+
+        let mut rva = buffer.position();
+        for (rva_slot, data) in &mut trailing {
+            rva_slot.set_value(buffer, rva)?;
+            self.rewrite(buffer, rva_slot.location())?;
+            rva = rva
+                .checked_add(data.len())
+                .ok_or(MemoryWriterError::RvaOverflow(rva))?;
+        }
+
+        for (_, mut data) in trailing {
+            let start = self.destination.stream_position()?;
+            data.write_to(&mut *self.destination)?;
+            let written = self.destination.stream_position()? - start;
+            if written != data.len() {
+                return Err(FileWriterError::TrailingDataLength {
+                    expected: data.len(),
+                    written,
+                });
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,50 @@
+//! Caller provided annotations, which are written to the minidump in the
+//! Crashpad annotation format.
+
+use minidump_common::format::MINIDUMP_ANNOTATION;
+
+/// The annotation type used for binary blobs, unless specified otherwise
+pub const ANNOTATION_TYPE_BLOB: u16 = MINIDUMP_ANNOTATION::TYPE_USER_DEFINED;
+
+/// A named binary annotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationBlob {
+    pub name: String,
+    /// The type of the data, values greater than
+    /// [`MINIDUMP_ANNOTATION::TYPE_USER_DEFINED`] can be used to distinguish
+    /// different kinds of blobs
+    pub ty: u16,
+    pub data: Vec<u8>,
+    /// An address within the module the blob belongs to, eg. of a function
+    /// or static in a shared library. Blobs without one belong to the main
+    /// executable.
+    pub module_address: Option<usize>,
+}
+
+/// Annotations for the process as a whole.
+///
+/// These are written to the `CrashpadInfoStream` as follows:
+///
+/// * `simple` is written, in order, to the `simple_annotations` dictionary
+///   of the `MINIDUMP_CRASHPAD_INFO`
+/// * `blobs` are written, in order, as the `annotation_objects` of the
+///   `MINIDUMP_MODULE_CRASHPAD_INFO` linked to the module that contains their
+///   [`AnnotationBlob::module_address`], or to the main executable if they
+///   don't have one. Crashpad has no concept of process wide annotation
+///   objects, so the main executable is where processors should look for
+///   them. They follow any annotation objects read from the module's own
+///   `CrashpadInfo`, see [`crashpad_info`](super::crashpad_info).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Annotations {
+    /// Ordered key/value pairs
+    pub simple: Vec<(String, String)>,
+    /// Ordered binary blobs
+    pub blobs: Vec<AnnotationBlob>,
+}
+
+impl Annotations {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.simple.is_empty() && self.blobs.is_empty()
+    }
+}
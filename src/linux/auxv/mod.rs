@@ -4,7 +4,10 @@ use {
     crate::serializers::*,
     error_graph::WriteErrorList,
     failspot::failspot,
-    std::{fs::File, io::BufReader},
+    std::{
+        fs::File,
+        io::{BufReader, Read},
+    },
     thiserror::Error,
 };
 
@@ -73,7 +76,7 @@ impl From<DirectAuxvDumpInfo> for AuxvDumpInfo {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct AuxvDumpInfo {
     program_header_count: Option<AuxvType>,
     program_header_address: Option<AuxvType>,
@@ -94,7 +97,20 @@ impl AuxvDumpInfo {
         let auxv_path = format!("/proc/{pid}/auxv");
         let auxv_file = File::open(&auxv_path).map_err(|e| AuxvError::OpenError(auxv_path, e))?;
 
-        for pair_result in ProcfsAuxvIter::new(BufReader::new(auxv_file)) {
+        self.fill_missing_info_from(BufReader::new(auxv_file), &mut soft_errors);
+
+        failspot!(FillMissingAuxvInfo soft_errors.push(AuxvError::InvalidFormat));
+
+        Ok(())
+    }
+    /// Fills the missing info from a raw auxiliary vector, eg. the `NT_AUXV`
+    /// note of a core file
+    pub fn fill_missing_info_from(
+        &mut self,
+        auxv: impl Read,
+        mut soft_errors: impl WriteErrorList<AuxvError>,
+    ) {
+        for pair_result in ProcfsAuxvIter::new(auxv) {
             let AuxvPair { key, value } = match pair_result {
                 Ok(pair) => pair,
                 Err(e) => {
@@ -113,10 +129,6 @@ impl AuxvDumpInfo {
                 *dest_field = Some(value);
             }
         }
-
-        failspot!(FillMissingAuxvInfo soft_errors.push(AuxvError::InvalidFormat));
-
-        Ok(())
     }
     pub fn get_program_header_count(&self) -> Option<AuxvType> {
         self.program_header_count
@@ -130,6 +142,23 @@ impl AuxvDumpInfo {
     pub fn get_entry_address(&self) -> Option<AuxvType> {
         self.entry_address
     }
+    /// Encodes the known entries as a raw auxiliary vector terminated by
+    /// `AT_NULL`, eg. for the `NT_AUXV` note of a core file
+    pub fn to_raw(&self) -> Vec<u8> {
+        let entries = [
+            (consts::AT_PHDR, self.program_header_address),
+            (consts::AT_PHNUM, self.program_header_count),
+            (consts::AT_ENTRY, self.entry_address),
+            (consts::AT_SYSINFO_EHDR, self.linux_gate_address),
+        ];
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .chain(std::iter::once((0, 0)))
+            .flat_map(|(key, value)| [key.to_ne_bytes(), value.to_ne_bytes()])
+            .flatten()
+            .collect()
+    }
     pub fn is_complete(&self) -> bool {
         self.program_header_count.is_some()
             && self.program_header_address.is_some()
@@ -0,0 +1,476 @@
+//! Reading of the state of a crashed process from an ELF core file, eg. one
+//! collected by `systemd-coredump`, so that it can be written as a minidump
+//! instead of the state of a live process, see
+//! [`MinidumpWriterConfig::from_core_file`](super::minidump_writer::MinidumpWriterConfig::from_core_file).
+//!
+//! Threads and their registers come from the `NT_PRSTATUS` notes and the
+//! notes following each of them, mappings from the `PT_LOAD` segments named
+//! by the `NT_FILE` note, and memory from the contents of the segments.
+
+use {
+    super::{serializers::*, Pid},
+    crate::serializers::*,
+    goblin::elf::{self, program_header},
+    std::{
+        ffi::OsString,
+        fmt::Write,
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::Path,
+    },
+};
+
+/// `NT_AUXV`, the auxiliary vector of the process
+pub const NT_AUXV: u32 = 6;
+/// `NT_PRFPREG`, the floating point registers of a thread
+pub const NT_PRFPREG: u32 = 2;
+/// `NT_PRXFPREG`, the extended floating point registers of a 32-bit x86
+/// thread
+pub const NT_PRXFPREG: u32 = 0x46e62b7f;
+/// `NT_ARM_VFP`, the VFP registers of a 32-bit ARM thread
+pub const NT_ARM_VFP: u32 = 0x400;
+
+pub(crate) const WORD: usize = std::mem::size_of::<usize>();
+
+/// `elf_prstatus` field offsets, `pr_info` and `pr_cursig` are followed by
+/// the signal masks, 4 pids and 4 `timeval`s before the registers
+pub(crate) const PRSTATUS_CURSIG_OFFSET: usize = 12;
+pub(crate) const PRSTATUS_PID_OFFSET: usize = 16 + 2 * WORD;
+/// The offset of `pr_reg` in `elf_prstatus`
+pub const PRSTATUS_REGS_OFFSET: usize = PRSTATUS_PID_OFFSET + 16 + 8 * WORD;
+
+/// `elf_prpsinfo` field offsets, 32-bit architectures use 16-bit user and
+/// group ids
+#[cfg(target_pointer_width = "64")]
+pub(crate) const PRPSINFO_PID_OFFSET: usize = 24;
+#[cfg(target_pointer_width = "32")]
+pub(crate) const PRPSINFO_PID_OFFSET: usize = 12;
+pub(crate) const PRPSINFO_FNAME_OFFSET: usize = PRPSINFO_PID_OFFSET + 16;
+pub(crate) const PRPSINFO_FNAME_SIZE: usize = 16;
+
+/// The offset of `si_addr` in `siginfo_t`
+pub(crate) const SIGINFO_ADDR_OFFSET: usize = if WORD == 8 { 16 } else { 12 };
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) const MACHINE: u16 = elf::header::EM_X86_64;
+#[cfg(target_arch = "x86")]
+pub(crate) const MACHINE: u16 = elf::header::EM_386;
+#[cfg(target_arch = "arm")]
+pub(crate) const MACHINE: u16 = elf::header::EM_ARM;
+#[cfg(target_arch = "aarch64")]
+pub(crate) const MACHINE: u16 = elf::header::EM_AARCH64;
+#[cfg(target_arch = "mips")]
+pub(crate) const MACHINE: u16 = elf::header::EM_MIPS;
+
+#[derive(Debug, thiserror::Error, serde::Serialize)]
+pub enum CoreFileError {
+    #[error("failed to read core file ({path}): {error}")]
+    MapFile {
+        path: std::path::PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        error: std::io::Error,
+    },
+    #[error("failed to parse the core file: {0}")]
+    Parsing(
+        #[from]
+        #[serde(serialize_with = "serialize_goblin_error")]
+        goblin::error::Error,
+    ),
+    #[error("not a core file (ELF type {0})")]
+    NotCore(u16),
+    #[error("the core file is for a different architecture (ELF machine {0}, 64-bit: {1})")]
+    UnsupportedArchitecture(u16, bool),
+    #[error("the core file contains no threads")]
+    NoThreads,
+    #[error("the {0} note is too small ({1} bytes)")]
+    NoteTooSmall(&'static str, usize),
+}
+
+/// A thread recorded in a core file
+#[derive(Debug)]
+pub struct CoreThread {
+    pub tid: Pid,
+    pub ppid: Pid,
+    /// The name of the thread, only known for the main thread
+    pub name: Option<String>,
+    /// The `elf_prstatus` of the thread
+    pub prstatus: Vec<u8>,
+    /// The notes following the `NT_PRSTATUS` note of the thread, eg. its
+    /// floating point registers
+    notes: Vec<(u32, Vec<u8>)>,
+}
+
+impl CoreThread {
+    /// The `pr_reg` registers of the thread
+    pub fn registers(&self) -> &[u8] {
+        self.prstatus
+            .get(PRSTATUS_REGS_OFFSET..)
+            .unwrap_or_default()
+    }
+
+    /// The contents of the note of type `n_type` for the thread, if any
+    pub fn note(&self, n_type: u32) -> Option<&[u8]> {
+        self.notes
+            .iter()
+            .find(|(ty, _)| *ty == n_type)
+            .map(|(_, desc)| desc.as_slice())
+    }
+}
+
+/// The signal that caused the core file to be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreSignal {
+    pub signo: u32,
+    pub code: i32,
+    /// The fault address, for signals that have one
+    pub address: u64,
+}
+
+/// A file mapped into the process, from the `NT_FILE` note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreFileMapping {
+    pub start_address: usize,
+    pub end_address: usize,
+    /// The offset in the file, in bytes
+    pub offset: usize,
+    pub path: OsString,
+}
+
+/// A `PT_LOAD` segment, each one is a mapping of the process. Only the first
+/// `file_size` bytes of its memory are in the core file, the rest was not
+/// dumped.
+#[derive(Debug, Clone)]
+struct CoreSegment {
+    start_address: usize,
+    memory_size: usize,
+    file_offset: usize,
+    file_size: usize,
+    flags: u32,
+}
+
+/// An ELF core file of a process of the same architecture as the writer
+pub struct CoreFile {
+    data: memmap2::Mmap,
+    pub process_id: Pid,
+    /// The threads of the process, starting with the thread that received the
+    /// signal
+    pub threads: Vec<CoreThread>,
+    pub signal: Option<CoreSignal>,
+    /// The raw auxiliary vector of the process
+    pub auxv: Vec<u8>,
+    pub files: Vec<CoreFileMapping>,
+    segments: Vec<CoreSegment>,
+}
+
+impl std::fmt::Debug for CoreFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoreFile")
+            .field("process_id", &self.process_id)
+            .field("threads", &self.threads.len())
+            .field("signal", &self.signal)
+            .field("segments", &self.segments.len())
+            .finish()
+    }
+}
+
+impl CoreFile {
+    /// Maps and parses the core file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CoreFileError> {
+        let path = path.as_ref();
+        let data = std::fs::File::open(path)
+            // Safety: core files are written once and not modified afterwards
+            .and_then(|file| unsafe { memmap2::Mmap::map(&file) })
+            .map_err(|error| CoreFileError::MapFile {
+                path: path.to_owned(),
+                error,
+            })?;
+        Self::parse(data)
+    }
+
+    fn parse(data: memmap2::Mmap) -> Result<Self, CoreFileError> {
+        let elf = elf::Elf::parse(&data)?;
+        if elf.header.e_type != elf::header::ET_CORE {
+            return Err(CoreFileError::NotCore(elf.header.e_type));
+        }
+        if elf.header.e_machine != MACHINE || elf.is_64 != (WORD == 8) {
+            return Err(CoreFileError::UnsupportedArchitecture(
+                elf.header.e_machine,
+                elf.is_64,
+            ));
+        }
+
+        let mut segments: Vec<_> = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == program_header::PT_LOAD && ph.p_memsz > 0)
+            .filter_map(|ph| {
+                // Segments of malformed core files that don't fit in the
+                // address space are skipped
+                let start_address: usize = ph.p_vaddr.try_into().ok()?;
+                let memory_size: usize = ph.p_memsz.try_into().ok()?;
+                start_address.checked_add(memory_size)?;
+                let file_offset: usize = ph.p_offset.try_into().unwrap_or(usize::MAX);
+                Some(CoreSegment {
+                    start_address,
+                    memory_size,
+                    file_offset,
+                    // Truncated core files are common, eg. because of size limits
+                    file_size: usize::try_from(ph.p_filesz)
+                        .unwrap_or(usize::MAX)
+                        .min(memory_size)
+                        .min(data.len().saturating_sub(file_offset)),
+                    flags: ph.p_flags,
+                })
+            })
+            .collect();
+        segments.sort_by_key(|segment| segment.start_address);
+
+        let mut process_id = None;
+        let mut process_name = None;
+        let mut threads: Vec<CoreThread> = Vec::new();
+        let mut signal = None;
+        let mut auxv = Vec::new();
+        let mut files = Vec::new();
+
+        for note in elf.iter_note_headers(&data).into_iter().flatten() {
+            let note = note?;
+            match note.n_type {
+                elf::note::NT_PRSTATUS => {
+                    if note.desc.len() < PRSTATUS_REGS_OFFSET {
+                        return Err(CoreFileError::NoteTooSmall("NT_PRSTATUS", note.desc.len()));
+                    }
+                    let tid = read_i32(note.desc, PRSTATUS_PID_OFFSET);
+                    let ppid = read_i32(note.desc, PRSTATUS_PID_OFFSET + 4);
+                    if threads.is_empty() {
+                        let signo = u16::from_ne_bytes([
+                            note.desc[PRSTATUS_CURSIG_OFFSET],
+                            note.desc[PRSTATUS_CURSIG_OFFSET + 1],
+                        ]);
+                        // A more detailed NT_SIGINFO note usually follows
+                        signal = (signo != 0).then_some(CoreSignal {
+                            signo: signo.into(),
+                            code: 0,
+                            address: 0,
+                        });
+                    }
+                    threads.push(CoreThread {
+                        tid,
+                        ppid,
+                        name: None,
+                        prstatus: note.desc.to_vec(),
+                        notes: Vec::new(),
+                    });
+                }
+                elf::note::NT_PRPSINFO if note.desc.len() >= PRPSINFO_FNAME_OFFSET => {
+                    process_id = Some(read_i32(note.desc, PRPSINFO_PID_OFFSET));
+                    let name = &note.desc[PRPSINFO_FNAME_OFFSET..]
+                        [..PRPSINFO_FNAME_SIZE.min(note.desc.len() - PRPSINFO_FNAME_OFFSET)];
+                    let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                    process_name = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                // Only the crashing thread has signal information
+                elf::note::NT_SIGINFO if threads.len() == 1 => {
+                    if note.desc.len() >= SIGINFO_ADDR_OFFSET + WORD {
+                        let code = read_i32(note.desc, 8);
+                        // Signals sent from userspace (si_code <= 0) carry the
+                        // sender's pid and uid where si_addr would be
+                        let address = if code > 0 {
+                            read_word(note.desc, SIGINFO_ADDR_OFFSET) as u64
+                        } else {
+                            0
+                        };
+                        signal = Some(CoreSignal {
+                            signo: read_i32(note.desc, 0) as u32,
+                            code,
+                            address,
+                        });
+                    }
+                }
+                NT_AUXV => auxv = note.desc.to_vec(),
+                elf::note::NT_FILE => files = parse_file_note(note.desc)?,
+                n_type => {
+                    if let Some(thread) = threads.last_mut() {
+                        thread.notes.push((n_type, note.desc.to_vec()));
+                    }
+                }
+            }
+        }
+
+        let process_id = process_id
+            .or_else(|| threads.first().map(|thread| thread.tid))
+            .ok_or(CoreFileError::NoThreads)?;
+        if let Some(main_thread) = threads.iter_mut().find(|thread| thread.tid == process_id) {
+            main_thread.name = process_name;
+        }
+
+        Ok(Self {
+            data,
+            process_id,
+            threads,
+            signal,
+            auxv,
+            files,
+            segments,
+        })
+    }
+
+    /// Copies the memory at `address` to `dst`, returning the number of bytes
+    /// that were available, which stops at the first byte that was not
+    /// dumped
+    pub fn read(&self, address: usize, dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        while copied < dst.len() {
+            let address = address + copied;
+            let index = self
+                .segments
+                .partition_point(|segment| segment.start_address <= address);
+            let Some(segment) = index.checked_sub(1).map(|index| &self.segments[index]) else {
+                break;
+            };
+            let offset = address - segment.start_address;
+            if offset >= segment.file_size {
+                break;
+            }
+
+            let len = (segment.file_size - offset).min(dst.len() - copied);
+            let start = segment.file_offset + offset;
+            dst[copied..copied + len].copy_from_slice(&self.data[start..start + len]);
+            copied += len;
+        }
+        copied
+    }
+
+    /// The mappings of the process in the format of `/proc/<pid>/maps`
+    ///
+    /// The kernel doesn't record whether a mapping is shared, so every mapping
+    /// is considered private, and only the vDSO is named among the special
+    /// mappings.
+    pub fn maps(&self, vdso_address: Option<usize>) -> String {
+        let mut maps = String::new();
+        let mut line = |start: usize, end: usize, flags: u32, offset: usize, name: &[u8]| {
+            let flag = |bit, c| if flags & bit != 0 { c } else { '-' };
+            let _ = writeln!(
+                maps,
+                "{start:x}-{end:x} {}{}{}p {offset:08x} 00:00 0 {}",
+                flag(program_header::PF_R, 'r'),
+                flag(program_header::PF_W, 'w'),
+                flag(program_header::PF_X, 'x'),
+                String::from_utf8_lossy(name),
+            );
+        };
+
+        for segment in &self.segments {
+            let Some(end) = segment.start_address.checked_add(segment.memory_size) else {
+                continue;
+            };
+            let file = self
+                .files
+                .iter()
+                .find(|file| file.start_address == segment.start_address);
+            match file {
+                Some(file) => line(
+                    segment.start_address,
+                    end,
+                    segment.flags,
+                    file.offset,
+                    file.path.as_bytes(),
+                ),
+                None if Some(segment.start_address) == vdso_address => {
+                    line(segment.start_address, end, segment.flags, 0, b"[vdso]")
+                }
+                None => line(segment.start_address, end, segment.flags, 0, b""),
+            }
+        }
+
+        // Mapped files without a segment, which the kernel doesn't normally omit
+        for file in &self.files {
+            if !self
+                .segments
+                .iter()
+                .any(|segment| segment.start_address == file.start_address)
+            {
+                line(
+                    file.start_address,
+                    file.end_address,
+                    program_header::PF_R,
+                    file.offset,
+                    file.path.as_bytes(),
+                );
+            }
+        }
+        maps
+    }
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_word(bytes: &[u8], offset: usize) -> usize {
+    usize::from_ne_bytes(bytes[offset..offset + WORD].try_into().unwrap())
+}
+
+/// Parses the `NT_FILE` note: the number of files and the page size, the
+/// start, end and page offset of each file, followed by their NUL terminated
+/// paths
+fn parse_file_note(desc: &[u8]) -> Result<Vec<CoreFileMapping>, CoreFileError> {
+    let too_small = || CoreFileError::NoteTooSmall("NT_FILE", desc.len());
+    if desc.len() < 2 * WORD {
+        return Err(too_small());
+    }
+
+    let count = read_word(desc, 0);
+    let page_size = read_word(desc, WORD);
+    let paths_offset = count
+        .checked_mul(3 * WORD)
+        .and_then(|len| len.checked_add(2 * WORD))
+        .filter(|&offset| offset <= desc.len())
+        .ok_or_else(too_small)?;
+
+    let mut paths = desc[paths_offset..].split(|&b| b == 0);
+    (0..count)
+        .map(|index| {
+            let entry = 2 * WORD + index * 3 * WORD;
+            let path = paths.next().ok_or_else(too_small)?;
+            Ok(CoreFileMapping {
+                start_address: read_word(desc, entry),
+                end_address: read_word(desc, entry + WORD),
+                offset: read_word(desc, entry + 2 * WORD).wrapping_mul(page_size),
+                path: OsString::from_vec(path.to_vec()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_note() {
+        let words: [usize; 8] = [2, 0x1000, 0x1000, 0x3000, 0, 0x8000, 0x9000, 3];
+        let mut desc: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        desc.extend_from_slice(b"/usr/bin/app\0/usr/lib/libc.so.6\0");
+
+        assert_eq!(
+            parse_file_note(&desc).unwrap(),
+            [
+                CoreFileMapping {
+                    start_address: 0x1000,
+                    end_address: 0x3000,
+                    offset: 0,
+                    path: "/usr/bin/app".into(),
+                },
+                CoreFileMapping {
+                    start_address: 0x8000,
+                    end_address: 0x9000,
+                    offset: 0x3000,
+                    path: "/usr/lib/libc.so.6".into(),
+                },
+            ]
+        );
+
+        // One of the paths is missing
+        assert!(parse_file_note(&desc[..desc.len() - 20]).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! Runs a target process under ptrace and writes a minidump when one of its
+//! threads is about to be killed by a signal, for binaries that can't install
+//! a signal handler themselves.
+
+use {
+    super::{
+        crash_context::CrashContext,
+        minidump_writer::{errors::WriterError, MinidumpWriterConfig},
+        serializers::*,
+        thread_info::{ThreadInfo, ThreadInfoError},
+        Pid,
+    },
+    crate::serializers::*,
+    nix::{
+        errno::Errno,
+        sys::{
+            ptrace,
+            signal::{self, Signal},
+            wait::{self, WaitPidFlag, WaitStatus},
+        },
+        unistd,
+    },
+    procfs_core::{process::Status, FromRead},
+    std::{
+        collections::BTreeSet,
+        fs::File,
+        os::unix::process::{CommandExt, ExitStatusExt},
+        path::PathBuf,
+        process::{Command, ExitStatus},
+    },
+    thiserror::Error,
+};
+
+type Configure = Box<dyn Fn(&mut MinidumpWriterConfig)>;
+
+/// The signals whose default action terminates the process with a core dump
+const CORE_SIGNALS: [Signal; 10] = [
+    Signal::SIGABRT,
+    Signal::SIGBUS,
+    Signal::SIGFPE,
+    Signal::SIGILL,
+    Signal::SIGQUIT,
+    Signal::SIGSEGV,
+    Signal::SIGSYS,
+    Signal::SIGTRAP,
+    Signal::SIGXCPU,
+    Signal::SIGXFSZ,
+];
+
+/// The signals that cause a group-stop
+const STOP_SIGNALS: [Signal; 4] = [
+    Signal::SIGSTOP,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum CrashCatcherError {
+    #[error("Failed to spawn the target")]
+    SpawnFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("The target {0} didn't stop as expected: {1}")]
+    UnexpectedStatus(Pid, String),
+    #[error("Failed to list the threads of {0}")]
+    ReadThreadsFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("ptrace request for thread {0} failed")]
+    PtraceFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to wait for the target")]
+    WaitFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to get the registers of thread {0}")]
+    ThreadInfoFailed(Pid, #[source] ThreadInfoError),
+    #[error("Failed to create the minidump file {0}")]
+    CreateDumpFailed(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to write the minidump file {0}")]
+    WriteDumpFailed(PathBuf, #[source] Box<WriterError>),
+}
+
+/// A signal that was about to kill the target
+#[derive(Debug)]
+pub struct CaughtCrash {
+    pub thread_id: Pid,
+    pub signal: i32,
+    /// Where the minidump was written, or why writing it failed
+    pub dump: Result<PathBuf, CrashCatcherError>,
+}
+
+/// How the target ended, and the crashes caught until then
+#[derive(Debug)]
+pub struct CatchOutcome {
+    pub status: ExitStatus,
+    pub crashes: Vec<CaughtCrash>,
+}
+
+/// Traces every thread of a target process, and writes a minidump into a
+/// directory when a signal that would kill it with a core dump is delivered.
+///
+/// Signals the target handles itself are passed on without a minidump, as
+/// the handler may recover from them. Handlers that don't usually restore
+/// the default action and raise the signal again, which is caught then.
+///
+/// ptrace requests have to be made by the tracing thread, so the catcher must
+/// be used on the thread that created it, which must not have other children.
+pub struct CrashCatcher {
+    process_id: Pid,
+    threads: BTreeSet<Pid>,
+    dump_directory: PathBuf,
+    configure: Option<Configure>,
+    dump_count: usize,
+}
+
+impl CrashCatcher {
+    /// Spawns the command as the target. It is traced from its first
+    /// instruction after `exec`.
+    pub fn spawn(
+        command: &mut Command,
+        dump_directory: impl Into<PathBuf>,
+    ) -> Result<Self, CrashCatcherError> {
+        // SAFETY: `PTRACE_TRACEME` is async-signal-safe
+        unsafe {
+            command.pre_exec(|| ptrace::traceme().map_err(std::io::Error::from));
+        }
+        let child = command.spawn().map_err(CrashCatcherError::SpawnFailed)?;
+        let pid = unistd::Pid::from_raw(child.id() as Pid);
+
+        // The target stops with `SIGTRAP` after `exec`. It is seized instead
+        // of traced with `PTRACE_TRACEME`, so that the other threads can be
+        // interrupted while dumping. To not miss anything in between, it is
+        // detached into a group-stop, seized and then continued.
+        expect_stop(pid, Signal::SIGTRAP, WaitPidFlag::__WALL)?;
+        ptrace::detach(pid, Signal::SIGSTOP)
+            .map_err(|e| CrashCatcherError::PtraceFailed(pid.as_raw(), e))?;
+        expect_stop(pid, Signal::SIGSTOP, WaitPidFlag::WUNTRACED)?;
+        ptrace::seize(pid, ptrace::Options::PTRACE_O_TRACECLONE)
+            .map_err(|e| CrashCatcherError::PtraceFailed(pid.as_raw(), e))?;
+        signal::kill(pid, Signal::SIGCONT)
+            .map_err(|e| CrashCatcherError::PtraceFailed(pid.as_raw(), e))?;
+
+        Ok(Self::new(
+            pid.as_raw(),
+            [pid.as_raw()].into(),
+            dump_directory,
+        ))
+    }
+
+    /// Seizes all threads of a running process as the target, which requires
+    /// the permission to ptrace it
+    pub fn seize(
+        process_id: Pid,
+        dump_directory: impl Into<PathBuf>,
+    ) -> Result<Self, CrashCatcherError> {
+        let mut threads = BTreeSet::new();
+        // Threads that were spawned by a thread before it was seized aren't
+        // traced automatically, so this repeats until there are no new ones
+        loop {
+            let tids = std::fs::read_dir(format!("/proc/{process_id}/task"))
+                .map_err(|e| CrashCatcherError::ReadThreadsFailed(process_id, e))?
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<Pid>().ok())
+                .filter(|tid| !threads.contains(tid))
+                .collect::<Vec<_>>();
+            if tids.is_empty() {
+                break;
+            }
+
+            for tid in tids {
+                match ptrace::seize(
+                    unistd::Pid::from_raw(tid),
+                    ptrace::Options::PTRACE_O_TRACECLONE,
+                ) {
+                    Ok(()) => {
+                        threads.insert(tid);
+                    }
+                    // The thread exited in the meantime
+                    Err(Errno::ESRCH) => {}
+                    Err(e) => return Err(CrashCatcherError::PtraceFailed(tid, e)),
+                }
+            }
+        }
+
+        Ok(Self::new(process_id, threads, dump_directory))
+    }
+
+    fn new(process_id: Pid, threads: BTreeSet<Pid>, dump_directory: impl Into<PathBuf>) -> Self {
+        Self {
+            process_id,
+            threads,
+            dump_directory: dump_directory.into(),
+            configure: None,
+            dump_count: 0,
+        }
+    }
+
+    pub fn process_id(&self) -> Pid {
+        self.process_id
+    }
+
+    /// Sets a function that adjusts the config of every minidump after it
+    /// has been filled in from the crash, e.g. to set a size limit
+    pub fn set_configure(
+        &mut self,
+        configure: impl Fn(&mut MinidumpWriterConfig) + 'static,
+    ) -> &mut Self {
+        self.configure = Some(Box::new(configure));
+        self
+    }
+
+    /// Runs the target until it exits, writing a minidump for every signal
+    /// that is about to kill it
+    pub fn run(&mut self) -> Result<CatchOutcome, CrashCatcherError> {
+        let mut crashes = Vec::new();
+        loop {
+            let status =
+                match wait::waitpid(None, Some(WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD)) {
+                    Err(Errno::EINTR) => continue,
+                    status => status.map_err(CrashCatcherError::WaitFailed)?,
+                };
+
+            match status {
+                // Signal-delivery-stop, the signal is delivered when the thread
+                // is continued with it
+                WaitStatus::Stopped(tid, signal) => {
+                    self.threads.insert(tid.as_raw());
+                    if self.is_fatal(signal) {
+                        crashes.push(CaughtCrash {
+                            thread_id: tid.as_raw(),
+                            signal: signal as i32,
+                            dump: self.dump(tid.as_raw()),
+                        });
+                    }
+                    let _ = ptrace::cont(tid, signal);
+                }
+                WaitStatus::PtraceEvent(tid, signal, event) => {
+                    self.threads.insert(tid.as_raw());
+                    self.handle_event(tid, signal, event);
+                }
+                WaitStatus::Exited(tid, code) => {
+                    self.threads.remove(&tid.as_raw());
+                    if tid.as_raw() == self.process_id {
+                        return Ok(CatchOutcome {
+                            status: ExitStatus::from_raw(code << 8),
+                            crashes,
+                        });
+                    }
+                }
+                WaitStatus::Signaled(tid, signal, core_dumped) => {
+                    self.threads.remove(&tid.as_raw());
+                    if tid.as_raw() == self.process_id {
+                        let core_flag = if core_dumped { 0x80 } else { 0 };
+                        return Ok(CatchOutcome {
+                            status: ExitStatus::from_raw(signal as i32 | core_flag),
+                            crashes,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Continues a thread after a ptrace event, a group-stop is kept until the
+    /// process is continued with `SIGCONT`
+    fn handle_event(&mut self, tid: unistd::Pid, signal: Signal, event: i32) {
+        if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+            if let Ok(new_tid) = ptrace::getevent(tid) {
+                self.threads.insert(new_tid as Pid);
+            }
+        }
+
+        if event == ptrace::Event::PTRACE_EVENT_STOP as i32 && STOP_SIGNALS.contains(&signal) {
+            // SAFETY: `PTRACE_LISTEN` only takes the tid
+            unsafe { libc::ptrace(libc::PTRACE_LISTEN, tid.as_raw(), 0, 0) };
+        } else {
+            let _ = ptrace::cont(tid, None);
+        }
+    }
+
+    /// Whether the signal kills the process with a core dump, rather than
+    /// being handled or ignored by it
+    fn is_fatal(&self, signal: Signal) -> bool {
+        if !CORE_SIGNALS.contains(&signal) {
+            return false;
+        }
+
+        let bit = 1u64 << (signal as i32 - 1);
+        match Status::from_file(format!("/proc/{}/status", self.process_id)) {
+            Ok(status) => (status.sigcgt | status.sigign) & bit == 0,
+            // Better to write a dump than to miss a crash
+            Err(_) => true,
+        }
+    }
+
+    /// Writes a minidump for the thread in a signal-delivery-stop, with the
+    /// other threads stopped meanwhile
+    fn dump(&mut self, tid: Pid) -> Result<PathBuf, CrashCatcherError> {
+        let stopped = self.stop_threads(tid);
+        let result = self.write_dump(tid);
+        for (tid, signal) in stopped {
+            let _ = ptrace::cont(unistd::Pid::from_raw(tid), signal);
+        }
+        result
+    }
+
+    fn write_dump(&mut self, tid: Pid) -> Result<PathBuf, CrashCatcherError> {
+        let siginfo = ptrace::getsiginfo(unistd::Pid::from_raw(tid))
+            .map_err(|e| CrashCatcherError::PtraceFailed(tid, e))?;
+        let thread_info = ThreadInfo::create(self.process_id, tid)
+            .map_err(|e| CrashCatcherError::ThreadInfoFailed(tid, e))?;
+        let crash_context =
+            CrashContext::from_thread_info(&thread_info, &siginfo, self.process_id, tid);
+
+        let mut config = MinidumpWriterConfig::new(self.process_id, tid);
+        config.set_crash_context(crash_context).traced_by_caller();
+        if let Some(configure) = &self.configure {
+            configure(&mut config);
+        }
+
+        let path = self
+            .dump_directory
+            .join(format!("{}-{}.dmp", self.process_id, self.dump_count));
+        self.dump_count += 1;
+
+        let mut file = File::create(&path)
+            .map_err(|e| CrashCatcherError::CreateDumpFailed(path.clone(), e))?;
+        config
+            .write(&mut file)
+            .map_err(|e| CrashCatcherError::WriteDumpFailed(path.clone(), Box::new(e)))?;
+        Ok(path)
+    }
+
+    /// Interrupts all threads except the one given, and waits for them to
+    /// stop. Returns the stopped threads with the signal to continue them
+    /// with, if they stopped for its delivery.
+    fn stop_threads(&mut self, except: Pid) -> Vec<(Pid, Option<Signal>)> {
+        let mut waiting: Vec<Pid> = self
+            .threads
+            .iter()
+            .copied()
+            .filter(|&tid| tid != except)
+            .filter(|&tid| ptrace::interrupt(unistd::Pid::from_raw(tid)).is_ok())
+            .collect();
+
+        let mut stopped = Vec::new();
+        while let Some(tid) = waiting.pop() {
+            let pid = unistd::Pid::from_raw(tid);
+            let status = loop {
+                match wait::waitpid(pid, Some(WaitPidFlag::__WALL)) {
+                    Err(Errno::EINTR) => continue,
+                    status => break status,
+                }
+            };
+
+            match status {
+                Ok(WaitStatus::Stopped(_, signal)) => stopped.push((tid, Some(signal))),
+                Ok(WaitStatus::PtraceEvent(_, _, event)) => {
+                    // A new thread starts in a ptrace-stop of its own
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+                        if let Ok(new_tid) = ptrace::getevent(pid) {
+                            self.threads.insert(new_tid as Pid);
+                            waiting.push(new_tid as Pid);
+                        }
+                    }
+                    stopped.push((tid, None));
+                }
+                _ => {
+                    self.threads.remove(&tid);
+                }
+            }
+        }
+        stopped
+    }
+}
+
+/// Waits for the target to stop with the signal
+fn expect_stop(
+    pid: unistd::Pid,
+    expected: Signal,
+    flags: WaitPidFlag,
+) -> Result<(), CrashCatcherError> {
+    match wait::waitpid(pid, Some(flags)).map_err(CrashCatcherError::WaitFailed)? {
+        WaitStatus::Stopped(_, signal) if signal == expected => Ok(()),
+        status => Err(CrashCatcherError::UnexpectedStatus(
+            pid.as_raw(),
+            format!("{status:?}"),
+        )),
+    }
+}
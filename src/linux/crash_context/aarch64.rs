@@ -1,5 +1,5 @@
 use {
-    super::CrashContext,
+    super::{super::thread_info::ThreadInfo, CrashContext},
     crate::{
         minidump_cpu::{RawContextCPU, FP_REG_COUNT, GP_REG_COUNT},
         minidump_format::format,
@@ -33,4 +33,42 @@ impl CrashContext {
             out.float_regs[..FP_REG_COUNT].copy_from_slice(&fs.vregs[..FP_REG_COUNT]);
         }
     }
+
+    /// Replaces the registers of the crashing thread, which are those of
+    /// the signal handler, with those at the time of the crash
+    pub fn fill_thread_info(&self, info: &mut ThreadInfo) {
+        {
+            let gregs = &self.inner.context.uc_mcontext;
+            info.regs.regs = gregs.regs;
+            info.regs.sp = gregs.sp;
+            info.regs.pc = gregs.pc;
+            info.regs.pstate = gregs.pstate;
+        }
+        info.stack_pointer = info.regs.sp as usize;
+
+        {
+            let fs = &self.inner.float_state;
+            info.fpregs.fpsr = fs.fpsr;
+            info.fpregs.fpcr = fs.fpcr;
+            info.fpregs.vregs = fs.vregs;
+        }
+    }
+
+    /// Sets the registers from those read from a stopped thread
+    pub(super) fn set_registers(&mut self, info: &ThreadInfo) {
+        {
+            let gregs = &mut self.inner.context.uc_mcontext;
+            gregs.regs = info.regs.regs;
+            gregs.sp = info.regs.sp;
+            gregs.pc = info.regs.pc;
+            gregs.pstate = info.regs.pstate;
+        }
+
+        {
+            let fs = &mut self.inner.float_state;
+            fs.fpsr = info.fpregs.fpsr;
+            fs.fpcr = info.fpregs.fpcr;
+            fs.vregs = info.fpregs.vregs;
+        }
+    }
 }
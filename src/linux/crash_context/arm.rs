@@ -1,4 +1,7 @@
-use {super::CrashContext, crate::minidump_cpu::RawContextCPU};
+use {
+    super::{super::thread_info::ThreadInfo, CrashContext},
+    crate::minidump_cpu::RawContextCPU,
+};
 
 impl CrashContext {
     pub fn get_instruction_pointer(&self) -> usize {
@@ -43,4 +46,55 @@ impl CrashContext {
         //out.float_save.regs = [0; MD_FLOATINGSAVEAREA_ARM_FPR_COUNT];
         //out.float_save.extra = [0; MD_FLOATINGSAVEAREA_ARM_FPEXTRA_COUNT];
     }
+
+    /// Replaces the registers of the crashing thread, which are those of
+    /// the signal handler, with those at the time of the crash
+    pub fn fill_thread_info(&self, info: &mut ThreadInfo) {
+        let gregs = &self.inner.context.uc_mcontext;
+        info.regs.uregs[..17].copy_from_slice(&[
+            gregs.arm_r0,
+            gregs.arm_r1,
+            gregs.arm_r2,
+            gregs.arm_r3,
+            gregs.arm_r4,
+            gregs.arm_r5,
+            gregs.arm_r6,
+            gregs.arm_r7,
+            gregs.arm_r8,
+            gregs.arm_r9,
+            gregs.arm_r10,
+            gregs.arm_fp,
+            gregs.arm_ip,
+            gregs.arm_sp,
+            gregs.arm_lr,
+            gregs.arm_pc,
+            gregs.arm_cpsr,
+        ]);
+        info.stack_pointer = gregs.arm_sp as usize;
+        // The floating point registers aren't part of the crash context
+    }
+
+    /// Sets the registers from those read from a stopped thread
+    pub(super) fn set_registers(&mut self, info: &ThreadInfo) {
+        let gregs = &mut self.inner.context.uc_mcontext;
+        let uregs = &info.regs.uregs;
+        gregs.arm_r0 = uregs[0];
+        gregs.arm_r1 = uregs[1];
+        gregs.arm_r2 = uregs[2];
+        gregs.arm_r3 = uregs[3];
+        gregs.arm_r4 = uregs[4];
+        gregs.arm_r5 = uregs[5];
+        gregs.arm_r6 = uregs[6];
+        gregs.arm_r7 = uregs[7];
+        gregs.arm_r8 = uregs[8];
+        gregs.arm_r9 = uregs[9];
+        gregs.arm_r10 = uregs[10];
+        gregs.arm_fp = uregs[11];
+        gregs.arm_ip = uregs[12];
+        gregs.arm_sp = uregs[13];
+        gregs.arm_lr = uregs[14];
+        gregs.arm_pc = uregs[15];
+        gregs.arm_cpsr = uregs[16];
+        // The floating point registers aren't part of the crash context
+    }
 }
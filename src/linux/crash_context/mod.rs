@@ -2,6 +2,8 @@
 //! structures which we get from the kernel. These are platform specific
 //! functions to juggle the `ucontext_t` and user structures into minidump format.
 
+use super::thread_info::ThreadInfo;
+
 pub struct CrashContext {
     pub inner: crash_context::CrashContext,
 }
@@ -16,6 +18,100 @@ impl std::fmt::Debug for CrashContext {
     }
 }
 
+impl CrashContext {
+    /// Builds the context of the calling thread from the arguments of a signal
+    /// handler installed with `SA_SIGINFO`
+    ///
+    /// # Safety
+    ///
+    /// Both pointers must be the ones passed to the signal handler
+    pub unsafe fn from_signal(info: *const libc::siginfo_t, context: *const libc::c_void) -> Self {
+        Self::from_ucontext(
+            &*context.cast(),
+            signalfd_siginfo(&*info),
+            nix::unistd::gettid().as_raw(),
+        )
+    }
+
+    /// Builds the context of a thread of another process from its registers,
+    /// read while it was stopped by the signal described by `siginfo`, e.g.
+    /// with `PTRACE_GETSIGINFO`
+    pub fn from_thread_info(
+        info: &ThreadInfo,
+        siginfo: &libc::siginfo_t,
+        pid: super::Pid,
+        tid: super::Pid,
+    ) -> Self {
+        // SAFETY: the context is plain old data, the pointers in it aren't
+        // followed when it is written to a minidump
+        let mut context = Self {
+            inner: unsafe { std::mem::zeroed() },
+        };
+        context.inner.siginfo = signalfd_siginfo(siginfo);
+        context.inner.pid = pid;
+        context.inner.tid = tid;
+        context.set_registers(info);
+        context
+    }
+
+    /// Builds the context of the calling thread from a context filled in by
+    /// the kernel or `crash_context_getcontext`
+    ///
+    /// # Safety
+    ///
+    /// The context must not have moved since it was filled in, as the
+    /// floating point registers are read through a pointer into it on x86
+    pub(crate) unsafe fn from_ucontext(
+        context: &crash_context::ucontext_t,
+        siginfo: libc::signalfd_siginfo,
+        tid: super::Pid,
+    ) -> Self {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
+                let float_state = if context.uc_mcontext.fpregs.is_null() {
+                    std::mem::zeroed()
+                } else {
+                    (*context.uc_mcontext.fpregs).clone()
+                };
+            } else if #[cfg(not(target_arch = "arm"))] {
+                let float_state = std::mem::zeroed();
+            }
+        }
+
+        Self {
+            inner: crash_context::CrashContext {
+                siginfo,
+                pid: std::process::id() as _,
+                tid,
+                context: context.clone(),
+                #[cfg(not(target_arch = "arm"))]
+                float_state,
+            },
+        }
+    }
+}
+
+/// Converts the `siginfo_t` of a signal to the `signalfd_siginfo` the crash
+/// context holds
+fn signalfd_siginfo(info: &libc::siginfo_t) -> libc::signalfd_siginfo {
+    // SAFETY: `signalfd_siginfo` is plain old data
+    let mut siginfo: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+    siginfo.ssi_signo = info.si_signo as u32;
+    siginfo.ssi_errno = info.si_errno;
+    siginfo.ssi_code = info.si_code;
+    // SAFETY: the union members that are read depend on the code
+    unsafe {
+        if info.si_code <= 0 {
+            // Sent by a process rather than the kernel
+            siginfo.ssi_pid = info.si_pid() as u32;
+            siginfo.ssi_uid = info.si_uid();
+        } else {
+            siginfo.ssi_addr = info.si_addr() as u64;
+        }
+    }
+    siginfo
+}
+
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
         mod x86_64;
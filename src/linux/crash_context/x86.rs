@@ -1,5 +1,5 @@
 use {
-    super::CrashContext,
+    super::{super::thread_info::ThreadInfo, CrashContext},
     crate::{minidump_cpu::RawContextCPU, minidump_format::format::ContextFlagsX86},
     libc::{
         REG_CS, REG_DS, REG_EAX, REG_EBP, REG_EBX, REG_ECX, REG_EDI, REG_EDX, REG_EFL, REG_EIP,
@@ -61,4 +61,99 @@ impl CrashContext {
             });
         }
     }
+
+    /// Replaces the registers of the crashing thread, which are those of
+    /// the signal handler, with those at the time of the crash
+    pub fn fill_thread_info(&self, info: &mut ThreadInfo) {
+        {
+            let gregs = &self.inner.context.uc_mcontext.gregs;
+            let regs = &mut info.regs;
+            regs.xgs = gregs[REG_GS as usize] as libc::c_long;
+            regs.xfs = gregs[REG_FS as usize] as libc::c_long;
+            regs.xes = gregs[REG_ES as usize] as libc::c_long;
+            regs.xds = gregs[REG_DS as usize] as libc::c_long;
+
+            regs.edi = gregs[REG_EDI as usize] as libc::c_long;
+            regs.esi = gregs[REG_ESI as usize] as libc::c_long;
+            regs.ebx = gregs[REG_EBX as usize] as libc::c_long;
+            regs.edx = gregs[REG_EDX as usize] as libc::c_long;
+            regs.ecx = gregs[REG_ECX as usize] as libc::c_long;
+            regs.eax = gregs[REG_EAX as usize] as libc::c_long;
+
+            regs.ebp = gregs[REG_EBP as usize] as libc::c_long;
+            regs.eip = gregs[REG_EIP as usize] as libc::c_long;
+            regs.xcs = gregs[REG_CS as usize] as libc::c_long;
+            regs.eflags = gregs[REG_EFL as usize] as libc::c_long;
+            regs.esp = gregs[REG_UESP as usize] as libc::c_long;
+            regs.xss = gregs[REG_SS as usize] as libc::c_long;
+        }
+        info.stack_pointer = info.regs.esp as usize;
+
+        {
+            let fs = &self.inner.float_state;
+            let fpregs = &mut info.fpregs;
+            fpregs.cwd = fs.cw as libc::c_long;
+            fpregs.swd = fs.sw as libc::c_long;
+            fpregs.twd = fs.tag as libc::c_long;
+            fpregs.fip = fs.ipoff as libc::c_long;
+            fpregs.fcs = fs.cssel as libc::c_long;
+            fpregs.foo = fs.dataoff as libc::c_long;
+            fpregs.fos = fs.datasel as libc::c_long;
+            // SAFETY: Both are the 80 bytes of the 8 x87 registers
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    fs._st.as_ptr().cast::<u8>(),
+                    fpregs.st_space.as_mut_ptr().cast::<u8>(),
+                    std::mem::size_of_val(&fpregs.st_space),
+                );
+            }
+        }
+    }
+
+    /// Sets the registers from those read from a stopped thread
+    pub(super) fn set_registers(&mut self, info: &ThreadInfo) {
+        {
+            let gregs = &mut self.inner.context.uc_mcontext.gregs;
+            let regs = &info.regs;
+            gregs[REG_GS as usize] = regs.xgs as i32;
+            gregs[REG_FS as usize] = regs.xfs as i32;
+            gregs[REG_ES as usize] = regs.xes as i32;
+            gregs[REG_DS as usize] = regs.xds as i32;
+
+            gregs[REG_EDI as usize] = regs.edi as i32;
+            gregs[REG_ESI as usize] = regs.esi as i32;
+            gregs[REG_EBX as usize] = regs.ebx as i32;
+            gregs[REG_EDX as usize] = regs.edx as i32;
+            gregs[REG_ECX as usize] = regs.ecx as i32;
+            gregs[REG_EAX as usize] = regs.eax as i32;
+
+            gregs[REG_EBP as usize] = regs.ebp as i32;
+            gregs[REG_EIP as usize] = regs.eip as i32;
+            gregs[REG_CS as usize] = regs.xcs as i32;
+            gregs[REG_EFL as usize] = regs.eflags as i32;
+            gregs[REG_ESP as usize] = regs.esp as i32;
+            gregs[REG_UESP as usize] = regs.esp as i32;
+            gregs[REG_SS as usize] = regs.xss as i32;
+        }
+
+        {
+            let fpregs = &info.fpregs;
+            let fs = &mut self.inner.float_state;
+            fs.cw = fpregs.cwd as u32;
+            fs.sw = fpregs.swd as u32;
+            fs.tag = fpregs.twd as u32;
+            fs.ipoff = fpregs.fip as u32;
+            fs.cssel = fpregs.fcs as u32;
+            fs.dataoff = fpregs.foo as u32;
+            fs.datasel = fpregs.fos as u32;
+            // SAFETY: Both are the 80 bytes of the 8 x87 registers
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    fpregs.st_space.as_ptr().cast::<u8>(),
+                    fs._st.as_mut_ptr().cast::<u8>(),
+                    std::mem::size_of_val(&fs._st),
+                );
+            }
+        }
+    }
 }
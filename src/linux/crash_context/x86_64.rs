@@ -1,5 +1,8 @@
 use {
-    super::{super::thread_info::copy_u32_registers, CrashContext},
+    super::{
+        super::thread_info::{copy_u32_registers, ThreadInfo},
+        CrashContext,
+    },
     crate::{minidump_cpu::RawContextCPU, minidump_format::format},
     libc::{
         REG_CSGSFS, REG_EFL, REG_R10, REG_R11, REG_R12, REG_R13, REG_R14, REG_R15, REG_R8, REG_R9,
@@ -75,4 +78,97 @@ impl CrashContext {
                 .expect("this is impossible");
         }
     }
+
+    /// Replaces the registers of the crashing thread, which are those of
+    /// the signal handler, with those at the time of the crash
+    pub fn fill_thread_info(&self, info: &mut ThreadInfo) {
+        {
+            let gregs = &self.inner.context.uc_mcontext.gregs;
+            let regs = &mut info.regs;
+            regs.cs = (gregs[REG_CSGSFS as usize] & 0xffff) as u64;
+            regs.eflags = gregs[REG_EFL as usize] as u64;
+
+            regs.rax = gregs[REG_RAX as usize] as u64;
+            regs.rcx = gregs[REG_RCX as usize] as u64;
+            regs.rdx = gregs[REG_RDX as usize] as u64;
+            regs.rbx = gregs[REG_RBX as usize] as u64;
+
+            regs.rsp = gregs[REG_RSP as usize] as u64;
+            regs.rbp = gregs[REG_RBP as usize] as u64;
+            regs.rsi = gregs[REG_RSI as usize] as u64;
+            regs.rdi = gregs[REG_RDI as usize] as u64;
+            regs.r8 = gregs[REG_R8 as usize] as u64;
+            regs.r9 = gregs[REG_R9 as usize] as u64;
+            regs.r10 = gregs[REG_R10 as usize] as u64;
+            regs.r11 = gregs[REG_R11 as usize] as u64;
+            regs.r12 = gregs[REG_R12 as usize] as u64;
+            regs.r13 = gregs[REG_R13 as usize] as u64;
+            regs.r14 = gregs[REG_R14 as usize] as u64;
+            regs.r15 = gregs[REG_R15 as usize] as u64;
+
+            regs.rip = gregs[REG_RIP as usize] as u64;
+        }
+        info.stack_pointer = info.regs.rsp as usize;
+
+        {
+            let fs = &self.inner.float_state;
+            let fpregs = &mut info.fpregs;
+            fpregs.cwd = fs.cwd;
+            fpregs.swd = fs.swd;
+            fpregs.ftw = fs.ftw;
+            fpregs.fop = fs.fop;
+            fpregs.rip = fs.rip;
+            fpregs.rdp = fs.rdp;
+            fpregs.mxcsr = fs.mxcsr;
+            fpregs.mxcr_mask = fs.mxcr_mask;
+            fpregs.st_space = fs.st_space;
+            fpregs.xmm_space = fs.xmm_space;
+        }
+    }
+
+    /// Sets the registers from those read from a stopped thread
+    pub(super) fn set_registers(&mut self, info: &ThreadInfo) {
+        {
+            let gregs = &mut self.inner.context.uc_mcontext.gregs;
+            let regs = &info.regs;
+            gregs[REG_CSGSFS as usize] =
+                (regs.cs & 0xffff | (regs.gs & 0xffff) << 16 | (regs.fs & 0xffff) << 32) as i64;
+            gregs[REG_EFL as usize] = regs.eflags as i64;
+
+            gregs[REG_RAX as usize] = regs.rax as i64;
+            gregs[REG_RCX as usize] = regs.rcx as i64;
+            gregs[REG_RDX as usize] = regs.rdx as i64;
+            gregs[REG_RBX as usize] = regs.rbx as i64;
+
+            gregs[REG_RSP as usize] = regs.rsp as i64;
+            gregs[REG_RBP as usize] = regs.rbp as i64;
+            gregs[REG_RSI as usize] = regs.rsi as i64;
+            gregs[REG_RDI as usize] = regs.rdi as i64;
+            gregs[REG_R8 as usize] = regs.r8 as i64;
+            gregs[REG_R9 as usize] = regs.r9 as i64;
+            gregs[REG_R10 as usize] = regs.r10 as i64;
+            gregs[REG_R11 as usize] = regs.r11 as i64;
+            gregs[REG_R12 as usize] = regs.r12 as i64;
+            gregs[REG_R13 as usize] = regs.r13 as i64;
+            gregs[REG_R14 as usize] = regs.r14 as i64;
+            gregs[REG_R15 as usize] = regs.r15 as i64;
+
+            gregs[REG_RIP as usize] = regs.rip as i64;
+        }
+
+        {
+            let fpregs = &info.fpregs;
+            let fs = &mut self.inner.float_state;
+            fs.cwd = fpregs.cwd;
+            fs.swd = fpregs.swd;
+            fs.ftw = fpregs.ftw;
+            fs.fop = fpregs.fop;
+            fs.rip = fpregs.rip;
+            fs.rdp = fpregs.rdp;
+            fs.mxcsr = fpregs.mxcsr;
+            fs.mxcr_mask = fpregs.mxcr_mask;
+            fs.st_space = fpregs.st_space;
+            fs.xmm_space = fpregs.xmm_space;
+        }
+    }
 }
@@ -0,0 +1,298 @@
+use {
+    super::{
+        super::{
+            annotations::Annotations,
+            crash_context::CrashContext,
+            minidump_writer::{self_dump::allow_ptrace_by, AuxvType, DirectAuxvDumpInfo},
+        },
+        peer_credentials,
+        protocol::{self, RequestHeader},
+        CrashGenerationError,
+    },
+    nix::errno::Errno,
+    std::{
+        os::{fd::AsRawFd, unix::net::UnixStream},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, AtomicI32, AtomicU8, Ordering},
+            OnceLock,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// The signals the handlers installed by
+/// [`CrashGenerationClient::install_signal_handlers`] request a dump for
+pub const FATAL_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGILL,
+    libc::SIGSEGV,
+    libc::SIGTRAP,
+];
+
+/// The client installed by [`CrashGenerationClient::install_signal_handlers`]
+/// and the handlers it replaced
+struct Installed {
+    client: CrashGenerationClient,
+    previous: [libc::sigaction; FATAL_SIGNALS.len()],
+}
+
+type SignalHandler = extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
+
+static INSTALLED: OnceLock<Installed> = OnceLock::new();
+
+/// The size of the alternate signal stacks installed by
+/// [`install_alternate_stack`], the context of the crash is kept on it while
+/// dumping
+pub const ALTERNATE_STACK_SIZE: usize = 64 * 1024;
+
+const NOT_DUMPED: u8 = 0;
+const DUMPING: u8 = 1;
+const DUMPED: u8 = 2;
+
+/// Whether a thread has crashed, only the first one requests a dump
+static CRASH_STATE: AtomicU8 = AtomicU8::new(NOT_DUMPED);
+/// The thread that requests the dump, set once [`CRASH_STATE`] is `DUMPING`
+static DUMPING_THREAD: AtomicI32 = AtomicI32::new(0);
+
+/// How long threads that crash while another one requests a dump wait for it
+/// before passing their signal on
+pub const CONCURRENT_CRASH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to a [`CrashGenerationServer`](super::CrashGenerationServer)
+#[derive(Debug)]
+pub struct CrashGenerationClient {
+    socket: UnixStream,
+    auxv: DirectAuxvDumpInfo,
+    annotations: Vec<u8>,
+    busy: AtomicBool,
+}
+
+impl CrashGenerationClient {
+    /// Connects to the server listening at the path, and allows it to ptrace
+    /// this process
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, CrashGenerationError> {
+        let path = path.as_ref();
+        let socket = UnixStream::connect(path)
+            .map_err(|e| CrashGenerationError::ConnectFailed(path.to_owned(), e))?;
+
+        let server = peer_credentials(&socket)?.pid;
+        allow_ptrace_by(server).map_err(|e| CrashGenerationError::SetPtracerFailed(server, e))?;
+
+        // SAFETY: `getauxval` returns 0 for missing entries
+        let auxv = unsafe {
+            DirectAuxvDumpInfo {
+                program_header_count: libc::getauxval(libc::AT_PHNUM) as AuxvType,
+                program_header_address: libc::getauxval(libc::AT_PHDR) as AuxvType,
+                linux_gate_address: libc::getauxval(libc::AT_SYSINFO_EHDR) as AuxvType,
+                entry_address: libc::getauxval(libc::AT_ENTRY) as AuxvType,
+            }
+        };
+
+        Ok(Self {
+            socket,
+            auxv,
+            annotations: protocol::encode_annotations(&Annotations::default()),
+            busy: AtomicBool::new(false),
+        })
+    }
+
+    /// Sets the annotations sent with every dump request
+    pub fn set_annotations(&mut self, annotations: &Annotations) -> &mut Self {
+        self.annotations = protocol::encode_annotations(annotations);
+        self
+    }
+
+    /// Asks the server to write a minidump of this process with the context
+    /// provided, and waits until it has been written.
+    ///
+    /// This doesn't allocate, so it can be called from a signal handler.
+    pub fn request_dump(&self, crash_context: &CrashContext) -> Result<(), CrashGenerationError> {
+        if self.busy.swap(true, Ordering::Acquire) {
+            return Err(CrashGenerationError::RequestInProgress);
+        }
+        let result = self.send_request(crash_context);
+        self.busy.store(false, Ordering::Release);
+        result
+    }
+
+    fn send_request(&self, crash_context: &CrashContext) -> Result<(), CrashGenerationError> {
+        let context = crash_context.inner.as_bytes();
+        let header = RequestHeader {
+            magic: protocol::MAGIC,
+            version: protocol::VERSION,
+            pid: crash_context.inner.pid,
+            tid: crash_context.inner.tid,
+            context_size: context.len() as u32,
+            annotations_size: self.annotations.len() as u32,
+            auxv: self.auxv.clone(),
+        };
+
+        let fd = self.socket.as_raw_fd();
+        for bytes in [header.as_bytes(), context, &self.annotations] {
+            send_all(fd, bytes).map_err(CrashGenerationError::SendFailed)?;
+        }
+
+        let mut reply = 0u8;
+        loop {
+            // SAFETY: receives at most one byte into `reply`
+            let res = unsafe { libc::recv(fd, (&mut reply as *mut u8).cast(), 1, 0) };
+            match Errno::result(res) {
+                Ok(0) => return Err(CrashGenerationError::ServerDisconnected),
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(CrashGenerationError::SendFailed(e)),
+            }
+        }
+
+        match reply {
+            protocol::REPLY_DUMPED => Ok(()),
+            _ => Err(CrashGenerationError::DumpFailed),
+        }
+    }
+
+    /// Installs handlers for the [`FATAL_SIGNALS`] that request a dump from
+    /// the server with the context of the crashing thread, and then pass the
+    /// signal on to the handlers they replaced.
+    ///
+    /// The handlers run on the alternate signal stack of the crashing thread
+    /// if it has one, which is needed for dumps of stack overflows. The
+    /// calling thread gets one with [`install_alternate_stack`], other threads
+    /// should call it too.
+    pub fn install_signal_handlers(self) -> Result<(), CrashGenerationError> {
+        install_alternate_stack()?;
+
+        // SAFETY: `sigaction` is plain old data
+        let mut previous: [libc::sigaction; FATAL_SIGNALS.len()] = unsafe { std::mem::zeroed() };
+        for (signal, previous) in FATAL_SIGNALS.iter().zip(previous.iter_mut()) {
+            // SAFETY: only reads the current handler
+            let res = unsafe { libc::sigaction(*signal, std::ptr::null(), previous) };
+            Errno::result(res)
+                .map_err(|e| CrashGenerationError::InstallHandlerFailed(*signal, e))?;
+        }
+
+        INSTALLED
+            .set(Installed {
+                client: self,
+                previous,
+            })
+            .map_err(|_| CrashGenerationError::AlreadyInstalled)?;
+
+        // SAFETY: `sigaction` is plain old data
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle_signal as SignalHandler as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        for signal in FATAL_SIGNALS {
+            // SAFETY: the handler only uses async-signal-safe functions
+            let res = unsafe {
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut())
+            };
+            Errno::result(res)
+                .map_err(|e| CrashGenerationError::InstallHandlerFailed(signal, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Installs an alternate signal stack for the calling thread, unless it has
+/// one of at least [`ALTERNATE_STACK_SIZE`] already. The default one of threads
+/// spawned by the standard library is too small for the signal handlers.
+pub fn install_alternate_stack() -> Result<(), CrashGenerationError> {
+    // SAFETY: `stack_t` is plain old data
+    let mut current: libc::stack_t = unsafe { std::mem::zeroed() };
+    // SAFETY: only reads the current alternate stack
+    let res = unsafe { libc::sigaltstack(std::ptr::null(), &mut current) };
+    Errno::result(res).map_err(CrashGenerationError::AlternateStackFailed)?;
+    if current.ss_flags & libc::SS_DISABLE == 0 && current.ss_size >= ALTERNATE_STACK_SIZE {
+        return Ok(());
+    }
+
+    // SAFETY: maps new memory, which is never unmapped as the stack may be
+    // in use until the thread exits
+    let stack = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            ALTERNATE_STACK_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK,
+            -1,
+            0,
+        )
+    };
+    if stack == libc::MAP_FAILED {
+        return Err(CrashGenerationError::AlternateStackFailed(Errno::last()));
+    }
+
+    let new = libc::stack_t {
+        ss_sp: stack,
+        ss_flags: 0,
+        ss_size: ALTERNATE_STACK_SIZE,
+    };
+    // SAFETY: the stack was just mapped
+    let res = unsafe { libc::sigaltstack(&new, std::ptr::null_mut()) };
+    Errno::result(res).map_err(CrashGenerationError::AlternateStackFailed)?;
+    Ok(())
+}
+
+/// Sends all the bytes, without raising `SIGPIPE` if the server is gone
+fn send_all(fd: libc::c_int, mut bytes: &[u8]) -> nix::Result<()> {
+    while !bytes.is_empty() {
+        // SAFETY: sends at most `bytes.len()` bytes from `bytes`
+        let res = unsafe { libc::send(fd, bytes.as_ptr().cast(), bytes.len(), libc::MSG_NOSIGNAL) };
+        match Errno::result(res) {
+            Ok(sent) => bytes = &bytes[sent as usize..],
+            Err(Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let Some(installed) = INSTALLED.get() else {
+        return;
+    };
+
+    // SAFETY: `gettid` has no arguments and can't fail
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::pid_t;
+    match CRASH_STATE.compare_exchange(NOT_DUMPED, DUMPING, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {
+            DUMPING_THREAD.store(tid, Ordering::Release);
+            // SAFETY: the pointers are the ones passed to the handler
+            let crash_context = unsafe { CrashContext::from_signal(info, context) };
+            // There is nothing left to do if this fails, the signal is passed
+            // on regardless
+            let _ = installed.client.request_dump(&crash_context);
+            CRASH_STATE.store(DUMPED, Ordering::Release);
+        }
+        // The thread requesting the dump crashed again while doing so, it
+        // can't wait for itself
+        Err(_) if DUMPING_THREAD.load(Ordering::Acquire) == tid => {}
+        Err(_) => {
+            // Another thread crashed first, wait for its dump rather than
+            // taking the process down in the middle of it, unless it is stuck
+            let started = Instant::now();
+            while CRASH_STATE.load(Ordering::Acquire) != DUMPED
+                && started.elapsed() < CONCURRENT_CRASH_TIMEOUT
+            {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    // SAFETY: restores the handlers that were replaced, and raises the signal
+    // again, which is blocked until this handler returns
+    unsafe {
+        for (signal, previous) in FATAL_SIGNALS.iter().zip(installed.previous.iter()) {
+            libc::sigaction(*signal, previous, std::ptr::null_mut());
+        }
+        libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, signal);
+    }
+}
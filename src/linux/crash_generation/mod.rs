@@ -0,0 +1,138 @@
+//! Out-of-process crash generation, where the crashing process sends its
+//! context to a server over a Unix domain socket, and the server writes the
+//! minidump while the crashing process waits.
+//!
+//! The server identifies the client with the `SO_PEERCRED` credentials of the
+//! socket, rather than the pid the client claims, which also works when the
+//! client is in a different pid namespace.
+
+use {
+    super::{minidump_writer::errors::WriterError, serializers::*, Pid},
+    crate::serializers::*,
+    std::{os::fd::AsRawFd, path::PathBuf},
+    thiserror::Error,
+};
+
+pub mod client;
+mod protocol;
+pub mod server;
+
+pub use {client::CrashGenerationClient, server::CrashGenerationServer};
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum CrashGenerationError {
+    #[error("Failed to bind the socket at {0}")]
+    BindFailed(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to connect to the socket at {0}")]
+    ConnectFailed(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to accept a connection")]
+    AcceptFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to get the credentials of the peer")]
+    PeerCredentialsFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to allow the server process {0} to ptrace this process")]
+    SetPtracerFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to install the handler for signal {0}")]
+    InstallHandlerFailed(
+        i32,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to install an alternate signal stack")]
+    AlternateStackFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("The signal handlers are already installed")]
+    AlreadyInstalled,
+    #[error("Another dump request is in progress")]
+    RequestInProgress,
+    #[error("Failed to send the dump request")]
+    SendFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("The server closed the connection before replying")]
+    ServerDisconnected,
+    #[error("The server failed to write the minidump")]
+    DumpFailed,
+    #[error("Failed to receive the dump request")]
+    ReceiveFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Invalid dump request: {0}")]
+    InvalidRequest(&'static str),
+    #[error("Failed to reply to the client")]
+    ReplyFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to open a pidfd for process {0}")]
+    PidfdOpenFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Process {0} exited before it was dumped")]
+    ClientExited(Pid),
+    #[error("Thread {1} of process {0} was not found in this pid namespace")]
+    ThreadNotFound(Pid, Pid),
+    #[error("Failed to create the minidump file {0}")]
+    CreateDumpFailed(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to write the minidump file {0}")]
+    WriteDumpFailed(PathBuf, #[source] Box<WriterError>),
+}
+
+/// Reads the credentials of the process on the other end of the socket, as
+/// they were when it connected
+fn peer_credentials(socket: &impl AsRawFd) -> Result<libc::ucred, CrashGenerationError> {
+    // SAFETY: `ucred` is plain old data
+    let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `SO_PEERCRED` writes at most `len` bytes to the `ucred`
+    let res = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    nix::errno::Errno::result(res).map_err(CrashGenerationError::PeerCredentialsFailed)?;
+    Ok(credentials)
+}
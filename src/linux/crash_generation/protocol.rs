@@ -0,0 +1,182 @@
+//! The messages exchanged between the client and the server. Both ends run on
+//! the same machine, so everything is in native byte order.
+//!
+//! A dump request is a [`RequestHeader`], followed by the bytes of the
+//! `crash_context::CrashContext` and the encoded annotations. The server
+//! replies with a single byte, [`REPLY_DUMPED`] or [`REPLY_FAILED`].
+
+use {
+    super::super::{
+        annotations::{AnnotationBlob, Annotations},
+        minidump_writer::DirectAuxvDumpInfo,
+        Pid,
+    },
+    std::mem::size_of,
+};
+
+pub const MAGIC: u32 = u32::from_le_bytes(*b"MDCG");
+pub const VERSION: u32 = 1;
+
+/// The largest encoded annotations the server accepts
+pub const MAX_ANNOTATIONS_SIZE: u32 = 16 * 1024 * 1024;
+
+pub const REPLY_DUMPED: u8 = 0;
+pub const REPLY_FAILED: u8 = 1;
+
+#[repr(C)]
+#[derive(Clone, Default)]
+pub struct RequestHeader {
+    pub magic: u32,
+    pub version: u32,
+    /// The pid and tid in the client's pid namespace
+    pub pid: Pid,
+    pub tid: Pid,
+    pub context_size: u32,
+    pub annotations_size: u32,
+    pub auxv: DirectAuxvDumpInfo,
+}
+
+impl RequestHeader {
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the header only consists of integers
+        unsafe { std::slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the header only consists of integers, which are valid for
+        // any bytes
+        unsafe { std::slice::from_raw_parts_mut((self as *mut Self).cast(), size_of::<Self>()) }
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Encodes the annotations ahead of time, as the client can't allocate when
+/// it sends them from a signal handler
+pub fn encode_annotations(annotations: &Annotations) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(annotations.simple.len() as u32).to_ne_bytes());
+    for (key, value) in &annotations.simple {
+        put_bytes(&mut out, key.as_bytes());
+        put_bytes(&mut out, value.as_bytes());
+    }
+    out.extend_from_slice(&(annotations.blobs.len() as u32).to_ne_bytes());
+    for blob in &annotations.blobs {
+        put_bytes(&mut out, blob.name.as_bytes());
+        out.extend_from_slice(&blob.ty.to_ne_bytes());
+        put_bytes(&mut out, &blob.data);
+        // No module can be mapped at 0
+        let module_address = blob.module_address.unwrap_or(0) as u64;
+        out.extend_from_slice(&module_address.to_ne_bytes());
+    }
+    out
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_ne_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_ne_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_ne_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+}
+
+/// Decodes annotations encoded by [`encode_annotations`], returning `None` if
+/// they are malformed
+pub fn decode_annotations(bytes: &[u8]) -> Option<Annotations> {
+    let mut decoder = Decoder { bytes };
+    let mut annotations = Annotations::default();
+
+    for _ in 0..decoder.u32()? {
+        let key = decoder.string()?;
+        let value = decoder.string()?;
+        annotations.simple.push((key, value));
+    }
+    for _ in 0..decoder.u32()? {
+        let name = decoder.string()?;
+        let ty = decoder.u16()?;
+        let data = decoder.bytes()?.to_vec();
+        let module_address = Some(usize::try_from(decoder.u64()?).ok()?).filter(|&a| a != 0);
+        annotations.blobs.push(AnnotationBlob {
+            name,
+            ty,
+            data,
+            module_address,
+        });
+    }
+
+    decoder.bytes.is_empty().then_some(annotations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotations_round_trip() {
+        let annotations = Annotations {
+            simple: vec![
+                ("channel".into(), "nightly".into()),
+                ("empty".into(), String::new()),
+            ],
+            blobs: vec![AnnotationBlob {
+                name: "flags".into(),
+                ty: 0x8001,
+                data: vec![1, 0, 1],
+                module_address: Some(0x7f00_0000),
+            }],
+        };
+
+        let encoded = encode_annotations(&annotations);
+        assert_eq!(decode_annotations(&encoded), Some(annotations));
+        assert_eq!(
+            decode_annotations(&encode_annotations(&Annotations::default())),
+            Some(Annotations::default())
+        );
+    }
+
+    #[test]
+    fn malformed_annotations() {
+        let encoded = encode_annotations(&Annotations {
+            simple: vec![("key".into(), "value".into())],
+            blobs: Vec::new(),
+        });
+
+        // Truncated, trailing bytes and a length past the end
+        assert_eq!(decode_annotations(&encoded[..encoded.len() - 1]), None);
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(decode_annotations(&trailing), None);
+        assert_eq!(decode_annotations(&u32::MAX.to_ne_bytes()), None);
+    }
+}
@@ -0,0 +1,323 @@
+use {
+    super::{
+        super::{
+            crash_context::CrashContext,
+            minidump_writer::{pidfd, MinidumpWriterConfig},
+            Pid,
+        },
+        peer_credentials,
+        protocol::{self, RequestHeader},
+        CrashGenerationError,
+    },
+    nix::errno::Errno,
+    procfs_core::{process::Status, FromRead},
+    std::{
+        fs::File,
+        io::{ErrorKind, Read, Write},
+        os::{
+            fd::OwnedFd,
+            unix::net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::SystemTime,
+    },
+};
+
+type Configure = Box<dyn Fn(&mut MinidumpWriterConfig) + Send + Sync>;
+
+/// A minidump written for a client
+#[derive(Debug, Clone)]
+pub struct CrashDump {
+    /// The pid and tid of the crashing thread in the server's pid namespace
+    pub process_id: Pid,
+    pub thread_id: Pid,
+    pub path: PathBuf,
+}
+
+/// Listens for [`CrashGenerationClient`](super::CrashGenerationClient)s, and
+/// writes minidumps of them into a directory when they ask for it
+pub struct CrashGenerationServer {
+    listener: UnixListener,
+    dump_directory: PathBuf,
+    configure: Option<Configure>,
+    dump_count: AtomicUsize,
+}
+
+impl CrashGenerationServer {
+    /// Listens on a Unix domain socket at the path, which must not exist yet
+    pub fn bind(
+        path: impl AsRef<Path>,
+        dump_directory: impl Into<PathBuf>,
+    ) -> Result<Self, CrashGenerationError> {
+        let path = path.as_ref();
+        let listener = UnixListener::bind(path)
+            .map_err(|e| CrashGenerationError::BindFailed(path.to_owned(), e))?;
+
+        Ok(Self {
+            listener,
+            dump_directory: dump_directory.into(),
+            configure: None,
+            dump_count: AtomicUsize::new(0),
+        })
+    }
+
+    /// Sets a function that adjusts the config of every minidump after it
+    /// has been filled in from the request, e.g. to set a size limit
+    pub fn set_configure(
+        &mut self,
+        configure: impl Fn(&mut MinidumpWriterConfig) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.configure = Some(Box::new(configure));
+        self
+    }
+
+    /// Accepts the connection of the next client
+    pub fn accept(&self) -> Result<UnixStream, CrashGenerationError> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => return Ok(stream),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(CrashGenerationError::AcceptFailed(e)),
+            }
+        }
+    }
+
+    /// Accepts clients and serves each of them on its own thread, until
+    /// accepting a connection fails. The result of every request is passed to
+    /// `on_dump`.
+    pub fn run(
+        &self,
+        on_dump: impl Fn(Result<CrashDump, CrashGenerationError>) + Sync,
+    ) -> Result<(), CrashGenerationError> {
+        std::thread::scope(|scope| loop {
+            let mut stream = self.accept()?;
+            let on_dump = &on_dump;
+            scope.spawn(move || loop {
+                match self.handle_request(&mut stream) {
+                    Ok(Some(dump)) => on_dump(Ok(dump)),
+                    Ok(None) => break,
+                    // The connection is unusable
+                    Err(
+                        e @ (CrashGenerationError::ReceiveFailed(_)
+                        | CrashGenerationError::InvalidRequest(_)
+                        | CrashGenerationError::PeerCredentialsFailed(_)
+                        | CrashGenerationError::ReplyFailed(_)),
+                    ) => {
+                        on_dump(Err(e));
+                        break;
+                    }
+                    Err(e) => on_dump(Err(e)),
+                }
+            });
+        })
+    }
+
+    /// Waits for the next dump request of the client and writes the minidump,
+    /// then tells the client whether it was written. Returns `None` if the
+    /// client closed the connection instead.
+    pub fn handle_request(
+        &self,
+        stream: &mut UnixStream,
+    ) -> Result<Option<CrashDump>, CrashGenerationError> {
+        let mut header = RequestHeader::default();
+        match stream.read_exact(header.as_bytes_mut()) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(CrashGenerationError::ReceiveFailed(e)),
+        }
+
+        if header.magic != protocol::MAGIC || header.version != protocol::VERSION {
+            return Err(CrashGenerationError::InvalidRequest("unknown protocol"));
+        }
+        if header.context_size as usize != std::mem::size_of::<crash_context::CrashContext>() {
+            return Err(CrashGenerationError::InvalidRequest(
+                "crash context size mismatch",
+            ));
+        }
+        if header.annotations_size > protocol::MAX_ANNOTATIONS_SIZE {
+            return Err(CrashGenerationError::InvalidRequest(
+                "annotations too large",
+            ));
+        }
+
+        let mut context = vec![0; header.context_size as usize];
+        let mut annotations = vec![0; header.annotations_size as usize];
+        stream
+            .read_exact(&mut context)
+            .and_then(|()| stream.read_exact(&mut annotations))
+            .map_err(CrashGenerationError::ReceiveFailed)?;
+        let context = crash_context::CrashContext::from_bytes(&context).ok_or(
+            CrashGenerationError::InvalidRequest("invalid crash context"),
+        )?;
+        let annotations = protocol::decode_annotations(&annotations)
+            .ok_or(CrashGenerationError::InvalidRequest("invalid annotations"))?;
+
+        // The credentials can't be forged, unlike the pid in the request. The
+        // pidfd keeps identifying the client if it exits and its pid is
+        // reused, it is only missing on kernels without pidfds.
+        let process_id = peer_credentials(stream)?.pid;
+        let pidfd = match pidfd::open(process_id) {
+            Ok(pidfd) => Some(pidfd),
+            Err(Errno::ENOSYS) => None,
+            Err(Errno::ESRCH) => return Err(CrashGenerationError::ClientExited(process_id)),
+            Err(e) => return Err(CrashGenerationError::PidfdOpenFailed(process_id, e)),
+        };
+
+        let result = self.write_dump(process_id, pidfd, &header, context, annotations);
+        let reply = match result {
+            Ok(_) => protocol::REPLY_DUMPED,
+            Err(_) => protocol::REPLY_FAILED,
+        };
+        stream
+            .write_all(&[reply])
+            .map_err(CrashGenerationError::ReplyFailed)?;
+
+        result.map(Some)
+    }
+
+    fn write_dump(
+        &self,
+        process_id: Pid,
+        pidfd: Option<OwnedFd>,
+        header: &RequestHeader,
+        mut context: crash_context::CrashContext,
+        annotations: super::super::annotations::Annotations,
+    ) -> Result<CrashDump, CrashGenerationError> {
+        let thread_id = find_thread(process_id, header)?;
+        // The thread was looked up by pid, which still has to be the client
+        if let Some(pidfd) = &pidfd {
+            if !pidfd::refers_to(pidfd, process_id) {
+                return Err(CrashGenerationError::ClientExited(process_id));
+            }
+        }
+        context.pid = process_id;
+        context.tid = thread_id;
+
+        let mut config = MinidumpWriterConfig::new(process_id, thread_id);
+        config
+            .set_crash_context(CrashContext { inner: context })
+            .set_direct_auxv_dump_info(header.auxv.clone())
+            .set_annotations(annotations);
+        if let Some(pidfd) = pidfd {
+            config.set_pidfd(pidfd);
+        }
+        if let Some(configure) = &self.configure {
+            configure(&mut config);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let count = self.dump_count.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .dump_directory
+            .join(format!("{process_id}-{timestamp}-{count}.dmp"));
+
+        let mut file = File::create(&path)
+            .map_err(|e| CrashGenerationError::CreateDumpFailed(path.clone(), e))?;
+        config
+            .write(&mut file)
+            .map_err(|e| CrashGenerationError::WriteDumpFailed(path.clone(), Box::new(e)))?;
+
+        Ok(CrashDump {
+            process_id,
+            thread_id,
+            path,
+        })
+    }
+}
+
+/// Translates the tid in the request to this pid namespace. When the client
+/// is in a different pid namespace, it is the thread whose innermost tid
+/// matches. Either way, the thread has to belong to the client process.
+fn find_thread(process_id: Pid, header: &RequestHeader) -> Result<Pid, CrashGenerationError> {
+    let not_found = || CrashGenerationError::ThreadNotFound(process_id, header.tid);
+    if header.pid == process_id {
+        return std::path::Path::new(&format!("/proc/{process_id}/task/{}", header.tid))
+            .exists()
+            .then_some(header.tid)
+            .ok_or_else(not_found);
+    }
+
+    let tasks = std::fs::read_dir(format!("/proc/{process_id}/task")).map_err(|_| not_found())?;
+    tasks
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<Pid>().ok())
+        .find(|tid| {
+            Status::from_file(format!("/proc/{process_id}/task/{tid}/status"))
+                .ok()
+                .and_then(|status| status.nspid?.last().copied())
+                == Some(header.tid)
+        })
+        .ok_or_else(not_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_of_other_processes() {
+        let process_id = std::process::id() as Pid;
+        // SAFETY: `gettid` has no arguments and can't fail
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as Pid;
+        let header = |tid| RequestHeader {
+            pid: process_id,
+            tid,
+            ..Default::default()
+        };
+
+        assert_eq!(find_thread(process_id, &header(tid)).ok(), Some(tid));
+        // The parent of the test process is never one of its threads
+        let parent = nix::unistd::getppid().as_raw();
+        assert!(matches!(
+            find_thread(process_id, &header(parent)),
+            Err(CrashGenerationError::ThreadNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn exited_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("server.sock");
+        let server = CrashGenerationServer::bind(&socket, dir.path()).unwrap();
+
+        let context_size = std::mem::size_of::<crash_context::CrashContext>();
+        let annotations = protocol::encode_annotations(&Default::default());
+        let header = RequestHeader {
+            magic: protocol::MAGIC,
+            version: protocol::VERSION,
+            context_size: context_size as u32,
+            annotations_size: annotations.len() as u32,
+            ..Default::default()
+        };
+        let mut request = header.as_bytes().to_vec();
+        request.resize(request.len() + context_size, 0);
+        request.extend_from_slice(&annotations);
+
+        // The client sends the whole request, and exits and is reaped before
+        // the request is handled
+        // SAFETY: the child only makes system calls before it exits
+        let child = match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => {
+                let sent = UnixStream::connect(&socket)
+                    .and_then(|mut stream| stream.write_all(&request))
+                    .is_ok();
+                // SAFETY: `_exit` doesn't run anything inherited from the parent
+                unsafe { libc::_exit(if sent { 0 } else { 1 }) };
+            }
+            nix::unistd::ForkResult::Parent { child } => child,
+        };
+        assert_eq!(
+            nix::sys::wait::waitpid(child, None).unwrap(),
+            nix::sys::wait::WaitStatus::Exited(child, 0)
+        );
+
+        let mut stream = server.accept().unwrap();
+        assert!(matches!(
+            server.handle_request(&mut stream),
+            Err(CrashGenerationError::ClientExited(pid)) if pid == child.as_raw()
+        ));
+    }
+}
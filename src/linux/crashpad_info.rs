@@ -0,0 +1,363 @@
+//! Reading of the annotations a Crashpad client stores in the memory of a
+//! module, so they can be written to the minidump without the process having
+//! to pass them to the writer explicitly.
+//!
+//! Every module linked against the Crashpad client contains a `CrashpadInfo`
+//! structure, located via an ELF note (see
+//! [`CrashpadInfoAddress`](super::module_reader::CrashpadInfoAddress)), which
+//! points to a `SimpleStringDictionary` of key/value pairs and an
+//! `AnnotationList` of typed annotation objects.
+
+use {
+    super::{
+        annotations::AnnotationBlob,
+        mem_reader::{CopyFromProcessError, MemReader},
+    },
+    std::mem::size_of,
+};
+
+/// `CrashpadInfo::kSignature`, 'CPad'
+pub const CRASHPAD_INFO_SIGNATURE: u32 = 0x64615043;
+/// The only `CrashpadInfo` version
+pub const CRASHPAD_INFO_VERSION: u32 = 1;
+
+/// The number of entries in a `SimpleStringDictionary`
+const SIMPLE_DICTIONARY_ENTRIES: usize = 64;
+/// The size of both the key and value buffers of a `SimpleStringDictionary`
+/// entry, including the NUL terminator
+const SIMPLE_DICTIONARY_STRING_SIZE: usize = 256;
+/// `Annotation::kNameMaxLength`
+const ANNOTATION_NAME_MAX_LENGTH: usize = 256;
+/// `Annotation::kValueMaxSize`
+const ANNOTATION_VALUE_MAX_SIZE: u32 = 5 * 4096;
+/// The same limit Crashpad uses when reading annotation lists, which also
+/// guards against cycles in a corrupted list
+const MAX_ANNOTATIONS: usize = 200;
+
+const PTR: usize = size_of::<usize>();
+
+/// `CrashpadInfo` field offsets. The header is followed by 4 `u32` fields
+/// and 4 `u8` fields, then the pointers
+const SIMPLE_ANNOTATIONS_OFFSET: usize = 24 + PTR;
+const ANNOTATIONS_LIST_OFFSET: usize = 24 + 3 * PTR;
+const CRASHPAD_INFO_SIZE: usize = 24 + 4 * PTR;
+
+/// `Annotation` layout: `link_node`, `name` and `value` pointers followed by
+/// the `u32` size and `u16` type
+const ANNOTATION_SIZE: usize = 3 * PTR + 6;
+
+#[derive(Debug, thiserror::Error, serde::Serialize)]
+pub enum ReadCrashpadInfoError {
+    #[error("failed to read process memory")]
+    CopyFromProcess(#[from] CopyFromProcessError),
+    #[error("only {read} of {length} bytes could be read at {address:#x}")]
+    ShortRead {
+        address: usize,
+        length: usize,
+        read: usize,
+    },
+    #[error("invalid CrashpadInfo signature {0:#x}")]
+    InvalidSignature(u32),
+    #[error("unsupported CrashpadInfo version {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// The annotations read from a single module's `CrashpadInfo`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleAnnotations {
+    /// The active entries of the `simple_annotations` dictionary
+    pub simple: Vec<(String, String)>,
+    /// The annotations in the `annotations_list` that have a value, in list
+    /// order
+    pub objects: Vec<AnnotationBlob>,
+}
+
+impl ModuleAnnotations {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.simple.is_empty() && self.objects.is_empty()
+    }
+
+    /// Reads the annotations of the `CrashpadInfo` at `address`
+    pub fn read(reader: &MemReader, address: usize) -> Result<Self, ReadCrashpadInfoError> {
+        let header = read_exact(reader, address, 12)?;
+        let signature = read_u32(&header, 0);
+        if signature != CRASHPAD_INFO_SIGNATURE {
+            return Err(ReadCrashpadInfoError::InvalidSignature(signature));
+        }
+        let version = read_u32(&header, 8);
+        if version != CRASHPAD_INFO_VERSION {
+            return Err(ReadCrashpadInfoError::UnsupportedVersion(version));
+        }
+
+        // Older clients have a smaller structure, fields past its size are
+        // treated as null
+        let size = (read_u32(&header, 4) as usize).min(CRASHPAD_INFO_SIZE);
+        let mut info = read_exact(reader, address, size)?;
+        info.resize(CRASHPAD_INFO_SIZE, 0);
+
+        let mut annotations = Self::default();
+
+        let simple_annotations = read_usize(&info, SIMPLE_ANNOTATIONS_OFFSET);
+        if simple_annotations != 0 {
+            annotations.simple = read_simple_string_dictionary(reader, simple_annotations)?;
+        }
+
+        let annotations_list = read_usize(&info, ANNOTATIONS_LIST_OFFSET);
+        if annotations_list != 0 {
+            annotations.objects = read_annotation_list(reader, address, annotations_list)?;
+        }
+
+        Ok(annotations)
+    }
+}
+
+fn read_simple_string_dictionary(
+    reader: &MemReader,
+    address: usize,
+) -> Result<Vec<(String, String)>, ReadCrashpadInfoError> {
+    let entry_size = 2 * SIMPLE_DICTIONARY_STRING_SIZE;
+    let dictionary = read_exact(reader, address, SIMPLE_DICTIONARY_ENTRIES * entry_size)?;
+
+    Ok(dictionary
+        .chunks_exact(entry_size)
+        // Unused entries have an empty key
+        .filter(|entry| entry[0] != 0)
+        .map(|entry| {
+            let (key, value) = entry.split_at(SIMPLE_DICTIONARY_STRING_SIZE);
+            (string_until_nul(key), string_until_nul(value))
+        })
+        .collect())
+}
+
+/// Reads an `AnnotationList`, which consists of the `tail_pointer` followed by
+/// the `head` and `tail` sentinel `Annotation`s. Annotations are linked from
+/// `head` to `tail` via their `link_node`. The annotations belong to the
+/// module whose `CrashpadInfo` is at `info_address`.
+fn read_annotation_list(
+    reader: &MemReader,
+    info_address: usize,
+    address: usize,
+) -> Result<Vec<AnnotationBlob>, ReadCrashpadInfoError> {
+    let list = read_exact(reader, address, 2 * PTR)?;
+    let tail = read_usize(&list, 0);
+    let mut current = read_usize(&list, PTR);
+
+    let mut objects = Vec::new();
+    for _ in 0..MAX_ANNOTATIONS {
+        if current == 0 || current == tail {
+            break;
+        }
+
+        let annotation = read_exact(reader, current, ANNOTATION_SIZE)?;
+        current = read_usize(&annotation, 0);
+
+        let name = read_usize(&annotation, PTR);
+        let value = read_usize(&annotation, 2 * PTR);
+        let size = read_u32(&annotation, 3 * PTR).min(ANNOTATION_VALUE_MAX_SIZE) as usize;
+        let ty = u16::from_ne_bytes([annotation[3 * PTR + 4], annotation[3 * PTR + 5]]);
+
+        // Annotations without a value have not been set (or have been cleared)
+        if size == 0 || value == 0 || name == 0 {
+            continue;
+        }
+
+        objects.push(AnnotationBlob {
+            name: read_c_string(reader, name, ANNOTATION_NAME_MAX_LENGTH)?,
+            ty,
+            data: read_exact(reader, value, size)?,
+            module_address: Some(info_address),
+        });
+    }
+
+    Ok(objects)
+}
+
+fn read_exact(
+    reader: &MemReader,
+    address: usize,
+    length: usize,
+) -> Result<Vec<u8>, ReadCrashpadInfoError> {
+    let mut buf = vec![0u8; length];
+    let read = reader.read(address, &mut buf)?;
+    if read != length {
+        return Err(ReadCrashpadInfoError::ShortRead {
+            address,
+            length,
+            read,
+        });
+    }
+    Ok(buf)
+}
+
+/// Reads a NUL terminated string of at most `max_length` bytes, without
+/// reading past the page the terminator is on, as the string may be at the
+/// very end of a mapping
+fn read_c_string(
+    reader: &MemReader,
+    address: usize,
+    max_length: usize,
+) -> Result<String, ReadCrashpadInfoError> {
+    const PAGE_SIZE: usize = 4096;
+
+    let mut bytes = Vec::new();
+    while bytes.len() < max_length {
+        let start = address + bytes.len();
+        let chunk_len = (PAGE_SIZE - start % PAGE_SIZE).min(max_length - bytes.len());
+        let chunk = read_exact(reader, start, chunk_len)?;
+        if let Some(nul) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..nul]);
+            break;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn string_until_nul(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn read_usize(bytes: &[u8], offset: usize) -> usize {
+    usize::from_ne_bytes(bytes[offset..offset + PTR].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct CrashpadInfo {
+        signature: u32,
+        size: u32,
+        version: u32,
+        indirectly_referenced_memory_cap: u32,
+        padding_0: u32,
+        flags: [u8; 4],
+        extra_memory_ranges: usize,
+        simple_annotations: usize,
+        user_data_minidump_stream_head: usize,
+        annotations_list: usize,
+    }
+
+    #[repr(C)]
+    struct Annotation {
+        link_node: usize,
+        name: usize,
+        value: usize,
+        size: u32,
+        ty: u16,
+    }
+
+    #[repr(C)]
+    struct AnnotationList {
+        tail_pointer: usize,
+        head: Annotation,
+        tail: Annotation,
+    }
+
+    fn annotation(name: &'static [u8], value: &[u8], ty: u16) -> Annotation {
+        Annotation {
+            link_node: 0,
+            name: name.as_ptr() as usize,
+            value: value.as_ptr() as usize,
+            size: value.len() as u32,
+            ty,
+        }
+    }
+
+    /// Reads the annotations from structures laid out the same way as
+    /// Crashpad's in the memory of this process
+    #[test]
+    fn read_from_memory() {
+        assert_eq!(size_of::<CrashpadInfo>(), CRASHPAD_INFO_SIZE);
+
+        let mut dictionary = vec![[[0u8; SIMPLE_DICTIONARY_STRING_SIZE]; 2]; 64];
+        dictionary[0][0][..3].copy_from_slice(b"key");
+        dictionary[0][1][..5].copy_from_slice(b"value");
+        // An unused entry
+        dictionary[1][1][..6].copy_from_slice(b"unused");
+        dictionary[5][0][..5].copy_from_slice(b"other");
+
+        let first_value = b"first value".to_vec();
+        let unset_value = Vec::new();
+        let second_value = vec![1u8, 2, 3, 4];
+
+        let mut first = annotation(b"first\0", &first_value, 1);
+        let mut unset = annotation(b"unset\0", &unset_value, 1);
+        let second = annotation(b"second\0", &second_value, 0x8001);
+
+        let mut list = Box::new(AnnotationList {
+            tail_pointer: 0,
+            head: annotation(b"\0", &[], 0),
+            tail: annotation(b"\0", &[], 0),
+        });
+        let tail = &list.tail as *const Annotation as usize;
+        let mut second = Box::new(second);
+        second.link_node = tail;
+        unset.link_node = &*second as *const Annotation as usize;
+        let unset = Box::new(unset);
+        first.link_node = &*unset as *const Annotation as usize;
+        let first = Box::new(first);
+        list.tail_pointer = tail;
+        list.head.link_node = &*first as *const Annotation as usize;
+
+        let info = CrashpadInfo {
+            signature: CRASHPAD_INFO_SIGNATURE,
+            size: size_of::<CrashpadInfo>() as u32,
+            version: CRASHPAD_INFO_VERSION,
+            indirectly_referenced_memory_cap: 0,
+            padding_0: 0,
+            flags: [0; 4],
+            extra_memory_ranges: 0,
+            simple_annotations: dictionary.as_ptr() as usize,
+            user_data_minidump_stream_head: 0,
+            annotations_list: &*list as *const AnnotationList as usize,
+        };
+
+        let reader = MemReader::new(std::process::id() as i32);
+        let info_address = &info as *const CrashpadInfo as usize;
+        let annotations = ModuleAnnotations::read(&reader, info_address).unwrap();
+
+        assert_eq!(
+            annotations,
+            ModuleAnnotations {
+                simple: vec![
+                    ("key".into(), "value".into()),
+                    ("other".into(), String::new())
+                ],
+                objects: vec![
+                    AnnotationBlob {
+                        name: "first".into(),
+                        ty: 1,
+                        data: first_value.clone(),
+                        module_address: Some(info_address),
+                    },
+                    AnnotationBlob {
+                        name: "second".into(),
+                        ty: 0x8001,
+                        data: second_value.clone(),
+                        module_address: Some(info_address),
+                    },
+                ],
+            }
+        );
+
+        let bad_signature = CrashpadInfo {
+            signature: 0,
+            ..info
+        };
+        assert!(matches!(
+            ModuleAnnotations::read(&reader, &bad_signature as *const CrashpadInfo as usize),
+            Err(ReadCrashpadInfoError::InvalidSignature(0))
+        ));
+    }
+}
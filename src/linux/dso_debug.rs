@@ -1,6 +1,7 @@
 use {
     super::{
-        auxv::AuxvDumpInfo, mem_reader::CopyFromProcessError, minidump_writer::MinidumpWriter,
+        auxv::AuxvDumpInfo,
+        mem_reader::{CopyFromProcessError, MemReader},
         serializers::*,
     },
     crate::{
@@ -96,11 +97,75 @@ pub struct RDebug {
     r_ldbase: ElfAddr, /* Base address the linker is loaded at.  */
 }
 
+/// The state of the dynamic linker that is written to the
+/// `MD_LINUX_DSO_DEBUG` stream
+pub struct DsoDebug {
+    debug_entry: RDebug,
+    /// The address of the `PT_DYNAMIC` segment of the program
+    dyn_addr: ElfAddr,
+    /// The entries of the `PT_DYNAMIC` segment, up to and including `DT_NULL`
+    dynamic: Vec<u8>,
+    /// The loaded DSOs and their file names
+    dsos: Vec<(LinkMap, String)>,
+}
+
 pub fn write_dso_debug_stream(
     buffer: &mut Buffer,
-    blamed_thread: i32,
+    reader: &MemReader,
     auxv: &AuxvDumpInfo,
 ) -> Result<MDRawDirectory> {
+    let DsoDebug {
+        debug_entry,
+        dyn_addr,
+        dynamic,
+        dsos,
+    } = read_dso_debug(reader, auxv)?;
+
+    let mut linkmap_rva = u32::MAX;
+    if !dsos.is_empty() {
+        // If we have at least one DSO, create an array of MDRawLinkMap
+        // entries in the minidump file.
+        let mut linkmap = MemoryArrayWriter::<MDRawLinkMap>::alloc_array(buffer, dsos.len())?;
+        linkmap_rva = linkmap.location().rva;
+
+        // Iterate over DSOs and write their information to mini dump
+        for (idx, (map, filename)) in dsos.iter().enumerate() {
+            let location = write_string_to_location(buffer, filename)?;
+            let entry = MDRawLinkMap {
+                addr: map.l_addr,
+                name: location.rva,
+                ld: map.l_ld as ElfAddr,
+            };
+
+            linkmap.set_value_at(buffer, entry, idx)?;
+        }
+    }
+
+    // Write MD_LINUX_DSO_DEBUG record
+    let debug = MDRawDebug {
+        version: debug_entry.r_version as u32,
+        map: linkmap_rva,
+        dso_count: dsos.len() as u32,
+        brk: debug_entry.r_brk,
+        ldbase: debug_entry.r_ldbase,
+        dynamic: dyn_addr,
+    };
+    let debug_loc = MemoryWriter::<MDRawDebug>::alloc_with_val(buffer, debug)?;
+
+    let mut dirent = MDRawDirectory {
+        stream_type: MDStreamType::LinuxDsoDebug as u32,
+        location: debug_loc.location(),
+    };
+
+    dirent.location.data_size += dynamic.len() as u32;
+    MemoryArrayWriter::write_bytes(buffer, &dynamic);
+
+    Ok(dirent)
+}
+
+/// Reads the state of the dynamic linker from the process, which is found
+/// from the program headers in the auxv
+pub fn read_dso_debug(reader: &MemReader, auxv: &AuxvDumpInfo) -> Result<DsoDebug> {
     let phnum_max =
         auxv.get_program_header_count()
             .ok_or(SectionDsoDebugError::CouldNotFind("AT_PHNUM in auxv"))? as usize;
@@ -108,7 +173,7 @@ pub fn write_dso_debug_stream(
         .get_program_header_address()
         .ok_or(SectionDsoDebugError::CouldNotFind("AT_PHDR in auxv"))? as usize;
 
-    let ph = MinidumpWriter::copy_from_process(blamed_thread, phdr, SIZEOF_PHDR * phnum_max)?;
+    let ph = reader.copy(phdr, SIZEOF_PHDR * phnum_max)?;
     let program_headers;
     #[cfg(target_pointer_width = "64")]
     {
@@ -154,11 +219,7 @@ pub fn write_dso_debug_stream(
     // DSOs loaded into the program. If this information is indeed available,
     // dump it to a MD_LINUX_DSO_DEBUG stream.
     loop {
-        let dyn_data = MinidumpWriter::copy_from_process(
-            blamed_thread,
-            dyn_addr as usize + dynamic_length,
-            dyn_size,
-        )?;
+        let dyn_data = reader.copy(dyn_addr as usize + dynamic_length, dyn_size)?;
         dynamic_length += dyn_size;
 
         // goblin::elf::Dyn doesn't have padding bytes
@@ -182,8 +243,7 @@ pub fn write_dso_debug_stream(
     // See <link.h> for a more detailed discussion of the how the dynamic
     // loader communicates with debuggers.
 
-    let debug_entry_data =
-        MinidumpWriter::copy_from_process(blamed_thread, r_debug, std::mem::size_of::<RDebug>())?;
+    let debug_entry_data = reader.copy(r_debug, std::mem::size_of::<RDebug>())?;
 
     // goblin::elf::Dyn doesn't have padding bytes
     let (head, body, _tail) = unsafe { debug_entry_data.align_to::<RDebug>() };
@@ -194,11 +254,7 @@ pub fn write_dso_debug_stream(
     let mut dso_vec = Vec::new();
     let mut curr_map = debug_entry.r_map;
     while curr_map != 0 {
-        let link_map_data = MinidumpWriter::copy_from_process(
-            blamed_thread,
-            curr_map,
-            std::mem::size_of::<LinkMap>(),
-        )?;
+        let link_map_data = reader.copy(curr_map, std::mem::size_of::<LinkMap>())?;
 
         // LinkMap is repr(C) and doesn't have padding bytes, so this should be safe
         let (head, body, _tail) = unsafe { link_map_data.align_to::<LinkMap>() };
@@ -209,56 +265,26 @@ pub fn write_dso_debug_stream(
         dso_vec.push(map.clone());
     }
 
-    let mut linkmap_rva = u32::MAX;
-    if !dso_vec.is_empty() {
-        // If we have at least one DSO, create an array of MDRawLinkMap
-        // entries in the minidump file.
-        let mut linkmap = MemoryArrayWriter::<MDRawLinkMap>::alloc_array(buffer, dso_vec.len())?;
-        linkmap_rva = linkmap.location().rva;
+    let mut dsos = Vec::with_capacity(dso_vec.len());
+    for map in dso_vec {
+        let mut filename = String::new();
+        if map.l_name > 0 {
+            let filename_data = reader.copy(map.l_name, 256)?;
 
-        // Iterate over DSOs and write their information to mini dump
-        for (idx, map) in dso_vec.iter().enumerate() {
-            let mut filename = String::new();
-            if map.l_name > 0 {
-                let filename_data =
-                    MinidumpWriter::copy_from_process(blamed_thread, map.l_name, 256)?;
-
-                // C - string is NULL-terminated
-                if let Some(name) = filename_data.splitn(2, |x| *x == b'\0').next() {
-                    filename = String::from_utf8(name.to_vec())?;
-                }
+            // C - string is NULL-terminated
+            if let Some(name) = filename_data.splitn(2, |x| *x == b'\0').next() {
+                filename = String::from_utf8(name.to_vec())?;
             }
-            let location = write_string_to_location(buffer, &filename)?;
-            let entry = MDRawLinkMap {
-                addr: map.l_addr,
-                name: location.rva,
-                ld: map.l_ld as ElfAddr,
-            };
-
-            linkmap.set_value_at(buffer, entry, idx)?;
         }
+        dsos.push((map, filename));
     }
 
-    // Write MD_LINUX_DSO_DEBUG record
-    let debug = MDRawDebug {
-        version: debug_entry.r_version as u32,
-        map: linkmap_rva,
-        dso_count: dso_vec.len() as u32,
-        brk: debug_entry.r_brk,
-        ldbase: debug_entry.r_ldbase,
-        dynamic: dyn_addr,
-    };
-    let debug_loc = MemoryWriter::<MDRawDebug>::alloc_with_val(buffer, debug)?;
-
-    let mut dirent = MDRawDirectory {
-        stream_type: MDStreamType::LinuxDsoDebug as u32,
-        location: debug_loc.location(),
-    };
+    let dynamic = reader.copy(dyn_addr as usize, dynamic_length)?;
 
-    dirent.location.data_size += dynamic_length as u32;
-    let dso_debug_data =
-        MinidumpWriter::copy_from_process(blamed_thread, dyn_addr as usize, dynamic_length)?;
-    MemoryArrayWriter::write_bytes(buffer, &dso_debug_data);
-
-    Ok(dirent)
+    Ok(DsoDebug {
+        debug_entry: debug_entry.clone(),
+        dyn_addr,
+        dynamic,
+        dsos,
+    })
 }
@@ -135,6 +135,16 @@ fn parse_features(_val: &str) -> u32 {
     0
 }
 
+/// The architecture this was built for, which is that of the processes it
+/// can dump
+pub fn processor_architecture() -> u16 {
+    if cfg!(target_arch = "aarch64") {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM64_OLD as u16
+    } else {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM as u16
+    }
+}
+
 pub fn write_cpu_information(sys_info: &mut MDRawSystemInfo) -> Result<()> {
     // The CPUID value is broken up in several entries in /proc/cpuinfo.
     // This table is used to rebuild it from the entries.
@@ -146,12 +156,7 @@ pub fn write_cpu_information(sys_info: &mut MDRawSystemInfo) -> Result<()> {
     ];
 
     // processor_architecture should always be set, do this first
-    if cfg!(target_arch = "aarch64") {
-        sys_info.processor_architecture =
-            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM64_OLD as u16;
-    } else {
-        sys_info.processor_architecture = MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM as u16;
-    }
+    sys_info.processor_architecture = processor_architecture();
 
     // /proc/cpuinfo is not readable under various sandboxed environments
     // (e.g. Android services with the android:isolatedProcess attribute)
@@ -23,7 +23,7 @@ cfg_if::cfg_if! {
     }
 }
 
-pub use imp::write_cpu_information;
+pub use imp::{processor_architecture, write_cpu_information};
 
 #[derive(Debug, thiserror::Error, serde::Serialize)]
 pub enum CpuInfoError {
@@ -26,6 +26,21 @@ impl CpuInfoEntry {
     }
 }
 
+/// The architecture this was built for, which is that of the processes it
+/// can dump
+pub fn processor_architecture() -> u16 {
+    let arch = if cfg!(target_arch = "mips") {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_MIPS
+    } else if cfg!(target_arch = "mips64") {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_MIPS64
+    } else if cfg!(target_arch = "x86") {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_INTEL
+    } else {
+        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_AMD64
+    };
+    arch as u16
+}
+
 pub fn write_cpu_information(sys_info: &mut MDRawSystemInfo) -> Result<()> {
     let vendor_id_name = "vendor_id";
     let mut cpu_info_table = [
@@ -39,15 +54,7 @@ pub fn write_cpu_information(sys_info: &mut MDRawSystemInfo) -> Result<()> {
     ];
 
     // processor_architecture should always be set, do this first
-    sys_info.processor_architecture = if cfg!(target_arch = "mips") {
-        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_MIPS
-    } else if cfg!(target_arch = "mips64") {
-        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_MIPS64
-    } else if cfg!(target_arch = "x86") {
-        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_INTEL
-    } else {
-        MDCPUArchitecture::PROCESSOR_ARCHITECTURE_AMD64
-    } as u16;
+    sys_info.processor_architecture = processor_architecture();
 
     failspot!(
         CpuInfoFileOpen
@@ -4,7 +4,7 @@ use {
     byteorder::{NativeEndian, ReadBytesExt},
     goblin::elf,
     memmap2::{Mmap, MmapOptions},
-    procfs_core::process::{MMPermissions, MMapPath, MemoryMaps},
+    procfs_core::process::{MMPermissions, MMapPath, MemoryMap, MemoryMaps},
     std::{
         ffi::{OsStr, OsString},
         fs::File,
@@ -116,7 +116,43 @@ fn sanitize_path(pathname: OsString) -> OsString {
     }
 }
 
+/// The name of a mapping as shown in `/proc/<pid>/maps`, without any
+/// ` (deleted)` suffix
+fn mapping_name(pathname: MMapPath) -> Option<OsString> {
+    match pathname {
+        MMapPath::Path(p) => Some(sanitize_path(p.into())),
+        MMapPath::Heap => Some("[heap]".into()),
+        MMapPath::Stack => Some("[stack]".into()),
+        MMapPath::TStack(i) => Some(format!("[stack:{i}]").into()),
+        MMapPath::Vdso => Some("[vdso]".into()),
+        MMapPath::Vvar => Some("[vvar]".into()),
+        MMapPath::Vsyscall => Some("[vsyscall]".into()),
+        MMapPath::Rollup => Some("[rollup]".into()),
+        MMapPath::Vsys(i) => Some(format!("/SYSV{i:x}").into()),
+        MMapPath::Other(n) => Some(format!("[{n}]").into()),
+        MMapPath::Anonymous => None,
+    }
+}
+
 impl MappingInfo {
+    /// A single mapping as the system reports it, unlike [`Self::aggregate`]
+    /// it isn't merged with its neighbours
+    pub fn from_memory_map(mm: &MemoryMap) -> Result<Self> {
+        let start_address: usize = mm.address.0.try_into()?;
+        let end_address: usize = mm.address.1.try_into()?;
+        Ok(MappingInfo {
+            start_address,
+            size: end_address - start_address,
+            system_mapping_info: SystemMappingInfo {
+                start_address,
+                end_address,
+            },
+            offset: mm.offset.try_into()?,
+            permissions: mm.perms,
+            name: mapping_name(mm.pathname.clone()),
+        })
+    }
+
     /// Return whether the `name` field is a path (contains a `/`).
     pub fn name_is_path(&self) -> bool {
         is_mapping_a_path(self.name.as_deref())
@@ -141,19 +177,7 @@ impl MappingInfo {
             let end_address: usize = mm.address.1.try_into()?;
             let mut offset: usize = mm.offset.try_into()?;
 
-            let mut pathname: Option<OsString> = match mm.pathname {
-                MMapPath::Path(p) => Some(sanitize_path(p.into())),
-                MMapPath::Heap => Some("[heap]".into()),
-                MMapPath::Stack => Some("[stack]".into()),
-                MMapPath::TStack(i) => Some(format!("[stack:{i}]").into()),
-                MMapPath::Vdso => Some("[vdso]".into()),
-                MMapPath::Vvar => Some("[vvar]".into()),
-                MMapPath::Vsyscall => Some("[vsyscall]".into()),
-                MMapPath::Rollup => Some("[rollup]".into()),
-                MMapPath::Vsys(i) => Some(format!("/SYSV{i:x}").into()),
-                MMapPath::Other(n) => Some(format!("[{n}]").into()),
-                MMapPath::Anonymous => None,
-            };
+            let mut pathname = mapping_name(mm.pathname);
 
             let is_path = is_mapping_a_path(pathname.as_deref());
 
@@ -609,6 +633,31 @@ ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0                  [vsysca
         assert_eq!(mappings[2], empty_map);
     }
 
+    #[test]
+    fn test_unmerged() {
+        let maps = MemoryMaps::from_read(LINES.as_bytes()).unwrap();
+        let mappings: Vec<_> = maps
+            .iter()
+            .map(|mm| MappingInfo::from_memory_map(mm).unwrap())
+            .collect();
+
+        // Every line is its own mapping, with its own permissions
+        assert_eq!(mappings.len(), LINES.lines().count());
+        let cat_data = MappingInfo {
+            start_address: 0x559748405000,
+            size: 4096,
+            system_mapping_info: SystemMappingInfo {
+                start_address: 0x559748405000,
+                end_address: 0x559748406000,
+            },
+            offset: 0x8000,
+            permissions: MMPermissions::READ | MMPermissions::WRITE | MMPermissions::PRIVATE,
+            name: Some("/usr/bin/cat".into()),
+        };
+        assert_eq!(mappings[4], cat_data);
+        assert_eq!(mappings[mappings.len() - 2].name, Some("[vdso]".into()));
+    }
+
     #[test]
     fn test_linux_gate_parsing() {
         let mappings = get_all_mappings();
@@ -1,8 +1,13 @@
 //! Functionality for reading a remote process's memory
 
 use {
-    super::{minidump_writer::MinidumpWriter, serializers::*, Pid},
-    std::sync::OnceLock,
+    super::{
+        core_file::CoreFile,
+        minidump_writer::{snapshot::MemoryRegions, MinidumpWriter},
+        serializers::*,
+        Pid,
+    },
+    std::sync::{Arc, Mutex, OnceLock},
 };
 
 #[derive(Debug)]
@@ -23,6 +28,10 @@ enum Style {
     /// Reads data one word at a time, so slow, but fairly reliable, as long as
     /// the process can be ptraced
     Ptrace,
+    /// Reads the memory dumped in a core file of the process
+    Core(Arc<CoreFile>),
+    /// Reads the memory recorded in a snapshot of the process
+    Snapshot(MemoryRegions),
     /// No methods succeeded, generally there isn't a case where failing a syscall
     /// will work if called again
     Unavailable {
@@ -47,6 +56,8 @@ pub struct MemReader {
     /// The pid of the child to read
     pid: nix::unistd::Pid,
     style: OnceLock<Style>,
+    /// Where the memory that is read is recorded, if anywhere
+    recording: Option<Arc<Mutex<MemoryRegions>>>,
 }
 
 impl std::fmt::Debug for MemReader {
@@ -55,6 +66,8 @@ impl std::fmt::Debug for MemReader {
             Some(Style::VirtualMem) => "process_vm_readv",
             Some(Style::File(_)) => "/proc/<pid>/mem",
             Some(Style::Ptrace) => "PTRACE_PEEKDATA",
+            Some(Style::Core(_)) => "core file",
+            Some(Style::Snapshot(_)) => "snapshot",
             Some(Style::Unavailable { vmem, file, ptrace }) => {
                 return write!(
                     f,
@@ -76,6 +89,7 @@ impl MemReader {
         Self {
             pid: nix::unistd::Pid::from_raw(pid),
             style: OnceLock::default(),
+            recording: None,
         }
     }
 
@@ -85,6 +99,7 @@ impl MemReader {
         Self {
             pid: nix::unistd::Pid::from_raw(pid),
             style: OnceLock::from(Style::VirtualMem),
+            recording: None,
         }
     }
 
@@ -96,6 +111,7 @@ impl MemReader {
         Ok(Self {
             pid: nix::unistd::Pid::from_raw(pid),
             style: OnceLock::from(Style::File(file)),
+            recording: None,
         })
     }
 
@@ -105,9 +121,39 @@ impl MemReader {
         Self {
             pid: nix::unistd::Pid::from_raw(pid),
             style: OnceLock::from(Style::Ptrace),
+            recording: None,
+        }
+    }
+
+    /// Creates a [`Self`] that reads the memory of the process from a core
+    /// file rather than the live process
+    #[inline]
+    pub fn for_core(core: Arc<CoreFile>) -> Self {
+        Self {
+            pid: nix::unistd::Pid::from_raw(core.process_id),
+            style: OnceLock::from(Style::Core(core)),
+            recording: None,
+        }
+    }
+
+    /// Creates a [`Self`] that reads the memory of the process from a
+    /// snapshot rather than the live process
+    #[inline]
+    pub fn for_snapshot(pid: i32, memory: MemoryRegions) -> Self {
+        Self {
+            pid: nix::unistd::Pid::from_raw(pid),
+            style: OnceLock::from(Style::Snapshot(memory)),
+            recording: None,
         }
     }
 
+    /// Records the memory that is read successfully in `memory`
+    #[inline]
+    pub fn recording_to(mut self, memory: Arc<Mutex<MemoryRegions>>) -> Self {
+        self.recording = Some(memory);
+        self
+    }
+
     #[inline]
     pub fn read_to_vec(
         &self,
@@ -121,11 +167,28 @@ impl MemReader {
     }
 
     pub fn read(&self, src: usize, dst: &mut [u8]) -> Result<usize, CopyFromProcessError> {
+        let len = self.read_unrecorded(src, dst)?;
+        if let Some(recording) = &self.recording {
+            let mut memory = recording.lock().unwrap_or_else(|e| e.into_inner());
+            memory.insert(src, &dst[..len]);
+        }
+        Ok(len)
+    }
+
+    fn read_unrecorded(&self, src: usize, dst: &mut [u8]) -> Result<usize, CopyFromProcessError> {
         if let Some(rs) = self.style.get() {
             let res = match rs {
                 Style::VirtualMem => Self::vmem(self.pid, src, dst).map_err(|s| (s, 0)),
                 Style::File(file) => Self::file(file, src, dst).map_err(|s| (s, 0)),
                 Style::Ptrace => Self::ptrace(self.pid, src, dst),
+                Style::Core(core) => match core.read(src, dst) {
+                    0 => Err((nix::Error::EFAULT, 0)),
+                    len => Ok(len),
+                },
+                Style::Snapshot(memory) => match memory.read(src, dst) {
+                    0 => Err((nix::Error::EFAULT, 0)),
+                    len => Ok(len),
+                },
                 Style::Unavailable { ptrace, .. } => Err((*ptrace, 0)),
             };
 
@@ -195,15 +258,27 @@ impl MemReader {
     fn file(file: &std::fs::File, src: usize, dst: &mut [u8]) -> Result<usize, nix::Error> {
         use std::os::unix::fs::FileExt;
 
-        file.read_exact_at(dst, src as u64).map_err(|err| {
-            if let Some(os) = err.raw_os_error() {
-                nix::Error::from_raw(os)
-            } else {
-                nix::Error::E2BIG /* EOF */
+        // Like process_vm_readv, return the bytes before the first one that
+        // can't be read rather than failing the whole read
+        let mut read = 0;
+        while read < dst.len() {
+            match file.read_at(&mut dst[read..], (src + read) as u64) {
+                Ok(0) => break,
+                Ok(len) => read += len,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) if read > 0 => break,
+                Err(err) => {
+                    return Err(err
+                        .raw_os_error()
+                        .map_or(nix::Error::EIO, nix::Error::from_raw));
+                }
             }
-        })?;
+        }
 
-        Ok(dst.len())
+        if read == 0 && !dst.is_empty() {
+            return Err(nix::Error::E2BIG /* EOF */);
+        }
+        Ok(read)
     }
 
     #[inline]
@@ -234,18 +309,13 @@ impl MemReader {
     }
 }
 
-impl MinidumpWriter {
+impl MemReader {
     /// Copies a block of bytes from the target process, returning the heap
-    /// allocated copy
-    #[inline]
-    pub fn copy_from_process(
-        pid: Pid,
-        src: usize,
-        length: usize,
-    ) -> Result<Vec<u8>, CopyFromProcessError> {
+    /// allocated copy, see [`MinidumpWriter::copy_from_process`]
+    pub fn copy(&self, src: usize, length: usize) -> Result<Vec<u8>, CopyFromProcessError> {
         let length = std::num::NonZeroUsize::new(length).ok_or(CopyFromProcessError {
             src,
-            child: pid,
+            child: self.pid.as_raw(),
             offset: 0,
             length,
             // TODO: We should make copy_from_process also take a NonZero,
@@ -254,7 +324,19 @@ impl MinidumpWriter {
             source: nix::errno::Errno::EINVAL,
         })?;
 
-        let mem = MemReader::new(pid);
-        mem.read_to_vec(src, length)
+        self.read_to_vec(src, length)
+    }
+}
+
+impl MinidumpWriter {
+    /// Copies a block of bytes from the target process, returning the heap
+    /// allocated copy
+    #[inline]
+    pub fn copy_from_process(
+        pid: Pid,
+        src: usize,
+        length: usize,
+    ) -> Result<Vec<u8>, CopyFromProcessError> {
+        MemReader::new(pid).copy(src, length)
     }
 }
@@ -0,0 +1,397 @@
+//! Options for capturing process memory into the minidump beyond thread
+//! stacks, either whole mappings selected by declarative rules, eg. the heap,
+//! or memory referenced from thread stacks.
+
+use {
+    super::{maps_reader::MappingInfo, pattern::Pattern},
+    procfs_core::process::MMPermissions,
+    std::os::unix::ffi::OsStrExt,
+};
+
+/// Selects mappings whose memory is copied into the `MemoryListStream`.
+///
+/// A mapping is selected if it matches every criterion of the rule, eg. a
+/// rule with the name pattern `[heap]` captures the heap, and a rule with the
+/// name pattern `""` (anonymous mappings) and `READ | WRITE | PRIVATE`
+/// permissions captures private anonymous read/write mappings.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryCaptureRule {
+    /// Selects mappings by name, as shown in `/proc/<pid>/maps` without any
+    /// ` (deleted)` suffix. The pattern may match either the full name or its
+    /// last path component, so both `/memfd:*` and `memfd:*` select memfd
+    /// mappings. Anonymous mappings have an empty name.
+    ///
+    /// If `None`, mappings are not selected by name.
+    pub name: Option<Pattern>,
+    /// The permissions a mapping must have, use [`MMPermissions::PRIVATE`] or
+    /// [`MMPermissions::SHARED`] to select private or shared mappings.
+    ///
+    /// Mappings that are not readable are never captured.
+    pub permissions: MMPermissions,
+    /// The maximum number of bytes captured by this rule, across all of the
+    /// mappings it selects. Mappings are captured from their start, in
+    /// address order, until the limit is reached.
+    ///
+    /// If `None`, the selected mappings are captured entirely, up to the
+    /// size limit of the minidump.
+    pub max_bytes: Option<usize>,
+    /// Rules with a higher priority are applied first, rules with the same
+    /// priority are applied in the order they were added. Each mapping is
+    /// only captured by the first rule that selects it.
+    pub priority: i32,
+}
+
+impl MemoryCaptureRule {
+    /// Returns true if the rule selects the mapping
+    pub fn matches(&self, mapping: &MappingInfo) -> bool {
+        if !mapping.permissions.contains(self.permissions) {
+            return false;
+        }
+
+        let Some(pattern) = &self.name else {
+            return true;
+        };
+        let name = mapping.name.as_deref().map_or(&[][..], |n| n.as_bytes());
+        let file_name = match name.iter().rposition(|&b| b == b'/') {
+            Some(slash) => &name[slash + 1..],
+            None => name,
+        };
+        pattern.matches(name) || pattern.matches(file_name)
+    }
+}
+
+/// A region of memory to capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureRegion {
+    pub start_address: usize,
+    pub length: usize,
+}
+
+/// Applies the rules to the mappings, returning the regions to capture in
+/// the order the rules were applied.
+///
+/// The `captured` regions, eg. thread stacks that have already been written,
+/// must be sorted by address and not overlap. They are subtracted from the
+/// selected mappings, so only the rest of each mapping is captured, and
+/// doesn't count toward the `max_bytes` of the rule.
+pub fn select_regions(
+    rules: &[MemoryCaptureRule],
+    mappings: &[MappingInfo],
+    captured: &[CaptureRegion],
+) -> Vec<CaptureRegion> {
+    let mut rules: Vec<_> = rules.iter().collect();
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+
+    let mut selected = vec![false; mappings.len()];
+    let mut regions = Vec::new();
+
+    for rule in rules {
+        let mut remaining = rule.max_bytes.unwrap_or(usize::MAX);
+        for (mapping, selected) in mappings.iter().zip(selected.iter_mut()) {
+            if remaining == 0 {
+                break;
+            }
+            if *selected || !mapping.is_readable() || !rule.matches(mapping) {
+                continue;
+            }
+            *selected = true;
+
+            for gap in subtract(mapping.start_address, mapping.end_address(), captured) {
+                if remaining == 0 {
+                    break;
+                }
+                let length = gap.length.min(remaining);
+                remaining -= length;
+                regions.push(CaptureRegion {
+                    start_address: gap.start_address,
+                    length,
+                });
+            }
+        }
+    }
+
+    regions
+}
+
+/// Returns the parts of `start..end` that aren't in any of the `captured`
+/// regions, which must be sorted by address and not overlap
+pub fn subtract(
+    start: usize,
+    end: usize,
+    captured: &[CaptureRegion],
+) -> impl Iterator<Item = CaptureRegion> + '_ {
+    let first = captured.partition_point(|c| c.start_address + c.length <= start);
+    let mut cursor = start;
+    let mut blocks = captured[first..]
+        .iter()
+        .take_while(move |c| c.start_address < end);
+
+    std::iter::from_fn(move || loop {
+        if cursor >= end {
+            return None;
+        }
+        let (gap_end, next) = match blocks.next() {
+            Some(block) => (
+                block.start_address.max(cursor),
+                block.start_address + block.length,
+            ),
+            None => (end, end),
+        };
+        let gap_start = cursor;
+        cursor = next.max(cursor);
+        if gap_end > gap_start {
+            return Some(CaptureRegion {
+                start_address: gap_start,
+                length: gap_end - gap_start,
+            });
+        }
+    })
+}
+
+/// Captures memory around the addresses that pointer-sized values on thread
+/// stacks point to, similarly to `MiniDumpWithIndirectlyReferencedMemory` on
+/// Windows. This captures the heap objects local variables point to.
+///
+/// Only addresses in readable and writable mappings that aren't stacks are
+/// considered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndirectMemory {
+    /// The number of bytes captured around each address, half before and half
+    /// after it
+    pub window_size: usize,
+    /// The maximum number of bytes captured across all threads
+    pub budget: usize,
+}
+
+impl Default for IndirectMemory {
+    fn default() -> Self {
+        Self {
+            window_size: 256,
+            budget: 1024 * 1024,
+        }
+    }
+}
+
+/// Captures memory around the addresses that general purpose registers point
+/// to, and around the fault address of the crash, if any.
+///
+/// Only addresses in readable mappings are considered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterMemory {
+    /// The number of bytes captured around each address, half before and half
+    /// after it
+    pub window_size: usize,
+    /// Captures memory around the registers of every thread, rather than only
+    /// the crashing thread
+    pub all_threads: bool,
+}
+
+impl Default for RegisterMemory {
+    fn default() -> Self {
+        Self {
+            window_size: 256,
+            all_threads: false,
+        }
+    }
+}
+
+/// Scans the pointer aligned words of `stack` at or above `sp_offset` for
+/// addresses in any of the `targets`, see [`regions_around`].
+pub fn referenced_regions(
+    stack: &[u8],
+    sp_offset: usize,
+    window_size: usize,
+    targets: &[CaptureRegion],
+) -> Vec<CaptureRegion> {
+    const WORD: usize = std::mem::size_of::<usize>();
+
+    let start = (sp_offset + WORD - 1) & !(WORD - 1);
+    let words = stack
+        .get(start..)
+        .unwrap_or_default()
+        .chunks_exact(WORD)
+        .map(|word| usize::from_ne_bytes(word.try_into().unwrap()));
+    regions_around(words, window_size, targets)
+}
+
+/// Returns the windows around the addresses that are in any of the
+/// `targets`, sorted by address, with overlapping windows merged.
+///
+/// `targets` must be sorted by address and not overlap, windows are clamped
+/// to the target they are in.
+pub fn regions_around(
+    addresses: impl IntoIterator<Item = usize>,
+    window_size: usize,
+    targets: &[CaptureRegion],
+) -> Vec<CaptureRegion> {
+    let mut windows: Vec<_> = addresses
+        .into_iter()
+        .filter_map(|addr| {
+            let index = targets.partition_point(|t| t.start_address <= addr);
+            let target = targets[..index].last()?;
+            let target_end = target.start_address + target.length;
+            if addr >= target_end {
+                return None;
+            }
+
+            let start_address = target
+                .start_address
+                .max(addr.saturating_sub(window_size / 2));
+            let end = target_end.min(addr.saturating_add(window_size / 2).max(addr + 1));
+            Some(CaptureRegion {
+                start_address,
+                length: end - start_address,
+            })
+        })
+        .collect();
+
+    windows.sort_by_key(|w| w.start_address);
+
+    let mut merged: Vec<CaptureRegion> = Vec::with_capacity(windows.len());
+    for window in windows {
+        match merged.last_mut() {
+            Some(last) if window.start_address <= last.start_address + last.length => {
+                let end =
+                    (last.start_address + last.length).max(window.start_address + window.length);
+                last.length = end - last.start_address;
+            }
+            _ => merged.push(window),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::maps_reader::SystemMappingInfo, *};
+
+    fn mapping(start_address: usize, size: usize, perms: &str, name: &str) -> MappingInfo {
+        let permissions = perms
+            .bytes()
+            .map(|b| match b {
+                b'r' => MMPermissions::READ,
+                b'w' => MMPermissions::WRITE,
+                b'x' => MMPermissions::EXECUTE,
+                b's' => MMPermissions::SHARED,
+                b'p' => MMPermissions::PRIVATE,
+                _ => MMPermissions::NONE,
+            })
+            .collect();
+
+        MappingInfo {
+            start_address,
+            size,
+            system_mapping_info: SystemMappingInfo {
+                start_address,
+                end_address: start_address + size,
+            },
+            offset: 0,
+            permissions,
+            name: (!name.is_empty()).then(|| name.into()),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let mappings = [
+            mapping(0x1000, 0x1000, "r-xp", "/usr/bin/app"),
+            mapping(0x2000, 0x3000, "rw-p", "[heap]"),
+            mapping(0x10000, 0x2000, "rw-p", ""),
+            mapping(0x20000, 0x1000, "rw-s", "/memfd:wayland-shm"),
+            mapping(0x30000, 0x1000, "---p", ""),
+            mapping(0x40000, 0x4000, "rw-p", ""),
+            mapping(0x50000, 0x1000, "rw-p", ""),
+        ];
+
+        let rules = [
+            MemoryCaptureRule {
+                name: Some("".into()),
+                permissions: MMPermissions::READ | MMPermissions::PRIVATE,
+                max_bytes: Some(0x3000),
+                priority: 0,
+            },
+            MemoryCaptureRule {
+                name: Some("memfd:*".into()),
+                permissions: MMPermissions::SHARED,
+                max_bytes: Some(0x10000),
+                priority: 0,
+            },
+            MemoryCaptureRule {
+                name: Some("[heap]".into()),
+                max_bytes: Some(0x2000),
+                priority: 1,
+                ..Default::default()
+            },
+        ];
+
+        let region = |start_address, length| CaptureRegion {
+            start_address,
+            length,
+        };
+
+        // The mapping at 0x10000 is entirely a stack that is already captured,
+        // the one at 0x40000 contains one in its middle, and the memfd mapping
+        // at 0x20000 starts in the middle of one
+        let captured = [
+            region(0x10000, 0x2000),
+            region(0x1f800, 0x1000),
+            region(0x41000, 0x1000),
+        ];
+        let regions = select_regions(&rules, &mappings, &captured);
+
+        assert_eq!(
+            regions,
+            [
+                // The heap rule has the highest priority, and is capped
+                region(0x2000, 0x2000),
+                // The anonymous rule skips the unreadable and already captured
+                // mappings, captures around the stack in the middle of the
+                // next one, and runs out of budget before the last one
+                region(0x40000, 0x1000),
+                region(0x42000, 0x2000),
+                region(0x20800, 0x800),
+            ]
+        );
+
+        // A rule without a byte cap captures its mappings entirely
+        let heap = MemoryCaptureRule {
+            name: Some("[heap]".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_regions(&[heap], &mappings, &[]),
+            [region(0x2000, 0x3000)]
+        );
+    }
+
+    #[test]
+    fn referenced() {
+        let region = |start_address, length| CaptureRegion {
+            start_address,
+            length,
+        };
+        let targets = [region(0x1000, 0x1000), region(0x8000, 0x100)];
+
+        let words: [usize; 8] = [
+            // Below the stack pointer
+            0x1800, // In the first target, the windows are clamped to its start
+            0x1900, 0x1010, 0x1040, // Outside of any target
+            0x3000, 0x7fff, // Clamped to the end of the second target
+            0x80f0, // Overlaps the windows of 0x1010 and 0x1040
+            0x1100,
+        ];
+        let stack: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+
+        // The stack pointer is in the middle of the first word, which is
+        // skipped
+        let sp_offset = std::mem::size_of::<usize>() / 2;
+        assert_eq!(
+            referenced_regions(&stack, sp_offset, 0x100, &targets),
+            [
+                region(0x1000, 0x180),
+                region(0x1880, 0x100),
+                region(0x8070, 0x90),
+            ]
+        );
+    }
+}
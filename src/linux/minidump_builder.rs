@@ -0,0 +1,390 @@
+//! Builds minidumps from data provided by the caller rather than from a live
+//! process, eg. for creating fixtures for minidump processors
+
+use {
+    crate::{
+        dir_section::{DirSection, DumpBuf, FileWriterError},
+        mem_writer::{
+            write_string_to_location, Buffer, MemoryArrayWriter, MemoryWriter, MemoryWriterError,
+        },
+        minidump_cpu::RawContextCPU,
+        minidump_format::*,
+    },
+    std::io::{Seek, Write},
+};
+
+#[derive(Debug, thiserror::Error, serde::Serialize)]
+pub enum MinidumpBuilderError {
+    #[error("Failed to write to memory")]
+    MemoryWriterError(#[from] MemoryWriterError),
+    #[error("Failed to write to file")]
+    FileWriterError(#[from] FileWriterError),
+    #[error("The exception is in thread {0}, which wasn't added")]
+    UnknownExceptionThread(u32),
+}
+
+#[derive(Debug)]
+struct BuilderThread {
+    thread_id: u32,
+    context: RawContextCPU,
+    stack_address: u64,
+    stack: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct BuilderModule {
+    base_address: u64,
+    size: u32,
+    name: String,
+    build_id: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct BuilderException {
+    thread_id: u32,
+    exception: MDException,
+}
+
+#[derive(Debug)]
+struct BuilderSystemInfo {
+    info: MDRawSystemInfo,
+    os_version: String,
+}
+
+/// Builds a minidump from threads, modules, memory and streams that are
+/// provided rather than read from a process. The minidump has a system info,
+/// thread list, module list and memory list stream, an exception stream if
+/// one is set, and the raw streams that were added.
+#[derive(Debug)]
+pub struct MinidumpBuilder {
+    threads: Vec<BuilderThread>,
+    modules: Vec<BuilderModule>,
+    memory: Vec<(u64, Vec<u8>)>,
+    exception: Option<BuilderException>,
+    system_info: BuilderSystemInfo,
+    streams: Vec<(u32, Vec<u8>)>,
+    time_date_stamp: u32,
+}
+
+impl Default for MinidumpBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MinidumpBuilder {
+    /// Creates a builder for a minidump of the current platform and CPU
+    /// architecture, with no threads, modules or memory
+    pub fn new() -> Self {
+        // SAFETY: POD
+        let mut info = unsafe { std::mem::zeroed::<MDRawSystemInfo>() };
+        info.platform_id = if cfg!(target_os = "android") {
+            PlatformId::Android
+        } else {
+            PlatformId::Linux
+        } as u32;
+        info.processor_architecture = if cfg!(target_arch = "x86_64") {
+            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_AMD64
+        } else if cfg!(target_arch = "x86") {
+            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_INTEL
+        } else if cfg!(target_arch = "aarch64") {
+            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM64_OLD
+        } else if cfg!(target_arch = "arm") {
+            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_ARM
+        } else {
+            MDCPUArchitecture::PROCESSOR_ARCHITECTURE_MIPS
+        } as u16;
+        info.number_of_processors = 1;
+
+        Self {
+            threads: Vec::new(),
+            modules: Vec::new(),
+            memory: Vec::new(),
+            exception: None,
+            system_info: BuilderSystemInfo {
+                info,
+                os_version: String::new(),
+            },
+            streams: Vec::new(),
+            time_date_stamp: 0,
+        }
+    }
+
+    /// Adds a thread with its context and the contents of its stack, which
+    /// starts at `stack_address`. The stack is also added to the memory list.
+    pub fn add_thread(
+        &mut self,
+        thread_id: u32,
+        context: RawContextCPU,
+        stack_address: u64,
+        stack: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.threads.push(BuilderThread {
+            thread_id,
+            context,
+            stack_address,
+            stack: stack.into(),
+        });
+        self
+    }
+
+    /// Adds a module mapped at `base_address`, identified by its build id.
+    /// The build id is written as is, it isn't truncated or padded to a GUID.
+    pub fn add_module(
+        &mut self,
+        base_address: u64,
+        size: u32,
+        name: &str,
+        build_id: &[u8],
+    ) -> &mut Self {
+        self.modules.push(BuilderModule {
+            base_address,
+            size,
+            name: name.to_owned(),
+            build_id: build_id.to_vec(),
+        });
+        self
+    }
+
+    /// Adds memory at `address` to the memory list
+    pub fn add_memory(&mut self, address: u64, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.memory.push((address, data.into()));
+        self
+    }
+
+    /// Sets the exception, which happened in the thread with the id. The
+    /// context of the exception is the context of that thread.
+    pub fn set_exception(&mut self, thread_id: u32, exception: MDException) -> &mut Self {
+        self.exception = Some(BuilderException {
+            thread_id,
+            exception,
+        });
+        self
+    }
+
+    /// Replaces the system info, which describes the current platform and CPU
+    /// architecture by default
+    pub fn set_system_info(&mut self, info: MDRawSystemInfo, os_version: &str) -> &mut Self {
+        self.system_info = BuilderSystemInfo {
+            info,
+            os_version: os_version.to_owned(),
+        };
+        self
+    }
+
+    /// Adds a stream of the type with the contents provided
+    pub fn add_stream(&mut self, stream_type: u32, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.streams.push((stream_type, data.into()));
+        self
+    }
+
+    /// Sets the time in the header, in seconds since the epoch. It is 0 by
+    /// default, so that the same minidump is built every time.
+    pub fn set_time_date_stamp(&mut self, time_date_stamp: u32) -> &mut Self {
+        self.time_date_stamp = time_date_stamp;
+        self
+    }
+
+    /// Writes the minidump to the destination, and returns it
+    pub fn write(
+        &self,
+        destination: &mut (impl Write + Seek),
+    ) -> Result<Vec<u8>, MinidumpBuilderError> {
+        let num_writers = 4 + self.exception.is_some() as u32 + self.streams.len() as u32;
+
+        let mut buffer = Buffer::with_capacity(0);
+        let mut header_section = MemoryWriter::<MDRawHeader>::alloc(&mut buffer)?;
+        let mut dir_section = DirSection::new(&mut buffer, num_writers, destination)?;
+
+        let header = MDRawHeader {
+            signature: MD_HEADER_SIGNATURE,
+            version: MD_HEADER_VERSION,
+            stream_count: num_writers,
+            stream_directory_rva: dir_section.position(),
+            checksum: 0,
+            time_date_stamp: self.time_date_stamp,
+            flags: 0,
+        };
+        header_section.set_value(&mut buffer, header)?;
+        dir_section.write_to_file(&mut buffer, None)?;
+
+        let dirent = self.write_system_info(&mut buffer)?;
+        dir_section.write_to_file(&mut buffer, Some(dirent))?;
+
+        let mut memory_blocks = Vec::with_capacity(self.threads.len() + self.memory.len());
+        let mut contexts = Vec::with_capacity(self.threads.len());
+        let dirent = self.write_thread_list(&mut buffer, &mut memory_blocks, &mut contexts)?;
+        dir_section.write_to_file(&mut buffer, Some(dirent))?;
+
+        let dirent = self.write_module_list(&mut buffer)?;
+        dir_section.write_to_file(&mut buffer, Some(dirent))?;
+
+        let dirent = self.write_memory_list(&mut buffer, memory_blocks)?;
+        dir_section.write_to_file(&mut buffer, Some(dirent))?;
+
+        if let Some(exception) = &self.exception {
+            let thread_context = self
+                .threads
+                .iter()
+                .position(|thread| thread.thread_id == exception.thread_id)
+                .map(|index| contexts[index])
+                .ok_or(MinidumpBuilderError::UnknownExceptionThread(
+                    exception.thread_id,
+                ))?;
+            let stream = MDRawExceptionStream {
+                thread_id: exception.thread_id,
+                exception_record: exception.exception.clone(),
+                __align: 0,
+                thread_context,
+            };
+            let exc = MemoryWriter::alloc_with_val(&mut buffer, stream)?;
+            let dirent = MDRawDirectory {
+                stream_type: MDStreamType::ExceptionStream as u32,
+                location: exc.location(),
+            };
+            dir_section.write_to_file(&mut buffer, Some(dirent))?;
+        }
+
+        for (stream_type, data) in &self.streams {
+            let section = MemoryArrayWriter::<u8>::write_bytes(&mut buffer, data);
+            let dirent = MDRawDirectory {
+                stream_type: *stream_type,
+                location: section.location(),
+            };
+            dir_section.write_to_file(&mut buffer, Some(dirent))?;
+        }
+
+        Ok(buffer.into())
+    }
+
+    fn write_system_info(&self, buffer: &mut DumpBuf) -> Result<MDRawDirectory, MemoryWriterError> {
+        let mut info_section = MemoryWriter::<MDRawSystemInfo>::alloc(buffer)?;
+        let os_version_loc = write_string_to_location(buffer, &self.system_info.os_version)?;
+
+        let mut info = self.system_info.info.clone();
+        info.csd_version_rva = os_version_loc.rva;
+        info_section.set_value(buffer, info)?;
+
+        Ok(MDRawDirectory {
+            stream_type: MDStreamType::SystemInfoStream as u32,
+            location: info_section.location(),
+        })
+    }
+
+    /// Writes the thread list, adding the stacks to the memory blocks and
+    /// the locations of the contexts to `contexts`
+    fn write_thread_list(
+        &self,
+        buffer: &mut DumpBuf,
+        memory_blocks: &mut Vec<MDMemoryDescriptor>,
+        contexts: &mut Vec<MDLocationDescriptor>,
+    ) -> Result<MDRawDirectory, MemoryWriterError> {
+        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, self.threads.len() as u32)?;
+        let mut thread_list =
+            MemoryArrayWriter::<MDRawThread>::alloc_array(buffer, self.threads.len())?;
+
+        let mut dirent = MDRawDirectory {
+            stream_type: MDStreamType::ThreadListStream as u32,
+            location: list_header.location(),
+        };
+        dirent.location.data_size += thread_list.location().data_size;
+
+        for (index, thread) in self.threads.iter().enumerate() {
+            let stack = write_memory(buffer, thread.stack_address, &thread.stack)?;
+            memory_blocks.push(stack);
+
+            let cpu_section = MemoryWriter::alloc_with_val(buffer, thread.context.clone())?;
+            contexts.push(cpu_section.location());
+
+            let raw_thread = MDRawThread {
+                thread_id: thread.thread_id,
+                suspend_count: 0,
+                priority_class: 0,
+                priority: 0,
+                teb: 0,
+                stack,
+                thread_context: cpu_section.location(),
+            };
+            thread_list.set_value_at(buffer, raw_thread, index)?;
+        }
+
+        Ok(dirent)
+    }
+
+    fn write_module_list(&self, buffer: &mut DumpBuf) -> Result<MDRawDirectory, MemoryWriterError> {
+        let mut modules = Vec::with_capacity(self.modules.len());
+        for module in &self.modules {
+            let cv_record = if module.build_id.is_empty() {
+                Default::default()
+            } else {
+                let cv_signature = format::CvSignature::Elf as u32;
+                let record: Vec<u8> = cv_signature
+                    .to_ne_bytes()
+                    .into_iter()
+                    .chain(module.build_id.iter().copied())
+                    .collect();
+                MemoryArrayWriter::<u8>::write_bytes(buffer, &record).location()
+            };
+            let name = write_string_to_location(buffer, &module.name)?;
+
+            modules.push(MDRawModule {
+                base_of_image: module.base_address,
+                size_of_image: module.size,
+                cv_record,
+                module_name_rva: name.rva,
+                ..Default::default()
+            });
+        }
+
+        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, modules.len() as u32)?;
+        let mut dirent = MDRawDirectory {
+            stream_type: MDStreamType::ModuleListStream as u32,
+            location: list_header.location(),
+        };
+
+        if !modules.is_empty() {
+            let module_list = MemoryArrayWriter::<MDRawModule>::alloc_from_iter(buffer, modules)?;
+            dirent.location.data_size += module_list.location().data_size;
+        }
+
+        Ok(dirent)
+    }
+
+    fn write_memory_list(
+        &self,
+        buffer: &mut DumpBuf,
+        mut memory_blocks: Vec<MDMemoryDescriptor>,
+    ) -> Result<MDRawDirectory, MemoryWriterError> {
+        for (address, data) in &self.memory {
+            memory_blocks.push(write_memory(buffer, *address, data)?);
+        }
+
+        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, memory_blocks.len() as u32)?;
+        let mut dirent = MDRawDirectory {
+            stream_type: MDStreamType::MemoryListStream as u32,
+            location: list_header.location(),
+        };
+
+        let block_list =
+            MemoryArrayWriter::<MDMemoryDescriptor>::alloc_from_array(buffer, &memory_blocks)?;
+        dirent.location.data_size += block_list.location().data_size;
+
+        Ok(dirent)
+    }
+}
+
+fn write_memory(
+    buffer: &mut DumpBuf,
+    address: u64,
+    data: &[u8],
+) -> Result<MDMemoryDescriptor, MemoryWriterError> {
+    let data_size = data.len().try_into()?;
+    let section = MemoryArrayWriter::<u8>::write_bytes(buffer, data);
+    Ok(MDMemoryDescriptor {
+        start_of_memory_range: address,
+        memory: MDLocationDescriptor {
+            data_size,
+            rva: section.position,
+        },
+    })
+}
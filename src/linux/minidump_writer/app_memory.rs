@@ -1,4 +1,4 @@
-use super::*;
+use super::{size_limit::SizePriority, *};
 
 #[derive(Debug, Error, serde::Serialize)]
 pub enum SectionAppMemoryError {
@@ -9,16 +9,35 @@ pub enum SectionAppMemoryError {
 }
 
 impl MinidumpWriter {
-    /// Write application-provided memory regions.
-    pub fn write_app_memory(&mut self, buffer: &mut DumpBuf) -> Result<(), SectionAppMemoryError> {
+    /// Write application-provided memory regions. In resilient mode, regions
+    /// that can't be read are skipped.
+    pub fn write_app_memory(
+        &mut self,
+        buffer: &mut DumpBuf,
+        mut soft_errors: impl WriteErrorList<SectionAppMemoryError>,
+    ) -> Result<(), SectionAppMemoryError> {
         let blamed_thread = self.blamed_thread;
-        for app_memory in &self.app_memory {
-            let data_copy =
-                Self::copy_from_process(blamed_thread, app_memory.ptr, app_memory.length)?;
+        for idx in 0..self.app_memory.len() {
+            let app_memory = &self.app_memory[idx];
+            let ptr = app_memory.ptr;
+            let length =
+                self.fit_memory_region(buffer, SizePriority::AppMemory, ptr, app_memory.length);
+            if length == 0 {
+                break;
+            }
+
+            let data_copy = match self.copy_memory(blamed_thread, ptr, length) {
+                Ok(data_copy) => data_copy,
+                Err(e) if self.resilient => {
+                    soft_errors.push(e.into());
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
 
             let section = MemoryArrayWriter::write_bytes(buffer, &data_copy);
             let desc = MDMemoryDescriptor {
-                start_of_memory_range: app_memory.ptr as u64,
+                start_of_memory_range: ptr as u64,
                 memory: section.location(),
             };
             self.memory_blocks.push(desc);
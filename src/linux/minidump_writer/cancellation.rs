@@ -0,0 +1,67 @@
+//! Stopping a minidump that takes too long, see
+//! [`MinidumpWriterConfig::set_deadline`](super::MinidumpWriterConfig::set_deadline)
+//! and [`MinidumpWriterConfig::set_cancellation_token`](super::MinidumpWriterConfig::set_cancellation_token)
+
+use {
+    super::MinidumpWriter,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Instant,
+    },
+};
+
+/// Cancels writing a minidump from another thread. Clones of the token
+/// refer to the same cancellation.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why writing a minidump stopped early
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum Interruption {
+    DeadlineExceeded,
+    Cancelled,
+}
+
+impl MinidumpWriter {
+    /// Whether writing should stop because the deadline has passed or it was
+    /// cancelled. Once it has been interrupted, it stays interrupted.
+    pub(super) fn is_interrupted(&self) -> bool {
+        if self.interruption.get().is_some() {
+            return true;
+        }
+
+        let interruption = if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            Interruption::Cancelled
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Interruption::DeadlineExceeded
+        } else {
+            return false;
+        };
+        let _ = self.interruption.set(interruption);
+        true
+    }
+}
@@ -0,0 +1,445 @@
+use {
+    super::{
+        super::core_file::{
+            MACHINE, NT_ARM_VFP, NT_AUXV, NT_PRXFPREG, PRPSINFO_FNAME_OFFSET, PRPSINFO_FNAME_SIZE,
+            PRPSINFO_PID_OFFSET, PRSTATUS_CURSIG_OFFSET, PRSTATUS_PID_OFFSET, PRSTATUS_REGS_OFFSET,
+            SIGINFO_ADDR_OFFSET, WORD,
+        },
+        memory64_list_stream::is_dumpable_mapping,
+        *,
+    },
+    goblin::elf::{header, note, program_header},
+    procfs_core::process::{MMapPath, MemoryMap},
+    scroll::Pwrite,
+    std::os::unix::ffi::OsStrExt,
+};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_pointer_width = "32")] {
+        use goblin::elf::{
+            header::header32::{Header, SIZEOF_EHDR},
+            program_header::program_header32::{ProgramHeader, SIZEOF_PHDR},
+        };
+        const ELF_CLASS: u8 = header::ELFCLASS32;
+    } else if #[cfg(target_pointer_width = "64")] {
+        use goblin::elf::{
+            header::header64::{Header, SIZEOF_EHDR},
+            program_header::program_header64::{ProgramHeader, SIZEOF_PHDR},
+        };
+        const ELF_CLASS: u8 = header::ELFCLASS64;
+    }
+}
+
+/// The size of `elf_prpsinfo`, `pr_fname` is followed by `pr_psargs`
+const PRPSINFO_SIZE: usize = PRPSINFO_FNAME_OFFSET + PRPSINFO_FNAME_SIZE + 80;
+/// The size of `siginfo_t`
+const SIGINFO_SIZE: usize = 128;
+/// The maximum number of program headers, a larger number is stored in the
+/// first section header, which gdb and lldb don't all support
+const MAX_PROGRAM_HEADERS: usize = 0xfffe;
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum CoreDumpError {
+    #[error("Failed to write the core file")]
+    IOError(
+        #[from]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to get the registers of thread {0}")]
+    ThreadInfoFailed(Pid, #[source] ThreadInfoError),
+    #[error("Only {1} of the {0} mappings were written")]
+    TooManyMappings(usize, usize),
+}
+
+/// A `PT_LOAD` segment for a mapping, of which the first `file_size` bytes
+/// are written to the core file
+struct LoadSegment {
+    start_address: u64,
+    memory_size: u64,
+    file_size: u64,
+    flags: u32,
+}
+
+impl MinidumpWriter {
+    /// Writes the state of the process as an ELF core file, like the ones
+    /// written by the kernel, which can be loaded in gdb or lldb.
+    ///
+    /// Like the kernel by default, the memory of file backed mappings that
+    /// weren't written to isn't included, except for ELF headers, as the
+    /// debugger reads it from the files listed in the `NT_FILE` note.
+    ///
+    /// The mappings and auxiliary vector are the ones read when the writer
+    /// was initialized, and the command line in the `NT_PRPSINFO` note is
+    /// passed through the command line filter. Returns the size of the core
+    /// file.
+    pub fn write_core_dump(
+        &mut self,
+        destination: &mut impl Write,
+        mut soft_errors: impl WriteErrorList<CoreDumpError>,
+    ) -> Result<u64, CoreDumpError> {
+        let mut maps = self.memory_maps.clone();
+        if maps.len() > MAX_PROGRAM_HEADERS - 1 {
+            soft_errors.push(CoreDumpError::TooManyMappings(
+                maps.len(),
+                MAX_PROGRAM_HEADERS - 1,
+            ));
+            maps.truncate(MAX_PROGRAM_HEADERS - 1);
+        }
+
+        let notes = self.core_notes(&maps, &mut soft_errors);
+        let segments: Vec<_> = maps
+            .iter()
+            .map(|mm| LoadSegment {
+                start_address: mm.address.0,
+                memory_size: mm.address.1.saturating_sub(mm.address.0),
+                file_size: self.core_dump_size(mm),
+                flags: segment_flags(mm),
+            })
+            .collect();
+
+        let phnum = segments.len() + 1;
+        let notes_offset = SIZEOF_EHDR + phnum * SIZEOF_PHDR;
+        let mut head = vec![0u8; notes_offset];
+        head.extend_from_slice(&notes);
+        // The memory starts at a page boundary, like in the kernel's cores
+        head.resize(head.len().next_multiple_of(self.page_size), 0);
+
+        let mut ident = [0u8; header::SIZEOF_IDENT];
+        ident[..header::SELFMAG].copy_from_slice(header::ELFMAG);
+        ident[header::EI_CLASS] = ELF_CLASS;
+        ident[header::EI_DATA] = if cfg!(target_endian = "little") {
+            header::ELFDATA2LSB
+        } else {
+            header::ELFDATA2MSB
+        };
+        ident[header::EI_VERSION] = header::EV_CURRENT;
+        let elf_header = Header {
+            e_ident: ident,
+            e_type: header::ET_CORE,
+            e_machine: MACHINE,
+            e_version: header::EV_CURRENT.into(),
+            e_phoff: SIZEOF_EHDR as _,
+            e_ehsize: SIZEOF_EHDR as u16,
+            e_phentsize: SIZEOF_PHDR as u16,
+            e_phnum: phnum as u16,
+            ..Default::default()
+        };
+        let mut offset = 0;
+        head.gwrite_with(elf_header, &mut offset, scroll::NATIVE)
+            .expect("the header fits");
+
+        head.gwrite_with(
+            ProgramHeader {
+                p_type: program_header::PT_NOTE,
+                p_offset: notes_offset as _,
+                p_filesz: notes.len() as _,
+                ..Default::default()
+            },
+            &mut offset,
+            scroll::NATIVE,
+        )
+        .expect("the program headers fit");
+
+        let mut file_offset = head.len() as u64;
+        for segment in &segments {
+            head.gwrite_with(
+                ProgramHeader {
+                    p_type: program_header::PT_LOAD,
+                    p_flags: segment.flags,
+                    p_offset: file_offset as _,
+                    p_vaddr: segment.start_address as _,
+                    p_filesz: segment.file_size as _,
+                    p_memsz: segment.memory_size as _,
+                    p_align: self.page_size as _,
+                    ..Default::default()
+                },
+                &mut offset,
+                scroll::NATIVE,
+            )
+            .expect("the program headers fit");
+            file_offset += segment.file_size;
+        }
+
+        destination.write_all(&head)?;
+
+        let memory = self.process_memory(
+            segments
+                .iter()
+                .filter(|segment| segment.file_size > 0)
+                .map(|segment| MDMemoryDescriptor64 {
+                    start_of_memory_range: segment.start_address,
+                    data_size: segment.file_size,
+                })
+                .collect(),
+        );
+        // The program headers already give the offset of every segment
+        memory.write_to(destination, || false)?;
+
+        Ok(file_offset)
+    }
+
+    /// The number of bytes of a mapping written to the core file
+    fn core_dump_size(&self, mm: &MemoryMap) -> u64 {
+        if !is_dumpable_mapping(mm) {
+            return 0;
+        }
+        let size = mm.address.1 - mm.address.0;
+
+        match &mm.pathname {
+            MMapPath::Path(_) => {
+                // Private file backed mappings that are writable may have
+                // been modified, the others have the contents of the file
+                if mm.perms.contains(MMPermissions::WRITE)
+                    && mm.perms.contains(MMPermissions::PRIVATE)
+                {
+                    return size;
+                }
+
+                // The ELF headers contain the build ids of the modules
+                let mut magic = [0u8; header::SELFMAG];
+                let is_elf = mm.offset == 0
+                    && matches!(
+                        self.mem_reader(self.process_id).read(mm.address.0 as usize, &mut magic),
+                        Ok(len) if len == magic.len()
+                    )
+                    && magic == *header::ELFMAG;
+                if is_elf {
+                    size.min(self.page_size as u64)
+                } else {
+                    0
+                }
+            }
+            _ => size,
+        }
+    }
+
+    /// The contents of the `PT_NOTE` segment: the blamed thread's
+    /// `NT_PRSTATUS`, followed by the notes for the process and the other
+    /// register notes of the thread, then those of the other threads
+    fn core_notes(
+        &self,
+        maps: &[MemoryMap],
+        soft_errors: &mut impl WriteErrorList<CoreDumpError>,
+    ) -> Vec<u8> {
+        let (pgrp, sid) = match &self.core_file {
+            Some(_) => (0, 0),
+            None => {
+                let pid = Some(nix::unistd::Pid::from_raw(self.process_id));
+                (
+                    nix::unistd::getpgid(pid).map_or(0, |pgrp| pgrp.as_raw()),
+                    nix::unistd::getsid(pid).map_or(0, |sid| sid.as_raw()),
+                )
+            }
+        };
+        let signal = self.core_signal_info();
+
+        let mut threads = Vec::with_capacity(self.threads.len());
+        for (index, thread) in self.threads.iter().enumerate() {
+            let mut info = match self.get_thread_info_by_index(index) {
+                Ok(info) => info,
+                Err(e) => {
+                    soft_errors.push(CoreDumpError::ThreadInfoFailed(thread.tid, e));
+                    continue;
+                }
+            };
+            let is_blamed = thread.tid == self.blamed_thread;
+            #[cfg(not(target_arch = "mips"))]
+            if is_blamed {
+                if let Some(crash_context) = &self.crash_context {
+                    crash_context.fill_thread_info(&mut info);
+                }
+            }
+
+            let mut prstatus = vec![0u8; PRSTATUS_REGS_OFFSET];
+            if is_blamed {
+                if let Some(signal) = &signal {
+                    let signo = read_i32(signal, 0);
+                    write_i32(&mut prstatus, 0, signo);
+                    prstatus[PRSTATUS_CURSIG_OFFSET..PRSTATUS_CURSIG_OFFSET + 2]
+                        .copy_from_slice(&(signo as u16).to_ne_bytes());
+                }
+            }
+            write_i32(&mut prstatus, PRSTATUS_PID_OFFSET, thread.tid);
+            write_i32(&mut prstatus, PRSTATUS_PID_OFFSET + 4, info.ppid);
+            write_i32(&mut prstatus, PRSTATUS_PID_OFFSET + 8, pgrp);
+            write_i32(&mut prstatus, PRSTATUS_PID_OFFSET + 12, sid);
+            prstatus.extend_from_slice(&info.core_registers());
+            // pr_fpvalid
+            prstatus.extend_from_slice(&1i32.to_ne_bytes());
+            prstatus.resize(prstatus.len().next_multiple_of(WORD), 0);
+
+            let entry = (thread.tid, info.ppid, prstatus, info.core_notes());
+            if is_blamed {
+                threads.insert(0, entry);
+            } else {
+                threads.push(entry);
+            }
+        }
+
+        let mut notes = Vec::new();
+        let mut threads = threads.into_iter();
+        let first_thread = threads.next();
+        if let Some((_, _, prstatus, _)) = &first_thread {
+            push_note(&mut notes, note::NT_PRSTATUS, prstatus);
+        }
+
+        let ppid = first_thread.as_ref().map_or(0, |(_, ppid, _, _)| *ppid);
+        push_note(
+            &mut notes,
+            note::NT_PRPSINFO,
+            &self.core_prpsinfo(ppid, pgrp, sid),
+        );
+        if let Some(signal) = &signal {
+            push_note(&mut notes, note::NT_SIGINFO, signal);
+        }
+        match &self.core_file {
+            Some(core_file) => push_note(&mut notes, NT_AUXV, &core_file.auxv),
+            None => push_note(&mut notes, NT_AUXV, &self.auxv.to_raw()),
+        }
+        push_note(&mut notes, note::NT_FILE, &self.core_file_note(maps));
+
+        if let Some((_, _, _, thread_notes)) = &first_thread {
+            for (n_type, desc) in thread_notes {
+                push_note(&mut notes, *n_type, desc);
+            }
+        }
+        for (_, _, prstatus, thread_notes) in threads {
+            push_note(&mut notes, note::NT_PRSTATUS, &prstatus);
+            for (n_type, desc) in &thread_notes {
+                push_note(&mut notes, *n_type, desc);
+            }
+        }
+
+        notes
+    }
+
+    /// The `siginfo_t` of the signal that caused the crash, if any
+    fn core_signal_info(&self) -> Option<Vec<u8>> {
+        let mut siginfo = vec![0u8; SIGINFO_SIZE];
+
+        if let Some(crash_context) = &self.crash_context {
+            let info = &crash_context.inner.siginfo;
+            write_i32(&mut siginfo, 0, info.ssi_signo as i32);
+            write_i32(&mut siginfo, 4, info.ssi_errno);
+            write_i32(&mut siginfo, 8, info.ssi_code);
+            if info.ssi_code > 0 {
+                let address = (info.ssi_addr as usize).to_ne_bytes();
+                siginfo[SIGINFO_ADDR_OFFSET..SIGINFO_ADDR_OFFSET + WORD].copy_from_slice(&address);
+            } else {
+                // Signals sent from userspace have the sender's pid and uid
+                write_i32(&mut siginfo, SIGINFO_ADDR_OFFSET, info.ssi_pid as i32);
+                write_i32(&mut siginfo, SIGINFO_ADDR_OFFSET + 4, info.ssi_uid as i32);
+            }
+        } else if let Some(signal) = self.core_file.as_ref().and_then(|core| core.signal) {
+            write_i32(&mut siginfo, 0, signal.signo as i32);
+            write_i32(&mut siginfo, 8, signal.code);
+            let address = (signal.address as usize).to_ne_bytes();
+            siginfo[SIGINFO_ADDR_OFFSET..SIGINFO_ADDR_OFFSET + WORD].copy_from_slice(&address);
+        } else {
+            return None;
+        }
+
+        Some(siginfo)
+    }
+
+    /// The `elf_prpsinfo` of the process, only the ids, the name and the
+    /// command line are filled in
+    fn core_prpsinfo(&self, ppid: Pid, pgrp: Pid, sid: Pid) -> Vec<u8> {
+        let mut prpsinfo = vec![0u8; PRPSINFO_SIZE];
+        write_i32(&mut prpsinfo, PRPSINFO_PID_OFFSET, self.process_id);
+        write_i32(&mut prpsinfo, PRPSINFO_PID_OFFSET + 4, ppid);
+        write_i32(&mut prpsinfo, PRPSINFO_PID_OFFSET + 8, pgrp);
+        write_i32(&mut prpsinfo, PRPSINFO_PID_OFFSET + 12, sid);
+
+        let name = self
+            .threads
+            .iter()
+            .find(|thread| thread.tid == self.process_id)
+            .and_then(|thread| thread.name.as_deref())
+            .unwrap_or_default()
+            .as_bytes();
+        // NUL terminated
+        let name = &name[..name.len().min(PRPSINFO_FNAME_SIZE - 1)];
+        prpsinfo[PRPSINFO_FNAME_OFFSET..PRPSINFO_FNAME_OFFSET + name.len()].copy_from_slice(name);
+
+        if self.core_file.is_none() {
+            if let Ok(mut cmdline) = self.read_filtered_proc_file("cmdline") {
+                // The arguments are separated by spaces and NUL terminated
+                cmdline.truncate(79);
+                for byte in &mut cmdline {
+                    if *byte == 0 {
+                        *byte = b' ';
+                    }
+                }
+                let psargs_offset = PRPSINFO_FNAME_OFFSET + PRPSINFO_FNAME_SIZE;
+                prpsinfo[psargs_offset..psargs_offset + cmdline.len()].copy_from_slice(&cmdline);
+            }
+        }
+
+        prpsinfo
+    }
+
+    /// The `NT_FILE` note listing the file backed mappings
+    fn core_file_note(&self, maps: &[MemoryMap]) -> Vec<u8> {
+        let files: Vec<_> = maps
+            .iter()
+            .filter_map(|mm| match &mm.pathname {
+                MMapPath::Path(path) => Some((mm, path)),
+                _ => None,
+            })
+            .collect();
+
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&files.len().to_ne_bytes());
+        desc.extend_from_slice(&self.page_size.to_ne_bytes());
+        for (mm, _) in &files {
+            desc.extend_from_slice(&(mm.address.0 as usize).to_ne_bytes());
+            desc.extend_from_slice(&(mm.address.1 as usize).to_ne_bytes());
+            desc.extend_from_slice(&(mm.offset as usize / self.page_size).to_ne_bytes());
+        }
+        for (_, path) in &files {
+            desc.extend_from_slice(path.as_os_str().as_bytes());
+            desc.push(0);
+        }
+        desc
+    }
+}
+
+fn segment_flags(mm: &MemoryMap) -> u32 {
+    let mut flags = 0;
+    if mm.perms.contains(MMPermissions::READ) {
+        flags |= program_header::PF_R;
+    }
+    if mm.perms.contains(MMPermissions::WRITE) {
+        flags |= program_header::PF_W;
+    }
+    if mm.perms.contains(MMPermissions::EXECUTE) {
+        flags |= program_header::PF_X;
+    }
+    flags
+}
+
+/// Appends a note, the architecture specific register sets are owned by
+/// "LINUX" rather than "CORE"
+fn push_note(notes: &mut Vec<u8>, n_type: u32, desc: &[u8]) {
+    let name: &[u8] = match n_type {
+        NT_PRXFPREG | NT_ARM_VFP => b"LINUX\0",
+        _ => b"CORE\0",
+    };
+    notes.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&n_type.to_ne_bytes());
+    notes.extend_from_slice(name);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_i32(bytes: &mut [u8], offset: usize, value: i32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
@@ -0,0 +1,289 @@
+use {
+    super::{
+        super::{
+            annotations::AnnotationBlob,
+            crashpad_info::{self, ReadCrashpadInfoError},
+            module_reader::CrashpadInfoAddress,
+        },
+        *,
+    },
+    crate::mem_writer::write_utf8_string_to_location,
+    format::{
+        MINIDUMP_ANNOTATION as MDRawAnnotation, MINIDUMP_CRASHPAD_INFO as MDRawCrashpadInfo,
+        MINIDUMP_MODULE_CRASHPAD_INFO as MDRawModuleCrashpadInfo,
+        MINIDUMP_MODULE_CRASHPAD_INFO_LINK as MDRawModuleCrashpadInfoLink,
+        MINIDUMP_SIMPLE_STRING_DICTIONARY_ENTRY as MDRawSimpleStringDictionaryEntry,
+    },
+};
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum SectionCrashpadInfoError {
+    #[error("Failed to write to memory")]
+    MemoryWriterError(#[from] MemoryWriterError),
+    #[error("Failed to read the Crashpad annotations of module {module_index} ({name:?})")]
+    ReadModuleAnnotationsFailed {
+        module_index: u32,
+        name: Option<String>,
+        #[source]
+        error: ReadCrashpadInfoError,
+    },
+    #[error("No module in the module list contains annotation {name:?}")]
+    NoModuleForAnnotation {
+        name: String,
+        module_address: Option<usize>,
+    },
+}
+
+/// The annotations attached to a single module
+struct ModuleAnnotations<'a> {
+    /// The index of the module in the module list
+    module_index: u32,
+    simple: &'a [(String, String)],
+    objects: Vec<&'a AnnotationBlob>,
+}
+
+impl MinidumpWriter {
+    /// Writes the caller provided annotations, along with the annotations
+    /// Crashpad clients store in the memory of each module in the module list,
+    /// to a `CrashpadInfoStream`.
+    ///
+    /// If there are no annotations, no stream is written and `None` is
+    /// returned, which leaves it out of the directory.
+    pub fn write_crashpad_info_stream(
+        &mut self,
+        buffer: &mut DumpBuf,
+        mut soft_errors: impl WriteErrorList<SectionCrashpadInfoError>,
+    ) -> Result<Option<MDRawDirectory>, SectionCrashpadInfoError> {
+        let module_annotations = self.read_module_annotations(&mut soft_errors);
+
+        let mut modules: Vec<_> = module_annotations
+            .iter()
+            .map(|(module_index, annotations)| ModuleAnnotations {
+                module_index: *module_index,
+                simple: &annotations.simple,
+                objects: annotations.objects.iter().collect(),
+            })
+            .collect();
+
+        // Caller provided blobs follow the objects read from the module they
+        // belong to
+        for blob in &self.annotations.blobs {
+            let Some(module_index) = self.annotation_module_index(blob) else {
+                soft_errors.push(SectionCrashpadInfoError::NoModuleForAnnotation {
+                    name: blob.name.clone(),
+                    module_address: blob.module_address,
+                });
+                continue;
+            };
+            match modules.iter_mut().find(|m| m.module_index == module_index) {
+                Some(module) => module.objects.push(blob),
+                None => modules.push(ModuleAnnotations {
+                    module_index,
+                    simple: &[],
+                    objects: vec![blob],
+                }),
+            }
+        }
+        modules.sort_by_key(|module| module.module_index);
+
+        if self.annotations.simple.is_empty() && modules.is_empty() {
+            return Ok(None);
+        }
+
+        let mut info_section = MemoryWriter::<MDRawCrashpadInfo>::alloc(buffer)?;
+        let dirent = MDRawDirectory {
+            stream_type: MDStreamType::CrashpadInfoStream as u32,
+            location: info_section.location(),
+        };
+
+        let simple_annotations = write_simple_string_dictionary(buffer, &self.annotations.simple)?;
+        let module_list = write_module_list(buffer, &modules)?;
+
+        let zero_guid = GUID {
+            data1: 0,
+            data2: 0,
+            data3: 0,
+            data4: [0; 8],
+        };
+
+        info_section.set_value(
+            buffer,
+            MDRawCrashpadInfo {
+                version: MDRawCrashpadInfo::VERSION,
+                report_id: zero_guid,
+                client_id: zero_guid,
+                simple_annotations,
+                module_list,
+            },
+        )?;
+
+        Ok(Some(dirent))
+    }
+
+    /// The index in the module list of the module that `blob` belongs to. The
+    /// main executable is the module containing the entry point, which is
+    /// normally the first one.
+    fn annotation_module_index(&self, blob: &AnnotationBlob) -> Option<u32> {
+        let address = blob
+            .module_address
+            .or_else(|| self.auxv.get_entry_address().map(|a| a as usize));
+        let index = match address {
+            Some(address) => self.module_list.iter().position(|module| {
+                (module.start_address..module.start_address + module.size).contains(&address)
+            }),
+            None => (!self.module_list.is_empty()).then_some(0),
+        };
+        index.map(|index| index as u32)
+    }
+
+    /// Reads the Crashpad annotations of every module in the module list that
+    /// has any
+    pub(super) fn read_module_annotations(
+        &self,
+        mut soft_errors: impl WriteErrorList<SectionCrashpadInfoError>,
+    ) -> Vec<(u32, crashpad_info::ModuleAnnotations)> {
+        let reader = self.mem_reader(self.process_id);
+        let mut module_annotations = Vec::new();
+
+        for (module_index, mapping) in self.module_list.iter().enumerate() {
+            let module_index = module_index as u32;
+            // Most modules don't use Crashpad, so a missing note isn't an error
+            let CrashpadInfoAddress(address) = match self.from_memory_for_mapping(mapping) {
+                Ok(address) => address,
+                Err(e) => {
+                    log::debug!("no Crashpad info for {:?}: {e}", mapping.name);
+                    continue;
+                }
+            };
+
+            let address = mapping.start_address.wrapping_add(address as usize);
+            match crashpad_info::ModuleAnnotations::read(&reader, address) {
+                Ok(annotations) if annotations.is_empty() => (),
+                Ok(annotations) => module_annotations.push((module_index, annotations)),
+                Err(error) => {
+                    soft_errors.push(SectionCrashpadInfoError::ReadModuleAnnotationsFailed {
+                        module_index,
+                        name: mapping
+                            .name
+                            .as_ref()
+                            .map(|n| n.to_string_lossy().into_owned()),
+                        error,
+                    })
+                }
+            }
+        }
+
+        module_annotations
+    }
+}
+
+/// Writes a `MINIDUMP_SIMPLE_STRING_DICTIONARY`, returning a default location if
+/// there are no entries
+fn write_simple_string_dictionary(
+    buffer: &mut DumpBuf,
+    entries: &[(String, String)],
+) -> Result<MDLocationDescriptor, MemoryWriterError> {
+    if entries.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let header = MemoryWriter::<u32>::alloc_with_val(buffer, entries.len() as u32)?;
+    let mut list =
+        MemoryArrayWriter::<MDRawSimpleStringDictionaryEntry>::alloc_array(buffer, entries.len())?;
+
+    for (idx, (key, value)) in entries.iter().enumerate() {
+        let key = write_utf8_string_to_location(buffer, key)?;
+        let value = write_utf8_string_to_location(buffer, value)?;
+        list.set_value_at(
+            buffer,
+            MDRawSimpleStringDictionaryEntry {
+                key: key.rva,
+                value: value.rva,
+            },
+            idx,
+        )?;
+    }
+
+    let mut location = header.location();
+    location.data_size += list.location().data_size;
+    Ok(location)
+}
+
+/// Writes a `MinidumpAnnotationList`, returning a default location if there
+/// are no annotations
+fn write_annotation_objects(
+    buffer: &mut DumpBuf,
+    objects: &[&AnnotationBlob],
+) -> Result<MDLocationDescriptor, MemoryWriterError> {
+    if objects.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let header = MemoryWriter::<u32>::alloc_with_val(buffer, objects.len() as u32)?;
+    let mut list = MemoryArrayWriter::<MDRawAnnotation>::alloc_array(buffer, objects.len())?;
+
+    for (idx, object) in objects.iter().enumerate() {
+        let name = write_utf8_string_to_location(buffer, &object.name)?;
+
+        // MinidumpByteArray, the length followed by the bytes
+        let value = MemoryWriter::<u32>::alloc_with_val(buffer, object.data.len().try_into()?)?;
+        buffer.write_all(&object.data);
+
+        list.set_value_at(
+            buffer,
+            MDRawAnnotation {
+                name: name.rva,
+                ty: object.ty,
+                _reserved: 0,
+                value: value.position,
+            },
+            idx,
+        )?;
+    }
+
+    let mut location = header.location();
+    location.data_size += list.location().data_size;
+    Ok(location)
+}
+
+/// Writes a `MINIDUMP_MODULE_CRASHPAD_INFO_LIST`, returning a default location
+/// if there are no modules with annotations
+fn write_module_list(
+    buffer: &mut DumpBuf,
+    modules: &[ModuleAnnotations<'_>],
+) -> Result<MDLocationDescriptor, MemoryWriterError> {
+    if modules.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let header = MemoryWriter::<u32>::alloc_with_val(buffer, modules.len() as u32)?;
+    let mut links =
+        MemoryArrayWriter::<MDRawModuleCrashpadInfoLink>::alloc_array(buffer, modules.len())?;
+
+    for (idx, module) in modules.iter().enumerate() {
+        let simple_annotations = write_simple_string_dictionary(buffer, module.simple)?;
+        let annotation_objects = write_annotation_objects(buffer, &module.objects)?;
+        let info = MemoryWriter::alloc_with_val(
+            buffer,
+            MDRawModuleCrashpadInfo {
+                version: MDRawModuleCrashpadInfo::VERSION,
+                list_annotations: Default::default(),
+                simple_annotations,
+                annotation_objects,
+            },
+        )?;
+
+        links.set_value_at(
+            buffer,
+            MDRawModuleCrashpadInfoLink {
+                minidump_module_list_index: module.module_index,
+                location: info.location(),
+            },
+            idx,
+        )?;
+    }
+
+    let mut location = header.location();
+    location.data_size += links.location().data_size;
+    Ok(location)
+}
@@ -4,22 +4,27 @@ use {
         dso_debug::SectionDsoDebugError,
         maps_reader::MapsReaderError,
         minidump_writer::{
-            app_memory::SectionAppMemoryError, exception_stream::SectionExceptionStreamError,
+            app_memory::SectionAppMemoryError, cancellation::Interruption,
+            core_dump::CoreDumpError, crashpad_info_stream::SectionCrashpadInfoError,
+            exception_stream::SectionExceptionStreamError,
             handle_data_stream::SectionHandleDataStreamError, mappings::SectionMappingsError,
+            memory64_list_stream::SectionMem64ListError, memory_capture::SectionMemoryCaptureError,
             memory_info_list_stream::SectionMemInfoListError,
-            memory_list_stream::SectionMemListError, systeminfo_stream::SectionSystemInfoError,
+            memory_list_stream::SectionMemListError, self_dump::SelfDumpError,
+            size_limit::SizeLimitError, systeminfo_stream::SectionSystemInfoError,
             thread_list_stream::SectionThreadListError,
-            thread_names_stream::SectionThreadNamesError,
+            thread_names_stream::SectionThreadNamesError, user_stream::UserStreamCallbackError,
         },
         module_reader::ModuleReaderError,
         serializers::*,
+        thread_info::{ProcThreadState, ThreadInfoError},
         Pid,
     },
     crate::{dir_section::FileWriterError, mem_writer::MemoryWriterError, serializers::*},
     error_graph::ErrorList,
     nix::errno::Errno,
     procfs_core::ProcError,
-    std::ffi::OsString,
+    std::{ffi::OsString, path::PathBuf},
     thiserror::Error,
 };
 
@@ -30,6 +35,8 @@ use super::super::android::AndroidError;
 pub enum WriterError {
     #[error("Error during init phase")]
     InitError(#[from] InitError),
+    #[error(transparent)]
+    ProcessChanged(#[from] ProcessChangedError),
     #[error("Failed when writing section AppMemory")]
     SectionAppMemoryError(#[from] SectionAppMemoryError),
     #[error("Failed when writing section ExceptionStream")]
@@ -42,6 +49,8 @@ pub enum WriterError {
     SectionMemListError(#[from] SectionMemListError),
     #[error("Failed when writing section SystemInfo")]
     SectionSystemInfoError(#[from] SectionSystemInfoError),
+    #[error("Failed when writing section Memory64List")]
+    SectionMem64ListError(#[from] SectionMem64ListError),
     #[error("Failed when writing section MemoryInfoList")]
     SectionMemoryInfoListError(#[from] SectionMemInfoListError),
     #[error("Failed when writing section ThreadList")]
@@ -50,6 +59,12 @@ pub enum WriterError {
     SectionThreadNamesError(#[from] SectionThreadNamesError),
     #[error("Failed when writing section DsoDebug")]
     SectionDsoDebugError(#[from] SectionDsoDebugError),
+    #[error("Failed when writing section CrashpadInfo")]
+    SectionCrashpadInfoError(#[from] SectionCrashpadInfoError),
+    #[error("Failed when writing core dump")]
+    CoreDumpError(#[from] CoreDumpError),
+    #[error("Failed when dumping the current process")]
+    SelfDumpError(#[from] SelfDumpError),
     #[error("Failed to write to memory")]
     MemoryWriterError(#[from] MemoryWriterError),
     #[error("Failed to write to file")]
@@ -66,6 +81,24 @@ pub enum WriterError {
     ResumeThreadsErrors(#[source] ErrorList<WriterError>),
     #[error("Errors occurred while writing system info")]
     WriteSystemInfoErrors(#[source] ErrorList<SectionSystemInfoError>),
+    #[error("Errors occurred while capturing memory selected by capture rules")]
+    WriteCapturedMemoryErrors(#[source] ErrorList<SectionMemoryCaptureError>),
+    #[error("Data was trimmed to fit the minidump size limit")]
+    SizeLimitErrors(#[source] ErrorList<SizeLimitError>),
+    #[error("Errors occurred while writing core dump")]
+    WriteCoreDumpErrors(#[source] ErrorList<CoreDumpError>),
+    #[error("Errors occurred while writing Crashpad info")]
+    WriteCrashpadInfoErrors(#[source] ErrorList<SectionCrashpadInfoError>),
+    #[error("Errors occurred while writing the thread list")]
+    WriteThreadListErrors(#[source] ErrorList<SectionThreadListError>),
+    #[error("Errors occurred while writing app memory")]
+    WriteAppMemoryErrors(#[source] ErrorList<SectionAppMemoryError>),
+    #[error("Stream {stream:#x} is registered before stream {dependency:#x}, which it depends on")]
+    StreamOrder { stream: u32, dependency: u32 },
+    #[error("Failed writing stream {0:#x}")]
+    WriteStreamFailed(u32, #[source] Box<WriterError>),
+    #[error("Writing was interrupted ({0:?}), the minidump is truncated")]
+    Interrupted(Interruption),
     #[error("Failed writing cpuinfo")]
     WriteCpuInfoFailed(#[source] MemoryWriterError),
     #[error("Failed writing thread proc status")]
@@ -88,6 +121,13 @@ pub enum WriterError {
     WriteHandleDataStreamFailed(#[source] SectionHandleDataStreamError),
     #[error("Failed writing handle data stream direction entry")]
     WriteHandleDataStreamDirentFailed(#[source] FileWriterError),
+    #[error("Failed writing user stream {0:#x}")]
+    WriteUserStreamFailed(
+        u32,
+        #[source]
+        #[serde(serialize_with = "serialize_boxed_error")]
+        UserStreamCallbackError,
+    ),
     #[error("Failed to convert soft error list to JSON")]
     ConvertToJsonFailed(
         #[source]
@@ -101,6 +141,20 @@ pub enum WriterError {
         #[serde(serialize_with = "serialize_nix_error")]
         nix::Error,
     ),
+    #[error("nix::ptrace::seize(Pid={0}) failed")]
+    PtraceSeizeError(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("nix::ptrace::interrupt(Pid={0}) failed")]
+    PtraceInterruptError(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
     #[error("nix::ptrace::detach(Pid={0}) failed")]
     PtraceDetachError(
         Pid,
@@ -117,6 +171,14 @@ pub enum WriterError {
     ),
     #[error("Skipped thread {0} due to it being part of the seccomp sandbox's trusted code")]
     DetachSkippedThread(Pid),
+    #[error("Thread {0} didn't stop within the attach timeout")]
+    SuspendThreadTimeout(Pid),
+    #[error("Thread {} didn't stop within the attach timeout, its context is approximate", .0.tid)]
+    ThreadNotSuspended(ProcThreadState),
+    #[error("Thread {0} never stopped, it stays traced until the dumping thread exits")]
+    UnstoppedThreadStillTraced(Pid),
+    #[error("Failed to continue the process")]
+    ContinueProcessFailed(#[source] ContinueProcessError),
     #[error("Maps reader error")]
     MapsReaderError(#[from] MapsReaderError),
     #[error("Failed to get PAGE_SIZE from system")]
@@ -174,6 +236,14 @@ pub enum InitError {
     ),
     #[error("Ptrace does not function within the same process")]
     CannotPtraceSameProcess,
+    #[error("Failed to open a pidfd for the process, its pid is not checked for reuse")]
+    PidfdOpenFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error(transparent)]
+    ProcessChanged(#[from] ProcessChangedError),
     #[error("Failed to stop the target process")]
     StopProcessFailed(#[source] StopProcessError),
     #[error("Errors occurred while filling missing Auxv info")]
@@ -214,10 +284,24 @@ pub enum InitError {
     SuspendThreadsErrors(#[source] ErrorList<WriterError>),
     #[error("No threads left to suspend out of {0}")]
     SuspendNoThreadsLeft(usize),
+    #[error("Errors occurred while resuming the process")]
+    ResumeProcessErrors(#[source] ErrorList<WriterError>),
+    #[error("Failed to read the state of thread {0} from procfs")]
+    ReadThreadStateFailed(Pid, #[source] ThreadInfoError),
+    #[error("Failed to capture the context of thread {0} before resuming the process")]
+    CaptureThreadFailed(Pid, #[source] ThreadInfoError),
+    #[error("The threads were read from procfs, their contexts are approximate")]
+    ApproximateThreadContexts(Vec<ProcThreadState>),
     #[error("Crash thread does not reference principal mapping")]
     PrincipalMappingNotReferenced,
 }
 
+/// The process identified by the pidfd exited, so its pid may refer to a
+/// different process now
+#[derive(Debug, Error, serde::Serialize)]
+#[error("Process {0} exited or its pid was reused, noticed {1}")]
+pub struct ProcessChangedError(pub Pid, pub String);
+
 #[derive(Debug, thiserror::Error, serde::Serialize)]
 pub enum StopProcessError {
     #[error("Failed to stop the process")]
@@ -234,10 +318,32 @@ pub enum StopProcessError {
     ),
     #[error("Timeout waiting for process to stop")]
     Timeout,
+    #[error("Process {0} is not in a cgroup v2")]
+    NoCgroup(Pid),
+    #[error("The cgroup {0:?} contains the dumping process")]
+    SameCgroup(PathBuf),
+    #[error("Failed to access the cgroup file {0:?}")]
+    Cgroup(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
 }
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, thiserror::Error, serde::Serialize)]
 pub enum ContinueProcessError {
     #[error("Failed to continue the process")]
-    Continue(#[from] Errno),
+    Continue(
+        #[from]
+        #[serde(serialize_with = "serialize_nix_error")]
+        Errno,
+    ),
+    #[error("Failed to thaw the cgroup with {0:?}")]
+    Thaw(
+        PathBuf,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
 }
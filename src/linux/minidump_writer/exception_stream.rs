@@ -18,6 +18,13 @@ impl MinidumpWriter {
                 exception_address: context.inner.siginfo.ssi_addr,
                 ..Default::default()
             }
+        } else if let Some(signal) = self.core_file.as_ref().and_then(|core| core.signal) {
+            MDException {
+                exception_code: signal.signo,
+                exception_flags: signal.code as u32,
+                exception_address: signal.address,
+                ..Default::default()
+            }
         } else {
             let addr = match &self.crashing_thread_context {
                 CrashingThreadContext::CrashContextPlusAddress((_, addr)) => *addr,
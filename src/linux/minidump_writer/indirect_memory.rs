@@ -0,0 +1,135 @@
+use {
+    super::{
+        super::memory_capture::{referenced_regions, subtract, CaptureRegion},
+        size_limit::SizePriority,
+        *,
+    },
+    std::os::unix::ffi::OsStrExt,
+};
+
+impl MinidumpWriter {
+    /// Captures the memory around the addresses that pointer-sized values on
+    /// a copy of a thread stack point to, if enabled, see [`IndirectMemory`].
+    ///
+    /// Mappings are considered stacks if they are named `[stack]`, or contain
+    /// the stack being scanned or the stack pointer of any thread, see
+    /// [`Self::thread_stack_mappings`]. Memory that has already been captured
+    /// is left out, and memory that can't be read is skipped, as is the
+    /// memory after writing is interrupted.
+    ///
+    /// [`IndirectMemory`]: super::super::memory_capture::IndirectMemory
+    pub(super) fn capture_indirect_memory(
+        &mut self,
+        buffer: &mut DumpBuf,
+        thread_id: Pid,
+        stack: &[u8],
+        stack_start: usize,
+        stack_pointer_offset: usize,
+    ) {
+        let Some(options) = self.indirect_memory else {
+            return;
+        };
+        let mut used: usize = self.indirect_memory_regions.iter().map(|r| r.length).sum();
+        if used >= options.budget {
+            return;
+        }
+
+        let targets = self.indirect_memory_targets(stack_start, &self.thread_stack_mappings);
+
+        // Multiple threads often reference the same objects, and the windows
+        // may overlap memory captured for other reasons
+        let captured = self.captured_regions();
+        let regions: Vec<_> =
+            referenced_regions(stack, stack_pointer_offset, options.window_size, &targets)
+                .into_iter()
+                .flat_map(|region| {
+                    subtract(
+                        region.start_address,
+                        region.start_address + region.length,
+                        &captured,
+                    )
+                })
+                .collect();
+
+        for region in regions {
+            let remaining = options.budget - used;
+            if remaining == 0 || self.is_interrupted() {
+                break;
+            }
+            let length = self.fit_memory_region(
+                buffer,
+                SizePriority::AppMemory,
+                region.start_address,
+                region.length.min(remaining),
+            );
+            if length == 0 {
+                break;
+            }
+
+            let Ok(data_copy) = self.copy_memory(thread_id, region.start_address, length) else {
+                continue;
+            };
+
+            let section = MemoryArrayWriter::write_bytes(buffer, &data_copy);
+            self.memory_blocks.push(MDMemoryDescriptor {
+                start_of_memory_range: region.start_address as u64,
+                memory: section.location(),
+            });
+            self.indirect_memory_regions.push(CaptureRegion {
+                start_address: region.start_address,
+                length,
+            });
+            used += length;
+        }
+    }
+
+    /// Returns the mappings that indirectly referenced memory is captured
+    /// from, sorted by address: the writable mappings other than stacks.
+    /// Mappings are considered stacks if they are named `[stack]`, contain
+    /// `stack_start`, or start at one of `stack_mappings`.
+    pub(super) fn indirect_memory_targets(
+        &self,
+        stack_start: usize,
+        stack_mappings: &[usize],
+    ) -> Vec<CaptureRegion> {
+        let is_stack = |mapping: &MappingInfo| {
+            mapping
+                .name
+                .as_ref()
+                .is_some_and(|name| name.as_bytes().starts_with(b"[stack"))
+                || mapping.contains_address(stack_start)
+                || stack_mappings.contains(&mapping.system_mapping_info.start_address)
+        };
+
+        let mut targets: Vec<_> = self
+            .mappings
+            .iter()
+            .filter(|mapping| mapping.is_readable() && mapping.is_writable() && !is_stack(mapping))
+            .map(|mapping| CaptureRegion {
+                start_address: mapping.system_mapping_info.start_address,
+                length: mapping.system_mapping_info.end_address
+                    - mapping.system_mapping_info.start_address,
+            })
+            .collect();
+        targets.sort_by_key(|target| target.start_address);
+        targets
+    }
+
+    /// Returns the start addresses of the mappings that contain the stack
+    /// pointer of any thread, so that the stacks of threads that haven't been
+    /// written yet aren't captured as if they were heap memory
+    pub(super) fn thread_stack_mappings(&self) -> Vec<usize> {
+        (0..self.threads.len())
+            .filter_map(|idx| {
+                let stack_pointer = match &self.crash_context {
+                    Some(crash_context) if self.threads[idx].tid == self.blamed_thread => {
+                        crash_context.get_stack_pointer()
+                    }
+                    _ => self.get_thread_context_by_index(idx).ok()?.1,
+                };
+                let mapping = self.find_mapping_no_bias(stack_pointer)?;
+                Some(mapping.system_mapping_info.start_address)
+            })
+            .collect()
+    }
+}
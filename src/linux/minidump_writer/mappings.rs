@@ -3,6 +3,8 @@ use super::{
         maps_reader::MappingInfo,
         module_reader::{BuildId, ReadFromModule, SoName},
     },
+    size_limit::SizePriority,
+    snapshot::SnapshotModule,
     *,
 };
 
@@ -24,9 +26,59 @@ impl MinidumpWriter {
         buffer: &mut DumpBuf,
     ) -> Result<MDRawDirectory, SectionMappingsError> {
         let mut modules = Vec::new();
+        let mut module_list = Vec::new();
 
-        // First write all the mappings from the dumper
+        // First write all the mappings from the dumper, or the snapshot
+        let dumper_modules = match &self.snapshot {
+            Some(snapshot) => snapshot.modules.clone(),
+            None => self.read_modules(),
+        };
+        for module in dumper_modules {
+            let module_raw =
+                fill_raw_module(buffer, &module.mapping, &module.build_id, module.soname)?;
+            modules.push(module_raw);
+            module_list.push(module.mapping);
+        }
+
+        // Next write all the mappings provided by the caller
+        for user in &self.user_mapping_list {
+            // GUID was provided by caller.
+            let module = fill_raw_module(buffer, &user.mapping, &user.identifier, None)?;
+            modules.push(module);
+            module_list.push(user.mapping.clone());
+        }
+        self.module_list = module_list;
+
+        // The module list has been written, so it no longer needs any space
+        // reserved for it
+        let estimate = self.estimate_module_list_size();
+        self.release_size(SizePriority::ModuleList, estimate);
+
+        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, modules.len() as u32)?;
+
+        let mut dirent = MDRawDirectory {
+            stream_type: MDStreamType::ModuleListStream as u32,
+            location: list_header.location(),
+        };
+
+        if !modules.is_empty() {
+            let mapping_list = MemoryArrayWriter::<MDRawModule>::alloc_from_iter(buffer, modules)?;
+            dirent.location.data_size += mapping_list.location().data_size;
+        }
+
+        Ok(dirent)
+    }
+
+    /// Reads the build ids and sonames of the mappings that are modules,
+    /// skipping those without a build id and those in the user mapping list.
+    /// The mappings after writing is interrupted are left out.
+    pub fn read_modules(&self) -> Vec<SnapshotModule> {
+        let mut modules = Vec::new();
         for map_idx in 0..self.mappings.len() {
+            if self.is_interrupted() {
+                break;
+            }
+
             // If the mapping is uninteresting, or if
             // there is caller-provided information about this mapping
             // in the user_mapping_list list, skip it
@@ -38,7 +90,7 @@ impl MinidumpWriter {
             }
             log::debug!("retrieving build id for {:?}", &self.mappings[map_idx]);
             let BuildId(identifier) = self
-            .from_process_memory_for_index(map_idx)
+            .from_memory_for_mapping(&self.mappings[map_idx])
             .or_else(|e| {
                 // If the mapping has an associated name that is a file, try to read the build id
                 // from the file. If there is no note segment with the build id in
@@ -71,34 +123,45 @@ impl MinidumpWriter {
             // SONAME should always be accessible through program headers alone, so we don't really
             // need to fall back to trying to read from the mapping file.
             let soname = self
-                .from_process_memory_for_index(map_idx)
+                .from_memory_for_mapping(&self.mappings[map_idx])
                 .ok()
                 .map(|SoName(n)| n);
 
-            let module = fill_raw_module(buffer, &self.mappings[map_idx], &identifier, soname)?;
-            modules.push(module);
-        }
-
-        // Next write all the mappings provided by the caller
-        for user in &self.user_mapping_list {
-            // GUID was provided by caller.
-            let module = fill_raw_module(buffer, &user.mapping, &user.identifier, None)?;
-            modules.push(module);
+            modules.push(SnapshotModule {
+                mapping: self.mappings[map_idx].clone(),
+                build_id: identifier,
+                soname,
+            });
         }
+        modules
+    }
 
-        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, modules.len() as u32)?;
-
-        let mut dirent = MDRawDirectory {
-            stream_type: MDStreamType::ModuleListStream as u32,
-            location: list_header.location(),
+    /// An upper bound of the size of the module list, to reserve space for
+    /// it when the minidump has a size limit
+    pub(super) fn estimate_module_list_size(&self) -> usize {
+        // The CodeView record, with a build id of at most 64 bytes
+        const MAX_CV_RECORD_SIZE: usize = 4 + 64;
+        // The file name might be replaced by the SONAME
+        const NAME_MARGIN: usize = 64;
+
+        let module_size = |mapping: &MappingInfo| {
+            let name_len = mapping.name.as_ref().map_or(0, |name| name.len());
+            // The name is written as a length followed by UTF-16 with a nul
+            // terminator
+            let name_size = 4 + 2 * (name_len + NAME_MARGIN + 1);
+            std::mem::size_of::<MDRawModule>() + MAX_CV_RECORD_SIZE + name_size
         };
 
-        if !modules.is_empty() {
-            let mapping_list = MemoryArrayWriter::<MDRawModule>::alloc_from_iter(buffer, modules)?;
-            dirent.location.data_size += mapping_list.location().data_size;
-        }
-
-        Ok(dirent)
+        let modules: usize = self
+            .mappings
+            .iter()
+            .filter(|mapping| {
+                mapping.is_interesting() && !mapping.is_contained_in(&self.user_mapping_list)
+            })
+            .chain(self.user_mapping_list.iter().map(|user| &user.mapping))
+            .map(module_size)
+            .sum();
+        4 + modules
     }
 }
 fn fill_raw_module(
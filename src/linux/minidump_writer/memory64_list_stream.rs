@@ -0,0 +1,243 @@
+use {
+    super::{
+        super::mem_reader::MemReader,
+        size_limit::{SizeLimitError, SizePriority},
+        *,
+    },
+    crate::mem_writer::SpooledData,
+    procfs_core::process::{MMPermissions, MMapPath, MemoryMap},
+};
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum SectionMem64ListError {
+    #[error("Failed to write to memory")]
+    MemoryWriterError(#[from] MemoryWriterError),
+    #[error("Failed to read from procfs")]
+    ProcfsError(
+        #[from]
+        #[serde(serialize_with = "serialize_proc_error")]
+        procfs_core::ProcError,
+    ),
+    #[error("Failed to write the memory to a temporary file")]
+    SpoolFailed(
+        #[from]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+}
+
+/// The memory of every dumpable mapping, read into a temporary file while
+/// the process was frozen
+#[derive(Debug)]
+pub(super) struct SpooledMemory {
+    ranges: Vec<MDMemoryDescriptor64>,
+    data: SpooledData,
+}
+
+impl MinidumpWriter {
+    /// Write a Memory64ListStream containing every readable mapping of the
+    /// process.
+    ///
+    /// Only the list of memory ranges is written to the buffer, the memory
+    /// itself is in a temporary file, which is copied to the destination
+    /// after the rest of the minidump once it is finalized, so it is never
+    /// held in memory and can exceed 4GiB. It was read before the process was
+    /// resumed, see [`Self::spool_full_memory`], or is read here otherwise.
+    /// The ranges after writing is interrupted are left out.
+    pub fn write_memory64_list_stream(
+        &mut self,
+        buffer: &mut DumpBuf,
+    ) -> Result<MDRawDirectory, SectionMem64ListError> {
+        let spooled = self
+            .frozen
+            .as_mut()
+            .and_then(|frozen| frozen.full_memory.take());
+        let (ranges, spooled) = match spooled {
+            Some(SpooledMemory { ranges, data }) => (ranges, Some(data)),
+            None => (self.memory64_ranges()?, None),
+        };
+        let mut ranges = self.fit_memory64_ranges(buffer, ranges);
+
+        let spooled = match spooled {
+            // The ranges were only trimmed from the end, so their memory is
+            // at the start of what was read
+            Some(mut spooled) => {
+                spooled.truncate(ranges.iter().map(|range| range.data_size).sum());
+                spooled
+            }
+            None => {
+                let mut spooled = SpooledData::new()?;
+                let written = self
+                    .process_memory(ranges.clone())
+                    .write_to(&mut spooled, || self.is_interrupted())?;
+                ranges.truncate(written);
+                spooled
+            }
+        };
+
+        // MINIDUMP_MEMORY64_LIST, the number of ranges and the RVA of the
+        // memory of the first range, the memory of each range follows that of
+        // the previous one
+        let list_header = MemoryWriter::<u64>::alloc_with_val(buffer, ranges.len() as u64)?;
+        let base_rva = MemoryWriter::<MDRVA64>::alloc(buffer)?;
+
+        let mut dirent = MDRawDirectory {
+            stream_type: MDStreamType::Memory64ListStream as u32,
+            location: list_header.location(),
+        };
+        dirent.location.data_size += base_rva.location().data_size;
+
+        let range_list =
+            MemoryArrayWriter::<MDMemoryDescriptor64>::alloc_from_array(buffer, &ranges)?;
+        dirent.location.data_size += range_list.location().data_size;
+
+        buffer.append_trailing_data(base_rva, spooled);
+
+        Ok(dirent)
+    }
+
+    /// Reads the memory of every dumpable mapping into a temporary file, so
+    /// that the process can be resumed before the Memory64ListStream is
+    /// written. The ranges after writing is interrupted are left out.
+    pub(super) fn spool_full_memory(&self) -> Result<SpooledMemory, SectionMem64ListError> {
+        let mut ranges = self.memory64_ranges()?;
+        let mut data = SpooledData::new()?;
+        let written = self
+            .process_memory(ranges.clone())
+            .write_to(&mut data, || self.is_interrupted())?;
+        ranges.truncate(written);
+        Ok(SpooledMemory { ranges, data })
+    }
+
+    /// The ranges of the dumpable mappings
+    pub(super) fn memory64_ranges(
+        &self,
+    ) -> Result<Vec<MDMemoryDescriptor64>, SectionMem64ListError> {
+        let maps = self.read_memory_maps()?;
+        Ok(maps
+            .iter()
+            .filter(|mm| is_dumpable_mapping(mm))
+            .map(|mm| MDMemoryDescriptor64 {
+                start_of_memory_range: mm.address.0,
+                data_size: mm.address.1 - mm.address.0,
+            })
+            .collect())
+    }
+
+    pub(super) fn process_memory(&self, ranges: Vec<MDMemoryDescriptor64>) -> ProcessMemoryRanges {
+        ProcessMemoryRanges {
+            reader: self.frozen_mem_reader(self.process_id),
+            page_size: self.page_size,
+            ranges,
+        }
+    }
+
+    /// Trims the ranges from the end until the memory they contain, and their
+    /// descriptors, fit in the size limit
+    fn fit_memory64_ranges(
+        &mut self,
+        buffer: &DumpBuf,
+        mut ranges: Vec<MDMemoryDescriptor64>,
+    ) -> Vec<MDMemoryDescriptor64> {
+        let Some(budget) = &mut self.size_budget else {
+            return ranges;
+        };
+
+        let descriptor_size = std::mem::size_of::<MDMemoryDescriptor64>() as u64;
+        let requested: u64 = ranges.iter().map(|range| range.data_size).sum();
+        // The header of the list is the number of ranges and the base RVA
+        let mut available = budget
+            .available(buffer, SizePriority::AppMemory)
+            .saturating_sub(16);
+
+        let mut fitted = 0;
+        for range in &mut ranges {
+            if available <= descriptor_size {
+                break;
+            }
+            available -= descriptor_size;
+            range.data_size = range.data_size.min(available);
+            available -= range.data_size;
+            fitted += 1;
+        }
+        ranges.truncate(fitted);
+
+        let written = ranges.iter().map(|range| range.data_size).sum();
+        if written < requested {
+            budget.record(SizeLimitError::StreamTrimmed {
+                stream_type: MDStreamType::Memory64ListStream as u32,
+                requested,
+                written,
+            });
+        }
+        ranges
+    }
+}
+
+/// Whether the memory of a mapping can be read without side effects
+pub(super) fn is_dumpable_mapping(mm: &MemoryMap) -> bool {
+    mm.perms.contains(MMPermissions::READ)
+        && mm.address.1 > mm.address.0
+        && match &mm.pathname {
+            // These can't be read via process_vm_readv or /proc/<pid>/mem
+            MMapPath::Vvar | MMapPath::Vsyscall => false,
+            // Reading device memory can have side effects
+            MMapPath::Path(path) => !path.starts_with("/dev/"),
+            _ => true,
+        }
+}
+
+/// The memory of a list of ranges in the process
+pub(super) struct ProcessMemoryRanges {
+    reader: MemReader,
+    page_size: usize,
+    ranges: Vec<MDMemoryDescriptor64>,
+}
+
+impl ProcessMemoryRanges {
+    const CHUNK_SIZE: usize = 1024 * 1024;
+
+    /// Reads a chunk of memory, any pages that can't be read, eg. because the
+    /// mapping changed or is backed by a truncated file, are left zeroed so
+    /// that the memory of the following ranges stays at the expected offsets
+    fn read_chunk(&self, address: usize, chunk: &mut [u8]) {
+        if matches!(self.reader.read(address, chunk), Ok(len) if len == chunk.len()) {
+            return;
+        }
+
+        for (index, page) in chunk.chunks_mut(self.page_size).enumerate() {
+            let page_address = address + index * self.page_size;
+            match self.reader.read(page_address, page) {
+                Ok(len) => page[len..].fill(0),
+                Err(_) => page.fill(0),
+            }
+        }
+    }
+
+    /// Reads the memory of the ranges and writes it to the destination, one
+    /// after the other. Stops before the next range once `interrupted`
+    /// returns true, and returns the number of ranges written.
+    pub fn write_to(
+        &self,
+        destination: &mut dyn Write,
+        interrupted: impl Fn() -> bool,
+    ) -> std::io::Result<usize> {
+        let mut chunk = vec![0u8; Self::CHUNK_SIZE];
+
+        for (index, range) in self.ranges.iter().enumerate() {
+            if interrupted() {
+                return Ok(index);
+            }
+            let mut offset = 0;
+            while offset < range.data_size {
+                let len = (range.data_size - offset).min(Self::CHUNK_SIZE as u64) as usize;
+                let chunk = &mut chunk[..len];
+                self.read_chunk((range.start_of_memory_range + offset) as usize, chunk);
+                destination.write_all(chunk)?;
+                offset += len as u64;
+            }
+        }
+
+        Ok(self.ranges.len())
+    }
+}
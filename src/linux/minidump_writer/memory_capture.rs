@@ -0,0 +1,119 @@
+use super::{
+    super::memory_capture::{select_regions, CaptureRegion},
+    size_limit::SizePriority,
+    *,
+};
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum SectionMemoryCaptureError {
+    #[error("Failed to copy memory from process")]
+    CopyFromProcessError(#[from] CopyFromProcessError),
+    #[error("Only {read} of {length} bytes at {address:#x} could be read")]
+    PartialRead {
+        address: usize,
+        length: usize,
+        read: usize,
+    },
+}
+
+impl MinidumpWriter {
+    /// Write the memory of the mappings selected by the memory capture rules.
+    ///
+    /// Memory that is already captured, ie. thread stacks, is left out of the
+    /// selected mappings. If only the start of a region can be read, the bytes
+    /// that were read are kept. The regions after writing is interrupted are
+    /// left out.
+    pub fn write_captured_memory(
+        &mut self,
+        buffer: &mut DumpBuf,
+        mut soft_errors: impl WriteErrorList<SectionMemoryCaptureError>,
+    ) {
+        if self.memory_capture_rules.is_empty() {
+            return;
+        }
+
+        let captured = self.captured_regions();
+        let regions = select_regions(
+            &self.memory_capture_rules,
+            &self.unmerged_mappings(),
+            &captured,
+        );
+
+        for region in regions {
+            if self.is_interrupted() {
+                break;
+            }
+            let length = self.fit_memory_region(
+                buffer,
+                SizePriority::AppMemory,
+                region.start_address,
+                region.length,
+            );
+            if length == 0 {
+                break;
+            }
+
+            let mut data = vec![0u8; length];
+            let read = match self
+                .frozen_mem_reader(self.blamed_thread)
+                .read(region.start_address, &mut data)
+            {
+                Ok(0) => continue,
+                Ok(read) => read,
+                // PTRACE_PEEKDATA reads a word at a time, and fails with the
+                // offset of the first word it couldn't read
+                Err(e) if e.offset > 0 => e.offset,
+                Err(e) => {
+                    soft_errors.push(e.into());
+                    continue;
+                }
+            };
+            if read < length {
+                soft_errors.push(SectionMemoryCaptureError::PartialRead {
+                    address: region.start_address,
+                    length,
+                    read,
+                });
+            }
+
+            let section = MemoryArrayWriter::write_bytes(buffer, &data[..read]);
+            self.memory_blocks.push(MDMemoryDescriptor {
+                start_of_memory_range: region.start_address as u64,
+                memory: section.location(),
+            });
+        }
+    }
+
+    /// The mappings the memory capture rules are applied to, each one as the
+    /// system reports it. The merged mappings of modules may include reserved
+    /// gaps, and have the permissions of all of their parts.
+    pub(super) fn unmerged_mappings(&self) -> Vec<MappingInfo> {
+        self.memory_maps
+            .iter()
+            .filter_map(|mm| MappingInfo::from_memory_map(mm).ok())
+            .collect()
+    }
+
+    /// The regions of the memory blocks captured so far, sorted by address
+    /// and merged where they overlap or are adjacent
+    pub(super) fn captured_regions(&self) -> Vec<CaptureRegion> {
+        let mut captured: Vec<_> = self
+            .memory_blocks
+            .iter()
+            .map(|block| CaptureRegion {
+                start_address: block.start_of_memory_range as usize,
+                length: block.memory.data_size as usize,
+            })
+            .collect();
+        captured.sort_by_key(|block| block.start_address);
+        captured.dedup_by(|next, prev| {
+            let prev_end = prev.start_address + prev.length;
+            if next.start_address > prev_end {
+                return false;
+            }
+            prev.length = prev_end.max(next.start_address + next.length) - prev.start_address;
+            true
+        });
+        captured
+    }
+}
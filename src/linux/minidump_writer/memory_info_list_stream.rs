@@ -1,7 +1,7 @@
 use {
     super::*,
     minidump_common::format::{MemoryProtection, MemoryState, MemoryType},
-    procfs_core::{process::MMPermissions, FromRead},
+    procfs_core::process::MMPermissions,
 };
 
 #[derive(Debug, Error, serde::Serialize)]
@@ -22,10 +22,7 @@ impl MinidumpWriter {
         &mut self,
         buffer: &mut DumpBuf,
     ) -> Result<MDRawDirectory, SectionMemInfoListError> {
-        let maps = procfs_core::process::MemoryMaps::from_file(std::path::PathBuf::from(format!(
-            "/proc/{}/maps",
-            self.blamed_thread
-        )))?;
+        let maps = self.read_memory_maps()?;
 
         let list_header = MemoryWriter::alloc_with_val(
             buffer,
@@ -1,4 +1,4 @@
-use super::*;
+use super::{size_limit::SizePriority, *};
 
 #[derive(Debug, Error, serde::Serialize)]
 pub enum SectionMemListError {
@@ -24,6 +24,12 @@ impl MinidumpWriter {
 
         dirent.location.data_size += block_list.location().data_size;
 
+        // The space reserved for the descriptors has now been used
+        self.release_size(
+            SizePriority::Essential,
+            block_list.location().data_size as usize,
+        );
+
         Ok(dirent)
     }
 }
@@ -1,15 +1,19 @@
 use {
     super::{
+        annotations::{AnnotationBlob, Annotations, ANNOTATION_TYPE_BLOB},
         app_memory::AppMemoryList,
         auxv::AuxvDumpInfo,
+        core_file::CoreFile,
         crash_context::CrashContext,
         dso_debug,
         dumper_cpu_info::CpuInfoError,
         maps_reader::{MappingInfo, MappingList, MapsReaderError},
-        mem_reader::CopyFromProcessError,
+        mem_reader::{CopyFromProcessError, MemReader},
+        memory_capture::{CaptureRegion, IndirectMemory, MemoryCaptureRule, RegisterMemory},
         module_reader,
+        privacy::{CmdLineFilter, EnvironFilter},
         serializers::*,
-        thread_info::{ThreadInfo, ThreadInfoError},
+        thread_info::{mark_context_approximate, ProcThreadState, ThreadInfo, ThreadInfoError},
         Pid,
     },
     crate::{
@@ -17,25 +21,34 @@ use {
         mem_writer::{
             write_string_to_location, Buffer, MemoryArrayWriter, MemoryWriter, MemoryWriterError,
         },
+        minidump_cpu::RawContextCPU,
         minidump_format::*,
         serializers::*,
     },
+    cancellation::{CancellationToken, Interruption},
     error_graph::{ErrorList, WriteErrorList},
-    errors::{ContinueProcessError, InitError, StopProcessError, WriterError},
+    errors::{ContinueProcessError, InitError, ProcessChangedError, StopProcessError, WriterError},
     failspot::failspot,
     nix::{
         errno::Errno,
         sys::{ptrace, signal, wait},
     },
     procfs_core::{
-        process::{MMPermissions, ProcState, Stat},
+        process::{MMPermissions, MemoryMap, MemoryMaps, ProcState, Stat},
         FromRead,
     },
+    report::{CoreDumpReport, StreamReport, StreamStatus, StreamTimer, WriteReport},
+    size_limit::{SizeBudget, SizePriority},
+    snapshot::{FrozenState, MemoryRegions, ProcessSnapshot},
     std::{
         io::{Seek, Write},
-        path,
+        os::fd::OwnedFd,
+        path::{self, PathBuf},
+        sync::{Arc, Mutex, OnceLock},
         time::{Duration, Instant},
     },
+    stop_strategy::StopStrategy,
+    streams::StreamRegistry,
     thiserror::Error,
 };
 
@@ -47,18 +60,33 @@ use super::thread_info;
 pub use super::auxv::{AuxvType, DirectAuxvDumpInfo};
 
 pub mod app_memory;
+pub mod cancellation;
+pub mod core_dump;
+pub mod crashpad_info_stream;
 pub mod errors;
 pub mod exception_stream;
 pub mod handle_data_stream;
+pub mod indirect_memory;
 pub mod mappings;
+pub mod memory64_list_stream;
+pub mod memory_capture;
 pub mod memory_info_list_stream;
 pub mod memory_list_stream;
+pub(crate) mod pidfd;
+pub mod register_memory;
+pub mod report;
+pub mod self_dump;
+pub mod size_limit;
+pub mod snapshot;
+pub mod stop_strategy;
+pub mod streams;
 pub mod systeminfo_stream;
 pub mod thread_list_stream;
 pub mod thread_names_stream;
+pub mod user_stream;
 
-/// The default timeout after a `SIGSTOP` after which minidump writing proceeds
-/// regardless of the process state
+/// The default timeout for the process to stop, after which minidump writing
+/// proceeds regardless of the process state
 pub const STOP_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[cfg(target_pointer_width = "32")]
@@ -81,7 +109,24 @@ pub struct MinidumpWriterConfig {
     crash_context: Option<CrashContext>,
     crashing_thread_context: CrashingThreadContext,
     stop_timeout: Duration,
+    thread_attach_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    cancellation_token: Option<CancellationToken>,
     direct_auxv_dump_info: Option<DirectAuxvDumpInfo>,
+    streams: StreamRegistry,
+    environ_filter: Option<EnvironFilter>,
+    cmdline_filter: Option<CmdLineFilter>,
+    annotations: Annotations,
+    memory_capture_rules: Vec<MemoryCaptureRule>,
+    indirect_memory: Option<IndirectMemory>,
+    register_memory: Option<RegisterMemory>,
+    core_file: Option<Arc<CoreFile>>,
+    snapshot: Option<ProcessSnapshot>,
+    non_invasive: bool,
+    resilient: bool,
+    stop_strategy: StopStrategy,
+    traced_by_caller: bool,
+    pidfd: Option<OwnedFd>,
 }
 
 #[derive(Debug)]
@@ -97,6 +142,14 @@ pub struct MinidumpWriter {
     pub user_mapping_list: MappingList,
     pub crashing_thread_context: CrashingThreadContext,
     stop_timeout: Duration,
+    thread_attach_timeout: Option<Duration>,
+    /// The threads that were attached to but didn't stop within the attach
+    /// timeout, they are detached once they have stopped
+    unstopped_threads: Vec<Pid>,
+    deadline: Option<Instant>,
+    cancellation_token: Option<CancellationToken>,
+    /// Why writing stopped early, if it did
+    interruption: OnceLock<Interruption>,
     pub skip_stacks_if_mapping_unreferenced: bool,
     principal_mapping_address: Option<usize>,
     pub principal_mapping: Option<MappingInfo>,
@@ -104,12 +157,79 @@ pub struct MinidumpWriter {
     pub crash_context: Option<CrashContext>,
     pub app_memory: AppMemoryList,
     pub memory_blocks: Vec<MDMemoryDescriptor>,
+    streams: StreamRegistry,
+    pub environ_filter: Option<EnvironFilter>,
+    pub cmdline_filter: Option<CmdLineFilter>,
+    pub annotations: Annotations,
+    /// The mappings of the process as they were listed when it was stopped,
+    /// unlike [`Self::mappings`] they aren't merged
+    pub memory_maps: Vec<MemoryMap>,
+    /// The mappings written to the module list, in order. Only populated
+    /// once the module list stream has been written.
+    pub module_list: Vec<MappingInfo>,
+    pub memory_capture_rules: Vec<MemoryCaptureRule>,
+    pub indirect_memory: Option<IndirectMemory>,
+    /// The memory captured so far because it was referenced from a stack
+    indirect_memory_regions: Vec<CaptureRegion>,
+    /// The mappings that contain the stack of a thread, see
+    /// [`Self::thread_stack_mappings`]
+    thread_stack_mappings: Vec<usize>,
+    pub register_memory: Option<RegisterMemory>,
+    size_budget: Option<SizeBudget>,
+    /// The stacks with this priority or lower are left out of the thread
+    /// list, when it doesn't fit in the size limit otherwise
+    dropped_stacks: Option<SizePriority>,
+    /// The core file the process state is read from, rather than the live
+    /// process
+    pub core_file: Option<Arc<CoreFile>>,
+    /// The snapshot the process state is read from, rather than the live
+    /// process
+    pub snapshot: Option<ProcessSnapshot>,
+    /// Where the memory read from the modules is recorded while collecting
+    /// a snapshot
+    recorded_memory: Option<Arc<Mutex<MemoryRegions>>>,
+    /// The thread contexts and memory captured while the process was
+    /// frozen, which are read instead of the process once it is resumed
+    frozen: Option<FrozenState>,
+    /// Whether the threads are read from procfs rather than with ptrace, in
+    /// which case their contexts are approximate
+    pub non_invasive: bool,
+    /// Whether streams and threads that fail to be written are skipped and
+    /// recorded in the soft errors, rather than failing the whole minidump
+    pub resilient: bool,
+    /// How the process was kept from running while it was dumped, which is
+    /// recorded in the soft errors
+    pub stop_strategy: StopStrategy,
+    /// Whether the threads are already traced and stopped by the calling
+    /// thread
+    traced_by_caller: bool,
+    /// Whether the process was stopped, it is continued on drop
+    process_stopped: bool,
+    /// When the process was stopped or started being suspended
+    pause_started: Option<Instant>,
+    /// How long the process was paused for, if it was resumed before the
+    /// minidump was written
+    pub paused_for: Option<Duration>,
+    /// The type of the stream that kept the process from being resumed
+    /// before the minidump was written
+    pub resume_deferred_by: Option<u32>,
+    /// The cgroup frozen by [`StopStrategy::CgroupFreeze`], it is thawed on
+    /// drop
+    frozen_cgroup: Option<PathBuf>,
+    /// Identifies the process, to notice if its pid is reused while dumping
+    pidfd: Option<OwnedFd>,
+    /// Whether this is the current process, whose threads are suspended by
+    /// a helper, see [`MinidumpWriterConfig::write_current_process`]
+    current_process: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Thread {
     pub tid: Pid,
     pub name: Option<String>,
+    /// The state of the thread read from procfs, in non-invasive mode or if
+    /// it didn't stop within the attach timeout
+    pub proc_state: Option<ProcThreadState>,
 }
 
 #[derive(Debug, Default)]
@@ -136,10 +256,82 @@ impl MinidumpWriterConfig {
             crash_context: Default::default(),
             crashing_thread_context: Default::default(),
             stop_timeout: STOP_TIMEOUT,
+            thread_attach_timeout: None,
+            deadline: None,
+            cancellation_token: None,
             direct_auxv_dump_info: Default::default(),
+            streams: Default::default(),
+            environ_filter: Default::default(),
+            cmdline_filter: Default::default(),
+            annotations: Default::default(),
+            memory_capture_rules: Default::default(),
+            indirect_memory: Default::default(),
+            register_memory: Default::default(),
+            core_file: Default::default(),
+            snapshot: Default::default(),
+            non_invasive: false,
+            resilient: false,
+            stop_strategy: Default::default(),
+            traced_by_caller: false,
+            pidfd: None,
         }
     }
 
+    /// Creates a config that writes a minidump of the process recorded in an
+    /// ELF core file, rather than of a live process. The thread that received
+    /// the signal that caused the core to be written is the blamed thread.
+    ///
+    /// The streams copied from `/proc/<pid>` files that are not in the core
+    /// file are not written, as the pid may have been reused since, nor are
+    /// the streams describing this system, as the core file may have been
+    /// written on another one. The system info only has the platform and the
+    /// CPU architecture.
+    pub fn from_core_file(core_file: CoreFile) -> Self {
+        let blamed_thread = core_file
+            .threads
+            .first()
+            .map_or(core_file.process_id, |thread| thread.tid);
+        let mut config = Self::new(core_file.process_id, blamed_thread);
+        for stream in [
+            MDStreamType::LinuxProcStatus,
+            MDStreamType::LinuxCmdLine,
+            MDStreamType::LinuxEnviron,
+            MDStreamType::MozLinuxLimits,
+            MDStreamType::HandleDataStream,
+            MDStreamType::LinuxCpuInfo,
+            MDStreamType::LinuxLsbRelease,
+        ] {
+            config.streams.remove(stream as u32);
+        }
+        config.streams.remove(stop_strategy::STOP_STRATEGY_STREAM);
+        config.core_file = Some(Arc::new(core_file));
+        config
+    }
+
+    /// Creates a config that writes a minidump of a snapshot collected with
+    /// [`Self::snapshot`], rather than of a live process. The crash context
+    /// and soft errors are taken from the snapshot.
+    ///
+    /// The memory written is limited to the memory in the snapshot, so
+    /// options that capture memory should match those used when collecting
+    /// it. The `HandleDataStream` is not written, as the pid may have been
+    /// reused since.
+    pub fn from_snapshot(mut snapshot: ProcessSnapshot) -> Self {
+        let mut config = Self::new(snapshot.process_id, snapshot.blamed_thread);
+        config.streams.remove(MDStreamType::HandleDataStream as u32);
+        config.crash_context = snapshot.crash_context.take();
+        config.non_invasive = snapshot.non_invasive;
+        config.stop_strategy = snapshot.stop_strategy;
+        config.snapshot = Some(snapshot);
+        config
+    }
+
+    /// Limits the size of the minidump, excluding the trailing data written
+    /// by [`Self::full_memory`] unless it is trimmed to fit.
+    ///
+    /// Data is trimmed or dropped in the order of [`size_limit::SizePriority`]
+    /// until the minidump fits, and what was trimmed is recorded in the
+    /// `MozSoftErrors` stream.
     pub fn set_minidump_size_limit(&mut self, limit: u64) -> &mut Self {
         self.minidump_size_limit = Some(limit);
         self
@@ -177,12 +369,102 @@ impl MinidumpWriterConfig {
 
     /// Sets the timeout after `SIGSTOP` is sent to the process, if the process
     /// has not stopped by the time the timeout has reached, we proceed with
-    /// minidump generation
+    /// minidump generation. It also applies to freezing its cgroup.
     pub fn stop_timeout(&mut self, duration: Duration) -> &mut Self {
         self.stop_timeout = duration;
         self
     }
 
+    /// Sets how long to wait for each thread to stop when attaching to it,
+    /// by default there is no limit. A thread that is in uninterruptible
+    /// sleep, eg. on a hung NFS read, doesn't stop until it wakes up.
+    ///
+    /// A thread that doesn't stop in time is still written to the thread
+    /// list, with the approximate context of [`Self::non_invasive`], and its
+    /// procfs state, including its `wchan` and kernel stack, is recorded in
+    /// the soft errors. If it was attached to, it is detached if it has
+    /// stopped by the time the threads are resumed, otherwise it stays traced
+    /// until the thread that wrote the minidump exits.
+    pub fn set_thread_attach_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.thread_attach_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets when writing the minidump has to be finished by. The deadline is
+    /// checked between threads, mappings and streams, and once it has passed
+    /// the rest of them are left out. The minidump that was written so far is
+    /// still finalized, and records that it was truncated in the soft errors.
+    pub fn set_deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets a token that cancels writing the minidump, with the same effect
+    /// as the deadline passing
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Sets how the process is kept from running while it is dumped, by
+    /// default with `SIGSTOP`. The strategy used is recorded in the
+    /// [`stop_strategy::STOP_STRATEGY_STREAM`] and the
+    /// [`WriteReport::stop_strategy`], it is [`StopStrategy::None`] when the
+    /// threads are not suspended with ptrace.
+    pub fn set_stop_strategy(&mut self, stop_strategy: StopStrategy) -> &mut Self {
+        self.stop_strategy = stop_strategy;
+        self
+    }
+
+    /// Dumps the process without stopping it or attaching to its threads with
+    /// ptrace, for when ptrace isn't permitted, eg. by Yama or in containers
+    /// without `CAP_SYS_PTRACE`. This mode is also used if attaching fails for
+    /// every thread.
+    ///
+    /// Only the stack and instruction pointers of the threads are known, if
+    /// reading `/proc/<pid>/task/<tid>/syscall` is permitted. Their contexts
+    /// are marked as approximate by only having the control registers set in
+    /// the context flags, and the procfs state of each thread is recorded in
+    /// the soft errors. Memory is read where permitted.
+    pub fn non_invasive(&mut self) -> &mut Self {
+        self.non_invasive = true;
+        self
+    }
+
+    /// Writes as much of the minidump as possible. A stream that fails to be
+    /// written gets an empty directory entry, and a thread whose stack or
+    /// context can't be read is left out of the thread list, as are app
+    /// memory regions that can't be read. The errors are recorded in the soft
+    /// errors instead of failing the whole minidump.
+    ///
+    /// Failing to suspend the process or noticing that it changed still
+    /// fails the minidump.
+    pub fn resilient(&mut self) -> &mut Self {
+        self.resilient = true;
+        self
+    }
+
+    /// Identifies the process with a pidfd, e.g. one opened right after
+    /// spawning it, or received from it over a socket. By default one is
+    /// opened for the pid when writing starts.
+    ///
+    /// The process is checked to still exist after every phase of dumping,
+    /// as its pid could refer to a different process once it has exited, and
+    /// writing fails with [`ProcessChangedError`] otherwise. The signals that
+    /// stop and continue the process are sent through the pidfd.
+    pub fn set_pidfd(&mut self, pidfd: OwnedFd) -> &mut Self {
+        self.pidfd = Some(pidfd);
+        self
+    }
+
+    /// Dumps a process whose threads are all traced by the calling thread and
+    /// in a ptrace-stop, eg. in a debugger or crash catcher. The process isn't
+    /// stopped and the threads aren't attached to, or detached from after.
+    pub fn traced_by_caller(&mut self) -> &mut Self {
+        self.traced_by_caller = true;
+        self
+    }
+
     /// Directly set important Auxv info determined by the crashing process
     ///
     /// Since `/proc/{pid}/auxv` can sometimes be inaccessible, the calling process should prefer to transfer this
@@ -197,18 +479,191 @@ impl MinidumpWriterConfig {
         self.direct_auxv_dump_info = Some(direct_auxv_dump_info);
         self
     }
+
+    /// Filters the process environment before it is written to the
+    /// `LinuxEnviron` stream. The copy of the environment in the memory of the
+    /// process is not filtered, see [`privacy`](super::privacy).
+    pub fn set_environ_filter(&mut self, environ_filter: EnvironFilter) -> &mut Self {
+        self.environ_filter = Some(environ_filter);
+        self
+    }
+
+    /// Filters the process command line before it is written to the
+    /// `LinuxCmdLine` stream. The copy of the command line in the memory of
+    /// the process is not filtered, see [`privacy`](super::privacy).
+    pub fn set_cmdline_filter(&mut self, cmdline_filter: CmdLineFilter) -> &mut Self {
+        self.cmdline_filter = Some(cmdline_filter);
+        self
+    }
+
+    /// Adds a key/value annotation, annotations are written to the
+    /// `CrashpadInfoStream` in the order they are added
+    pub fn add_annotation(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> &mut Self {
+        self.annotations.simple.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a named binary annotation for the main executable, see
+    /// [`Annotations`] for where it is written in the minidump
+    pub fn add_annotation_blob(
+        &mut self,
+        name: impl Into<String>,
+        data: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.annotations.blobs.push(AnnotationBlob {
+            name: name.into(),
+            ty: ANNOTATION_TYPE_BLOB,
+            data: data.into(),
+            module_address: None,
+        });
+        self
+    }
+
+    /// Adds a named binary annotation for the module containing
+    /// `module_address`, eg. a shared library
+    pub fn add_module_annotation_blob(
+        &mut self,
+        module_address: usize,
+        name: impl Into<String>,
+        data: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.annotations.blobs.push(AnnotationBlob {
+            name: name.into(),
+            ty: ANNOTATION_TYPE_BLOB,
+            data: data.into(),
+            module_address: Some(module_address),
+        });
+        self
+    }
+
+    /// Replaces all annotations previously added
+    pub fn set_annotations(&mut self, annotations: Annotations) -> &mut Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Adds a rule selecting mappings whose memory is copied into the
+    /// `MemoryListStream`
+    pub fn add_memory_capture_rule(&mut self, rule: MemoryCaptureRule) -> &mut Self {
+        self.memory_capture_rules.push(rule);
+        self
+    }
+
+    /// Replaces all memory capture rules previously added
+    pub fn set_memory_capture_rules(&mut self, rules: Vec<MemoryCaptureRule>) -> &mut Self {
+        self.memory_capture_rules = rules;
+        self
+    }
+
+    /// Captures the memory referenced by pointers on thread stacks into the
+    /// `MemoryListStream`. Off by default.
+    pub fn capture_indirect_memory(&mut self, options: IndirectMemory) -> &mut Self {
+        self.indirect_memory = Some(options);
+        self
+    }
+
+    /// Captures the memory around the addresses in the registers of the
+    /// crashing thread, and the fault address, into the `MemoryListStream`.
+    /// Off by default.
+    pub fn capture_register_memory(&mut self, options: RegisterMemory) -> &mut Self {
+        self.register_memory = Some(options);
+        self
+    }
+
+    /// Writes every readable mapping of the process to a `Memory64ListStream`,
+    /// in addition to the memory written to the `MemoryListStream`. Off by
+    /// default.
+    ///
+    /// The memory is read into a temporary file before the process is
+    /// resumed, and copied to the destination after the rest of the minidump,
+    /// so the minidump returned by [`Self::write`] does not contain it.
+    pub fn full_memory(&mut self) -> &mut Self {
+        let stream_type = MDStreamType::Memory64ListStream as u32;
+        if !self.streams.contains(stream_type) {
+            self.streams.push(streams::BuiltinStream::Memory64List);
+        }
+        self
+    }
+
+    /// Replaces the set of streams that will be written to the minidump
+    ///
+    /// By default all [`streams::BuiltinStream`]s are written.
+    pub fn set_stream_registry(&mut self, streams: StreamRegistry) -> &mut Self {
+        self.streams = streams;
+        self
+    }
+
+    /// Allows reordering, removing or replacing the streams that will be
+    /// written to the minidump
+    pub fn stream_registry(&mut self) -> &mut StreamRegistry {
+        &mut self.streams
+    }
+
+    /// Adds a caller provided stream, which is written after the streams that
+    /// are currently registered
+    pub fn add_user_stream(&mut self, stream: user_stream::UserStream) -> &mut Self {
+        self.streams.push(stream);
+        self
+    }
+
     /// Generates a minidump and writes to the destination provided. Returns the in-memory
-    /// version of the minidump as well.
-    pub fn write(self, destination: &mut (impl Write + Seek)) -> Result<Vec<u8>, WriterError> {
+    /// version of the minidump as well, along with the soft errors and what was
+    /// written for each stream.
+    ///
+    /// The process is resumed as soon as the thread contexts and the memory
+    /// the thread list, memory list and memory64 list streams need have been
+    /// captured, see [`WriteReport::paused_for`]. The other streams are
+    /// written while it runs, unless one of them needs it frozen, such as a
+    /// user stream callback, see [`WriteReport::resume_deferred_by`].
+    pub fn write(self, destination: &mut (impl Write + Seek)) -> Result<WriteReport, WriterError> {
+        let mut soft_errors = ErrorList::default();
+
+        let mut writer = self.build();
+        let mut init_errors = soft_errors.subwriter(WriterError::InitErrors);
+        writer.init(&mut init_errors)?;
+        writer.capture_and_resume(&mut init_errors, true)?;
+        drop(init_errors);
+
+        writer.write_report(destination, soft_errors)
+    }
+
+    /// Writes an ELF core file of the process to the destination provided,
+    /// instead of a minidump, for opening in gdb or lldb. Returns the errors
+    /// that didn't prevent writing it, and its size.
+    pub fn write_core_dump(
+        self,
+        destination: &mut impl Write,
+    ) -> Result<CoreDumpReport, WriterError> {
         let mut soft_errors = ErrorList::default();
 
         let mut writer = self.build();
         writer.init(soft_errors.subwriter(WriterError::InitErrors))?;
 
-        let mut buffer = Buffer::with_capacity(0);
-        writer.write_dump(&mut buffer, destination, soft_errors)?;
-        Ok(buffer.into())
+        let size = writer.write_core_dump(
+            destination,
+            soft_errors.subwriter(WriterError::WriteCoreDumpErrors),
+        )?;
+        writer.check_process("after writing the core dump")?;
+        Ok(CoreDumpReport { soft_errors, size })
     }
+
+    /// Collects a snapshot of the process to write a minidump of later with
+    /// [`Self::from_snapshot`], and resumes the process. Its memory is what a
+    /// minidump written with this config would contain, along with the
+    /// memory the Crashpad annotations and the DSO debug info are read from.
+    pub fn snapshot(self) -> Result<ProcessSnapshot, WriterError> {
+        let mut soft_errors = ErrorList::default();
+
+        let mut writer = self.build();
+        writer.init(&mut soft_errors)?;
+
+        writer.collect_snapshot(soft_errors)
+    }
+
     /// Allows testing code to inspect the pre-output state of the MinidumpWriter
     pub fn build_for_testing(
         self,
@@ -236,6 +691,11 @@ impl MinidumpWriterConfig {
             user_mapping_list: self.user_mapping_list,
             crashing_thread_context: self.crashing_thread_context,
             stop_timeout: self.stop_timeout,
+            thread_attach_timeout: self.thread_attach_timeout,
+            unstopped_threads: Default::default(),
+            deadline: self.deadline,
+            cancellation_token: self.cancellation_token,
+            interruption: OnceLock::new(),
             skip_stacks_if_mapping_unreferenced: self.skip_stacks_if_mapping_unreferenced,
             principal_mapping_address: self.principal_mapping_address,
             principal_mapping: self.principal_mapping,
@@ -243,6 +703,34 @@ impl MinidumpWriterConfig {
             crash_context: self.crash_context,
             app_memory: self.app_memory,
             memory_blocks: self.memory_blocks,
+            streams: self.streams,
+            environ_filter: self.environ_filter,
+            cmdline_filter: self.cmdline_filter,
+            annotations: self.annotations,
+            memory_maps: Default::default(),
+            module_list: Default::default(),
+            memory_capture_rules: self.memory_capture_rules,
+            indirect_memory: self.indirect_memory,
+            indirect_memory_regions: Default::default(),
+            thread_stack_mappings: Default::default(),
+            register_memory: self.register_memory,
+            size_budget: self.minidump_size_limit.map(SizeBudget::new),
+            dropped_stacks: None,
+            core_file: self.core_file,
+            snapshot: self.snapshot,
+            recorded_memory: None,
+            frozen: None,
+            non_invasive: self.non_invasive,
+            resilient: self.resilient,
+            stop_strategy: self.stop_strategy,
+            traced_by_caller: self.traced_by_caller,
+            process_stopped: false,
+            pause_started: None,
+            paused_for: None,
+            resume_deferred_by: None,
+            frozen_cgroup: None,
+            pidfd: self.pidfd,
+            current_process: false,
         }
     }
 }
@@ -250,14 +738,49 @@ impl MinidumpWriterConfig {
 impl MinidumpWriter {
     // TODO: late_init for chromeos and android
     fn init(&mut self, mut soft_errors: impl WriteErrorList<InitError>) -> Result<(), InitError> {
-        if self.process_id == std::process::id() as i32 {
+        if let Some(core_file) = self.core_file.clone() {
+            return self.init_from_core(&core_file, soft_errors);
+        }
+        if let Some(mut snapshot) = self.snapshot.take() {
+            let result = self.init_from_snapshot(&mut snapshot, soft_errors);
+            self.snapshot = Some(snapshot);
+            return result;
+        }
+
+        if self.process_id == std::process::id() as i32 && !self.current_process {
             return Err(InitError::CannotPtraceSameProcess);
         }
 
+        // The process is checked after every phase, which also covers the
+        // start of the next one
+        if self.pidfd.is_none() {
+            match pidfd::open(self.process_id) {
+                Ok(pidfd) => self.pidfd = Some(pidfd),
+                Err(e) => soft_errors.push(InitError::PidfdOpenFailed(e)),
+            }
+        }
+        self.check_process("before stopping it")?;
+
+        if self.non_invasive || self.traced_by_caller {
+            self.stop_strategy = StopStrategy::None;
+        }
+
+        self.page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?
+            .expect("page size apparently unlimited: doesn't make sense.")
+            as usize;
+
         // Stopping the process is best-effort.
+        self.pause_started = Some(Instant::now());
         if let Err(e) = self.stop_process(self.stop_timeout) {
             soft_errors.push(InitError::StopProcessFailed(e));
         }
+        self.check_process("after stopping it")?;
+
+        // Nothing more is read once writing is interrupted, as every stream is
+        // skipped anyway
+        if self.is_interrupted() {
+            return Ok(());
+        }
 
         // Even if we completely fail to fill in any additional Auxv info, we can still press
         // forward.
@@ -275,29 +798,107 @@ impl MinidumpWriter {
         {
             soft_errors.push(InitError::EnumerateThreadsFailed(Box::new(e)));
         }
+        self.check_process("after enumerating its threads")?;
 
         // Same with mappings -- Some information is still better than no information!
         if let Err(e) = self.enumerate_mappings() {
             soft_errors.push(InitError::EnumerateMappingsFailed(Box::new(e)));
         }
+        self.check_process("after enumerating its mappings")?;
+        if self.is_interrupted() {
+            return Ok(());
+        }
 
-        self.page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?
-            .expect("page size apparently unlimited: doesn't make sense.")
-            as usize;
+        // The threads of the current process are suspended by the helper
+        if !self.current_process {
+            self.suspend_threads_or_fall_back(&mut soft_errors)?;
+            if self.is_interrupted() {
+                return Ok(());
+            }
+        }
 
+        #[cfg(target_os = "android")]
+        {
+            late_process_mappings(self.process_id, &mut self.mappings)?;
+        }
+
+        self.find_principal_mapping(soft_errors);
+        self.check_process("after initializing")?;
+
+        Ok(())
+    }
+
+    /// Suspends the threads, or reads their state from procfs in
+    /// non-invasive mode or if none of them could be suspended
+    fn suspend_threads_or_fall_back(
+        &mut self,
+        mut soft_errors: impl WriteErrorList<InitError>,
+    ) -> Result<(), InitError> {
         let threads_count = self.threads.len();
 
-        self.suspend_threads(soft_errors.subwriter(InitError::SuspendThreadsErrors));
+        if !self.non_invasive && !self.traced_by_caller {
+            let threads = self.threads.clone();
+            self.suspend_threads(soft_errors.subwriter(InitError::SuspendThreadsErrors));
 
-        if self.threads.is_empty() {
-            soft_errors.push(InitError::SuspendNoThreadsLeft(threads_count));
+            if self.is_interrupted() {
+                return Ok(());
+            }
+            if self.threads.is_empty() {
+                soft_errors.push(InitError::SuspendNoThreadsLeft(threads_count));
+                // The threads can still be read from procfs
+                self.threads = threads;
+                self.threads_suspended = false;
+                self.non_invasive = true;
+                if self.stop_strategy == StopStrategy::PtraceInterrupt {
+                    self.stop_strategy = StopStrategy::None;
+                }
+            }
+            self.check_process("after suspending its threads")?;
         }
 
-        #[cfg(target_os = "android")]
-        {
-            late_process_mappings(self.process_id, &mut self.mappings)?;
+        if self.non_invasive {
+            self.read_proc_thread_states(&mut soft_errors);
         }
+        Ok(())
+    }
 
+    /// Initializes the state from a core file, there is no process to stop
+    /// or threads to suspend
+    fn init_from_core(
+        &mut self,
+        core_file: &CoreFile,
+        mut soft_errors: impl WriteErrorList<InitError>,
+    ) -> Result<(), InitError> {
+        self.stop_strategy = StopStrategy::None;
+        self.auxv.fill_missing_info_from(
+            core_file.auxv.as_slice(),
+            soft_errors.subwriter(InitError::FillMissingAuxvInfoErrors),
+        );
+
+        self.threads = core_file
+            .threads
+            .iter()
+            .map(|thread| Thread {
+                tid: thread.tid,
+                name: thread.name.clone(),
+                proc_state: None,
+            })
+            .collect();
+
+        if let Err(e) = self.enumerate_mappings() {
+            soft_errors.push(InitError::EnumerateMappingsFailed(Box::new(e)));
+        }
+
+        self.page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?
+            .expect("page size apparently unlimited: doesn't make sense.")
+            as usize;
+
+        self.find_principal_mapping(soft_errors);
+
+        Ok(())
+    }
+
+    fn find_principal_mapping(&mut self, mut soft_errors: impl WriteErrorList<InitError>) {
         if self.skip_stacks_if_mapping_unreferenced {
             if let Some(address) = self.principal_mapping_address {
                 self.principal_mapping = self.find_mapping_no_bias(address).cloned();
@@ -307,9 +908,27 @@ impl MinidumpWriter {
                 soft_errors.push(InitError::PrincipalMappingNotReferenced);
             }
         }
+    }
 
-        Ok(())
+    /// Writes the minidump of the initialized process to the destination
+    /// provided, and reports it along with the soft errors
+    fn write_report(
+        &mut self,
+        destination: &mut (impl Write + Seek),
+        soft_errors: ErrorList<WriterError>,
+    ) -> Result<WriteReport, WriterError> {
+        let mut buffer = Buffer::with_capacity(0);
+        let (soft_errors, streams) = self.write_dump(&mut buffer, destination, soft_errors)?;
+        Ok(WriteReport {
+            buffer: buffer.into(),
+            soft_errors,
+            streams,
+            paused_for: self.paused_for,
+            resume_deferred_by: self.resume_deferred_by,
+            stop_strategy: self.stop_strategy,
+        })
     }
+
     /// Generates a minidump and writes to the destination provided. Returns the in-memory
     /// version of the minidump as well.
     fn write_dump(
@@ -317,10 +936,16 @@ impl MinidumpWriter {
         buffer: &mut DumpBuf,
         destination: &mut (impl Write + Seek),
         mut soft_errors: ErrorList<WriterError>,
-    ) -> Result<(), WriterError> {
+    ) -> Result<(ErrorList<WriterError>, Vec<StreamReport>), WriterError> {
+        self.streams.check_order()?;
+
+        // The streams are written with a mutable reference to ourselves, so
+        // temporarily take them out
+        let mut streams = std::mem::replace(&mut self.streams, StreamRegistry::empty());
+
         // A minidump file contains a number of tagged streams. This is the number
-        // of streams which we write.
-        let num_writers = 18u32;
+        // of streams which we write, every registered stream plus the soft errors
+        let num_writers = streams.len() as u32 + 1;
 
         let mut header_section = MemoryWriter::<MDRawHeader>::alloc(buffer)?;
 
@@ -339,125 +964,105 @@ impl MinidumpWriter {
                 .as_secs() as u32, // TODO: This is not Y2038 safe, but thats how its currently defined as
             flags: 0,
         };
-        header_section.set_value(buffer, header)?;
+        header_section.set_value(buffer, header.clone())?;
 
         // Ensure the header gets flushed. If we crash somewhere below,
         // we should have a mostly-intact dump
         dir_section.write_to_file(buffer, None)?;
 
-        let dirent = self.write_thread_list_stream(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        let dirent = self.write_mappings(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        self.write_app_memory(buffer)?;
-        dir_section.write_to_file(buffer, None)?;
-
-        let dirent = self.write_memory_list_stream(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        let dirent = self.write_exception_stream(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        let dirent = systeminfo_stream::write(
-            buffer,
-            soft_errors.subwriter(WriterError::WriteSystemInfoErrors),
-        )?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        let dirent = self.write_memory_info_list_stream(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        let mut proc_root = {
-            let mut pr = String::with_capacity(24);
-            use std::fmt::Write;
-            write!(&mut pr, "/proc/{}/", self.blamed_thread).unwrap(); // infallbile barring OOM
-            pr
-        };
-
-        macro_rules! file_entry {
-            (res $write:expr, $kind:ident, $err:ident) => {
-                let dirent = match $write {
-                    Ok(location) => MDRawDirectory {
-                        stream_type: MDStreamType::$kind as u32,
-                        location,
-                    },
-                    Err(e) => {
-                        soft_errors.push(WriterError::$err(e));
-                        Default::default()
-                    }
-                };
-                dir_section.write_to_file(buffer, Some(dirent))?;
-            };
-            ($fname:literal, $kind:ident, $err:ident) => {
-                let trunc = proc_root.len();
-                proc_root.push_str($fname);
-
-                file_entry!(res write_file(buffer, &proc_root), $kind, $err);
+        if streams.contains(MDStreamType::ModuleListStream as u32) {
+            let estimate = self.estimate_module_list_size();
+            self.reserve_size(SizePriority::ModuleList, estimate);
+        }
 
-                proc_root.truncate(trunc);
+        let mut reports = Vec::with_capacity(num_writers as usize);
+        for stream in streams.iter_mut() {
+            let timer = StreamTimer::start(buffer, soft_errors.len());
+            if self.is_interrupted() {
+                reports.push(timer.finish(buffer, stream.stream_type(), false, soft_errors.len()));
+                continue;
+            }
+            let mut dirent = match stream.write(self, buffer, &mut soft_errors) {
+                Ok(dirent) => dirent,
+                Err(e) if self.resilient => {
+                    self.discard_written(buffer, timer.position());
+                    soft_errors.push(WriterError::WriteStreamFailed(
+                        stream.stream_type(),
+                        Box::new(e),
+                    ));
+                    None
+                }
+                Err(e) => return Err(e),
             };
+            self.check_process(format!("after writing stream {:#x}", stream.stream_type()))?;
+            self.fit_stream(
+                buffer,
+                timer.position(),
+                stream.size_priority(),
+                &mut dirent,
+            );
+            reports.push(timer.finish(
+                buffer,
+                stream.stream_type(),
+                dirent.is_some(),
+                soft_errors.len(),
+            ));
+            // Streams that weren't written are left out of the directory
+            dir_section.write_to_file(buffer, dirent)?;
         }
 
-        file_entry!(
-            res write_file(buffer, "/proc/cpuinfo"),
-            LinuxCpuInfo,
-            WriteCpuInfoFailed
-        );
-        file_entry!("status", LinuxProcStatus, WriteThreadProcStatusFailed);
+        self.streams = streams;
 
-        // Unfortunately neither of these files exist on Android, and there doesn't seem
-        // to be a way to read equivalent information from elsewhere on the file system
-        #[cfg(not(target_os = "android"))]
-        {
-            file_entry!(
-                res write_file(buffer, "/etc/lsb-release")
-                    .or_else(|_| write_file(buffer, "/etc/os-release")),
-                LinuxLsbRelease,
-                WriteOsReleaseInfoFailed
-            );
+        if let Some(interruption) = self.interruption.get() {
+            soft_errors.push(WriterError::Interrupted(*interruption));
         }
 
-        file_entry!("cmdline", LinuxCmdLine, WriteCommandLineFailed);
-        file_entry!("environ", LinuxEnviron, WriteEnvironmentFailed);
-        file_entry!("auxv", LinuxAuxv, WriteEnvironmentFailed);
-        file_entry!("maps", LinuxMaps, WriteMapsFailed);
-
-        let dirent = match dso_debug::write_dso_debug_stream(buffer, self.process_id, &self.auxv) {
-            Ok(dirent) => dirent,
-            Err(e) => {
-                soft_errors.push(WriterError::WriteDSODebugStreamFailed(e));
-                Default::default()
+        let mut max_soft_errors_len = None;
+        if let Some(budget) = &mut self.size_budget {
+            let mut size_limit_errors = soft_errors.subwriter(WriterError::SizeLimitErrors);
+            for error in budget.take_trimmed() {
+                size_limit_errors.push(error);
             }
-        };
-        dir_section.write_to_file(buffer, Some(dirent))?;
-
-        file_entry!("limits", MozLinuxLimits, WriteLimitsFailed);
+            drop(size_limit_errors);
+            max_soft_errors_len = Some(budget.remaining(buffer));
+        }
 
-        let dirent = self.write_thread_names_stream(buffer)?;
-        dir_section.write_to_file(buffer, Some(dirent))?;
+        // If this fails, there's really nothing we can do about that (other than ignore it).
+        let timer = StreamTimer::start(buffer, 0);
+        let result = write_soft_errors(buffer, &soft_errors, max_soft_errors_len);
+        let failed = result.is_err();
+        let dirent = result.ok().flatten().map(|location| MDRawDirectory {
+            stream_type: MDStreamType::MozSoftErrors as u32,
+            location,
+        });
+        let mut report = timer.finish(
+            buffer,
+            MDStreamType::MozSoftErrors as u32,
+            dirent.is_some(),
+            0,
+        );
+        if failed {
+            report.status = StreamStatus::Failed;
+        }
+        reports.push(report);
+        dir_section.write_to_file(buffer, dirent)?;
 
-        let dirent = match self.write_handle_data_stream(buffer) {
-            Ok(dirent) => dirent,
-            Err(e) => {
-                soft_errors.push(WriterError::WriteHandleDataStreamFailed(e));
-                Default::default()
-            }
-        };
-        dir_section.write_to_file(buffer, Some(dirent))?;
+        // The directory was sized for every stream, only count the ones that
+        // were written
+        header_section.set_value(
+            buffer,
+            MDRawHeader {
+                stream_count: dir_section.len() as u32,
+                ..header
+            },
+        )?;
+        dir_section.rewrite(buffer, header_section.location())?;
 
-        // If this fails, there's really nothing we can do about that (other than ignore it).
-        let dirent = write_soft_errors(buffer, soft_errors)
-            .map(|location| MDRawDirectory {
-                stream_type: MDStreamType::MozSoftErrors as u32,
-                location,
-            })
-            .unwrap_or_default();
-        dir_section.write_to_file(buffer, Some(dirent))?;
+        // Memory that is too large to keep in the buffer, which is written
+        // after everything else so that it doesn't affect any 32-bit RVAs
+        dir_section.write_trailing_data(buffer)?;
 
-        // If you add more directory entries, don't forget to update num_writers, above.
-        Ok(())
+        Ok((soft_errors, reports))
     }
 
     fn crash_thread_references_principal_mapping(&self) -> bool {
@@ -496,11 +1101,8 @@ impl MinidumpWriter {
             }
         };
 
-        let stack_copy = match MinidumpWriter::copy_from_process(
-            self.blamed_thread,
-            valid_stack_pointer,
-            stack_len,
-        ) {
+        let stack_copy = match self.copy_memory(self.blamed_thread, valid_stack_pointer, stack_len)
+        {
             Ok(x) => x,
             Err(_) => {
                 return false;
@@ -514,27 +1116,59 @@ impl MinidumpWriter {
             .stack_has_pointer_to_mapping(&stack_copy, sp_offset)
     }
 
-    /// Suspends a thread by attaching to it.
-    fn suspend_thread(child: Pid) -> Result<(), WriterError> {
+    /// Suspends a thread by attaching to it, or by seizing and interrupting
+    /// it if `seize` is set. If it doesn't stop by `deadline`, it stays
+    /// attached and [`WriterError::SuspendThreadTimeout`] is returned.
+    fn suspend_thread(
+        child: Pid,
+        seize: bool,
+        deadline: Option<Instant>,
+    ) -> Result<(), WriterError> {
         use WriterError::PtraceAttachError as AttachErr;
 
         let pid = nix::unistd::Pid::from_raw(child);
         // This may fail if the thread has just died or debugged.
-        ptrace::attach(pid).map_err(|e| AttachErr(child, e))?;
+        if seize {
+            ptrace::seize(pid, ptrace::Options::empty())
+                .map_err(|e| WriterError::PtraceSeizeError(child, e))?;
+            if let Err(e) = ptrace::interrupt(pid) {
+                ptrace_detach(child)?;
+                return Err(WriterError::PtraceInterruptError(child, e));
+            }
+        } else {
+            ptrace::attach(pid).map_err(|e| AttachErr(child, e))?;
+        }
+        let mut flags = wait::WaitPidFlag::__WALL;
+        if deadline.is_some() {
+            flags |= wait::WaitPidFlag::WNOHANG;
+        }
         loop {
-            match wait::waitpid(pid, Some(wait::WaitPidFlag::__WALL)) {
+            match wait::waitpid(pid, Some(flags)) {
                 Ok(status) => {
-                    let wait::WaitStatus::Stopped(_, status) = status else {
-                        return Err(WriterError::WaitPidError(
-                            child,
-                            nix::errno::Errno::UnknownErrno,
-                        ));
+                    let status = match status {
+                        wait::WaitStatus::Stopped(_, status) => status,
+                        // The stop for the interrupt, which has no signal to
+                        // be delivered
+                        wait::WaitStatus::PtraceEvent(..) if seize => break,
+                        wait::WaitStatus::StillAlive => {
+                            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                                return Err(WriterError::SuspendThreadTimeout(child));
+                            }
+                            std::thread::sleep(stop_strategy::POLL_INTERVAL);
+                            continue;
+                        }
+                        _ => {
+                            return Err(WriterError::WaitPidError(
+                                child,
+                                nix::errno::Errno::UnknownErrno,
+                            ))
+                        }
                     };
 
                     // Any signal will stop the thread, make sure it is SIGSTOP. Otherwise, this
                     // signal will be delivered after PTRACE_DETACH, and the thread will enter
                     // the "T (stopped)" state.
-                    if status == nix::sys::signal::SIGSTOP {
+                    if !seize && status == nix::sys::signal::SIGSTOP {
                         break;
                     }
 
@@ -587,18 +1221,99 @@ impl MinidumpWriter {
         ptrace_detach(child)
     }
 
+    /// Detaches from a thread that didn't stop within the attach timeout, if
+    /// it has stopped since. A traced thread can only be detached while it
+    /// is stopped.
+    fn release_unstopped_thread(child: Pid) -> Result<(), WriterError> {
+        let pid = nix::unistd::Pid::from_raw(child);
+        let flags = wait::WaitPidFlag::__WALL | wait::WaitPidFlag::WNOHANG;
+        match wait::waitpid(pid, Some(flags)) {
+            Ok(wait::WaitStatus::StillAlive) => Err(WriterError::UnstoppedThreadStillTraced(child)),
+            // Deliver the signal it stopped for, unless it is the one sent
+            // when attaching
+            Ok(wait::WaitStatus::Stopped(_, signal)) if signal != nix::sys::signal::SIGSTOP => {
+                ptrace::detach(pid, signal).map_err(|e| WriterError::PtraceDetachError(child, e))
+            }
+            Ok(_) => ptrace_detach(child),
+            // The thread has exited
+            Err(Errno::ECHILD) => Ok(()),
+            Err(e) => Err(WriterError::WaitPidError(child, e)),
+        }
+    }
+
+    /// Waits for a thread to leave uninterruptible sleep, which it can't be
+    /// stopped in. Returns whether it did by `deadline`, or has exited.
+    fn wait_until_interruptible(process_id: Pid, child: Pid, deadline: Instant) -> bool {
+        let stat_path = format!("/proc/{process_id}/task/{child}/stat");
+        loop {
+            match Stat::from_file(&stat_path) {
+                Ok(stat) if stat.state == 'D' => (),
+                _ => return true,
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(stop_strategy::POLL_INTERVAL);
+        }
+    }
+
     fn suspend_threads(&mut self, mut soft_errors: impl WriteErrorList<WriterError>) {
         // Iterate over all threads and try to suspend them.
         // If the thread either disappeared before we could attach to it, or if
         // it was part of the seccomp sandbox's trusted code, it is OK to
-        // silently drop it from the minidump.
-        self.threads.retain(|x| match Self::suspend_thread(x.tid) {
-            Ok(()) => true,
-            Err(e) => {
-                soft_errors.push(e);
-                false
+        // silently drop it from the minidump. The threads after writing is
+        // interrupted are left out too.
+        let seize = self.stop_strategy.seizes_threads();
+        let process_id = self.process_id;
+        let attach_timeout = self.thread_attach_timeout;
+        let mut unstopped_threads = Vec::new();
+        let mut threads = std::mem::take(&mut self.threads);
+        threads.retain_mut(|thread| {
+            if self.is_interrupted() {
+                return false;
+            }
+            let deadline = attach_timeout.map(|timeout| Instant::now() + timeout);
+            // A thread in uninterruptible sleep isn't attached to at all, so
+            // that it isn't left traced if it doesn't wake up in time
+            let result = match deadline {
+                Some(deadline)
+                    if !Self::wait_until_interruptible(process_id, thread.tid, deadline) =>
+                {
+                    Err(WriterError::SuspendThreadTimeout(thread.tid))
+                }
+                _ => {
+                    let result = Self::suspend_thread(thread.tid, seize, deadline);
+                    if let Err(WriterError::SuspendThreadTimeout(tid)) = result {
+                        unstopped_threads.push(tid);
+                    }
+                    result
+                }
+            };
+
+            match result {
+                Ok(()) => true,
+                // The thread is still written, with the state it is stuck in
+                Err(WriterError::SuspendThreadTimeout(tid)) => {
+                    match ProcThreadState::read(process_id, tid) {
+                        Ok(state) => {
+                            soft_errors.push(WriterError::ThreadNotSuspended(state.clone()));
+                            thread.proc_state = Some(state);
+                            true
+                        }
+                        Err(_) => {
+                            soft_errors.push(WriterError::SuspendThreadTimeout(tid));
+                            false
+                        }
+                    }
+                }
+                Err(e) => {
+                    soft_errors.push(e);
+                    false
+                }
             }
         });
+        self.threads = threads;
+        self.unstopped_threads.extend(unstopped_threads);
 
         self.threads_suspended = true;
 
@@ -607,7 +1322,12 @@ impl MinidumpWriter {
 
     fn resume_threads(&mut self, mut soft_errors: impl WriteErrorList<WriterError>) {
         if self.threads_suspended {
-            for thread in &self.threads {
+            // Threads that weren't suspended have their procfs state instead
+            for thread in self
+                .threads
+                .iter()
+                .filter(|thread| thread.proc_state.is_none())
+            {
                 match Self::resume_thread(thread.tid) {
                     Ok(()) => (),
                     Err(e) => {
@@ -615,24 +1335,93 @@ impl MinidumpWriter {
                     }
                 }
             }
+            for tid in self.unstopped_threads.drain(..) {
+                if let Err(e) = Self::release_unstopped_thread(tid) {
+                    soft_errors.push(e);
+                }
+            }
         }
         self.threads_suspended = false;
     }
 
-    /// Send SIGSTOP to the process so that we can get a consistent state.
+    /// Resumes the threads and continues the process, once everything that
+    /// needs it frozen has been captured
+    fn resume_process(&mut self, mut soft_errors: impl WriteErrorList<WriterError>) {
+        self.resume_threads(&mut soft_errors);
+        if self.process_stopped {
+            if let Err(e) = self.continue_process() {
+                soft_errors.push(WriterError::ContinueProcessFailed(e));
+            }
+            self.process_stopped = false;
+        }
+        self.paused_for = self.pause_started.take().map(|started| started.elapsed());
+    }
+
+    /// Reads the state of the threads from procfs, as they couldn't be
+    /// attached to
+    fn read_proc_thread_states(&mut self, mut soft_errors: impl WriteErrorList<InitError>) {
+        for thread in &mut self.threads {
+            match ProcThreadState::read(self.process_id, thread.tid) {
+                Ok(state) => thread.proc_state = Some(state),
+                Err(e) => soft_errors.push(InitError::ReadThreadStateFailed(thread.tid, e)),
+            }
+        }
+
+        let states = self
+            .threads
+            .iter()
+            .filter_map(|thread| thread.proc_state.clone())
+            .collect();
+        soft_errors.push(InitError::ApproximateThreadContexts(states));
+    }
+
+    /// Checks that the pid still refers to the process identified by the
+    /// pidfd, if there is one
+    fn check_process(&self, phase: impl Into<String>) -> Result<(), ProcessChangedError> {
+        match &self.pidfd {
+            Some(pidfd) if !pidfd::refers_to(pidfd, self.process_id) => {
+                Err(ProcessChangedError(self.process_id, phase.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends a signal to the process, through the pidfd if there is one
+    fn send_signal(&self, signal: signal::Signal) -> nix::Result<()> {
+        match &self.pidfd {
+            Some(pidfd) => pidfd::send_signal(pidfd, Some(signal)),
+            None => signal::kill(nix::unistd::Pid::from_raw(self.process_id), Some(signal)),
+        }
+    }
+
+    /// Stops the process with the stop strategy so that we can get a
+    /// consistent state. With [`StopStrategy::PtraceInterrupt`] the threads
+    /// are only stopped when they are suspended.
     ///
     /// This will block waiting for the process to stop until `timeout` has passed.
     fn stop_process(&mut self, timeout: Duration) -> Result<(), StopProcessError> {
+        match self.stop_strategy {
+            StopStrategy::Signal => {
+                self.process_stopped = true;
+                self.send_stop_signal(timeout)
+            }
+            StopStrategy::CgroupFreeze => {
+                self.frozen_cgroup = stop_strategy::freeze_cgroup(self.process_id, timeout)?;
+                self.process_stopped = true;
+                Ok(())
+            }
+            StopStrategy::PtraceInterrupt | StopStrategy::None => Ok(()),
+        }
+    }
+
+    /// Send SIGSTOP to the process, and wait until it is stopped.
+    fn send_stop_signal(&mut self, timeout: Duration) -> Result<(), StopProcessError> {
         failspot!(StopProcess bail(nix::Error::EPERM));
 
-        signal::kill(
-            nix::unistd::Pid::from_raw(self.process_id),
-            Some(signal::SIGSTOP),
-        )?;
+        self.send_signal(signal::SIGSTOP)?;
 
         // Something like waitpid for non-child processes would be better, but we have no such
         // tool, so we poll the status.
-        const POLL_INTERVAL: Duration = Duration::from_millis(1);
         let proc_file = format!("/proc/{}/stat", self.process_id);
         let end = Instant::now() + timeout;
 
@@ -641,21 +1430,27 @@ impl MinidumpWriter {
                 return Ok(());
             }
 
-            std::thread::sleep(POLL_INTERVAL);
+            std::thread::sleep(stop_strategy::POLL_INTERVAL);
             if Instant::now() > end {
                 return Err(StopProcessError::Timeout);
             }
         }
     }
 
-    /// Send SIGCONT to the process to continue.
+    /// Continues the process stopped by `stop_process`, by sending SIGCONT or
+    /// thawing its cgroup.
     ///
     /// Unlike `stop_process`, this function does not wait for the process to continue.
     fn continue_process(&mut self) -> Result<(), ContinueProcessError> {
-        signal::kill(
-            nix::unistd::Pid::from_raw(self.process_id),
-            Some(signal::SIGCONT),
-        )?;
+        match self.stop_strategy {
+            StopStrategy::Signal => self.send_signal(signal::SIGCONT)?,
+            StopStrategy::CgroupFreeze => {
+                if let Some(cgroup) = self.frozen_cgroup.take() {
+                    stop_strategy::thaw_cgroup(&cgroup)?;
+                }
+            }
+            StopStrategy::PtraceInterrupt | StopStrategy::None => {}
+        }
         Ok(())
     }
 
@@ -706,7 +1501,11 @@ impl MinidumpWriter {
                 }
             };
 
-            self.threads.push(Thread { tid, name });
+            self.threads.push(Thread {
+                tid,
+                name,
+                proc_state: None,
+            });
         }
 
         Ok(())
@@ -720,13 +1519,17 @@ impl MinidumpWriter {
         // case its entry when creating the list of mappings.
         // See http://www.trilithium.com/johan/2005/08/linux-gate/ for more
         // information.
-        let maps_path = format!("/proc/{}/maps", self.process_id);
-        let maps_file =
-            std::fs::File::open(&maps_path).map_err(|e| InitError::IOError(maps_path, e))?;
-
-        let maps = procfs_core::process::MemoryMaps::from_read(maps_file)
-            .map_err(InitError::ReadProcessMapFileFailed)?;
+        let maps = if self.core_file.is_some() {
+            self.read_memory_maps()
+        } else {
+            let maps_path = format!("/proc/{}/maps", self.process_id);
+            let maps_file =
+                std::fs::File::open(&maps_path).map_err(|e| InitError::IOError(maps_path, e))?;
+            MemoryMaps::from_read(maps_file)
+        }
+        .map_err(InitError::ReadProcessMapFileFailed)?;
 
+        self.memory_maps = maps.0.clone();
         self.mappings = MappingInfo::aggregate(maps, self.auxv.get_linux_gate_address())
             .map_err(InitError::AggregateMappingsFailed)?;
 
@@ -753,6 +1556,64 @@ impl MinidumpWriter {
         Ok(())
     }
 
+    /// Reads the mappings of the process from `/proc/<pid>/maps`, or from the
+    /// core file or snapshot if there is one
+    pub fn read_memory_maps(&self) -> procfs_core::ProcResult<MemoryMaps> {
+        match &self.core_file {
+            Some(core_file) => {
+                let vdso_address = self.auxv.get_linux_gate_address().map(|a| a as usize);
+                MemoryMaps::from_read(core_file.maps(vdso_address).as_bytes())
+            }
+            None if self.snapshot.is_some() => {
+                MemoryMaps::from_read(self.read_proc_file("maps")?.as_slice())
+            }
+            None => MemoryMaps::from_file(format!("/proc/{}/maps", self.blamed_thread)),
+        }
+    }
+
+    /// Writes the contents of a file in `/proc/<pid>`, see
+    /// [`Self::read_filtered_proc_file`]
+    fn write_proc_file(
+        &self,
+        buffer: &mut DumpBuf,
+        name: &str,
+    ) -> std::result::Result<MDLocationDescriptor, MemoryWriterError> {
+        let content = self.read_filtered_proc_file(name)?;
+
+        let section = MemoryArrayWriter::write_bytes(buffer, &content);
+        Ok(section.location())
+    }
+
+    /// Reads a file in `/proc/<pid>` like [`Self::read_proc_file`], with the
+    /// environment and command line passed through their privacy filters
+    pub fn read_filtered_proc_file(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        let content = self.read_proc_file(name)?;
+        Ok(match name {
+            "environ" => match &self.environ_filter {
+                Some(filter) => filter.apply(&content),
+                None => content,
+            },
+            "cmdline" => match &self.cmdline_filter {
+                Some(filter) => filter.apply(&content),
+                None => content,
+            },
+            _ => content,
+        })
+    }
+
+    /// Reads a file in `/proc/<pid>`, from the snapshot if there is one
+    pub fn read_proc_file(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.proc_files.get(name).cloned().ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{name} is not in the snapshot"),
+                )
+            }),
+            None => std::fs::read(format!("/proc/{}/{name}", self.blamed_thread)),
+        }
+    }
+
     /// Read thread info from /proc/$pid/status.
     /// Fill out the |tgid|, |ppid| and |pid| members of |info|. If unavailable,
     /// these members are set to -1. Returns true if all three members are
@@ -762,7 +1623,70 @@ impl MinidumpWriter {
             return Err(ThreadInfoError::IndexOutOfBounds(index, self.threads.len()));
         }
 
-        ThreadInfo::create(self.process_id, self.threads[index].tid)
+        let thread = &self.threads[index];
+        match &self.core_file {
+            Some(core_file) => ThreadInfo::from_core(self.process_id, &core_file.threads[index]),
+            None if self.snapshot.is_some() || self.frozen.is_some() => {
+                Err(ThreadInfoError::SnapshotContextOnly)
+            }
+            None if self.non_invasive || thread.proc_state.is_some() => {
+                let state = thread.proc_state.clone().unwrap_or(ProcThreadState {
+                    tid: thread.tid,
+                    ..Default::default()
+                });
+                ThreadInfo::from_proc(self.process_id, &state)
+            }
+            None => ThreadInfo::create(self.process_id, thread.tid),
+        }
+    }
+
+    /// Whether the thread is written with its procfs state, as it didn't
+    /// stop within the attach timeout
+    pub(crate) fn is_unsuspended(&self, tid: Pid) -> bool {
+        !self.non_invasive
+            && self
+                .threads
+                .iter()
+                .any(|thread| thread.tid == tid && thread.proc_state.is_some())
+    }
+
+    /// Returns the instruction pointer, stack pointer and context of the
+    /// thread, which are read from the snapshot if there is one, or from
+    /// what was captured while the process was frozen
+    pub fn get_thread_context_by_index(
+        &self,
+        index: usize,
+    ) -> Result<(usize, usize, RawContextCPU), ThreadInfoError> {
+        let captured_threads = match (&self.snapshot, &self.frozen) {
+            (Some(snapshot), _) => Some(&snapshot.threads),
+            (None, Some(frozen)) => Some(&frozen.threads),
+            (None, None) => None,
+        };
+        if let Some(threads) = captured_threads {
+            let tid = self
+                .threads
+                .get(index)
+                .ok_or(ThreadInfoError::IndexOutOfBounds(index, self.threads.len()))?
+                .tid;
+            // Threads whose context couldn't be captured are left out
+            let thread = threads
+                .iter()
+                .find(|thread| thread.tid == tid)
+                .ok_or(ThreadInfoError::NotCaptured(tid))?;
+            return Ok((
+                thread.instruction_pointer,
+                thread.stack_pointer,
+                thread.context.clone(),
+            ));
+        }
+
+        let info = self.get_thread_info_by_index(index)?;
+        let mut cpu = RawContextCPU::default();
+        info.fill_cpu_context(&mut cpu);
+        if self.non_invasive || self.threads[index].proc_state.is_some() {
+            mark_context_approximate(&mut cpu);
+        }
+        Ok((info.get_instruction_pointer(), info.stack_pointer, cpu))
     }
 
     // Returns a valid stack pointer and the mapping that contains the stack.
@@ -946,7 +1870,22 @@ impl MinidumpWriter {
     ) -> Result<T, WriterError> {
         assert!(idx < self.mappings.len());
 
-        Self::from_process_memory_for_mapping(&self.mappings[idx], self.process_id)
+        self.from_memory_for_mapping(&self.mappings[idx])
+    }
+
+    /// Like [`Self::from_process_memory_for_mapping`], but reads from the
+    /// core file if there is one
+    pub fn from_memory_for_mapping<T: module_reader::ReadFromModule>(
+        &self,
+        mapping: &MappingInfo,
+    ) -> Result<T, WriterError> {
+        Ok(T::read_from_module(
+            module_reader::ProcessReader::from_mem_reader(
+                self.mem_reader(self.process_id),
+                mapping.start_address,
+            )
+            .into(),
+        )?)
     }
 
     pub fn from_process_memory_for_mapping<T: module_reader::ReadFromModule>(
@@ -957,14 +1896,54 @@ impl MinidumpWriter {
             module_reader::ProcessReader::new(pid, mapping.start_address).into(),
         )?)
     }
+
+    /// Returns a reader for the memory of the process, from the core file or
+    /// snapshot if there is one, or else from the thread `pid`
+    pub fn mem_reader(&self, pid: Pid) -> MemReader {
+        let reader = match (&self.core_file, &self.snapshot) {
+            (Some(core_file), _) => MemReader::for_core(core_file.clone()),
+            (None, Some(snapshot)) => {
+                MemReader::for_snapshot(snapshot.process_id, snapshot.memory.clone())
+            }
+            (None, None) => MemReader::new(pid),
+        };
+        match &self.recorded_memory {
+            Some(memory) => reader.recording_to(memory.clone()),
+            None => reader,
+        }
+    }
+
+    /// Returns a reader for the memory the thread list, memory list and
+    /// memory64 list streams read, which is only what was captured while the
+    /// process was frozen once it is resumed, see [`Self::capture_and_resume`]
+    pub(super) fn frozen_mem_reader(&self, pid: Pid) -> MemReader {
+        match &self.frozen {
+            Some(frozen) => MemReader::for_snapshot(pid, frozen.memory.clone()),
+            None => self.mem_reader(pid),
+        }
+    }
+
+    /// Like [`Self::copy_from_process`], but reads from the core file or
+    /// snapshot if there is one, or from the memory captured while the
+    /// process was frozen once it is resumed
+    pub fn copy_memory(
+        &self,
+        pid: Pid,
+        src: usize,
+        length: usize,
+    ) -> Result<Vec<u8>, CopyFromProcessError> {
+        self.frozen_mem_reader(pid).copy(src, length)
+    }
 }
 
 impl Drop for MinidumpWriter {
     fn drop(&mut self) {
         // Always try to resume all threads (e.g. in case of error)
         self.resume_threads(error_graph::strategy::DontCare);
-        // Always allow the process to continue.
-        let _ = self.continue_process();
+        // Always allow the process to continue if it was stopped
+        if self.process_stopped {
+            let _ = self.continue_process();
+        }
     }
 }
 
@@ -994,12 +1973,56 @@ fn write_file(
     Ok(section.location())
 }
 
+/// Writes the soft errors as JSON. If the JSON is longer than `max_len`, the
+/// last errors are dropped until it fits, except for the errors recording
+/// what was trimmed to fit the size limit, which are moved first and trimmed
+/// last. Returns `None` if not even an empty list fits.
 fn write_soft_errors(
     buffer: &mut DumpBuf,
-    soft_errors: ErrorList<WriterError>,
-) -> Result<MDLocationDescriptor, WriterError> {
-    let soft_errors_json_str =
+    soft_errors: &ErrorList<WriterError>,
+    max_len: Option<u64>,
+) -> Result<Option<MDLocationDescriptor>, WriterError> {
+    let mut soft_errors_json_str =
         serde_json::to_string_pretty(&soft_errors).map_err(WriterError::ConvertToJsonFailed)?;
+
+    if let Some(max_len) = max_len.filter(|&max_len| soft_errors_json_str.len() as u64 > max_len) {
+        let mut errors: Vec<_> = soft_errors
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .map_err(WriterError::ConvertToJsonFailed)?;
+        if let Some(index) = soft_errors
+            .iter()
+            .position(|error| matches!(error, WriterError::SizeLimitErrors(_)))
+        {
+            let size_limit_errors = errors.remove(index);
+            errors.insert(0, size_limit_errors);
+        }
+
+        loop {
+            soft_errors_json_str =
+                serde_json::to_string_pretty(&errors).map_err(WriterError::ConvertToJsonFailed)?;
+            if soft_errors_json_str.len() as u64 <= max_len {
+                break;
+            }
+            // The record of the trimmed data is trimmed last, from its end
+            let size_limit_errors = match errors.as_mut_slice() {
+                [only] => only
+                    .get_mut("SizeLimitErrors")
+                    .and_then(|errors| errors.as_array_mut())
+                    .filter(|errors| !errors.is_empty()),
+                _ => None,
+            };
+            if let Some(size_limit_errors) = size_limit_errors {
+                size_limit_errors.pop();
+            } else if errors.pop().is_none() {
+                // The stream is left out rather than written without a
+                // location
+                return Ok(None);
+            }
+        }
+    }
+
     let section = MemoryArrayWriter::write_bytes(buffer, soft_errors_json_str.as_bytes());
-    Ok(section.location())
+    Ok(Some(section.location()))
 }
@@ -0,0 +1,74 @@
+//! The process is identified by a pidfd while it is dumped, so that it is
+//! noticed if it exits and its pid is reused, see
+//! [`MinidumpWriterConfig::set_pidfd`](super::MinidumpWriterConfig::set_pidfd)
+
+use {
+    super::Pid,
+    nix::{errno::Errno, sys::signal::Signal},
+    std::os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+/// Opens a pidfd for the process
+pub(crate) fn open(pid: Pid) -> nix::Result<OwnedFd> {
+    // SAFETY: `pidfd_open` has no memory arguments
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    let fd = Errno::result(fd)?;
+    // SAFETY: the fd was just opened, and isn't owned by anything else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as _) })
+}
+
+/// Sends a signal to the process of the pidfd, or checks that it still exists
+/// if no signal is given
+pub(crate) fn send_signal(pidfd: &OwnedFd, signal: Option<Signal>) -> nix::Result<()> {
+    let signal = signal.map_or(0, |signal| signal as libc::c_int);
+    // SAFETY: no siginfo is passed, the kernel fills it in like for `kill`
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+/// Whether the pidfd refers to the process with the pid, which can't have
+/// been reused as long as the process exists, even if it is a zombie
+pub(crate) fn refers_to(pidfd: &OwnedFd, pid: Pid) -> bool {
+    // Other errors, eg. `EPERM` for a process in another user namespace, don't
+    // mean that the process is gone, the fdinfo tells
+    if send_signal(pidfd, None) == Err(Errno::ESRCH) {
+        return false;
+    }
+
+    // The pid in the fdinfo is only missing on kernels older than 5.4, it is
+    // in the pid namespace of the procfs mount
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()));
+    match fdinfo.ok().as_deref().and_then(parse_fdinfo_pid) {
+        Some(fd_pid) => fd_pid == pid,
+        None => true,
+    }
+}
+
+fn parse_fdinfo_pid(fdinfo: &str) -> Option<Pid> {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Pid:"))
+        .and_then(|pid| pid.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fdinfo_pid() {
+        let fdinfo =
+            "pos:\t0\nflags:\t02000002\nmnt_id:\t15\nino:\t1057\nPid:\t4321\nNSpid:\t4321\t1\n";
+        assert_eq!(parse_fdinfo_pid(fdinfo), Some(4321));
+        assert_eq!(parse_fdinfo_pid("pos:\t0\nPid:\t-1\n"), Some(-1));
+        assert_eq!(parse_fdinfo_pid("pos:\t0\nflags:\t02000002\n"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use {
+    super::{
+        super::memory_capture::{regions_around, subtract, CaptureRegion},
+        size_limit::SizePriority,
+        *,
+    },
+    crate::minidump_cpu::{general_purpose_registers, RawContextCPU},
+};
+
+impl MinidumpWriter {
+    /// Captures the memory around the addresses the general purpose registers
+    /// of a thread point to, and around `fault_address`, if enabled, see
+    /// [`RegisterMemory`].
+    ///
+    /// Memory that has already been captured, eg. the stack, is left out of
+    /// the windows, and memory that can't be read is skipped.
+    ///
+    /// [`RegisterMemory`]: super::super::memory_capture::RegisterMemory
+    pub(super) fn capture_register_memory(
+        &mut self,
+        buffer: &mut DumpBuf,
+        thread_id: Pid,
+        context: &RawContextCPU,
+        fault_address: Option<usize>,
+    ) {
+        let captured = self.captured_regions();
+        let regions: Vec<_> = self
+            .register_memory_regions(context, fault_address)
+            .into_iter()
+            .flat_map(|region| {
+                subtract(
+                    region.start_address,
+                    region.start_address + region.length,
+                    &captured,
+                )
+            })
+            .collect();
+
+        for region in regions {
+            let length = self.fit_memory_region(
+                buffer,
+                SizePriority::AppMemory,
+                region.start_address,
+                region.length,
+            );
+            if length == 0 {
+                break;
+            }
+
+            let Ok(data_copy) = self.copy_memory(thread_id, region.start_address, length) else {
+                continue;
+            };
+
+            let section = MemoryArrayWriter::write_bytes(buffer, &data_copy);
+            self.memory_blocks.push(MDMemoryDescriptor {
+                start_of_memory_range: region.start_address as u64,
+                memory: section.location(),
+            });
+        }
+    }
+    /// Returns the windows around the addresses the general purpose registers
+    /// of a thread point to, and around `fault_address`, that are in readable
+    /// mappings, or nothing if [`RegisterMemory`] isn't enabled.
+    ///
+    /// [`RegisterMemory`]: super::super::memory_capture::RegisterMemory
+    pub(super) fn register_memory_regions(
+        &self,
+        context: &RawContextCPU,
+        fault_address: Option<usize>,
+    ) -> Vec<CaptureRegion> {
+        let Some(options) = self.register_memory else {
+            return Vec::new();
+        };
+
+        let mut targets: Vec<_> = self
+            .mappings
+            .iter()
+            .filter(|mapping| mapping.is_readable())
+            .map(|mapping| CaptureRegion {
+                start_address: mapping.system_mapping_info.start_address,
+                length: mapping.system_mapping_info.end_address
+                    - mapping.system_mapping_info.start_address,
+            })
+            .collect();
+        targets.sort_by_key(|target| target.start_address);
+
+        let addresses = general_purpose_registers(context)
+            .into_iter()
+            .chain(fault_address);
+        regions_around(addresses, options.window_size, &targets)
+    }
+}
+
+/// Returns the fault address of a signal, for the signals that have one, the
+/// address is unrelated to the crash for the others, eg. `SIGABRT`
+pub(super) fn signal_fault_address(signo: u32, address: u64) -> Option<usize> {
+    matches!(
+        signo as i32,
+        libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE
+    )
+    .then_some(address as usize)
+}
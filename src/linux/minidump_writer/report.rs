@@ -0,0 +1,122 @@
+//! What was written to a minidump, see [`MinidumpWriterConfig::write`](super::MinidumpWriterConfig::write)
+
+use {
+    super::{errors::WriterError, stop_strategy::StopStrategy},
+    error_graph::ErrorList,
+    std::time::{Duration, Instant},
+};
+
+/// The outcome of writing a minidump
+#[derive(Debug)]
+pub struct WriteReport {
+    /// The in-memory version of the minidump. Memory that was streamed to the
+    /// destination after everything else, such as the full memory of the
+    /// process, is not included.
+    pub buffer: Vec<u8>,
+    /// The errors that didn't prevent writing the minidump, which are also
+    /// written to its `MozSoftErrors` stream
+    pub soft_errors: ErrorList<WriterError>,
+    /// The streams in the order they were written, the `MozSoftErrors` stream
+    /// is last
+    pub streams: Vec<StreamReport>,
+    /// How long the process was paused for, from when it was stopped until it
+    /// was resumed once everything that needs it frozen was captured. It is
+    /// `None` if it wasn't paused, or stayed paused until the minidump was
+    /// written, see [`Self::resume_deferred_by`].
+    pub paused_for: Option<Duration>,
+    /// The type of the stream that needed the process to stay paused until
+    /// the minidump was written, eg. a user stream callback, in which case it
+    /// wasn't resumed early
+    pub resume_deferred_by: Option<u32>,
+    /// How the process was kept from running, which is also written to the
+    /// [`STOP_STRATEGY_STREAM`](super::stop_strategy::STOP_STRATEGY_STREAM)
+    pub stop_strategy: StopStrategy,
+}
+
+impl WriteReport {
+    /// The total size of the streams
+    pub fn size(&self) -> u64 {
+        self.streams.iter().map(|stream| stream.size).sum()
+    }
+}
+
+/// The outcome of writing a core file, see
+/// [`MinidumpWriterConfig::write_core_dump`](super::MinidumpWriterConfig::write_core_dump)
+#[derive(Debug)]
+pub struct CoreDumpReport {
+    /// The errors that didn't prevent writing the core file
+    pub soft_errors: ErrorList<WriterError>,
+    /// The number of bytes written to the destination
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StreamReport {
+    pub stream_type: u32,
+    /// The number of bytes the stream added to the minidump, including the
+    /// memory it refers to
+    pub size: u64,
+    pub elapsed: Duration,
+    pub status: StreamStatus,
+    /// The number of soft errors that happened while writing the stream
+    pub soft_errors: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum StreamStatus {
+    Written,
+    /// The stream had nothing to write, or didn't fit in the size limit
+    Skipped,
+    /// The stream couldn't be written, the reasons are in the soft errors
+    Failed,
+}
+
+/// Measures the writing of a single stream
+pub(super) struct StreamTimer {
+    start: Instant,
+    position: u64,
+    trailing_len: u64,
+    soft_errors: usize,
+}
+
+impl StreamTimer {
+    pub(super) fn start(buffer: &crate::mem_writer::Buffer, soft_errors: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            position: buffer.position(),
+            trailing_len: buffer.trailing_len(),
+            soft_errors,
+        }
+    }
+
+    /// The position in the buffer the stream started at
+    pub(super) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Finishes measuring the stream once its directory entry is final
+    pub(super) fn finish(
+        self,
+        buffer: &crate::mem_writer::Buffer,
+        stream_type: u32,
+        written: bool,
+        soft_errors: usize,
+    ) -> StreamReport {
+        let soft_errors = soft_errors - self.soft_errors;
+        let status = if written {
+            StreamStatus::Written
+        } else if soft_errors > 0 {
+            StreamStatus::Failed
+        } else {
+            StreamStatus::Skipped
+        };
+
+        StreamReport {
+            stream_type,
+            size: (buffer.position() - self.position) + (buffer.trailing_len() - self.trailing_len),
+            elapsed: self.start.elapsed(),
+            status,
+            soft_errors,
+        }
+    }
+}
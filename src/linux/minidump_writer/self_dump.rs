@@ -0,0 +1,479 @@
+use {
+    super::{
+        snapshot::{FrozenState, MemoryRegions, SnapshotThread},
+        *,
+    },
+    nix::unistd::{self, ForkResult},
+    scroll::{ctx::SizeWith, Pread, Pwrite},
+    std::{
+        fs::File,
+        io::Read,
+        os::fd::OwnedFd,
+        panic::{self, AssertUnwindSafe},
+    },
+};
+
+/// The exit codes of the helper, other than 0
+const HELPER_NOT_STARTED: i32 = 1;
+const HELPER_PIPE_FAILED: i32 = 2;
+const HELPER_PANICKED: i32 = 3;
+
+#[derive(Debug, Error, serde::Serialize)]
+pub enum SelfDumpError {
+    #[error("Failed to get the context of the calling thread")]
+    GetContextFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to create a pipe to the helper")]
+    CreatePipeFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to fork the helper process")]
+    ForkFailed(
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to allow the helper process {0} to ptrace this process")]
+    SetPtracerFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("Failed to communicate with the helper process {0}")]
+    HelperPipeFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_io_error")]
+        std::io::Error,
+    ),
+    #[error("Failed to wait for the helper process {0}")]
+    WaitFailed(
+        Pid,
+        #[source]
+        #[serde(serialize_with = "serialize_nix_error")]
+        nix::Error,
+    ),
+    #[error("The helper process terminated without sending what it captured: {0}")]
+    HelperTerminated(String),
+    #[error("What the helper process captured couldn't be decoded")]
+    MalformedCapture,
+    #[error("The helper process hit errors while capturing this process: {0}")]
+    HelperErrors(serde_json::Value),
+}
+
+impl MinidumpWriterConfig {
+    /// Creates a config for dumping the current process with the calling
+    /// thread blamed, see [`Self::write_current_process`]
+    pub fn current_process() -> Self {
+        Self::new(std::process::id() as _, unistd::gettid().as_raw())
+    }
+
+    /// Generates a minidump of the current process without crashing it, and
+    /// writes it to the destination provided. Returns the same report as
+    /// [`Self::write`].
+    ///
+    /// Since a process can't ptrace itself, this forks a helper process which
+    /// is allowed to ptrace this one. The helper suspends the threads,
+    /// captures what [`Self::write`] captures while the process is frozen,
+    /// resumes them and only then sends it back. Meanwhile the calling thread
+    /// only waits in `read`, so it can't block on a lock held by a suspended
+    /// thread, eg. in the allocator. The helper is a copy of this process, in
+    /// which the C library resets the allocator locks, so it can allocate.
+    /// The minidump is then written by the calling thread while the process
+    /// runs, including the user stream callbacks. This must not be called
+    /// from a signal handler.
+    ///
+    /// The calling thread is blamed, and written with the context captured
+    /// here unless a crash context was set.
+    ///
+    /// The threads are always stopped with `PTRACE_INTERRUPT`, as a stopped
+    /// process would be reported to its parent, eg. a shell would consider
+    /// the job stopped. The stop strategy set on the config is ignored, and
+    /// [`StopStrategy::PtraceInterrupt`] is recorded instead.
+    pub fn write_current_process(
+        mut self,
+        destination: &mut (impl Write + Seek),
+    ) -> Result<WriteReport, WriterError> {
+        self.process_id = std::process::id() as _;
+        self.blamed_thread = unistd::gettid().as_raw();
+        self.stop_strategy = StopStrategy::PtraceInterrupt;
+        if self.crash_context.is_none() {
+            // The context is captured in this frame, which stays alive while
+            // the minidump is written
+            let mut context = std::mem::MaybeUninit::uninit();
+            // SAFETY: the context is filled in before it is read
+            unsafe {
+                Errno::result(crash_context::crash_context_getcontext(
+                    context.as_mut_ptr(),
+                ))
+                .map_err(SelfDumpError::GetContextFailed)?;
+                self.crash_context = Some(CrashContext::from_ucontext(
+                    context.assume_init_ref(),
+                    std::mem::zeroed(),
+                    self.blamed_thread,
+                ));
+            }
+        }
+
+        let mut soft_errors = ErrorList::default();
+
+        let mut writer = self.build();
+        writer.current_process = true;
+        writer.init(soft_errors.subwriter(WriterError::InitErrors))?;
+        writer.capture_in_helper(&mut soft_errors)?;
+
+        writer.write_report(destination, soft_errors)
+    }
+}
+
+impl MinidumpWriter {
+    /// Forks the helper that captures the initialized current process, see
+    /// [`MinidumpWriterConfig::write_current_process`], and takes what it
+    /// captured as if the process had been frozen here. Nothing is allocated
+    /// until the helper has resumed the threads.
+    fn capture_in_helper(
+        &mut self,
+        mut soft_errors: impl WriteErrorList<WriterError>,
+    ) -> Result<(), WriterError> {
+        let (control_read, control_write) =
+            unistd::pipe().map_err(SelfDumpError::CreatePipeFailed)?;
+        let (result_read, result_write) =
+            unistd::pipe().map_err(SelfDumpError::CreatePipeFailed)?;
+
+        // SAFETY: the helper exits with `_exit`, without returning into the
+        // caller
+        let pid = match unsafe { unistd::fork() }.map_err(SelfDumpError::ForkFailed)? {
+            ForkResult::Child => {
+                drop(control_write);
+                drop(result_read);
+                self.run_helper(control_read, result_write)
+            }
+            ForkResult::Parent { child } => child,
+        };
+        drop(control_read);
+        drop(result_write);
+
+        let helper = pid.as_raw();
+        let mut control = File::from(control_write);
+        let mut result = File::from(result_read);
+        // The helper sends the length of what it captured once it has resumed
+        // the threads, which is read into the stack
+        let mut len = [0u8; 8];
+        let header = allow_ptrace_by(helper)
+            .map_err(|e| SelfDumpError::SetPtracerFailed(helper, e))
+            .and_then(|()| {
+                control
+                    .write_all(&[1])
+                    .and_then(|()| result.read_exact(&mut len))
+                    .map_err(|e| SelfDumpError::HelperPipeFailed(helper, e))
+            });
+        // The helper exits without being sent the start if the ptracer
+        // couldn't be set
+        drop(control);
+        let capture = header.and_then(|()| {
+            let mut capture = vec![0u8; u64::from_ne_bytes(len) as usize];
+            result
+                .read_exact(&mut capture)
+                .map(|()| capture)
+                .map_err(|e| SelfDumpError::HelperPipeFailed(helper, e))
+        });
+
+        let status = loop {
+            match wait::waitpid(pid, None) {
+                Err(Errno::EINTR) => continue,
+                other => break other.map_err(|e| SelfDumpError::WaitFailed(helper, e))?,
+            }
+        };
+        let capture = match (capture, status) {
+            (Ok(capture), _) => capture,
+            // The pipe is closed early if the helper fails
+            (Err(SelfDumpError::HelperPipeFailed(..)), status)
+                if status != wait::WaitStatus::Exited(pid, 0) =>
+            {
+                return Err(SelfDumpError::HelperTerminated(format!("{status:?}")).into());
+            }
+            (Err(e), _) => return Err(e.into()),
+        };
+
+        self.adopt_capture(&capture, &mut soft_errors)?;
+        Ok(())
+    }
+
+    /// Runs in the forked helper: suspends the threads once the parent has
+    /// allowed us to ptrace it, captures them along with their memory,
+    /// resumes them, and sends what was captured back
+    fn run_helper(&mut self, control: OwnedFd, result: OwnedFd) -> ! {
+        let code = panic::catch_unwind(AssertUnwindSafe(|| {
+            // The parent closes its end without writing if it couldn't allow
+            // us to ptrace it
+            let mut byte = [0u8];
+            if File::from(control).read_exact(&mut byte).is_err() {
+                return HELPER_NOT_STARTED;
+            }
+            let capture = self.capture_for_parent();
+            let mut result = File::from(result);
+            match result
+                .write_all(&(capture.len() as u64).to_ne_bytes())
+                .and_then(|()| result.write_all(&capture))
+            {
+                Ok(()) => 0,
+                Err(_) => HELPER_PIPE_FAILED,
+            }
+        }))
+        .unwrap_or(HELPER_PANICKED);
+
+        // SAFETY: exiting without running the destructors and exit handlers
+        // that belong to the parent, the threads are detached by the kernel if
+        // they are still suspended
+        unsafe { libc::_exit(code) }
+    }
+
+    /// Suspends the threads, captures the state [`Self::capture_and_resume`]
+    /// does, the full memory included, and resumes them. Returns it encoded
+    /// for the parent, see [`Self::adopt_capture`].
+    fn capture_for_parent(&mut self) -> Vec<u8> {
+        let mut soft_errors = ErrorList::default();
+
+        self.pause_started = Some(Instant::now());
+        let suspended = self.suspend_threads_or_fall_back(&mut soft_errors);
+        let (threads, memory) = match suspended {
+            Ok(()) if !self.is_interrupted() => {
+                let threads = self.capture_threads(&mut soft_errors);
+                let full_memory = self
+                    .streams
+                    .contains(MDStreamType::Memory64ListStream as u32);
+                let memory = self.capture_memory(&threads, full_memory);
+                (threads, memory)
+            }
+            Ok(()) => Default::default(),
+            Err(e) => {
+                soft_errors.push(e);
+                Default::default()
+            }
+        };
+        self.resume_process(soft_errors.subwriter(InitError::ResumeProcessErrors));
+
+        let mut encoder = CaptureEncoder::default();
+        encoder.option(
+            self.paused_for
+                .map(|paused_for| paused_for.as_nanos() as u64),
+        );
+        encoder.u64(self.non_invasive as u64);
+
+        encoder.u64(self.threads.len() as u64);
+        for thread in &self.threads {
+            encoder.u64(thread.tid as u64);
+            encoder.u64(thread.proc_state.is_some() as u64);
+            if let Some(state) = &thread.proc_state {
+                encoder.u64(state.state as u64);
+                encoder.string(state.wchan.as_deref());
+                encoder.string(state.kernel_stack.as_deref());
+                encoder.string(state.syscall.as_deref());
+                encoder.option(state.stack_pointer.map(|sp| sp as u64));
+                encoder.option(state.instruction_pointer.map(|ip| ip as u64));
+            }
+        }
+
+        encoder.u64(threads.len() as u64);
+        for thread in threads {
+            encoder.u64(thread.tid as u64);
+            encoder.u64(thread.instruction_pointer as u64);
+            encoder.u64(thread.stack_pointer as u64);
+            let mut context = vec![0u8; RawContextCPU::size_with(&scroll::NATIVE)];
+            let written = context
+                .pwrite_with(thread.context, 0, scroll::NATIVE)
+                .unwrap_or_default();
+            encoder.bytes(&context[..written]);
+        }
+
+        let regions: Vec<_> = memory.iter().collect();
+        encoder.u64(regions.len() as u64);
+        for (address, data) in regions {
+            encoder.u64(address as u64);
+            encoder.bytes(data);
+        }
+
+        // The errors can't be deserialized, so they are sent as JSON
+        let errors = (!soft_errors.is_empty())
+            .then(|| serde_json::to_vec(&soft_errors).ok())
+            .flatten();
+        encoder.bytes(errors.as_deref().unwrap_or_default());
+        encoder.0
+    }
+
+    /// Takes the threads and memory captured by the helper as the frozen
+    /// state of the process, which was resumed by the helper
+    fn adopt_capture(
+        &mut self,
+        capture: &[u8],
+        mut soft_errors: impl WriteErrorList<WriterError>,
+    ) -> Result<(), SelfDumpError> {
+        let mut decoder = CaptureDecoder(capture);
+        self.pause_started = None;
+        self.paused_for = decoder.option()?.map(Duration::from_nanos);
+        if decoder.u64()? != 0 {
+            // The helper fell back to reading the threads from procfs
+            self.non_invasive = true;
+            if self.stop_strategy == StopStrategy::PtraceInterrupt {
+                self.stop_strategy = StopStrategy::None;
+            }
+        }
+
+        // The threads that weren't suspended or have exited are left out
+        let thread_count = decoder.u64()?;
+        let mut threads = Vec::new();
+        for _ in 0..thread_count {
+            let tid = decoder.u64()? as Pid;
+            let proc_state = match decoder.u64()? {
+                0 => None,
+                _ => Some(ProcThreadState {
+                    tid,
+                    state: char::from_u32(decoder.u64()? as u32).unwrap_or_default(),
+                    wchan: decoder.string()?,
+                    kernel_stack: decoder.string()?,
+                    syscall: decoder.string()?,
+                    stack_pointer: decoder.option()?.map(|sp| sp as usize),
+                    instruction_pointer: decoder.option()?.map(|ip| ip as usize),
+                }),
+            };
+            let name = self
+                .threads
+                .iter()
+                .find(|thread| thread.tid == tid)
+                .and_then(|thread| thread.name.clone());
+            threads.push(Thread {
+                tid,
+                name,
+                proc_state,
+            });
+        }
+        self.threads = threads;
+
+        let captured_count = decoder.u64()?;
+        let mut captured = Vec::new();
+        for _ in 0..captured_count {
+            let tid = decoder.u64()? as Pid;
+            let instruction_pointer = decoder.u64()? as usize;
+            let stack_pointer = decoder.u64()? as usize;
+            let context: RawContextCPU = decoder
+                .bytes()?
+                .pread_with(0, scroll::NATIVE)
+                .map_err(|_| SelfDumpError::MalformedCapture)?;
+            let thread = self.threads.iter().find(|thread| thread.tid == tid);
+            captured.push(SnapshotThread {
+                tid,
+                name: thread.and_then(|thread| thread.name.clone()),
+                proc_state: thread.and_then(|thread| thread.proc_state.clone()),
+                instruction_pointer,
+                stack_pointer,
+                context,
+            });
+        }
+
+        let region_count = decoder.u64()?;
+        let mut memory = MemoryRegions::default();
+        for _ in 0..region_count {
+            let address = decoder.u64()? as usize;
+            memory.insert(address, decoder.bytes()?);
+        }
+
+        let errors = decoder.bytes()?;
+        if !errors.is_empty() {
+            let errors =
+                serde_json::from_slice(errors).map_err(|_| SelfDumpError::MalformedCapture)?;
+            soft_errors.push(SelfDumpError::HelperErrors(errors).into());
+        }
+
+        self.frozen = Some(FrozenState {
+            threads: captured,
+            memory,
+            full_memory: None,
+        });
+        Ok(())
+    }
+}
+
+/// What the helper captured, encoded to be sent to the parent
+#[derive(Default)]
+struct CaptureEncoder(Vec<u8>);
+
+impl CaptureEncoder {
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    fn option(&mut self, value: Option<u64>) {
+        self.u64(value.is_some() as u64);
+        if let Some(value) = value {
+            self.u64(value);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, value: Option<&str>) {
+        self.u64(value.is_some() as u64);
+        if let Some(value) = value {
+            self.bytes(value.as_bytes());
+        }
+    }
+}
+
+/// Decodes what [`CaptureEncoder`] encoded
+struct CaptureDecoder<'a>(&'a [u8]);
+
+impl<'a> CaptureDecoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SelfDumpError> {
+        if len > self.0.len() {
+            return Err(SelfDumpError::MalformedCapture);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, SelfDumpError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_ne_bytes(bytes.try_into().unwrap_or_default()))
+    }
+
+    fn option(&mut self) -> Result<Option<u64>, SelfDumpError> {
+        match self.u64()? {
+            0 => Ok(None),
+            _ => self.u64().map(Some),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SelfDumpError> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<Option<String>, SelfDumpError> {
+        match self.u64()? {
+            0 => Ok(None),
+            _ => Ok(Some(String::from_utf8_lossy(self.bytes()?).into_owned())),
+        }
+    }
+}
+
+/// Allows the process to ptrace this one when Yama restricts ptrace to
+/// descendants, which is the case for helpers and crash generation servers
+pub(crate) fn allow_ptrace_by(pid: Pid) -> nix::Result<()> {
+    // SAFETY: `PR_SET_PTRACER` only takes integer arguments
+    let res = unsafe { libc::prctl(libc::PR_SET_PTRACER, pid as libc::c_ulong, 0, 0, 0) };
+    match Errno::result(res) {
+        // Yama isn't enabled, so there is nothing to allow
+        Ok(_) | Err(Errno::EINVAL) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
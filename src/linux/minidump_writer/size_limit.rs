@@ -0,0 +1,256 @@
+//! Enforcement of [`MinidumpWriterConfig::set_minidump_size_limit`].
+
+use super::*;
+
+/// Space kept free by all data for the data written after everything else,
+/// ie. the `MozSoftErrors` stream, so that the record of the data that was
+/// trimmed to fit the limit can be written. The stream is trimmed to whatever
+/// space is left.
+const TAIL_RESERVE: u64 = 4 * 1024;
+
+/// The size of the descriptor of each memory block in the `MemoryListStream`
+const DESCRIPTOR_SIZE: u64 = std::mem::size_of::<MDMemoryDescriptor>() as u64;
+
+/// The priority of the data in a minidump when it has a size limit, from the
+/// highest to the lowest.
+///
+/// Space is reserved for higher priority data that is written later, so
+/// lower priority data is trimmed or dropped first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum SizePriority {
+    /// Data that the rest of the minidump depends on, eg. the thread list and
+    /// thread contexts, the system info and the exception
+    Essential,
+    /// The stack of the crashing thread, and the memory around its
+    /// instruction pointer
+    CrashingThreadStack,
+    ModuleList,
+    /// The stacks of the other threads
+    ThreadStacks,
+    /// Memory provided by the application, or selected by the memory capture
+    /// options
+    AppMemory,
+    /// Streams copied from `/proc` and similar files
+    ProcFiles,
+    /// Streams that don't have a priority of their own, eg. user streams
+    Other,
+}
+
+impl SizePriority {
+    const COUNT: usize = Self::Other as usize + 1;
+}
+
+/// Data that was trimmed or dropped to fit the minidump size limit
+#[derive(Debug, Error, serde::Serialize)]
+pub enum SizeLimitError {
+    #[error("The stack of thread {thread_id} was trimmed from {requested} to {written} bytes")]
+    StackTrimmed {
+        thread_id: u32,
+        requested: u64,
+        written: u64,
+    },
+    #[error("The memory at {address:#x} was trimmed from {requested} to {written} bytes")]
+    MemoryTrimmed {
+        address: u64,
+        requested: u64,
+        written: u64,
+    },
+    #[error("Stream {stream_type:#x} was trimmed from {requested} to {written} bytes")]
+    StreamTrimmed {
+        stream_type: u32,
+        requested: u64,
+        written: u64,
+    },
+    #[error("Stream {stream_type:#x} of {size} bytes was dropped")]
+    StreamDropped { stream_type: u32, size: u64 },
+}
+
+/// Tracks the space left for each priority of data in a minidump with a
+/// size limit
+#[derive(Debug)]
+pub struct SizeBudget {
+    limit: u64,
+    reserved: [u64; SizePriority::COUNT],
+    trimmed: Vec<SizeLimitError>,
+}
+
+impl SizeBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            reserved: Default::default(),
+            trimmed: Vec::new(),
+        }
+    }
+
+    /// Reserves space for data that will be written later
+    pub fn reserve(&mut self, priority: SizePriority, len: u64) {
+        self.reserved[priority as usize] += len;
+    }
+
+    /// Releases space reserved by [`Self::reserve`]
+    pub fn release(&mut self, priority: SizePriority, len: u64) {
+        let reserved = &mut self.reserved[priority as usize];
+        *reserved = reserved.saturating_sub(len);
+    }
+
+    /// The number of bytes that data with `priority` can use, after the
+    /// space reserved for higher priority data
+    pub fn available(&self, buffer: &DumpBuf, priority: SizePriority) -> u64 {
+        self.limit
+            .saturating_sub(buffer.position() + buffer.trailing_len() + self.reserved(priority))
+    }
+
+    /// Returns true if everything written so far by data with `priority`
+    /// leaves the space reserved for higher priority data
+    pub fn fits(&self, buffer: &DumpBuf, priority: SizePriority) -> bool {
+        buffer.position() + buffer.trailing_len() + self.reserved(priority) <= self.limit
+    }
+
+    /// The space that data with `priority` must leave free
+    fn reserved(&self, priority: SizePriority) -> u64 {
+        self.reserved[..priority as usize].iter().sum::<u64>() + TAIL_RESERVE
+    }
+
+    /// The number of bytes left before the limit is reached, regardless of
+    /// any reservation
+    pub fn remaining(&self, buffer: &DumpBuf) -> u64 {
+        self.limit
+            .saturating_sub(buffer.position() + buffer.trailing_len())
+    }
+
+    /// Records data that was trimmed or dropped
+    pub fn record(&mut self, error: SizeLimitError) {
+        self.trimmed.push(error);
+    }
+
+    /// The number of records of trimmed or dropped data
+    pub fn trimmed_len(&self) -> usize {
+        self.trimmed.len()
+    }
+
+    /// Forgets the records after the first `len`, eg. for data that is
+    /// written again
+    pub fn truncate_trimmed(&mut self, len: usize) {
+        self.trimmed.truncate(len);
+    }
+
+    pub fn take_trimmed(&mut self) -> Vec<SizeLimitError> {
+        std::mem::take(&mut self.trimmed)
+    }
+}
+
+impl MinidumpWriter {
+    /// Returns how many of the `len` bytes of a memory block with `priority`
+    /// fit in the size limit, along with the descriptor of the block, which
+    /// is reserved if anything fits.
+    ///
+    /// Memory blocks that don't fit entirely are trimmed from the end.
+    pub(super) fn fit_memory_block(
+        &mut self,
+        buffer: &DumpBuf,
+        priority: SizePriority,
+        len: usize,
+    ) -> usize {
+        let Some(budget) = &mut self.size_budget else {
+            return len;
+        };
+
+        let available = budget
+            .available(buffer, priority)
+            .saturating_sub(DESCRIPTOR_SIZE);
+        let len = len.min(available.try_into().unwrap_or(usize::MAX));
+        if len > 0 {
+            budget.reserve(SizePriority::Essential, DESCRIPTOR_SIZE);
+        }
+        len
+    }
+
+    /// Like [`Self::fit_memory_block`], recording the memory block at
+    /// `address` if it was trimmed
+    pub(super) fn fit_memory_region(
+        &mut self,
+        buffer: &DumpBuf,
+        priority: SizePriority,
+        address: usize,
+        len: usize,
+    ) -> usize {
+        let fitted = self.fit_memory_block(buffer, priority, len);
+        if fitted < len {
+            self.record_trimmed(SizeLimitError::MemoryTrimmed {
+                address: address as u64,
+                requested: len as u64,
+                written: fitted as u64,
+            });
+        }
+        fitted
+    }
+
+    /// Reserves space for data with `priority` that will be written later
+    pub(super) fn reserve_size(&mut self, priority: SizePriority, len: usize) {
+        if let Some(budget) = &mut self.size_budget {
+            budget.reserve(priority, len as u64);
+        }
+    }
+
+    /// Releases space reserved by [`Self::reserve_size`], or for the
+    /// descriptors of memory blocks
+    pub(super) fn release_size(&mut self, priority: SizePriority, len: usize) {
+        if let Some(budget) = &mut self.size_budget {
+            budget.release(priority, len as u64);
+        }
+    }
+
+    pub(super) fn record_trimmed(&mut self, error: SizeLimitError) {
+        if let Some(budget) = &mut self.size_budget {
+            budget.record(error);
+        }
+    }
+
+    /// Drops a stream written from `start` if it doesn't fit in the size
+    /// limit, along with the memory blocks and thread context it contained
+    pub(super) fn fit_stream(
+        &mut self,
+        buffer: &mut DumpBuf,
+        start: u64,
+        priority: SizePriority,
+        dirent: &mut Option<MDRawDirectory>,
+    ) {
+        let Some(budget) = &mut self.size_budget else {
+            return;
+        };
+
+        if budget.fits(buffer, priority) {
+            return;
+        }
+
+        if let Some(dropped) = dirent.take() {
+            budget.record(SizeLimitError::StreamDropped {
+                stream_type: dropped.stream_type,
+                size: buffer.position() - start,
+            });
+        }
+        self.discard_written(buffer, start);
+    }
+
+    /// Discards everything written to the buffer from `start`, along with the
+    /// memory blocks and thread context it contained
+    pub(super) fn discard_written(&mut self, buffer: &mut DumpBuf, start: u64) {
+        buffer.truncate(start);
+
+        let blocks = self.memory_blocks.len();
+        self.memory_blocks
+            .retain(|block| (block.memory.rva as u64) < start);
+        let dropped = blocks - self.memory_blocks.len();
+        self.release_size(SizePriority::Essential, dropped * DESCRIPTOR_SIZE as usize);
+
+        let context_rva = match &self.crashing_thread_context {
+            CrashingThreadContext::None => None,
+            CrashingThreadContext::CrashContext(location) => Some(location.rva),
+            CrashingThreadContext::CrashContextPlusAddress((location, _)) => Some(location.rva),
+        };
+        if context_rva.is_some_and(|rva| rva as u64 >= start) {
+            self.crashing_thread_context = CrashingThreadContext::None;
+        }
+    }
+}
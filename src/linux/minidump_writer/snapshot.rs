@@ -0,0 +1,550 @@
+//! The state of a process collected separately from writing it to a minidump,
+//! see [`MinidumpWriterConfig::snapshot`](super::MinidumpWriterConfig::snapshot)
+
+use {
+    super::{
+        super::memory_capture::{referenced_regions, select_regions, subtract, CaptureRegion},
+        memory64_list_stream::SpooledMemory,
+        register_memory::signal_fault_address,
+        *,
+    },
+    crate::minidump_cpu::RawContextCPU,
+    std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// The files in `/proc/<pid>` that are copied into minidumps
+pub const PROC_FILES: &[&str] = &["status", "cmdline", "environ", "auxv", "maps", "limits"];
+
+/// The state of a process needed to write a minidump of it, which can be
+/// inspected or modified before it is written with
+/// [`MinidumpWriterConfig::from_snapshot`]
+#[derive(Debug)]
+pub struct ProcessSnapshot {
+    pub process_id: Pid,
+    pub blamed_thread: Pid,
+    pub crash_context: Option<CrashContext>,
+    pub threads: Vec<SnapshotThread>,
+    /// Whether the threads were read from procfs rather than with ptrace, in
+    /// which case their contexts are approximate
+    pub non_invasive: bool,
+    pub mappings: Vec<MappingInfo>,
+    /// The mappings with a build id, which are written to the module list
+    /// along with the user mapping list
+    pub modules: Vec<SnapshotModule>,
+    /// The memory of the process that was read while collecting the
+    /// snapshot, further memory can be added to be written with it
+    pub memory: MemoryRegions,
+    /// The contents of the [`PROC_FILES`] that could be read, by name
+    pub proc_files: BTreeMap<String, Vec<u8>>,
+    /// How the process was kept from running while it was collected
+    pub stop_strategy: StopStrategy,
+    /// The errors that didn't prevent collecting the snapshot, which are
+    /// written to the minidump
+    pub soft_errors: ErrorList<InitError>,
+    pub(super) auxv: AuxvDumpInfo,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotThread {
+    pub tid: Pid,
+    pub name: Option<String>,
+    /// The state of the thread read from procfs, in non-invasive mode
+    pub proc_state: Option<ProcThreadState>,
+    pub instruction_pointer: usize,
+    pub stack_pointer: usize,
+    pub context: RawContextCPU,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotModule {
+    pub mapping: MappingInfo,
+    pub build_id: Vec<u8>,
+    pub soname: Option<String>,
+}
+
+/// The state of a live process that needs it to be frozen, captured so that
+/// it can be resumed before the rest of the minidump is written
+#[derive(Debug)]
+pub(super) struct FrozenState {
+    pub(super) threads: Vec<SnapshotThread>,
+    pub(super) memory: MemoryRegions,
+    /// The memory of the `Memory64ListStream`, if it is written to a minidump
+    pub(super) full_memory: Option<SpooledMemory>,
+}
+
+/// Memory of a process in non-overlapping regions, where adjacent regions
+/// are merged. It is shared when cloned, until it is modified.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegions {
+    regions: Arc<BTreeMap<usize, Vec<u8>>>,
+}
+
+impl MemoryRegions {
+    /// Adds the memory at `address`, replacing the memory already there
+    pub fn insert(&mut self, address: usize, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        // The regions that overlap or touch the new one are merged into it
+        let regions = Arc::make_mut(&mut self.regions);
+        let end = address + data.len();
+        let touching: Vec<usize> = regions
+            .range(..=end)
+            .rev()
+            .take_while(|&(&start, region)| start + region.len() >= address)
+            .map(|(&start, _)| start)
+            .collect();
+
+        let start = touching.last().map_or(address, |&start| start.min(address));
+        let merged_end = touching
+            .first()
+            .map_or(end, |&last| end.max(last + regions[&last].len()));
+        let mut merged = vec![0; merged_end - start];
+        for region_start in touching {
+            let region = regions.remove(&region_start).unwrap_or_default();
+            merged[region_start - start..][..region.len()].copy_from_slice(&region);
+        }
+        merged[address - start..][..data.len()].copy_from_slice(data);
+        regions.insert(start, merged);
+    }
+
+    /// Copies the memory at `address` to `dst`, returning the number of
+    /// bytes that were available, which stops at the first missing byte
+    pub fn read(&self, address: usize, dst: &mut [u8]) -> usize {
+        let Some((&start, region)) = self.regions.range(..=address).next_back() else {
+            return 0;
+        };
+        let Some(available) = region.get(address - start..) else {
+            return 0;
+        };
+
+        let len = available.len().min(dst.len());
+        dst[..len].copy_from_slice(&available[..len]);
+        len
+    }
+
+    /// Iterates the start addresses and contents of the regions, in order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        self.regions
+            .iter()
+            .map(|(&start, region)| (start, region.as_slice()))
+    }
+
+    /// The address ranges of the regions, in order
+    fn regions(&self) -> Vec<CaptureRegion> {
+        self.iter()
+            .map(|(start, region)| CaptureRegion {
+                start_address: start,
+                length: region.len(),
+            })
+            .collect()
+    }
+
+    /// The total size of the regions
+    pub fn size(&self) -> usize {
+        self.regions.values().map(Vec::len).sum()
+    }
+}
+
+impl MinidumpWriter {
+    /// Collects the snapshot of the initialized process. The thread contexts
+    /// and the memory a minidump of it would contain are captured directly,
+    /// see [`Self::capture_memory`], and the process is resumed before the
+    /// modules and files are read. The memory that the streams reading the
+    /// modules read is recorded too, so that they can be written from the
+    /// snapshot.
+    pub(super) fn collect_snapshot(
+        &mut self,
+        mut soft_errors: ErrorList<InitError>,
+    ) -> Result<ProcessSnapshot, WriterError> {
+        let threads = self.capture_threads(&mut soft_errors);
+        let full_memory = self
+            .streams
+            .contains(MDStreamType::Memory64ListStream as u32);
+        let memory = self.capture_memory(&threads, full_memory);
+        if self.threads_suspended || self.process_stopped {
+            self.resume_process(soft_errors.subwriter(InitError::ResumeProcessErrors));
+            self.check_process("after resuming it")?;
+        }
+
+        let modules = self.read_modules();
+        let memory = self.record_module_memory(&modules, memory);
+
+        let mut proc_files = BTreeMap::new();
+        for name in PROC_FILES {
+            // Missing files are noticed when the streams copying them are
+            // written
+            if let Ok(content) = self.read_proc_file(name) {
+                proc_files.insert(name.to_string(), content);
+            }
+        }
+
+        Ok(ProcessSnapshot {
+            process_id: self.process_id,
+            blamed_thread: self.blamed_thread,
+            crash_context: self.crash_context.take(),
+            threads,
+            non_invasive: self.non_invasive,
+            mappings: self.mappings.clone(),
+            modules,
+            memory,
+            proc_files,
+            stop_strategy: self.stop_strategy,
+            soft_errors,
+            auxv: self.auxv.clone(),
+        })
+    }
+
+    /// Adds the memory that the Crashpad info and DSO debug streams read from
+    /// the modules to `memory`. Their errors are hit again when they are
+    /// written from the snapshot.
+    fn record_module_memory(
+        &mut self,
+        modules: &[SnapshotModule],
+        memory: MemoryRegions,
+    ) -> MemoryRegions {
+        let memory = Arc::new(Mutex::new(memory));
+        self.recorded_memory = Some(memory.clone());
+
+        if self
+            .streams
+            .contains(MDStreamType::CrashpadInfoStream as u32)
+        {
+            self.module_list = modules
+                .iter()
+                .map(|module| module.mapping.clone())
+                .chain(
+                    self.user_mapping_list
+                        .iter()
+                        .map(|user| user.mapping.clone()),
+                )
+                .collect();
+            self.read_module_annotations(ErrorList::default());
+            self.module_list.clear();
+        }
+        if self.streams.contains(MDStreamType::LinuxDsoDebug as u32) {
+            let _ = dso_debug::read_dso_debug(&self.mem_reader(self.process_id), &self.auxv);
+        }
+
+        self.recorded_memory = None;
+        let memory = std::mem::take(&mut *memory.lock().unwrap_or_else(|e| e.into_inner()));
+        memory
+    }
+
+    /// Reads the contexts of the threads. The threads whose context can't be
+    /// read are left out, and recorded in the soft errors.
+    pub(super) fn capture_threads(
+        &self,
+        mut soft_errors: impl WriteErrorList<InitError>,
+    ) -> Vec<SnapshotThread> {
+        let mut threads = Vec::with_capacity(self.threads.len());
+        for (index, thread) in self.threads.iter().enumerate() {
+            let result = failspot!(if CaptureThread {
+                Err(ThreadInfoError::PtraceError(nix::Error::ESRCH))
+            } else {
+                self.get_thread_context_by_index(index)
+            });
+            let (instruction_pointer, stack_pointer, context) = match result {
+                Ok(context) => context,
+                Err(e) => {
+                    soft_errors.push(InitError::CaptureThreadFailed(thread.tid, e));
+                    continue;
+                }
+            };
+            threads.push(SnapshotThread {
+                tid: thread.tid,
+                name: thread.name.clone(),
+                proc_state: thread.proc_state.clone(),
+                instruction_pointer,
+                stack_pointer,
+                context,
+            });
+        }
+        threads
+    }
+
+    /// Captures the state that can only be read while the process is frozen
+    /// and then resumes it, so that it is paused for as short as possible.
+    /// That is the thread contexts, and the memory the thread list and memory
+    /// list streams read: the stacks, the memory around the instruction
+    /// pointers and referenced from registers and stacks, the app memory and
+    /// the memory selected by the capture rules. Everything else, such as
+    /// reading the modules and the files in `/proc/<pid>`, is done while the
+    /// process runs.
+    ///
+    /// If the full memory is written, it is read too, into a temporary file
+    /// if `spool_full_memory` is set, or along with the rest of the memory
+    /// otherwise.
+    ///
+    /// The process stays frozen until the minidump is written if a stream
+    /// needs it to, such as a user stream callback, which is recorded in
+    /// [`Self::resume_deferred_by`]. The threads whose context can't be read
+    /// are left out of the minidump.
+    pub(super) fn capture_and_resume(
+        &mut self,
+        mut soft_errors: impl WriteErrorList<InitError>,
+        spool_full_memory: bool,
+    ) -> Result<(), WriterError> {
+        if !(self.threads_suspended || self.process_stopped) {
+            return Ok(());
+        }
+        if let Some(stream_type) = self.streams.stream_needing_frozen_process() {
+            self.resume_deferred_by = Some(stream_type);
+            return Ok(());
+        }
+        let threads = self.capture_threads(&mut soft_errors);
+
+        let full_memory = self
+            .streams
+            .contains(MDStreamType::Memory64ListStream as u32);
+        let memory = self.capture_memory(&threads, full_memory && !spool_full_memory);
+
+        // Its errors are hit again when the stream reads the process instead
+        let full_memory = (full_memory && spool_full_memory)
+            .then(|| self.spool_full_memory().ok())
+            .flatten();
+
+        self.frozen = Some(FrozenState {
+            threads,
+            memory,
+            full_memory,
+        });
+        self.resume_process(soft_errors.subwriter(InitError::ResumeProcessErrors));
+        self.check_process("after resuming it")?;
+        Ok(())
+    }
+
+    /// Reads the memory that the thread list and memory list streams write
+    /// from the process, without writing anything: the stacks, the memory
+    /// around the crashing instruction pointer and referenced from registers
+    /// and stacks, the app memory and the memory selected by the capture
+    /// rules. The memory of every dumpable mapping is read too if
+    /// `full_memory` is set.
+    ///
+    /// It is read without the size limit, which trims it when it is written.
+    /// The regions are computed the same way the streams do, with the whole
+    /// stacks, so what they write is a part of what is read here, and memory
+    /// that can't be read is left out.
+    pub(super) fn capture_memory(
+        &self,
+        threads: &[SnapshotThread],
+        full_memory: bool,
+    ) -> MemoryRegions {
+        let mut memory = MemoryRegions::default();
+
+        let mut stacks = Vec::with_capacity(threads.len());
+        for thread in threads {
+            // The crashing thread is written with the context it crashed in
+            let crash_context = self
+                .crash_context
+                .as_ref()
+                .filter(|_| thread.tid == self.blamed_thread);
+            let (stack_pointer, context, fault_address) = match crash_context {
+                Some(crash_context) => {
+                    let instruction_ptr = crash_context.get_instruction_pointer();
+                    if let Some(region) = self.ip_memory_region(instruction_ptr) {
+                        self.capture_region(&mut memory, thread.tid, region);
+                    }
+                    let mut cpu = RawContextCPU::default();
+                    crash_context.fill_cpu_context(&mut cpu);
+                    let siginfo = &crash_context.inner.siginfo;
+                    let fault_address = signal_fault_address(siginfo.ssi_signo, siginfo.ssi_addr);
+                    (crash_context.get_stack_pointer(), cpu, fault_address)
+                }
+                None => (thread.stack_pointer, thread.context.clone(), None),
+            };
+
+            if let Ok((stack_start, stack_len)) = self.get_stack_info(stack_pointer) {
+                let region = CaptureRegion {
+                    start_address: stack_start,
+                    length: stack_len,
+                };
+                let stack = self.capture_region(&mut memory, thread.tid, region);
+                let stack_pointer_offset = stack_pointer.saturating_sub(stack_start);
+                stacks.push((thread.tid, stack, stack_start, stack_pointer_offset));
+            }
+
+            if thread.tid == self.blamed_thread
+                || self
+                    .register_memory
+                    .is_some_and(|options| options.all_threads)
+            {
+                for region in self.register_memory_regions(&context, fault_address) {
+                    self.capture_region(&mut memory, thread.tid, region);
+                }
+            }
+        }
+
+        if let Some(options) = self.indirect_memory {
+            let stack_mappings = self.thread_stack_mappings();
+            let mut used = 0;
+            'stacks: for (tid, stack, stack_start, stack_pointer_offset) in &stacks {
+                let targets = self.indirect_memory_targets(*stack_start, &stack_mappings);
+                let captured = memory.regions();
+                let regions: Vec<_> =
+                    referenced_regions(stack, *stack_pointer_offset, options.window_size, &targets)
+                        .into_iter()
+                        .flat_map(|region| {
+                            subtract(
+                                region.start_address,
+                                region.start_address + region.length,
+                                &captured,
+                            )
+                        })
+                        .collect();
+
+                for region in regions {
+                    if used >= options.budget || self.is_interrupted() {
+                        break 'stacks;
+                    }
+                    let length = region.length.min(options.budget - used);
+                    let region = CaptureRegion {
+                        start_address: region.start_address,
+                        length,
+                    };
+                    used += self.capture_region(&mut memory, *tid, region).len();
+                }
+            }
+        }
+
+        for app_memory in &self.app_memory {
+            let region = CaptureRegion {
+                start_address: app_memory.ptr,
+                length: app_memory.length,
+            };
+            self.capture_region(&mut memory, self.blamed_thread, region);
+        }
+
+        let captured = memory.regions();
+        let mappings = self.unmerged_mappings();
+        for region in select_regions(&self.memory_capture_rules, &mappings, &captured) {
+            if self.is_interrupted() {
+                break;
+            }
+            self.capture_region(&mut memory, self.blamed_thread, region);
+        }
+
+        // Its errors are hit again when the stream is written
+        if let Some(ranges) = full_memory.then(|| self.memory64_ranges().ok()).flatten() {
+            for range in ranges {
+                if self.is_interrupted() {
+                    break;
+                }
+                // Unreadable pages are zeroed, as they would be when written
+                let mut data = Vec::with_capacity(range.data_size as usize);
+                if self
+                    .process_memory(vec![range])
+                    .write_to(&mut data, || false)
+                    .is_ok()
+                {
+                    memory.insert(range.start_of_memory_range as usize, &data);
+                }
+            }
+        }
+
+        memory
+    }
+
+    /// Reads a region of the process into `memory`, keeping the start of it
+    /// if only that can be read, and returns what was read
+    fn capture_region(
+        &self,
+        memory: &mut MemoryRegions,
+        pid: Pid,
+        region: CaptureRegion,
+    ) -> Vec<u8> {
+        let mut data = vec![0u8; region.length];
+        let read = match self.mem_reader(pid).read(region.start_address, &mut data) {
+            Ok(read) => read,
+            // PTRACE_PEEKDATA reads a word at a time, and fails with the
+            // offset of the first word it couldn't read
+            Err(e) => e.offset,
+        };
+        data.truncate(read);
+        memory.insert(region.start_address, &data);
+        data
+    }
+
+    /// Initializes the state from a snapshot, the process isn't accessed
+    pub(super) fn init_from_snapshot(
+        &mut self,
+        snapshot: &mut ProcessSnapshot,
+        mut soft_errors: impl WriteErrorList<InitError>,
+    ) -> Result<(), InitError> {
+        for error in std::mem::take(&mut snapshot.soft_errors) {
+            soft_errors.push(error);
+        }
+
+        self.auxv = snapshot.auxv.clone();
+        self.threads = snapshot
+            .threads
+            .iter()
+            .map(|thread| Thread {
+                tid: thread.tid,
+                name: thread.name.clone(),
+                proc_state: thread.proc_state.clone(),
+            })
+            .collect();
+        self.mappings = snapshot.mappings.clone();
+        self.memory_maps = snapshot
+            .proc_files
+            .get("maps")
+            .and_then(|maps| MemoryMaps::from_read(maps.as_slice()).ok())
+            .map_or_else(Vec::new, |maps| maps.0);
+
+        self.page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?
+            .expect("page size apparently unlimited: doesn't make sense.")
+            as usize;
+
+        self.find_principal_mapping(soft_errors);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions(memory: &MemoryRegions) -> Vec<(usize, Vec<u8>)> {
+        memory
+            .iter()
+            .map(|(start, region)| (start, region.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn memory_regions() {
+        let mut memory = MemoryRegions::default();
+        memory.insert(0x1000, &[1; 4]);
+        memory.insert(0x2000, &[2; 4]);
+        memory.insert(0x1002, &[3; 4]);
+        memory.insert(0x0ffc, &[4; 4]);
+        memory.insert(0x1ff0, &[]);
+        assert_eq!(
+            regions(&memory),
+            [
+                (0x0ffc, vec![4, 4, 4, 4, 1, 1, 3, 3, 3, 3]),
+                (0x2000, vec![2; 4])
+            ]
+        );
+        assert_eq!(memory.size(), 14);
+
+        // Regions are merged across the gap between them
+        memory.insert(0x1004, &vec![5; 0x1000 - 4]);
+        assert_eq!(memory.iter().count(), 1);
+        assert_eq!(memory.size(), 0x1008);
+
+        let mut dst = [0; 8];
+        assert_eq!(memory.read(0x0ffa, &mut dst), 0);
+        assert_eq!(memory.read(0x0ffe, &mut dst), 8);
+        assert_eq!(dst, [4, 4, 1, 1, 3, 3, 5, 5]);
+        assert_eq!(memory.read(0x2002, &mut dst), 2);
+        assert_eq!(dst[..2], [2, 2]);
+        assert_eq!(memory.read(0x2004, &mut dst), 0);
+    }
+}
@@ -0,0 +1,160 @@
+//! How the process is kept from running while it is dumped, see
+//! [`MinidumpWriterConfig::set_stop_strategy`](super::MinidumpWriterConfig::set_stop_strategy)
+
+use {
+    super::{
+        errors::{ContinueProcessError, StopProcessError, WriterError},
+        MinidumpWriter, Pid,
+    },
+    crate::{dir_section::DumpBuf, mem_writer::MemoryArrayWriter, minidump_format::MDRawDirectory},
+    std::{
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
+};
+
+/// The type of the stream that records the [`StopStrategy`] used to write a
+/// minidump, as a JSON string, eg. `"Signal"`
+pub const STOP_STRATEGY_STREAM: u32 = 0x4d77_0001;
+
+/// How often the state of the process is polled until it is stopped
+pub(super) const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub enum StopStrategy {
+    /// Sends `SIGSTOP` to the process and waits until it is stopped, then
+    /// `SIGCONT` after dumping. The parent of the process is notified of both,
+    /// so a shell reports the job as stopped.
+    #[default]
+    Signal,
+    /// Freezes the cgroup v2 of the process with `cgroup.freeze`, which
+    /// freezes every other process in it too. The cgroup is only thawed after
+    /// dumping if it wasn't frozen already.
+    CgroupFreeze,
+    /// Stops the threads with `PTRACE_SEIZE` and `PTRACE_INTERRUPT` when they
+    /// are suspended rather than `PTRACE_ATTACH`, which sends no signals.
+    /// Threads keep running until they are suspended one after the other.
+    PtraceInterrupt,
+    /// Doesn't stop the process, its threads are still suspended one after
+    /// the other
+    None,
+}
+
+impl StopStrategy {
+    /// Whether threads are suspended with `PTRACE_SEIZE` and
+    /// `PTRACE_INTERRUPT`. A frozen thread only enters the ptrace-stop for an
+    /// interrupt, not for the `SIGSTOP` sent by `PTRACE_ATTACH`.
+    pub(super) fn seizes_threads(self) -> bool {
+        matches!(self, Self::CgroupFreeze | Self::PtraceInterrupt)
+    }
+}
+
+/// Freezes the cgroup of the process and waits until it is frozen. Returns
+/// the cgroup directory if it was frozen by this, and needs to be thawed.
+pub(super) fn freeze_cgroup(
+    pid: Pid,
+    timeout: Duration,
+) -> Result<Option<PathBuf>, StopProcessError> {
+    let cgroup = find_cgroup(pid)?;
+    // Freezing the cgroup of the dumping process would never finish
+    if find_cgroup(std::process::id() as Pid)?.starts_with(&cgroup) {
+        return Err(StopProcessError::SameCgroup(cgroup));
+    }
+
+    let freeze = cgroup.join("cgroup.freeze");
+    let already_frozen = read_file(&freeze)?.trim() == "1";
+    if !already_frozen {
+        std::fs::write(&freeze, "1").map_err(|e| StopProcessError::Cgroup(freeze, e))?;
+    }
+
+    let events = cgroup.join("cgroup.events");
+    let end = Instant::now() + timeout;
+    loop {
+        if read_file(&events)?.lines().any(|line| line == "frozen 1") {
+            break;
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+        if Instant::now() > end {
+            if !already_frozen {
+                let _ = thaw_cgroup(&cgroup);
+            }
+            return Err(StopProcessError::Timeout);
+        }
+    }
+
+    Ok((!already_frozen).then_some(cgroup))
+}
+
+pub(super) fn thaw_cgroup(cgroup: &Path) -> Result<(), ContinueProcessError> {
+    let freeze = cgroup.join("cgroup.freeze");
+    std::fs::write(&freeze, "0").map_err(|e| ContinueProcessError::Thaw(freeze, e))
+}
+
+/// Finds the directory of the cgroup v2 the process belongs to
+fn find_cgroup(pid: Pid) -> Result<PathBuf, StopProcessError> {
+    let path = parse_cgroup_path(&read_file(format!("/proc/{pid}/cgroup"))?)
+        .ok_or(StopProcessError::NoCgroup(pid))?
+        .to_owned();
+    let mount_point = parse_cgroup2_mount(&read_file("/proc/self/mounts")?)
+        .ok_or(StopProcessError::NoCgroup(pid))?
+        .to_owned();
+    Ok(PathBuf::from(mount_point).join(path.trim_start_matches('/')))
+}
+
+fn read_file(path: impl AsRef<Path>) -> Result<String, StopProcessError> {
+    let path = path.as_ref();
+    std::fs::read_to_string(path).map_err(|e| StopProcessError::Cgroup(path.to_owned(), e))
+}
+
+/// Gets the cgroup v2 path from the contents of `/proc/<pid>/cgroup`, which
+/// is the entry with hierarchy 0 and no controllers
+fn parse_cgroup_path(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Gets where the cgroup v2 hierarchy is mounted from the contents of
+/// `/proc/self/mounts`. It is usually `/sys/fs/cgroup`, or
+/// `/sys/fs/cgroup/unified` on systems that also mount cgroup v1.
+fn parse_cgroup2_mount(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| {
+        let mut fields = line.split(' ');
+        let mount_point = fields.nth(1)?;
+        (fields.next()? == "cgroup2").then_some(mount_point)
+    })
+}
+
+impl MinidumpWriter {
+    /// Writes the stop strategy that was used, which is
+    /// [`StopStrategy::None`] if the threads weren't suspended with ptrace
+    pub fn write_stop_strategy_stream(
+        &self,
+        buffer: &mut DumpBuf,
+    ) -> Result<MDRawDirectory, WriterError> {
+        let json =
+            serde_json::to_vec(&self.stop_strategy).map_err(WriterError::ConvertToJsonFailed)?;
+        Ok(MDRawDirectory {
+            stream_type: STOP_STRATEGY_STREAM,
+            location: MemoryArrayWriter::write_bytes(buffer, &json).location(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_paths() {
+        let cgroup = "12:freezer:/\n1:name=systemd:/user.slice\n0::/user.slice/app.scope\n";
+        assert_eq!(parse_cgroup_path(cgroup), Some("/user.slice/app.scope"));
+        assert_eq!(parse_cgroup_path("4:memory:/\n1:cpu:/\n"), None);
+
+        let mounts = "proc /proc proc rw 0 0\n\
+            tmpfs /sys/fs/cgroup tmpfs rw 0 0\n\
+            cgroup /sys/fs/cgroup/freezer cgroup rw,freezer 0 0\n\
+            cgroup2 /sys/fs/cgroup/unified cgroup2 rw 0 0\n";
+        assert_eq!(parse_cgroup2_mount(mounts), Some("/sys/fs/cgroup/unified"));
+        assert_eq!(parse_cgroup2_mount("proc /proc proc rw 0 0\n"), None);
+    }
+}
@@ -0,0 +1,430 @@
+//! The set of streams written into a minidump, and the order they are written in.
+
+use super::{size_limit::SizePriority, *};
+
+/// Writes a single minidump stream.
+///
+/// Writers are stored in a [`StreamRegistry`] and invoked in order from
+/// [`MinidumpWriterConfig::write`], after the process has been suspended and
+/// its threads and mappings enumerated. The process is resumed once its
+/// thread contexts and the memory read by the builtin thread list, memory list
+/// and memory64 list streams are captured, so other streams read the running
+/// process, unless a registered stream [needs it
+/// frozen](StreamWriter::needs_frozen_process).
+///
+/// Writers are `Send` so that a [`MinidumpWriterConfig`] can be prepared on
+/// one thread and written on another, eg. a crash handling thread.
+pub trait StreamWriter: Send {
+    /// The stream type recorded in the minidump directory for this stream.
+    fn stream_type(&self) -> u32;
+
+    /// The priority of the stream when the minidump has a size limit, streams
+    /// that don't fit are dropped. By default, streams have the lowest
+    /// priority.
+    fn size_priority(&self) -> SizePriority {
+        SizePriority::Other
+    }
+
+    /// Whether the stream reads the process, and needs it to stay frozen
+    /// until it is written, which keeps the process from being resumed early.
+    /// By default, streams don't.
+    fn needs_frozen_process(&self) -> bool {
+        false
+    }
+
+    /// Writes the stream contents to `buffer`, returning the directory entry
+    /// for it, or `None` if there was nothing to write, in which case the
+    /// stream is left out of the directory.
+    ///
+    /// Errors that should not abort the whole dump should be pushed to
+    /// `soft_errors` instead, in which case `None` can be returned.
+    fn write(
+        &mut self,
+        writer: &mut MinidumpWriter,
+        buffer: &mut DumpBuf,
+        soft_errors: &mut ErrorList<WriterError>,
+    ) -> Result<Option<MDRawDirectory>, WriterError>;
+}
+
+/// The streams that are written by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinStream {
+    ThreadList,
+    ModuleList,
+    /// Memory selected by the memory capture rules and application provided
+    /// memory are written as part of this stream, so it needs to come after
+    /// every stream which captures memory, ie the thread list.
+    MemoryList,
+    /// Relies on the crashing thread context recorded by the thread list, so
+    /// it needs to come after it.
+    Exception,
+    SystemInfo,
+    MemoryInfoList,
+    CpuInfo,
+    ProcStatus,
+    LsbRelease,
+    CmdLine,
+    Environ,
+    Auxv,
+    Maps,
+    DsoDebug,
+    Limits,
+    ThreadNames,
+    HandleData,
+    /// The full memory of the process, not written by default, see
+    /// [`MinidumpWriterConfig::full_memory`]
+    Memory64List,
+    /// How the process was kept from running, see
+    /// [`stop_strategy::STOP_STRATEGY_STREAM`]
+    StopStrategy,
+    /// Includes the Crashpad annotations of the modules in the module list, so
+    /// it needs to come after it. Only written if there are annotations.
+    CrashpadInfo,
+}
+
+impl BuiltinStream {
+    /// The builtin streams that are written by default, in order
+    pub const DEFAULT_ORDER: &'static [Self] = &[
+        Self::ThreadList,
+        Self::ModuleList,
+        Self::MemoryList,
+        Self::Exception,
+        Self::SystemInfo,
+        Self::MemoryInfoList,
+        Self::CpuInfo,
+        Self::ProcStatus,
+        // Unfortunately neither of these files exist on Android, and there doesn't seem
+        // to be a way to read equivalent information from elsewhere on the file system
+        #[cfg(not(target_os = "android"))]
+        Self::LsbRelease,
+        Self::CmdLine,
+        Self::Environ,
+        Self::Auxv,
+        Self::Maps,
+        Self::DsoDebug,
+        Self::Limits,
+        Self::ThreadNames,
+        Self::HandleData,
+        Self::StopStrategy,
+        Self::CrashpadInfo,
+    ];
+
+    /// The streams that use state recorded by another stream, and the stream
+    /// they depend on, which has to be written before them
+    pub const DEPENDENCIES: &'static [(Self, Self)] = &[
+        (Self::MemoryList, Self::ThreadList),
+        (Self::Exception, Self::ThreadList),
+        (Self::CrashpadInfo, Self::ModuleList),
+    ];
+}
+
+impl StreamWriter for BuiltinStream {
+    fn stream_type(&self) -> u32 {
+        let kind = match self {
+            Self::ThreadList => MDStreamType::ThreadListStream,
+            Self::ModuleList => MDStreamType::ModuleListStream,
+            Self::MemoryList => MDStreamType::MemoryListStream,
+            Self::Exception => MDStreamType::ExceptionStream,
+            Self::SystemInfo => MDStreamType::SystemInfoStream,
+            Self::MemoryInfoList => MDStreamType::MemoryInfoListStream,
+            Self::CpuInfo => MDStreamType::LinuxCpuInfo,
+            Self::ProcStatus => MDStreamType::LinuxProcStatus,
+            Self::LsbRelease => MDStreamType::LinuxLsbRelease,
+            Self::CmdLine => MDStreamType::LinuxCmdLine,
+            Self::Environ => MDStreamType::LinuxEnviron,
+            Self::Auxv => MDStreamType::LinuxAuxv,
+            Self::Maps => MDStreamType::LinuxMaps,
+            Self::DsoDebug => MDStreamType::LinuxDsoDebug,
+            Self::Limits => MDStreamType::MozLinuxLimits,
+            Self::ThreadNames => MDStreamType::ThreadNamesStream,
+            Self::HandleData => MDStreamType::HandleDataStream,
+            Self::Memory64List => MDStreamType::Memory64ListStream,
+            Self::CrashpadInfo => MDStreamType::CrashpadInfoStream,
+            Self::StopStrategy => return stop_strategy::STOP_STRATEGY_STREAM,
+        };
+        kind as u32
+    }
+
+    fn size_priority(&self) -> SizePriority {
+        match self {
+            Self::ModuleList => SizePriority::ModuleList,
+            Self::Memory64List => SizePriority::AppMemory,
+            Self::MemoryInfoList
+            | Self::CpuInfo
+            | Self::ProcStatus
+            | Self::LsbRelease
+            | Self::CmdLine
+            | Self::Environ
+            | Self::Auxv
+            | Self::Maps
+            | Self::DsoDebug
+            | Self::Limits
+            | Self::HandleData => SizePriority::ProcFiles,
+            Self::ThreadList
+            | Self::MemoryList
+            | Self::Exception
+            | Self::SystemInfo
+            | Self::ThreadNames
+            | Self::StopStrategy
+            | Self::CrashpadInfo => SizePriority::Essential,
+        }
+    }
+
+    fn write(
+        &mut self,
+        writer: &mut MinidumpWriter,
+        buffer: &mut DumpBuf,
+        soft_errors: &mut ErrorList<WriterError>,
+    ) -> Result<Option<MDRawDirectory>, WriterError> {
+        let stream_type = self.stream_type();
+
+        macro_rules! file_entry {
+            ($write:expr, $err:ident) => {
+                Ok(match $write {
+                    Ok(location) => Some(MDRawDirectory {
+                        stream_type,
+                        location,
+                    }),
+                    Err(e) => {
+                        soft_errors.push(WriterError::$err(e));
+                        None
+                    }
+                })
+            };
+            (proc $fname:literal, $err:ident) => {
+                file_entry!(writer.write_proc_file(buffer, $fname), $err)
+            };
+        }
+
+        match self {
+            Self::ThreadList => Ok(Some(writer.write_thread_list_stream(
+                buffer,
+                soft_errors.subwriter(WriterError::WriteThreadListErrors),
+            )?)),
+            Self::ModuleList => Ok(Some(writer.write_mappings(buffer)?)),
+            Self::MemoryList => {
+                writer.write_captured_memory(
+                    buffer,
+                    soft_errors.subwriter(WriterError::WriteCapturedMemoryErrors),
+                );
+                writer.write_app_memory(
+                    buffer,
+                    soft_errors.subwriter(WriterError::WriteAppMemoryErrors),
+                )?;
+                Ok(Some(writer.write_memory_list_stream(buffer)?))
+            }
+            Self::Exception => Ok(Some(writer.write_exception_stream(buffer)?)),
+            Self::SystemInfo if writer.core_file.is_some() => {
+                Ok(Some(systeminfo_stream::write_for_core_file(buffer)?))
+            }
+            Self::SystemInfo => Ok(Some(systeminfo_stream::write(
+                buffer,
+                soft_errors.subwriter(WriterError::WriteSystemInfoErrors),
+            )?)),
+            Self::MemoryInfoList => Ok(Some(writer.write_memory_info_list_stream(buffer)?)),
+            Self::CpuInfo => file_entry!(write_file(buffer, "/proc/cpuinfo"), WriteCpuInfoFailed),
+            Self::ProcStatus => file_entry!(proc "status", WriteThreadProcStatusFailed),
+            Self::LsbRelease => file_entry!(
+                write_file(buffer, "/etc/lsb-release")
+                    .or_else(|_| write_file(buffer, "/etc/os-release")),
+                WriteOsReleaseInfoFailed
+            ),
+            Self::CmdLine => file_entry!(proc "cmdline", WriteCommandLineFailed),
+            Self::Environ => file_entry!(proc "environ", WriteEnvironmentFailed),
+            Self::Auxv => match &writer.core_file {
+                Some(core_file) => Ok(Some(MDRawDirectory {
+                    stream_type,
+                    location: MemoryArrayWriter::write_bytes(buffer, &core_file.auxv).location(),
+                })),
+                None => file_entry!(proc "auxv", WriteEnvironmentFailed),
+            },
+            Self::Maps => match &writer.core_file {
+                Some(core_file) => {
+                    let vdso_address = writer.auxv.get_linux_gate_address().map(|a| a as usize);
+                    let maps = core_file.maps(vdso_address);
+                    Ok(Some(MDRawDirectory {
+                        stream_type,
+                        location: MemoryArrayWriter::write_bytes(buffer, maps.as_bytes())
+                            .location(),
+                    }))
+                }
+                None => file_entry!(proc "maps", WriteMapsFailed),
+            },
+            Self::DsoDebug => Ok(
+                match dso_debug::write_dso_debug_stream(
+                    buffer,
+                    &writer.mem_reader(writer.process_id),
+                    &writer.auxv,
+                ) {
+                    Ok(dirent) => Some(dirent),
+                    Err(e) => {
+                        soft_errors.push(WriterError::WriteDSODebugStreamFailed(e));
+                        None
+                    }
+                },
+            ),
+            Self::Limits => file_entry!(proc "limits", WriteLimitsFailed),
+            Self::ThreadNames => Ok(Some(writer.write_thread_names_stream(buffer)?)),
+            Self::HandleData => Ok(match writer.write_handle_data_stream(buffer) {
+                Ok(dirent) => Some(dirent),
+                Err(e) => {
+                    soft_errors.push(WriterError::WriteHandleDataStreamFailed(e));
+                    None
+                }
+            }),
+            Self::Memory64List => Ok(Some(writer.write_memory64_list_stream(buffer)?)),
+            Self::StopStrategy => Ok(Some(writer.write_stop_strategy_stream(buffer)?)),
+            Self::CrashpadInfo => Ok(writer.write_crashpad_info_stream(
+                buffer,
+                soft_errors.subwriter(WriterError::WriteCrashpadInfoErrors),
+            )?),
+        }
+    }
+}
+
+/// An ordered list of the streams to write into a minidump.
+///
+/// Each entry produces at most one directory entry in the minidump, which
+/// allows the size of the stream directory to be known before any stream is
+/// written. Streams that don't return a directory entry are left out of the
+/// directory. The `MozSoftErrors` stream is always written last, after every
+/// registered stream, so that it can contain errors from all of them.
+///
+/// Entries are identified by their stream type, so there is at most one
+/// entry per stream type that can be addressed by [`Self::remove`],
+/// [`Self::replace`] and [`Self::insert_before`].
+///
+/// Writing fails with [`WriterError::StreamOrder`] if a builtin stream is
+/// registered before a stream it depends on, see [`Self::check_order`].
+pub struct StreamRegistry {
+    entries: Vec<Box<dyn StreamWriter>>,
+}
+
+impl StreamRegistry {
+    /// Creates a registry without any streams
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The number of registered streams
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates the stream types of the registered streams, in write order
+    pub fn stream_types(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries.iter().map(|e| e.stream_type())
+    }
+
+    /// Returns the type of the first registered stream that needs the
+    /// process to stay frozen until it is written, if any, see
+    /// [`StreamWriter::needs_frozen_process`]
+    pub fn stream_needing_frozen_process(&self) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|e| e.needs_frozen_process())
+            .map(|e| e.stream_type())
+    }
+
+    /// Returns true if a stream of the specified type is registered
+    pub fn contains(&self, stream_type: u32) -> bool {
+        self.position(stream_type).is_some()
+    }
+
+    /// Appends a stream writer, it will be written after all current entries
+    pub fn push(&mut self, writer: impl StreamWriter + 'static) -> &mut Self {
+        self.entries.push(Box::new(writer));
+        self
+    }
+
+    /// Inserts a stream writer before the entry for `stream_type`, or at the
+    /// end if there is no such entry
+    pub fn insert_before(
+        &mut self,
+        stream_type: u32,
+        writer: impl StreamWriter + 'static,
+    ) -> &mut Self {
+        let index = self.position(stream_type).unwrap_or(self.entries.len());
+        self.entries.insert(index, Box::new(writer));
+        self
+    }
+
+    /// Removes the entry for `stream_type`, returning it if it was registered
+    pub fn remove(&mut self, stream_type: u32) -> Option<Box<dyn StreamWriter>> {
+        let index = self.position(stream_type)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Replaces the entry for `stream_type` in place, returning the previous
+    /// entry. If there was no such entry the writer is appended instead.
+    pub fn replace(
+        &mut self,
+        stream_type: u32,
+        writer: impl StreamWriter + 'static,
+    ) -> Option<Box<dyn StreamWriter>> {
+        let writer: Box<dyn StreamWriter> = Box::new(writer);
+        match self.position(stream_type) {
+            Some(index) => Some(std::mem::replace(&mut self.entries[index], writer)),
+            None => {
+                self.entries.push(writer);
+                None
+            }
+        }
+    }
+
+    /// Checks that every [`BuiltinStream`] comes after the streams it depends
+    /// on, see [`BuiltinStream::DEPENDENCIES`]. Streams are identified by
+    /// their type, so a replaced builtin stream is still expected to come
+    /// after its dependencies.
+    pub fn check_order(&self) -> Result<(), WriterError> {
+        for (stream, dependency) in BuiltinStream::DEPENDENCIES {
+            let (stream, dependency) = (stream.stream_type(), dependency.stream_type());
+            if let (Some(index), Some(dependency_index)) =
+                (self.position(stream), self.position(dependency))
+            {
+                if index < dependency_index {
+                    return Err(WriterError::StreamOrder { stream, dependency });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn position(&self, stream_type: u32) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.stream_type() == stream_type)
+    }
+
+    #[inline]
+    pub(super) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn StreamWriter>> {
+        self.entries.iter_mut()
+    }
+}
+
+impl Default for StreamRegistry {
+    /// Creates a registry with all of the [`BuiltinStream`]s in their default
+    /// order
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        for builtin in BuiltinStream::DEFAULT_ORDER {
+            registry.push(*builtin);
+        }
+        registry
+    }
+}
+
+impl std::fmt::Debug for StreamRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.stream_types()).finish()
+    }
+}
@@ -38,3 +38,27 @@ pub fn write(
     info_section.set_value(buffer, info)?;
     Ok(dirent)
 }
+
+/// Writes the system info of a process recorded in a core file. Only the
+/// platform and the CPU architecture, which the core file was checked to
+/// match, are known, the OS version and CPU details of this system may not
+/// be those of the one the core file was written on.
+pub fn write_for_core_file(buffer: &mut DumpBuf) -> Result<MDRawDirectory, SectionSystemInfoError> {
+    let mut info_section = MemoryWriter::<MDRawSystemInfo>::alloc(buffer)?;
+    let dirent = MDRawDirectory {
+        stream_type: MDStreamType::SystemInfoStream as u32,
+        location: info_section.location(),
+    };
+
+    let (platform_id, _) = dci::os_information();
+    let os_version_loc = write_string_to_location(buffer, "")?;
+
+    // SAFETY: POD
+    let mut info = unsafe { std::mem::zeroed::<MDRawSystemInfo>() };
+    info.platform_id = platform_id as u32;
+    info.csd_version_rva = os_version_loc.rva;
+    info.processor_architecture = dci::processor_architecture();
+
+    info_section.set_value(buffer, info)?;
+    Ok(dirent)
+}
@@ -1,26 +1,15 @@
-use {super::*, crate::minidump_cpu::RawContextCPU, std::cmp::min};
-
-// The following kLimit* constants are for when minidump_size_limit_ is set
-// and the minidump size might exceed it.
-//
-// Estimate for how big each thread's stack will be (in bytes).
-const LIMIT_AVERAGE_THREAD_STACK_LENGTH: usize = 8 * 1024;
-// Number of threads whose stack size we don't want to limit.  These base
-// threads will simply be the first N threads returned by the dumper (although
-// the crashing thread will never be limited).  Threads beyond this count are
-// the extra threads.
-const LIMIT_BASE_THREAD_COUNT: usize = 20;
-// Maximum stack size to dump for any extra thread (in bytes).
-const LIMIT_MAX_EXTRA_THREAD_STACK_LEN: usize = 2 * 1024;
-// Make sure this number of additional bytes can fit in the minidump
-// (exclude the stack data).
-const LIMIT_MINIDUMP_FUDGE_FACTOR: u64 = 64 * 1024;
-
-#[derive(Debug, Clone, Copy)]
-enum MaxStackLen {
-    None,
-    Len(usize),
-}
+use {
+    super::{
+        super::memory_capture::CaptureRegion,
+        register_memory::signal_fault_address,
+        size_limit::{SizeLimitError, SizePriority},
+        *,
+    },
+    crate::minidump_cpu::RawContextCPU,
+};
+
+// The number of bytes copied around the crashing instruction pointer.
+const IP_MEMORY_SIZE: usize = 256;
 
 #[derive(Debug, Error, serde::Serialize)]
 pub enum SectionThreadListError {
@@ -44,18 +33,67 @@ pub enum SectionThreadListError {
     ),
     #[error("Failed to sanitize stack copy")]
     SanitizeStackCopyFailed(#[source] Box<WriterError>),
+    #[error("Skipped thread {0}")]
+    ThreadSkipped(Pid, #[source] Box<SectionThreadListError>),
 }
 
 impl MinidumpWriter {
+    /// Writes the thread list stream.
+    ///
+    /// The thread list is essential, so if it doesn't fit in the size limit,
+    /// it is written again without the stacks of the other threads, and then
+    /// without any stacks, rather than being dropped.
     pub fn write_thread_list_stream(
         &mut self,
         buffer: &mut DumpBuf,
+        mut soft_errors: impl WriteErrorList<SectionThreadListError>,
+    ) -> Result<MDRawDirectory, SectionThreadListError> {
+        let start = buffer.position();
+        let trimmed = self.size_budget.as_ref().map_or(0, |b| b.trimmed_len());
+        let indirect_memory_regions = self.indirect_memory_regions.clone();
+
+        let mut dropped = [
+            None,
+            Some(SizePriority::ThreadStacks),
+            Some(SizePriority::CrashingThreadStack),
+        ]
+        .into_iter()
+        .peekable();
+        while let Some(dropped_stacks) = dropped.next() {
+            self.dropped_stacks = dropped_stacks;
+            let mut errors = ErrorList::default();
+            let result = self.write_thread_list(buffer, &mut errors);
+            let fits = self
+                .size_budget
+                .as_ref()
+                .is_none_or(|budget| budget.fits(buffer, SizePriority::Essential));
+            if result.is_err() || fits || dropped.peek().is_none() {
+                self.dropped_stacks = None;
+                for error in errors {
+                    soft_errors.push(error);
+                }
+                return result;
+            }
+
+            self.discard_written(buffer, start);
+            self.indirect_memory_regions = indirect_memory_regions.clone();
+            if let Some(budget) = &mut self.size_budget {
+                budget.truncate_trimmed(trimmed);
+            }
+        }
+        unreachable!("the thread list is written at least once")
+    }
+
+    fn write_thread_list(
+        &mut self,
+        buffer: &mut DumpBuf,
+        mut soft_errors: impl WriteErrorList<SectionThreadListError>,
     ) -> Result<MDRawDirectory, SectionThreadListError> {
         let num_threads = self.threads.len();
         // Memory looks like this:
         // <num_threads><thread_1><thread_2>...
 
-        let list_header = MemoryWriter::<u32>::alloc_with_val(buffer, num_threads as u32)?;
+        let mut list_header = MemoryWriter::<u32>::alloc_with_val(buffer, num_threads as u32)?;
 
         let mut dirent = MDRawDirectory {
             stream_type: MDStreamType::ThreadListStream as u32,
@@ -64,130 +102,139 @@ impl MinidumpWriter {
 
         let mut thread_list = MemoryArrayWriter::<MDRawThread>::alloc_array(buffer, num_threads)?;
         dirent.location.data_size += thread_list.location().data_size;
-        // If there's a minidump size limit, check if it might be exceeded.  Since
-        // most of the space is filled with stack data, just check against that.
-        // If this expects to exceed the limit, set extra_thread_stack_len such
-        // that any thread beyond the first kLimitBaseThreadCount threads will
-        // have only kLimitMaxExtraThreadStackLen bytes dumped.
-        let mut extra_thread_stack_len = MaxStackLen::None; // default to no maximum
-        if let Some(minidump_size_limit) = self.minidump_size_limit {
-            let estimated_total_stack_size =
-                (num_threads * LIMIT_AVERAGE_THREAD_STACK_LENGTH) as u64;
-            let curr_pos = buffer.position();
-            let estimated_minidump_size =
-                curr_pos + estimated_total_stack_size + LIMIT_MINIDUMP_FUDGE_FACTOR;
-            if estimated_minidump_size > minidump_size_limit {
-                extra_thread_stack_len = MaxStackLen::Len(LIMIT_MAX_EXTRA_THREAD_STACK_LEN);
-            }
+
+        // With a size limit, the stacks are trimmed to whatever fits in the
+        // space left for their priority
+        if self.size_budget.is_some() {
+            self.reserve_thread_stacks();
+        }
+        if self.indirect_memory.is_some() {
+            self.thread_stack_mappings = self.thread_stack_mappings();
         }
 
+        let mut written = 0;
         for (idx, item) in self.threads.clone().iter().enumerate() {
-            let mut thread = MDRawThread {
-                thread_id: item.tid.try_into()?,
-                suspend_count: 0,
-                priority_class: 0,
-                priority: 0,
-                teb: 0,
-                stack: MDMemoryDescriptor::default(),
-                thread_context: MDLocationDescriptor::default(),
+            if self.is_interrupted() {
+                break;
+            }
+
+            let start = buffer.position();
+            let thread = match self.write_thread(buffer, idx, item) {
+                Ok(thread) => thread,
+                Err(e) if self.resilient => {
+                    self.discard_written(buffer, start);
+                    soft_errors.push(SectionThreadListError::ThreadSkipped(item.tid, Box::new(e)));
+                    continue;
+                }
+                Err(e) => return Err(e),
             };
+            thread_list.set_value_at(buffer, thread, written)?;
+            written += 1;
+        }
 
-            // We have a different source of information for the crashing thread. If
-            // we used the actual state of the thread we would find it running in the
-            // signal handler with the alternative stack, which would be deeply
-            // unhelpful.
-            if self.crash_context.is_some() && thread.thread_id == self.blamed_thread as u32 {
-                let crash_context = self.crash_context.as_ref().unwrap();
-                let instruction_ptr = crash_context.get_instruction_pointer();
-                let stack_pointer = crash_context.get_stack_pointer();
-                self.fill_thread_stack(
-                    buffer,
-                    &mut thread,
-                    instruction_ptr,
-                    stack_pointer,
-                    MaxStackLen::None,
-                )?;
-                // Copy 256 bytes around crashing instruction pointer to minidump.
-                let ip_memory_size: usize = 256;
-                // Bound it to the upper and lower bounds of the memory map
-                // it's contained within. If it's not in mapped memory,
-                // don't bother trying to write it.
-                for mapping in &self.mappings {
-                    if instruction_ptr < mapping.start_address
-                        || instruction_ptr >= mapping.start_address + mapping.size
-                    {
-                        continue;
-                    }
-                    // Try to get 128 bytes before and after the IP, but
-                    // settle for whatever's available.
-                    let mut ip_memory_d = MDMemoryDescriptor {
-                        start_of_memory_range: std::cmp::max(
-                            mapping.start_address,
-                            instruction_ptr - ip_memory_size / 2,
-                        ) as u64,
-                        ..Default::default()
-                    };
+        // Skipped threads, and those after an interruption, leave unused
+        // entries at the end of the list
+        if written < num_threads {
+            list_header.set_value(buffer, written as u32)?;
+            dirent.location.data_size = list_header.location().data_size
+                + thread_list.location_of_index(0).data_size * written as u32;
+        }
+        Ok(dirent)
+    }
 
-                    let end_of_range = std::cmp::min(
-                        mapping.start_address + mapping.size,
-                        instruction_ptr + ip_memory_size / 2,
-                    ) as u64;
-                    ip_memory_d.memory.data_size =
-                        (end_of_range - ip_memory_d.start_of_memory_range) as u32;
+    /// Writes the stack and context of a thread, returning its entry in the
+    /// thread list
+    fn write_thread(
+        &mut self,
+        buffer: &mut DumpBuf,
+        idx: usize,
+        item: &Thread,
+    ) -> Result<MDRawThread, SectionThreadListError> {
+        let mut thread = MDRawThread {
+            thread_id: item.tid.try_into()?,
+            suspend_count: 0,
+            priority_class: 0,
+            priority: 0,
+            teb: 0,
+            stack: MDMemoryDescriptor::default(),
+            thread_context: MDLocationDescriptor::default(),
+        };
 
-                    let memory_copy = MinidumpWriter::copy_from_process(
-                        thread.thread_id as i32,
-                        ip_memory_d.start_of_memory_range as _,
-                        ip_memory_d.memory.data_size as usize,
-                    )?;
+        // We have a different source of information for the crashing thread. If
+        // we used the actual state of the thread we would find it running in the
+        // signal handler with the alternative stack, which would be deeply
+        // unhelpful.
+        if self.crash_context.is_some() && thread.thread_id == self.blamed_thread as u32 {
+            let crash_context = self.crash_context.as_ref().unwrap();
+            let instruction_ptr = crash_context.get_instruction_pointer();
+            let stack_pointer = crash_context.get_stack_pointer();
+            self.fill_thread_stack(buffer, &mut thread, instruction_ptr, stack_pointer)?;
+            // Copy 256 bytes around crashing instruction pointer to minidump.
+            self.release_size(SizePriority::CrashingThreadStack, IP_MEMORY_SIZE);
+            if let Some(region) = self.ip_memory_region(instruction_ptr) {
+                let length = self.fit_memory_region(
+                    buffer,
+                    SizePriority::CrashingThreadStack,
+                    region.start_address,
+                    region.length,
+                );
+                if length > 0 {
+                    let memory_copy =
+                        self.copy_memory(thread.thread_id as i32, region.start_address, length)?;
 
                     let mem_section = MemoryArrayWriter::alloc_from_array(buffer, &memory_copy)?;
-                    ip_memory_d.memory = mem_section.location();
-                    self.memory_blocks.push(ip_memory_d);
-                    break;
+                    self.memory_blocks.push(MDMemoryDescriptor {
+                        start_of_memory_range: region.start_address as u64,
+                        memory: mem_section.location(),
+                    });
                 }
-                // let cpu = MemoryWriter::alloc(buffer, &memory_copy)?;
-                let mut cpu: RawContextCPU = Default::default();
-                let crash_context = self.crash_context.as_ref().unwrap();
-                crash_context.fill_cpu_context(&mut cpu);
-                let cpu_section = MemoryWriter::alloc_with_val(buffer, cpu)?;
-                thread.thread_context = cpu_section.location();
-
-                self.crashing_thread_context =
-                    CrashingThreadContext::CrashContext(cpu_section.location());
-            } else {
-                let info = self.get_thread_info_by_index(idx)?;
-                let max_stack_len =
-                    if self.minidump_size_limit.is_some() && idx >= LIMIT_BASE_THREAD_COUNT {
-                        extra_thread_stack_len
-                    } else {
-                        MaxStackLen::None // default to no maximum for this thread
-                    };
-                let instruction_ptr = info.get_instruction_pointer();
-                self.fill_thread_stack(
-                    buffer,
-                    &mut thread,
+            }
+            // let cpu = MemoryWriter::alloc(buffer, &memory_copy)?;
+            let mut cpu: RawContextCPU = Default::default();
+            let crash_context = self.crash_context.as_ref().unwrap();
+            crash_context.fill_cpu_context(&mut cpu);
+            let siginfo = &crash_context.inner.siginfo;
+            let fault_address = signal_fault_address(siginfo.ssi_signo, siginfo.ssi_addr);
+            self.capture_register_memory(buffer, item.tid, &cpu, fault_address);
+            self.release_size(SizePriority::Essential, std::mem::size_of_val(&cpu));
+            let cpu_section = MemoryWriter::alloc_with_val(buffer, cpu)?;
+            thread.thread_context = cpu_section.location();
+
+            self.crashing_thread_context =
+                CrashingThreadContext::CrashContext(cpu_section.location());
+        } else {
+            let (instruction_ptr, stack_pointer, cpu) = self.get_thread_context_by_index(idx)?;
+            self.fill_thread_stack(buffer, &mut thread, instruction_ptr, stack_pointer)?;
+
+            if item.tid == self.blamed_thread
+                || self
+                    .register_memory
+                    .is_some_and(|options| options.all_threads)
+            {
+                // A core file records the faulting address of the signal
+                // that killed the process
+                let fault_address = self
+                    .core_file
+                    .as_ref()
+                    .filter(|_| item.tid == self.blamed_thread)
+                    .and_then(|core| core.signal)
+                    .and_then(|signal| signal_fault_address(signal.signo, signal.address));
+                self.capture_register_memory(buffer, item.tid, &cpu, fault_address);
+            }
+            self.release_size(SizePriority::Essential, std::mem::size_of_val(&cpu));
+            let cpu_section = MemoryWriter::<RawContextCPU>::alloc_with_val(buffer, cpu)?;
+            thread.thread_context = cpu_section.location();
+            if item.tid == self.blamed_thread {
+                // This is the crashing thread of a live process, but
+                // no context was provided, so set the crash address
+                // while the instruction pointer is already here.
+                self.crashing_thread_context = CrashingThreadContext::CrashContextPlusAddress((
+                    cpu_section.location(),
                     instruction_ptr,
-                    info.stack_pointer,
-                    max_stack_len,
-                )?;
-
-                let mut cpu = RawContextCPU::default();
-                info.fill_cpu_context(&mut cpu);
-                let cpu_section = MemoryWriter::<RawContextCPU>::alloc_with_val(buffer, cpu)?;
-                thread.thread_context = cpu_section.location();
-                if item.tid == self.blamed_thread {
-                    // This is the crashing thread of a live process, but
-                    // no context was provided, so set the crash address
-                    // while the instruction pointer is already here.
-                    self.crashing_thread_context = CrashingThreadContext::CrashContextPlusAddress(
-                        (cpu_section.location(), instruction_ptr),
-                    );
-                }
+                ));
             }
-            thread_list.set_value_at(buffer, thread, idx)?;
         }
-        Ok(dirent)
+        Ok(thread)
     }
 
     fn fill_thread_stack(
@@ -196,24 +243,47 @@ impl MinidumpWriter {
         thread: &mut MDRawThread,
         instruction_ptr: usize,
         stack_ptr: usize,
-        max_stack_len: MaxStackLen,
     ) -> Result<(), SectionThreadListError> {
         thread.stack.start_of_memory_range = stack_ptr.try_into()?;
         thread.stack.memory.data_size = 0;
         thread.stack.memory.rva = buffer.position() as u32;
 
         if let Ok((valid_stack_ptr, stack_len)) = self.get_stack_info(stack_ptr) {
-            let stack_len = if let MaxStackLen::Len(max_stack_len) = max_stack_len {
-                min(stack_len, max_stack_len)
+            let priority = if thread.thread_id == self.blamed_thread as u32 {
+                SizePriority::CrashingThreadStack
+            } else {
+                SizePriority::ThreadStacks
+            };
+            self.release_size(priority, stack_len);
+            let requested = stack_len;
+            let stack_len = if self
+                .dropped_stacks
+                .is_some_and(|dropped| priority >= dropped)
+            {
+                0
             } else {
-                stack_len
+                self.fit_memory_block(buffer, priority, stack_len)
             };
+            if stack_len < requested {
+                self.record_trimmed(SizeLimitError::StackTrimmed {
+                    thread_id: thread.thread_id,
+                    requested: requested as u64,
+                    written: stack_len as u64,
+                });
+            }
+            if stack_len == 0 {
+                return Ok(());
+            }
 
-            let mut stack_bytes = MinidumpWriter::copy_from_process(
-                thread.thread_id.try_into()?,
-                valid_stack_ptr,
-                stack_len,
-            )?;
+            let mut stack_bytes =
+                match self.copy_memory(thread.thread_id.try_into()?, valid_stack_ptr, stack_len) {
+                    Ok(stack_bytes) => stack_bytes,
+                    // Without ptrace, reading the memory may not be permitted
+                    Err(_) if self.non_invasive || self.is_unsuspended(thread.thread_id as Pid) => {
+                        return Ok(())
+                    }
+                    Err(e) => return Err(e.into()),
+                };
             let stack_pointer_offset = stack_ptr.saturating_sub(valid_stack_ptr);
             if self.skip_stacks_if_mapping_unreferenced {
                 if let Some(principal_mapping) = &self.principal_mapping {
@@ -230,6 +300,11 @@ impl MinidumpWriter {
                 }
             }
 
+            // Indirectly referenced memory is found before sanitization, which
+            // replaces the pointers
+            let unsanitized_stack = (self.sanitize_stack && self.indirect_memory.is_some())
+                .then(|| stack_bytes.clone());
+
             if self.sanitize_stack {
                 self.sanitize_stack_copy(&mut stack_bytes, stack_ptr, stack_pointer_offset)
                     .map_err(|e| SectionThreadListError::SanitizeStackCopyFailed(Box::new(e)))?;
@@ -243,7 +318,75 @@ impl MinidumpWriter {
             thread.stack.start_of_memory_range = valid_stack_ptr as u64;
             thread.stack.memory = stack_location;
             self.memory_blocks.push(thread.stack);
+
+            self.capture_indirect_memory(
+                buffer,
+                thread.thread_id.try_into()?,
+                unsanitized_stack.as_deref().unwrap_or(&stack_bytes),
+                valid_stack_ptr,
+                stack_pointer_offset,
+            );
         }
         Ok(())
     }
+
+    /// Returns the memory around the crashing instruction pointer that is
+    /// written with the thread list. It is bounded to the mapping it's
+    /// contained within, and if it's not in mapped memory, there is none.
+    pub(super) fn ip_memory_region(&self, instruction_ptr: usize) -> Option<CaptureRegion> {
+        let mapping = self.mappings.iter().find(|mapping| {
+            instruction_ptr >= mapping.start_address
+                && instruction_ptr < mapping.start_address + mapping.size
+        })?;
+        // Try to get 128 bytes before and after the IP, but settle for
+        // whatever's available.
+        let start = mapping
+            .start_address
+            .max(instruction_ptr.saturating_sub(IP_MEMORY_SIZE / 2));
+        let end = (mapping.start_address + mapping.size).min(instruction_ptr + IP_MEMORY_SIZE / 2);
+        Some(CaptureRegion {
+            start_address: start,
+            length: end - start,
+        })
+    }
+
+    /// Reserves space for the contexts and stacks of every thread, and the
+    /// memory around the crashing instruction pointer, so that they can take
+    /// priority over data written before them when the minidump has a size
+    /// limit
+    fn reserve_thread_stacks(&mut self) {
+        for (idx, item) in self.threads.clone().iter().enumerate() {
+            self.reserve_size(
+                SizePriority::Essential,
+                std::mem::size_of::<RawContextCPU>(),
+            );
+
+            let crashing = item.tid == self.blamed_thread;
+            let stack_pointer = match &self.crash_context {
+                Some(crash_context) if crashing => {
+                    let stack_pointer = crash_context.get_stack_pointer();
+                    self.reserve_size(SizePriority::CrashingThreadStack, IP_MEMORY_SIZE);
+                    stack_pointer
+                }
+                _ => {
+                    // The thread info is read again when the thread is written
+                    let Ok((_, stack_pointer, _)) = self.get_thread_context_by_index(idx) else {
+                        continue;
+                    };
+                    stack_pointer
+                }
+            };
+
+            let Ok((_, stack_len)) = self.get_stack_info(stack_pointer) else {
+                continue;
+            };
+
+            let priority = if crashing {
+                SizePriority::CrashingThreadStack
+            } else {
+                SizePriority::ThreadStacks
+            };
+            self.reserve_size(priority, stack_len);
+        }
+    }
 }
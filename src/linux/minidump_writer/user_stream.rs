@@ -0,0 +1,107 @@
+use super::{streams::StreamWriter, *};
+
+/// The error type returned by [`UserStream`] callbacks
+pub type UserStreamCallbackError = Box<dyn std::error::Error + Send + Sync>;
+
+type UserStreamCallback =
+    Box<dyn FnMut(&mut DumpBuf, &MinidumpWriter) -> Result<(), UserStreamCallbackError> + Send>;
+
+/// The contents of a [`UserStream`]
+pub enum UserStreamData {
+    /// Bytes that are copied verbatim into the stream
+    Bytes(Vec<u8>),
+    /// A callback that writes the stream contents to the buffer.
+    ///
+    /// The callback is invoked while the threads of the process are still
+    /// suspended, so it can read consistent memory from the process, eg. via
+    /// [`MemReader`](crate::mem_reader::MemReader). The process is kept
+    /// frozen until the minidump is written when there is such a stream.
+    /// Everything written to the buffer by the callback becomes the contents
+    /// of the stream, unless it fails, in which case it is discarded.
+    Callback(UserStreamCallback),
+}
+
+/// A caller provided stream written into the minidump, similarly to the user
+/// streams that can be passed to `MiniDumpWriteDump` on Windows.
+pub struct UserStream {
+    stream_type: u32,
+    data: UserStreamData,
+}
+
+impl UserStream {
+    /// Creates a stream with the specified type containing `bytes`
+    pub fn from_bytes(stream_type: u32, bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            stream_type,
+            data: UserStreamData::Bytes(bytes.into()),
+        }
+    }
+
+    /// Creates a stream with the specified type whose contents are written by
+    /// `callback`
+    pub fn from_fn<F>(stream_type: u32, callback: F) -> Self
+    where
+        F: FnMut(&mut DumpBuf, &MinidumpWriter) -> Result<(), UserStreamCallbackError>
+            + Send
+            + 'static,
+    {
+        Self {
+            stream_type,
+            data: UserStreamData::Callback(Box::new(callback)),
+        }
+    }
+}
+
+impl std::fmt::Debug for UserStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = match &self.data {
+            UserStreamData::Bytes(bytes) => format!("{} bytes", bytes.len()),
+            UserStreamData::Callback(_) => "callback".to_owned(),
+        };
+        f.debug_struct("UserStream")
+            .field("stream_type", &self.stream_type)
+            .field("data", &data)
+            .finish()
+    }
+}
+
+impl StreamWriter for UserStream {
+    fn stream_type(&self) -> u32 {
+        self.stream_type
+    }
+
+    fn needs_frozen_process(&self) -> bool {
+        matches!(self.data, UserStreamData::Callback(_))
+    }
+
+    fn write(
+        &mut self,
+        writer: &mut MinidumpWriter,
+        buffer: &mut DumpBuf,
+        soft_errors: &mut ErrorList<WriterError>,
+    ) -> Result<Option<MDRawDirectory>, WriterError> {
+        let location = match &mut self.data {
+            UserStreamData::Bytes(bytes) => {
+                MemoryArrayWriter::write_bytes(buffer, bytes).location()
+            }
+            UserStreamData::Callback(callback) => {
+                let start = buffer.position();
+                if let Err(e) = callback(buffer, writer) {
+                    buffer.truncate(start);
+                    soft_errors.push(WriterError::WriteUserStreamFailed(self.stream_type, e));
+                    return Ok(None);
+                }
+
+                MDLocationDescriptor {
+                    data_size: (buffer.position() - start).try_into()?,
+                    rva: start.try_into()?,
+                }
+            }
+        };
+
+        Ok(Some(MDRawDirectory {
+            stream_type: self.stream_type,
+            location,
+        }))
+    }
+}
@@ -3,15 +3,24 @@
 
 #[cfg(target_os = "android")]
 mod android;
+pub mod annotations;
 pub mod app_memory;
 pub(crate) mod auxv;
+pub mod core_file;
+pub mod crash_catcher;
 pub mod crash_context;
+pub mod crash_generation;
+pub mod crashpad_info;
 mod dso_debug;
 mod dumper_cpu_info;
 pub mod maps_reader;
 pub mod mem_reader;
+pub mod memory_capture;
+pub mod minidump_builder;
 pub mod minidump_writer;
 pub mod module_reader;
+pub mod pattern;
+pub mod privacy;
 mod serializers;
 pub mod thread_info;
 
@@ -12,6 +12,10 @@ type Buf<'buf> = Cow<'buf, [u8]>;
 type Error = ModuleReaderError;
 
 const NOTE_SECTION_NAME: &[u8] = b".note.gnu.build-id\0";
+const CRASHPAD_NOTE_SECTION_NAME: &[u8] = b".note.crashpad.info\0";
+const CRASHPAD_NOTE_NAME: &str = "Crashpad";
+/// `CRASHPAD_ELF_NOTE_TYPE_CRASHPAD_INFO`, 'INFO'
+const CRASHPAD_NOTE_TYPE_INFO: u32 = 0x4f464e49;
 
 pub struct ProcessReader {
     inner: MemReader,
@@ -48,6 +52,12 @@ pub enum ModuleReaderError {
     NoStrTab,
     #[error("no build id note sections")]
     NoSectionNote,
+    #[error("no Crashpad info note in program headers")]
+    NoCrashpadInfoProgramHeaderNote,
+    #[error("no Crashpad info note section")]
+    NoCrashpadInfoSectionNote,
+    #[error("the Crashpad info note has an invalid size ({0})")]
+    InvalidCrashpadInfoNote(usize),
     #[error("the ELF data contains no program headers")]
     NoProgramHeaders,
     #[error("the ELF data contains no sections")]
@@ -82,12 +92,25 @@ pub enum ModuleReaderError {
         program_headers: Box<Self>,
         section: Box<Self>,
     },
+    #[error(
+        "failed to locate Crashpad info\n\
+    ... from program headers: {program_headers}\n\
+    ... from sections: {section}"
+    )]
+    NoCrashpadInfo {
+        program_headers: Box<Self>,
+        section: Box<Self>,
+    },
 }
 
 impl ProcessReader {
     pub fn new(pid: i32, start_address: usize) -> Self {
+        Self::from_mem_reader(MemReader::new(pid), start_address)
+    }
+
+    pub fn from_mem_reader(inner: MemReader, start_address: usize) -> Self {
         Self {
-            inner: MemReader::new(pid),
+            inner,
             start_address: start_address as u64,
         }
     }
@@ -161,6 +184,17 @@ impl<'buf> ProcessMemory<'buf> {
     }
 }
 
+/// The virtual address the start of the module is linked at, which is 0 for
+/// position independent modules, and eg. `0x400000` for non-PIE executables.
+/// Subtracting it from a virtual address of the module gives the offset from
+/// the start of its mapping.
+fn load_base(program_headers: &[elf::ProgramHeader]) -> u64 {
+    program_headers
+        .iter()
+        .find(|header| header.p_type == elf::program_header::PT_LOAD)
+        .map_or(0, |header| header.p_vaddr.wrapping_sub(header.p_offset))
+}
+
 #[inline]
 fn is_executable_section(header: &elf::SectionHeader) -> bool {
     header.sh_type == elf::section_header::SHT_PROGBITS
@@ -314,6 +348,35 @@ impl ReadFromModule for SoName {
     }
 }
 
+/// The virtual address of the module's Crashpad `CrashpadInfo` structure,
+/// relative to the load address of the module.
+///
+/// Modules linked against the Crashpad client contain a `Crashpad` note whose
+/// description is the offset from the note description to `g_crashpad_info`.
+///
+/// The address is relative to the start of the module, ie. the load bias has
+/// to be added to it, which is the start address of the module's mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrashpadInfoAddress(pub u64);
+
+impl ReadFromModule for CrashpadInfoAddress {
+    fn read_from_module(module_memory: ProcessMemory<'_>) -> Result<Self, Error> {
+        let mut reader = ModuleReader::new(module_memory)?;
+        let program_headers = match reader.crashpad_info_from_program_headers() {
+            Ok(v) => return Ok(CrashpadInfoAddress(v)),
+            Err(e) => Box::new(e),
+        };
+        let section = match reader.crashpad_info_from_section() {
+            Ok(v) => return Ok(CrashpadInfoAddress(v)),
+            Err(e) => Box::new(e),
+        };
+        Err(Error::NoCrashpadInfo {
+            program_headers,
+            section,
+        })
+    }
+}
+
 pub struct ModuleReader<'buf> {
     module_memory: ProcessMemory<'buf>,
     header: elf::Header,
@@ -431,9 +494,13 @@ impl<'buf> ModuleReader<'buf> {
             if header.p_type != elf::program_header::PT_NOTE {
                 continue;
             }
-            if let Ok(Some(result)) =
-                self.find_build_id_note(header.p_offset, header.p_filesz, header.p_align)
-            {
+            if let Ok(Some((_, result))) = self.find_note(
+                header.p_offset,
+                header.p_filesz,
+                header.p_align,
+                "GNU",
+                elf::note::NT_GNU_BUILD_ID,
+            ) {
                 return Ok(result);
             }
         }
@@ -452,13 +519,95 @@ impl<'buf> ModuleReader<'buf> {
         )?
         .ok_or(Error::NoSectionNote)?;
 
-        match self.find_build_id_note(header.sh_offset, header.sh_size, header.sh_addralign) {
-            Ok(Some(v)) => Ok(v),
+        match self.find_note(
+            header.sh_offset,
+            header.sh_size,
+            header.sh_addralign,
+            "GNU",
+            elf::note::NT_GNU_BUILD_ID,
+        ) {
+            Ok(Some((_, v))) => Ok(v),
             Ok(None) => Err(Error::NoSectionNote),
             Err(e) => Err(e),
         }
     }
 
+    /// Locate the Crashpad info structure from a program header note, relative
+    /// to the start of the module.
+    pub fn crashpad_info_from_program_headers(&mut self) -> Result<u64, Error> {
+        let program_headers = self.read_program_headers()?;
+        let base = load_base(&program_headers);
+        for header in program_headers {
+            if header.p_type != elf::program_header::PT_NOTE {
+                continue;
+            }
+            let vaddr = header.p_vaddr.wrapping_sub(base);
+            let (offset, size) = if self.module_memory.is_process_memory() {
+                (vaddr, header.p_memsz)
+            } else {
+                (header.p_offset, header.p_filesz)
+            };
+            if let Ok(Some((desc_offset, desc))) = self.find_note(
+                offset,
+                size,
+                header.p_align,
+                CRASHPAD_NOTE_NAME,
+                CRASHPAD_NOTE_TYPE_INFO,
+            ) {
+                return self.crashpad_info_from_note(vaddr + desc_offset, &desc);
+            }
+        }
+        Err(Error::NoCrashpadInfoProgramHeaderNote)
+    }
+
+    /// Locate the Crashpad info structure from its notes section, relative to
+    /// the start of the module.
+    pub fn crashpad_info_from_section(&mut self) -> Result<u64, Error> {
+        let base = match self.read_program_headers() {
+            Ok(program_headers) => load_base(&program_headers),
+            Err(_) => 0,
+        };
+        let section_headers = self.read_section_headers()?;
+
+        let header = section_header_with_name(
+            &section_headers,
+            self.header.e_shstrndx as usize,
+            CRASHPAD_NOTE_SECTION_NAME,
+            &mut self.module_memory,
+        )?
+        .ok_or(Error::NoCrashpadInfoSectionNote)?;
+
+        let vaddr = header.sh_addr.wrapping_sub(base);
+        let offset = if self.module_memory.is_process_memory() {
+            vaddr
+        } else {
+            header.sh_offset
+        };
+        match self.find_note(
+            offset,
+            header.sh_size,
+            header.sh_addralign,
+            CRASHPAD_NOTE_NAME,
+            CRASHPAD_NOTE_TYPE_INFO,
+        )? {
+            Some((desc_offset, desc)) => self.crashpad_info_from_note(vaddr + desc_offset, &desc),
+            None => Err(Error::NoCrashpadInfoSectionNote),
+        }
+    }
+
+    /// The note description is a pointer sized offset from the description
+    /// itself (at `desc_address`) to the Crashpad info structure.
+    fn crashpad_info_from_note(&self, desc_address: u64, desc: &[u8]) -> Result<u64, Error> {
+        use scroll::Pread;
+        let delta = match (self.context.container, desc.len()) {
+            (Container::Big, 8) => desc.pread_with::<i64>(0, self.context.le),
+            (Container::Little, 4) => desc.pread_with::<i32>(0, self.context.le).map(i64::from),
+            (_, len) => return Err(Error::InvalidCrashpadInfoNote(len)),
+        }
+        .map_err(goblin::error::Error::from)?;
+        Ok(desc_address.wrapping_add_signed(delta))
+    }
+
     /// Generate a build id by hashing the first page of the text section.
     pub fn build_id_generate_from_text(&mut self) -> Result<Vec<u8>, Error> {
         let Some(text_header) = self
@@ -544,12 +693,16 @@ impl<'buf> ModuleReader<'buf> {
         Ok(section_headers)
     }
 
-    fn find_build_id_note(
+    /// Find the note with the specified name and type, returning the offset of
+    /// its description relative to `offset` along with the description.
+    fn find_note(
         &mut self,
         offset: u64,
         size: u64,
         alignment: u64,
-    ) -> Result<Option<Vec<u8>>, Error> {
+        name: &str,
+        n_type: u32,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
         let notes = self.module_memory.read(offset, size)?;
         for note in (elf::note::NoteDataIterator {
             data: &notes,
@@ -560,8 +713,10 @@ impl<'buf> ModuleReader<'buf> {
             ctx: (alignment as usize, self.context),
         }) {
             let Ok(note) = note else { break };
-            if note.name == "GNU" && note.n_type == elf::note::NT_GNU_BUILD_ID {
-                return Ok(Some(note.desc.to_owned()));
+            if note.name == name && note.n_type == n_type {
+                // The description always borrows from `notes`
+                let desc_offset = note.desc.as_ptr() as usize - notes.as_ptr() as usize;
+                return Ok(Some((desc_offset as u64, note.desc.to_owned())));
             }
         }
         Ok(None)
@@ -687,4 +842,59 @@ mod test {
         let soname = reader.soname_from_sections().unwrap();
         assert_eq!(soname, "libfoo.so.1");
     }
+
+    /// A 64-bit little-endian non-PIE executable linked at `0x400000`, with a
+    /// Crashpad note pointing at `g_crashpad_info` at `0x400100`
+    fn crashpad_elf() -> Vec<u8> {
+        const BASE: u64 = 0x400000;
+        const NOTE_OFFSET: u64 = 64 + 2 * 56;
+        const NOTE_SIZE: u64 = 12 + 12 + 8;
+        const INFO_OFFSET: u64 = 0x100;
+
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        elf.extend_from_slice(&elf::header::ET_EXEC.to_le_bytes());
+        elf.extend_from_slice(&elf::header::EM_X86_64.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        for value in [BASE, 64, 0] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for value in [64u16, 56, 2, 64, 0, 0] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for (p_type, offset, size, align) in [
+            (elf::program_header::PT_LOAD, 0, 0x200, 0x1000),
+            (elf::program_header::PT_NOTE, NOTE_OFFSET, NOTE_SIZE, 4),
+        ] {
+            elf.extend_from_slice(&p_type.to_le_bytes());
+            elf.extend_from_slice(&4u32.to_le_bytes());
+            for value in [offset, BASE + offset, BASE + offset, size, size, align] {
+                elf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        elf.extend_from_slice(&9u32.to_le_bytes());
+        elf.extend_from_slice(&8u32.to_le_bytes());
+        elf.extend_from_slice(&CRASHPAD_NOTE_TYPE_INFO.to_le_bytes());
+        elf.extend_from_slice(b"Crashpad\0\0\0\0");
+        let desc_offset = elf.len() as u64;
+        elf.extend_from_slice(&(INFO_OFFSET as i64 - desc_offset as i64).to_le_bytes());
+        elf.resize(0x200, 0);
+        elf
+    }
+
+    #[test]
+    fn crashpad_info_non_pie() {
+        let elf = crashpad_elf();
+
+        let mut reader = ModuleReader::new(elf.as_slice().into()).unwrap();
+        assert_eq!(reader.crashpad_info_from_program_headers().unwrap(), 0x100);
+
+        // In memory, the module is read relative to the start of its mapping
+        let process = ProcessReader::new(std::process::id() as i32, elf.as_ptr() as usize);
+        let mut reader = ModuleReader::new(process.into()).unwrap();
+        assert_eq!(reader.crashpad_info_from_program_headers().unwrap(), 0x100);
+    }
 }
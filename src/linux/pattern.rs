@@ -0,0 +1,80 @@
+//! Simple wildcard patterns used to select items by name
+
+/// A wildcard pattern, where `*` matches any (possibly empty) sequence of
+/// bytes and `?` matches exactly one byte. All other bytes match themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(Vec<u8>);
+
+impl Pattern {
+    pub fn new(pattern: impl Into<Vec<u8>>) -> Self {
+        Self(pattern.into())
+    }
+
+    /// Returns true if the pattern matches all of `text`
+    pub fn matches(&self, text: &[u8]) -> bool {
+        let pattern = &self.0;
+        let (mut p, mut t) = (0, 0);
+        // The position of the last `*` seen in the pattern, and the position
+        // in the text it was matched against, so we can backtrack
+        let mut star = None;
+
+        while t < text.len() {
+            match pattern.get(p) {
+                Some(b'*') => {
+                    star = Some((p, t));
+                    p += 1;
+                }
+                Some(&c) if c == b'?' || c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                _ => {
+                    let Some((sp, st)) = star else {
+                        return false;
+                    };
+                    // Let the last `*` consume one more byte
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+            }
+        }
+
+        pattern[p..].iter().all(|&c| c == b'*')
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(s: &str) -> Self {
+        Self::new(s)
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(s: String) -> Self {
+        Self::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        let matches = |p: &str, t: &str| Pattern::from(p).matches(t.as_bytes());
+
+        assert!(matches("[heap]", "[heap]"));
+        assert!(!matches("[heap]", "[heap]x"));
+        assert!(matches("memfd:*", "memfd:"));
+        assert!(matches("memfd:*", "memfd:wayland-shm (deleted)"));
+        assert!(!matches("memfd:*", "/memfd:"));
+        assert!(matches("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(!matches("*_TOKEN", "GITHUB_TOKENS"));
+        assert!(matches("*SECRET*", "MY_SECRET_KEY"));
+        assert!(matches("a?c*f", "abcdef"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*", ""));
+        assert!(!matches("", "a"));
+    }
+}
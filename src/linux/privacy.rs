@@ -0,0 +1,230 @@
+//! Filtering of potentially sensitive process information, such as the
+//! environment and command line, before it is written to a minidump.
+//!
+//! Redacted values are replaced with [`REDACTED`] rather than removed, so that
+//! the minidump records that redaction took place.
+//!
+//! The filters only apply to the copies of `/proc/<pid>/environ` and
+//! `/proc/<pid>/cmdline` written to the `LinuxEnviron` and `LinuxCmdLine`
+//! streams, and to the command line in the notes of a core dump. They do not
+//! apply to the memory of the process: the environment
+//! and arguments are stored at the top of the main thread's stack, so they
+//! are still in its stack memory, in memory captured by rules, and in the
+//! full memory or the loadable segments of a core dump. Use
+//! [`sanitize_stack`](super::minidump_writer::MinidumpWriterConfig::sanitize_stack)
+//! and avoid capturing the stack mapping when this matters.
+
+use super::pattern::Pattern;
+
+/// The marker that replaces redacted values
+pub const REDACTED: &[u8] = b"<redacted>";
+
+/// Selects variables by name
+#[derive(Debug, Default, Clone)]
+pub enum NameFilter {
+    /// Every name is selected
+    #[default]
+    All,
+    /// Only names matching one of the patterns are selected
+    AllowList(Vec<Pattern>),
+    /// Only names that don't match any of the patterns are selected
+    DenyList(Vec<Pattern>),
+}
+
+impl NameFilter {
+    pub fn is_selected(&self, name: &[u8]) -> bool {
+        match self {
+            Self::All => true,
+            Self::AllowList(allowed) => allowed.iter().any(|p| p.matches(name)),
+            Self::DenyList(denied) => !denied.iter().any(|p| p.matches(name)),
+        }
+    }
+}
+
+/// Filter applied to the contents of `/proc/<pid>/environ`
+#[derive(Debug, Default, Clone)]
+pub struct EnvironFilter {
+    /// The variables whose values are kept, the values of all other variables
+    /// are redacted
+    pub names: NameFilter,
+    /// The values of variables that match any of these patterns are redacted,
+    /// even if the variable is selected by `names`
+    pub value_patterns: Vec<Pattern>,
+}
+
+impl EnvironFilter {
+    /// Applies the filter to the NUL separated `NAME=value` entries of an
+    /// environment block
+    ///
+    /// Entries without a `=` are matched against both `names` and
+    /// `value_patterns`, and are replaced by [`REDACTED`] entirely when
+    /// redacted.
+    pub fn apply(&self, environ: &[u8]) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(environ.len());
+
+        for entry in split_nul(environ) {
+            let (name, value) = match entry.iter().position(|&b| b == b'=') {
+                Some(eq) => (&entry[..eq], Some(&entry[eq + 1..])),
+                None => (entry, None),
+            };
+
+            let redact = !self.names.is_selected(name)
+                || self
+                    .value_patterns
+                    .iter()
+                    .any(|p| p.matches(value.unwrap_or(entry)));
+
+            if redact {
+                // An entry without a separator has no value to redact, so the
+                // whole entry is redacted rather than inventing one
+                if value.is_some() {
+                    filtered.extend_from_slice(name);
+                    filtered.push(b'=');
+                }
+                filtered.extend_from_slice(REDACTED);
+            } else {
+                filtered.extend_from_slice(entry);
+            }
+            filtered.push(0);
+        }
+
+        filtered
+    }
+}
+
+/// Filter applied to the contents of `/proc/<pid>/cmdline`
+///
+/// The first argument (the program) is never redacted.
+#[derive(Debug, Default, Clone)]
+pub struct CmdLineFilter {
+    /// Redacts every argument
+    pub redact_all: bool,
+    /// Flags whose values are redacted, either the argument following the
+    /// flag (`--password hunter2`) or the part after `=` (`--password=hunter2`)
+    pub value_flags: Vec<Pattern>,
+    /// Arguments matching any of these patterns are redacted. If the argument
+    /// is of the form `--flag=value` only the value is redacted
+    pub patterns: Vec<Pattern>,
+}
+
+impl CmdLineFilter {
+    /// Applies the filter to the NUL separated arguments of a command line
+    pub fn apply(&self, cmdline: &[u8]) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(cmdline.len());
+        let mut redact_next = false;
+
+        for (i, arg) in split_nul(cmdline).enumerate() {
+            let is_value_flag = |flag: &Pattern| flag.matches(arg);
+            let flag_value = arg.iter().position(|&b| b == b'=').and_then(|eq| {
+                let (flag, value) = (&arg[..eq], &arg[eq + 1..]);
+                self.value_flags
+                    .iter()
+                    .any(|pattern| pattern.matches(flag))
+                    .then_some(value)
+            });
+
+            if i == 0 {
+                filtered.extend_from_slice(arg);
+            } else if self.redact_all || redact_next {
+                filtered.extend_from_slice(REDACTED);
+            } else if let Some(value) = flag_value {
+                filtered.extend_from_slice(&arg[..arg.len() - value.len()]);
+                filtered.extend_from_slice(REDACTED);
+            } else if self.patterns.iter().any(|p| p.matches(arg)) {
+                if let Some(eq) = arg.iter().position(|&b| b == b'=') {
+                    filtered.extend_from_slice(&arg[..=eq]);
+                }
+                filtered.extend_from_slice(REDACTED);
+            } else {
+                filtered.extend_from_slice(arg);
+            }
+            filtered.push(0);
+
+            redact_next = i > 0 && self.value_flags.iter().any(is_value_flag);
+        }
+
+        filtered
+    }
+}
+
+/// Splits a NUL separated (and usually NUL terminated) block
+fn split_nul(block: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let block = block.strip_suffix(b"\0").unwrap_or(block);
+    block.split(|&b| b == 0).filter(|_| !block.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environ_deny_list() {
+        let filter = EnvironFilter {
+            names: NameFilter::DenyList(vec!["*_TOKEN".into(), "AWS_*".into()]),
+            value_patterns: vec!["ghp_*".into()],
+        };
+
+        let environ = b"HOME=/home/user\0GITHUB_TOKEN=abc\0AWS_SECRET=xyz\0OTHER=ghp_1234\0EMPTY\0";
+        assert_eq!(
+            filter.apply(environ),
+            b"HOME=/home/user\0GITHUB_TOKEN=<redacted>\0AWS_SECRET=<redacted>\0OTHER=<redacted>\0EMPTY\0"
+        );
+    }
+
+    #[test]
+    fn environ_allow_list() {
+        let filter = EnvironFilter {
+            names: NameFilter::AllowList(vec!["HOME".into(), "LANG".into()]),
+            ..Default::default()
+        };
+
+        let environ = b"HOME=/home/user\0SESSION=abc\0LANG=C";
+        assert_eq!(
+            filter.apply(environ),
+            b"HOME=/home/user\0SESSION=<redacted>\0LANG=C\0"
+        );
+    }
+
+    #[test]
+    fn environ_without_separator() {
+        let filter = EnvironFilter {
+            names: NameFilter::AllowList(vec!["HOME".into(), "*VISIBLE".into()]),
+            value_patterns: vec!["*secret*".into()],
+        };
+
+        let environ = b"HOME=/home/user\0HIDDEN\0VISIBLE\0my_secret_VISIBLE\0";
+        assert_eq!(
+            filter.apply(environ),
+            b"HOME=/home/user\0<redacted>\0VISIBLE\0<redacted>\0"
+        );
+    }
+
+    #[test]
+    fn cmdline() {
+        let filter = CmdLineFilter {
+            value_flags: vec!["--password".into(), "--api-*".into()],
+            patterns: vec!["--token=*".into(), "secret*".into()],
+            ..Default::default()
+        };
+
+        let cmdline =
+            b"/usr/bin/app\0--password\0hunter2\0--password=hunter2\0--token=abc\0secret.txt\0-v\0";
+        assert_eq!(
+            filter.apply(cmdline),
+            b"/usr/bin/app\0--password\0<redacted>\0--password=<redacted>\0--token=<redacted>\0<redacted>\0-v\0"
+        );
+        assert_eq!(
+            filter.apply(b"/usr/bin/app\0--api-key\0abc\0--api-secret=xyz\0"),
+            b"/usr/bin/app\0--api-key\0<redacted>\0--api-secret=<redacted>\0"
+        );
+
+        let filter = CmdLineFilter {
+            redact_all: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.apply(b"/usr/bin/app\0-v\0--password\0"),
+            b"/usr/bin/app\0<redacted>\0<redacted>\0"
+        );
+    }
+}
@@ -38,3 +38,12 @@ pub fn serialize_system_time_error<S: Serializer>(
 ) -> Result<S::Ok, S::Error> {
     serialize_generic_error(error, serializer)
 }
+/// Serialize boxed errors, which don't implement [Error][std::error::Error] themselves
+#[allow(clippy::borrowed_box)]
+pub fn serialize_boxed_error<S: Serializer>(
+    error: &Box<dyn std::error::Error + Send + Sync>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let dbg = format!("{error:#?}");
+    serializer.serialize_str(&dbg)
+}
@@ -1,5 +1,8 @@
 use {
-    super::{CommonThreadInfo, NT_Elf, Pid, ThreadInfoError},
+    super::{
+        super::core_file::{CoreThread, NT_PRFPREG},
+        core_note_bytes, read_core_note, CommonThreadInfo, NT_Elf, Pid, ThreadInfoError,
+    },
     crate::minidump_cpu::{RawContextCPU, FP_REG_COUNT, GP_REG_COUNT},
     nix::sys::ptrace,
 };
@@ -100,4 +103,48 @@ impl ThreadInfoAarch64 {
             fpregs,
         })
     }
+
+    pub fn from_core_impl(tgid: Pid, thread: &CoreThread) -> Result<Self> {
+        let regs: libc::user_regs_struct = read_core_note("NT_PRSTATUS", Some(thread.registers()))?;
+        let fpregs = read_core_note("NT_PRFPREG", thread.note(NT_PRFPREG))
+            .unwrap_or_else(|_| unsafe { std::mem::zeroed() });
+
+        Ok(Self {
+            stack_pointer: regs.sp as usize,
+            tgid,
+            ppid: thread.ppid,
+            regs,
+            fpregs,
+        })
+    }
+
+    pub fn from_proc_impl(
+        tgid: Pid,
+        ppid: Pid,
+        stack_pointer: usize,
+        instruction_pointer: usize,
+    ) -> Self {
+        // SAFETY: The register sets are plain C structs
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.sp = stack_pointer as u64;
+        regs.pc = instruction_pointer as u64;
+
+        Self {
+            stack_pointer,
+            tgid,
+            ppid,
+            regs,
+            fpregs: unsafe { std::mem::zeroed() },
+        }
+    }
+
+    /// The `pr_reg` of the thread's `NT_PRSTATUS` note in a core file
+    pub fn core_registers(&self) -> Vec<u8> {
+        core_note_bytes(&self.regs)
+    }
+
+    /// The other register notes of the thread in a core file
+    pub fn core_notes(&self) -> Vec<(u32, Vec<u8>)> {
+        vec![(NT_PRFPREG, core_note_bytes(&self.fpregs))]
+    }
 }
@@ -1,5 +1,8 @@
 use {
-    super::{CommonThreadInfo, NT_Elf, Pid, ThreadInfoError},
+    super::{
+        super::core_file::{CoreThread, NT_ARM_VFP},
+        core_note_bytes, read_core_note, CommonThreadInfo, NT_Elf, Pid, ThreadInfoError,
+    },
     crate::minidump_cpu::RawContextCPU,
     nix::sys::ptrace,
 };
@@ -18,7 +21,7 @@ pub struct user_fpregs_struct {
 #[repr(C)]
 #[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Default)]
 pub struct user_regs_struct {
-    uregs: [u32; 18],
+    pub uregs: [u32; 18],
 }
 
 #[derive(Debug)]
@@ -80,4 +83,45 @@ impl ThreadInfoArm {
             fpregs,
         })
     }
+    pub fn from_core_impl(tgid: Pid, thread: &CoreThread) -> Result<Self> {
+        let regs: user_regs_struct = read_core_note("NT_PRSTATUS", Some(thread.registers()))?;
+        let fpregs = read_core_note("NT_ARM_VFP", thread.note(NT_ARM_VFP)).unwrap_or_default();
+
+        Ok(ThreadInfoArm {
+            stack_pointer: regs.uregs[13] as usize,
+            tgid,
+            ppid: thread.ppid,
+            regs,
+            fpregs,
+        })
+    }
+
+    pub fn from_proc_impl(
+        tgid: Pid,
+        ppid: Pid,
+        stack_pointer: usize,
+        instruction_pointer: usize,
+    ) -> Self {
+        let mut regs = user_regs_struct::default();
+        regs.uregs[13] = stack_pointer as u32;
+        regs.uregs[15] = instruction_pointer as u32;
+
+        ThreadInfoArm {
+            stack_pointer,
+            tgid,
+            ppid,
+            regs,
+            fpregs: Default::default(),
+        }
+    }
+
+    /// The `pr_reg` of the thread's `NT_PRSTATUS` note in a core file
+    pub fn core_registers(&self) -> Vec<u8> {
+        core_note_bytes(&self.regs)
+    }
+
+    /// The other register notes of the thread in a core file
+    pub fn core_notes(&self) -> Vec<(u32, Vec<u8>)> {
+        vec![(NT_ARM_VFP, core_note_bytes(&self.fpregs))]
+    }
 }
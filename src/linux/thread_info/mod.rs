@@ -1,7 +1,8 @@
 use {
-    super::{serializers::*, Pid},
+    super::{core_file::CoreThread, serializers::*, Pid},
     crate::serializers::*,
     nix::{errno::Errno, sys::ptrace},
+    procfs_core::{process::Stat, FromRead},
     std::{
         io::{self, BufRead},
         path,
@@ -36,6 +37,20 @@ pub enum ThreadInfoError {
     ),
     #[error("Invalid line in /proc/{0}/status: {1}")]
     InvalidProcStatusFile(Pid, String),
+    #[error("The core file has no {0} note for the thread")]
+    MissingCoreNote(&'static str),
+    #[error("The {0} note in the core file is too small ({1} bytes)")]
+    CoreNoteTooSmall(&'static str, usize),
+    #[error("The snapshot only has the context of the thread")]
+    SnapshotContextOnly,
+    #[error("The context of thread {0} wasn't captured")]
+    NotCaptured(Pid),
+    #[error("Failed to read from procfs")]
+    ProcfsError(
+        #[from]
+        #[serde(serialize_with = "serialize_proc_error")]
+        procfs_core::ProcError,
+    ),
 }
 
 cfg_if::cfg_if! {
@@ -187,8 +202,135 @@ trait CommonThreadInfo {
         }
     }
 }
+/// Reads a register set from the contents of a note in a core file
+fn read_core_note<T>(name: &'static str, desc: Option<&[u8]>) -> Result<T> {
+    let desc = desc.ok_or(ThreadInfoError::MissingCoreNote(name))?;
+    if desc.len() < std::mem::size_of::<T>() {
+        return Err(ThreadInfoError::CoreNoteTooSmall(name, desc.len()));
+    }
+    // SAFETY: The register sets are plain C structs for which any bit pattern
+    // is valid, written by the kernel in the same layout as ptrace returns
+    Ok(unsafe { std::ptr::read_unaligned(desc.as_ptr().cast()) })
+}
+
+/// The contents of a note in a core file for a register set
+fn core_note_bytes<T>(regs: &T) -> Vec<u8> {
+    // SAFETY: The register sets are plain C structs
+    unsafe { std::slice::from_raw_parts((regs as *const T).cast::<u8>(), std::mem::size_of::<T>()) }
+        .to_vec()
+}
+
+/// The state of a thread read from procfs rather than with ptrace, so only
+/// the stack and instruction pointers of its registers are known, and only if
+/// reading `/proc/<pid>/task/<tid>/syscall` is permitted
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ProcThreadState {
+    pub tid: Pid,
+    /// The state from `/proc/<pid>/task/<tid>/stat`, eg. `S` or `D`
+    pub state: char,
+    /// The kernel function the thread is waiting in
+    pub wchan: Option<String>,
+    /// The kernel stack of the thread from `/proc/<pid>/task/<tid>/stack`,
+    /// which is only readable with `CAP_SYS_ADMIN`
+    pub kernel_stack: Option<String>,
+    /// The system call the thread is blocked in, followed by its arguments,
+    /// `-1` if it's blocked outside of one, or `running`
+    pub syscall: Option<String>,
+    pub stack_pointer: Option<usize>,
+    pub instruction_pointer: Option<usize>,
+}
+
+impl ProcThreadState {
+    pub fn read(pid: Pid, tid: Pid) -> Result<Self> {
+        let task = format!("/proc/{pid}/task/{tid}");
+        let stat = Stat::from_file(format!("{task}/stat"))?;
+
+        let wchan = std::fs::read_to_string(format!("{task}/wchan"))
+            .ok()
+            .filter(|wchan| !wchan.is_empty() && wchan != "0");
+        let kernel_stack = std::fs::read_to_string(format!("{task}/stack"))
+            .ok()
+            .filter(|stack| !stack.is_empty());
+        let syscall = std::fs::read_to_string(format!("{task}/syscall"))
+            .ok()
+            .map(|syscall| syscall.trim_end().to_owned());
+
+        // The stack and instruction pointers are the last two values, unless
+        // the thread is running
+        let (mut stack_pointer, mut instruction_pointer) = syscall
+            .as_deref()
+            .map(|syscall| syscall.split(' ').collect::<Vec<_>>())
+            .filter(|values| values.len() >= 3)
+            .map_or((None, None), |values| {
+                let parse =
+                    |value: &str| usize::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+                (
+                    parse(values[values.len() - 2]),
+                    parse(values[values.len() - 1]),
+                )
+            });
+        // These are only filled in by the kernel for threads that are dumping
+        // core, but that is better than nothing
+        if stack_pointer.is_none() && stat.kstkesp != 0 {
+            stack_pointer = Some(stat.kstkesp as usize);
+            instruction_pointer = Some(stat.kstkeip as usize);
+        }
+
+        Ok(Self {
+            tid,
+            state: stat.state,
+            wchan,
+            kernel_stack,
+            syscall,
+            stack_pointer,
+            instruction_pointer,
+        })
+    }
+}
+
+/// Limits the context flags to the control registers, which are the only ones
+/// known for a thread read from procfs
+pub fn mark_context_approximate(out: &mut crate::minidump_cpu::RawContextCPU) {
+    use crate::minidump_format::format;
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            out.context_flags = format::ContextFlagsAmd64::CONTEXT_AMD64_CONTROL.bits();
+        } else if #[cfg(target_arch = "x86")] {
+            out.context_flags = format::ContextFlagsX86::CONTEXT_X86_CONTROL.bits();
+        } else if #[cfg(target_arch = "arm")] {
+            out.context_flags = format::ContextFlagsArm::CONTEXT_ARM_CONTROL.bits();
+        } else if #[cfg(target_arch = "aarch64")] {
+            // There is no control register set in the old arm64 context
+            out.context_flags =
+                format::ContextFlagsArm64Old::CONTEXT_ARM64_OLD_INTEGER.bits() as u64;
+        }
+    }
+}
+
 impl ThreadInfo {
     pub fn create(pid: Pid, tid: Pid) -> std::result::Result<Self, ThreadInfoError> {
         Self::create_impl(pid, tid)
     }
+
+    /// Creates the thread info of a thread that couldn't be attached to, from
+    /// its procfs state, all other registers are zero
+    pub fn from_proc(
+        tgid: Pid,
+        state: &ProcThreadState,
+    ) -> std::result::Result<Self, ThreadInfoError> {
+        let (ppid, _) = Self::get_ppid_and_tgid(state.tid)?;
+        Ok(Self::from_proc_impl(
+            tgid,
+            ppid,
+            state.stack_pointer.unwrap_or_default(),
+            state.instruction_pointer.unwrap_or_default(),
+        ))
+    }
+
+    /// Creates the thread info of a thread recorded in a core file of the
+    /// process `tgid`
+    pub fn from_core(tgid: Pid, thread: &CoreThread) -> std::result::Result<Self, ThreadInfoError> {
+        Self::from_core_impl(tgid, thread)
+    }
 }
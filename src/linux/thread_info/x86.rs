@@ -1,5 +1,8 @@
 use {
-    super::{CommonThreadInfo, NT_Elf, Pid, ThreadInfoError},
+    super::{
+        super::core_file::{CoreThread, NT_PRFPREG},
+        core_note_bytes, read_core_note, CommonThreadInfo, NT_Elf, Pid, ThreadInfoError,
+    },
     crate::{minidump_cpu::RawContextCPU, minidump_format::format},
     core::mem::size_of_val,
     nix::sys::ptrace,
@@ -221,6 +224,85 @@ impl ThreadInfoX86 {
         })
     }
 
+    pub fn from_core_impl(tgid: Pid, thread: &CoreThread) -> Result<Self> {
+        let regs: user_regs_struct = read_core_note("NT_PRSTATUS", Some(thread.registers()))?;
+        // The floating point registers are only missing if the thread never
+        // used them
+        let fpregs = read_core_note("NT_PRFPREG", thread.note(NT_PRFPREG))
+            .unwrap_or_else(|_| unsafe { std::mem::zeroed() });
+        #[cfg(target_arch = "x86")]
+        let fpxregs = read_core_note(
+            "NT_PRXFPREG",
+            thread.note(super::super::core_file::NT_PRXFPREG),
+        )
+        .unwrap_or_else(|_| unsafe { std::mem::zeroed() });
+
+        #[cfg(target_arch = "x86_64")]
+        let stack_pointer = regs.rsp as usize;
+        #[cfg(target_arch = "x86")]
+        let stack_pointer = regs.esp as usize;
+
+        Ok(Self {
+            stack_pointer,
+            tgid,
+            ppid: thread.ppid,
+            regs,
+            fpregs,
+            // Debug registers are not included in core files
+            dregs: Default::default(),
+            #[cfg(target_arch = "x86")]
+            fpxregs,
+        })
+    }
+
+    /// The `pr_reg` of the thread's `NT_PRSTATUS` note in a core file
+    pub fn core_registers(&self) -> Vec<u8> {
+        core_note_bytes(&self.regs)
+    }
+
+    /// The other register notes of the thread in a core file
+    pub fn core_notes(&self) -> Vec<(u32, Vec<u8>)> {
+        vec![
+            (NT_PRFPREG, core_note_bytes(&self.fpregs)),
+            #[cfg(target_arch = "x86")]
+            (
+                super::super::core_file::NT_PRXFPREG,
+                core_note_bytes(&self.fpxregs),
+            ),
+        ]
+    }
+
+    pub fn from_proc_impl(
+        tgid: Pid,
+        ppid: Pid,
+        stack_pointer: usize,
+        instruction_pointer: usize,
+    ) -> Self {
+        // SAFETY: The register sets are plain C structs
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        #[cfg(target_arch = "x86_64")]
+        {
+            regs.rsp = stack_pointer as _;
+            regs.rip = instruction_pointer as _;
+        }
+        #[cfg(target_arch = "x86")]
+        {
+            regs.esp = stack_pointer as _;
+            regs.eip = instruction_pointer as _;
+        }
+
+        Self {
+            stack_pointer,
+            tgid,
+            ppid,
+            regs,
+            fpregs: unsafe { std::mem::zeroed() },
+            dregs: Default::default(),
+            #[cfg(target_arch = "x86")]
+            fpxregs: unsafe { std::mem::zeroed() },
+        }
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn get_instruction_pointer(&self) -> usize {
         self.regs.rip as usize
@@ -1,6 +1,6 @@
 use {
     crate::{
-        minidump_format::{MDLocationDescriptor, MDRVA},
+        minidump_format::{MDLocationDescriptor, MDRVA, MDRVA64},
         serializers::*,
     },
     scroll::ctx::{SizeWith, TryIntoCtx},
@@ -26,6 +26,8 @@ pub enum MemoryWriterError {
         #[serde(serialize_with = "serialize_scroll_error")]
         scroll::Error,
     ),
+    #[error("The minidump position {0} can't be addressed by an RVA")]
+    RvaOverflow(u64),
 }
 
 type WriteResult<T> = std::result::Result<T, MemoryWriterError>;
@@ -36,17 +38,123 @@ macro_rules! size {
     };
 }
 
+/// Data that is written after everything else in the minidump.
+///
+/// Trailing data is streamed to the destination when the minidump is
+/// finalized, rather than being held in memory, and is only referenced by
+/// 64-bit RVAs, so it can exceed the 4GiB that can be addressed by the
+/// (32-bit) RVAs of the rest of the minidump.
+///
+/// It is written after the process may have been resumed, so it must not
+/// read from the process, see [`SpooledData`].
+pub trait TrailingData: Send {
+    /// The number of bytes that [`Self::write_to`] writes
+    fn len(&self) -> u64;
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes exactly [`Self::len`] bytes to the destination
+    fn write_to(&mut self, destination: &mut dyn std::io::Write) -> std::io::Result<()>;
+}
+
+/// Trailing data that was written to a temporary file beforehand
+#[derive(Debug)]
+pub struct SpooledData {
+    file: std::fs::File,
+    len: u64,
+}
+
+impl SpooledData {
+    /// Creates an empty temporary file to write the data to
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            file: tempfile::tempfile()?,
+            len: 0,
+        })
+    }
+
+    /// Keeps only the first `len` bytes of the data
+    pub fn truncate(&mut self, len: u64) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl std::io::Write for SpooledData {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.file.write(buf)?;
+        self.len += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl TrailingData for SpooledData {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn write_to(&mut self, destination: &mut dyn std::io::Write) -> std::io::Result<()> {
+        use std::io::{Read, Seek};
+
+        self.file.rewind()?;
+        let copied = std::io::copy(&mut (&mut self.file).take(self.len), destination)?;
+        if copied != self.len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) type TrailingEntry = (MemoryWriter<MDRVA64>, Box<dyn TrailingData>);
+
 pub struct Buffer {
     inner: Vec<u8>,
+    trailing: Vec<TrailingEntry>,
 }
 
 impl Buffer {
     pub fn with_capacity(cap: usize) -> Self {
         Self {
             inner: Vec::with_capacity(cap),
+            trailing: Vec::new(),
         }
     }
 
+    /// Appends data after the end of the minidump. The RVA of the data is
+    /// written to `rva` once the minidump is finalized.
+    pub fn append_trailing_data(
+        &mut self,
+        rva: MemoryWriter<MDRVA64>,
+        data: impl TrailingData + 'static,
+    ) {
+        self.trailing.push((rva, Box::new(data)));
+    }
+
+    #[inline]
+    pub(crate) fn take_trailing_data(&mut self) -> Vec<TrailingEntry> {
+        std::mem::take(&mut self.trailing)
+    }
+
+    /// The total length of the trailing data
+    #[inline]
+    pub fn trailing_len(&self) -> u64 {
+        self.trailing.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// Discards everything written after `position`, including trailing data
+    /// whose RVA would have been written there
+    pub fn truncate(&mut self, position: u64) {
+        self.inner.truncate(position as usize);
+        self.trailing
+            .retain(|(rva, _)| (rva.position as u64) < position);
+    }
+
     #[inline]
     pub fn position(&self) -> u64 {
         self.inner.len() as u64
@@ -287,3 +395,20 @@ pub fn write_string_to_location(
 
     Ok(location)
 }
+
+/// Writes a `MINIDUMP_UTF8_STRING`, ie. the length of the string in bytes,
+/// followed by the NUL terminated UTF-8 bytes of the string
+pub fn write_utf8_string_to_location(
+    buffer: &mut Buffer,
+    text: &str,
+) -> WriteResult<MDLocationDescriptor> {
+    let text_header = MemoryWriter::<u32>::alloc_with_val(buffer, text.len().try_into()?)?;
+
+    let position = buffer.reserve(text.len() + 1);
+    buffer.inner[position..position + text.len()].copy_from_slice(text.as_bytes());
+
+    let mut location = text_header.location();
+    location.data_size += (text.len() + 1) as u32;
+
+    Ok(location)
+}
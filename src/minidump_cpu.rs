@@ -24,3 +24,41 @@ cfg_if::cfg_if! {
         compile_error!("unsupported target architecture");
     }
 }
+
+/// The values of the general purpose registers of a context, excluding the
+/// stack and instruction pointers
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn general_purpose_registers(context: &RawContextCPU) -> Vec<usize> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            [
+                context.rax, context.rbx, context.rcx, context.rdx, context.rsi, context.rdi,
+                context.rbp, context.r8, context.r9, context.r10, context.r11, context.r12,
+                context.r13, context.r14, context.r15,
+            ]
+            .iter()
+            .map(|&reg| reg as usize)
+            .collect()
+        } else if #[cfg(target_arch = "x86")] {
+            [
+                context.eax, context.ebx, context.ecx, context.edx, context.esi, context.edi,
+                context.ebp,
+            ]
+            .iter()
+            .map(|&reg| reg as usize)
+            .collect()
+        } else if #[cfg(target_arch = "arm")] {
+            // r13 is the stack pointer and r15 the program counter
+            context.iregs[..13]
+                .iter()
+                .chain(&context.iregs[14..15])
+                .map(|&reg| reg as usize)
+                .collect()
+        } else if #[cfg(target_arch = "aarch64")] {
+            context.iregs[..GP_REG_COUNT]
+                .iter()
+                .map(|&reg| reg as usize)
+                .collect()
+        }
+    }
+}
@@ -5,7 +5,8 @@ pub use minidump_common::format::{
     MINIDUMP_HANDLE_DATA_STREAM as MDRawHandleDataStream,
     MINIDUMP_HANDLE_DESCRIPTOR as MDRawHandleDescriptor, MINIDUMP_HEADER as MDRawHeader,
     MINIDUMP_LOCATION_DESCRIPTOR as MDLocationDescriptor,
-    MINIDUMP_MEMORY_DESCRIPTOR as MDMemoryDescriptor, MINIDUMP_MEMORY_INFO as MDMemoryInfo,
+    MINIDUMP_MEMORY_DESCRIPTOR as MDMemoryDescriptor,
+    MINIDUMP_MEMORY_DESCRIPTOR64 as MDMemoryDescriptor64, MINIDUMP_MEMORY_INFO as MDMemoryInfo,
     MINIDUMP_MEMORY_INFO_LIST as MDMemoryInfoList, MINIDUMP_MODULE as MDRawModule,
     MINIDUMP_SIGNATURE as MD_HEADER_SIGNATURE, MINIDUMP_STREAM_TYPE as MDStreamType,
     MINIDUMP_SYSTEM_INFO as MDRawSystemInfo, MINIDUMP_THREAD as MDRawThread,
@@ -16,6 +17,9 @@ pub use minidump_common::format::{
 /* An MDRVA is an offset into the minidump file.  The beginning of the
  * MDRawHeader is at offset 0. */
 pub type MDRVA = u32;
+/* A 64-bit offset into the minidump file, used by streams that can refer to
+ * data past the first 4GiB of the file, ie. the Memory64ListStream. */
+pub type MDRVA64 = u64;
 
 pub type MDRawThreadList = Vec<MDRawThread>;
 
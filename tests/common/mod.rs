@@ -26,6 +26,11 @@ fn build_command() -> Command {
     } else {
         cmd = Command::new("cargo");
         cmd.args(["run", "-q", "--bin", "test"]);
+        // Allow running the child in a different working directory
+        cmd.args([
+            "--manifest-path",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+        ]);
 
         // In normal cases where the host and target are the same this won't matter,
         // but tests will fail if you are eg running in a cross container which will
@@ -77,6 +82,38 @@ pub fn start_child_and_wait_for_create_files(num: usize) -> Child {
     start_child_and_wait_for_threads_helper("create_files_wait", num)
 }
 
+/// Starts the `spawn_and_wait` helper in `dir` with an unlimited core file
+/// size, so a fatal signal leaves a core file in `dir` (depending on the
+/// system's `core_pattern`)
+#[allow(unused)]
+pub fn start_child_and_wait_for_threads_with_core_dumps(
+    num: usize,
+    dir: &std::path::Path,
+) -> Child {
+    use std::os::unix::process::CommandExt;
+
+    let mut cmd = build_command();
+    cmd.arg("spawn_and_wait").arg(num.to_string());
+    cmd.stdout(Stdio::piped()).current_dir(dir);
+    // SAFETY: setrlimit is async-signal-safe
+    unsafe {
+        cmd.pre_exec(|| {
+            let limit = libc::rlimit {
+                rlim_cur: libc::RLIM_INFINITY,
+                rlim_max: libc::RLIM_INFINITY,
+            };
+            if libc::setrlimit(libc::RLIMIT_CORE, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = cmd.spawn().expect("failed to spawn cargo");
+    wait_for_threads(&mut child, num);
+    child
+}
+
 #[allow(unused)]
 pub fn wait_for_threads(child: &mut Child, num: usize) {
     let mut f = BufReader::new(child.stdout.as_mut().expect("Can't open stdout"));
@@ -3,14 +3,35 @@
 
 use {
     common::*,
+    error_graph::ErrorList,
     minidump::*,
     minidump_common::format::{GUID, MINIDUMP_STREAM_TYPE::*},
     minidump_writer::{
         app_memory::AppMemory,
+        core_file::CoreFile,
+        crash_catcher::CrashCatcher,
         crash_context::CrashContext,
+        crash_generation::CrashGenerationServer,
+        dir_section::DumpBuf,
         maps_reader::{MappingEntry, MappingInfo, SystemMappingInfo},
-        minidump_writer::{errors::WriterError, MinidumpWriter, MinidumpWriterConfig},
+        mem_reader::MemReader,
+        memory_capture::{IndirectMemory, MemoryCaptureRule, RegisterMemory},
+        minidump_builder::{MinidumpBuilder, MinidumpBuilderError},
+        minidump_cpu::RawContextCPU,
+        minidump_format::{MDException, MDRawDirectory},
+        minidump_writer::{
+            cancellation::CancellationToken,
+            errors::{InitError, ProcessChangedError, WriterError},
+            report::StreamStatus,
+            snapshot::MemoryRegions,
+            stop_strategy::{StopStrategy, STOP_STRATEGY_STREAM},
+            streams::{BuiltinStream, StreamWriter},
+            thread_list_stream::SectionThreadListError,
+            user_stream::UserStream,
+            MinidumpWriter, MinidumpWriterConfig,
+        },
         module_reader::{BuildId, ReadFromModule},
+        privacy::{CmdLineFilter, EnvironFilter, NameFilter},
         Pid,
     },
     nix::{errno::Errno, sys::signal::Signal},
@@ -19,8 +40,12 @@ use {
     std::{
         collections::HashSet,
         io::{BufRead, BufReader},
-        os::unix::process::ExitStatusExt,
+        os::{
+            fd::{FromRawFd, OwnedFd},
+            unix::process::ExitStatusExt,
+        },
         process::{Command, Stdio},
+        time::{Duration, Instant},
     },
 };
 
@@ -108,7 +133,7 @@ contextual_test! {
             .unwrap();
 
         let tmp = context.minidump_writer(pid);
-        let in_memory_buffer = tmp.write(&mut tmpfile).expect("Could not write minidump");
+        let report = tmp.write(&mut tmpfile).expect("Could not write minidump");
         child.kill().expect("Failed to kill process");
 
         // Reap child
@@ -121,8 +146,8 @@ contextual_test! {
         assert!(meta.len() > 0);
 
         let mem_slice = std::fs::read(tmpfile.path()).expect("Failed to minidump");
-        assert_eq!(mem_slice.len(), in_memory_buffer.len());
-        assert_eq!(mem_slice, in_memory_buffer);
+        assert_eq!(mem_slice.len(), report.buffer.len());
+        assert_eq!(mem_slice, report.buffer);
     }
 }
 
@@ -621,31 +646,15 @@ fn minidump_size_limit() {
         let min = std::cmp::min(meta.len(), normal_file_size);
         let max = std::cmp::max(meta.len(), normal_file_size);
 
-        // Setting a stack limit limits the size of non-main stacks even before
-        // the limit is reached. This will cause slight variations in size
-        // between a limited and an unlimited minidump.
+        // The stacks can vary slightly in size between the two minidumps, as
+        // the threads kept running in between
         assert!(max - min < 1024, "max = {max:} min = {min:}");
     }
 
     // Third, write a minidump with a size limit small enough to be triggered.
     {
-        // Set size limit to some arbitrary amount, such that the limiting code
-        // will kick in.  The equation used to set this value was determined by
-        // simply reversing the size-limit logic a little bit in order to pick a
-        // size we know will trigger it.
-
-        // Copyied from sections/thread_list_stream.rs
-        const LIMIT_AVERAGE_THREAD_STACK_LENGTH: u64 = 8 * 1024;
-        let mut minidump_size_limit = LIMIT_AVERAGE_THREAD_STACK_LENGTH * 40;
-
-        // If, in reality, each of the threads' stack is *smaller* than
-        // kLimitAverageThreadStackLength, the normal file size could very well be
-        // smaller than the arbitrary limit that was just set.  In that case,
-        // either of these numbers should trigger the size-limiting code, but we
-        // might as well pick the smallest.
-        if normal_file_size < minidump_size_limit {
-            minidump_size_limit = normal_file_size;
-        }
+        // Half of the stacks don't fit in the limit
+        let minidump_size_limit = normal_file_size - total_normal_stack_size as u64 / 2;
 
         let mut tmpfile = tempfile::Builder::new()
             .prefix("write_dump_limited")
@@ -658,36 +667,38 @@ fn minidump_size_limit() {
 
         let meta = std::fs::metadata(tmpfile.path()).expect("Couldn't get metadata for tempfile");
         assert!(meta.len() > 0);
-        // Make sure the file size is at least smaller than the original.  If this
-        // fails because it's the same size, then the size-limit logic didn't kick
-        // in like it was supposed to.
-        assert!(meta.len() < normal_file_size);
+        assert!(meta.len() <= minidump_size_limit);
 
         let mut total_limit_stack_size = 0;
         // Read dump file and check its contents
         let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
         let thread_list: MinidumpThreadList =
             dump.get_stream().expect("Couldn't find MinidumpThreadList");
-        for thread in thread_list.threads {
+        assert_eq!(thread_list.threads.len(), num_of_threads);
+        for thread in &thread_list.threads {
             assert!(thread.raw.thread_id > 0);
-            assert!(thread.raw.stack.memory.data_size > 0);
             total_limit_stack_size += thread.raw.stack.memory.data_size;
         }
-
-        // Make sure stack size shrunk by at least 1KB per extra thread.
-        // Note: The 1KB is arbitrary, and assumes that the thread stacks are big
-        // enough to shrink by that much.  For example, if each thread stack was
-        // originally only 2KB, the current size-limit logic wouldn't actually
-        // shrink them because that's the size to which it tries to shrink.  If
-        // you fail this part of the test due to something like that, the test
-        // logic should probably be improved to account for your situation.
-
-        // Copyied from sections/thread_list_stream.rs
-        const LIMIT_BASE_THREAD_COUNT: usize = 20;
-        const MIN_PER_EXTRA_THREAD_STACK_REDUCTION: usize = 1024;
-        let min_expected_reduction =
-            (40 - LIMIT_BASE_THREAD_COUNT) * MIN_PER_EXTRA_THREAD_STACK_REDUCTION;
-        assert!(total_limit_stack_size < total_normal_stack_size - min_expected_reduction as u32);
+        // The stack of the blamed thread has a higher priority than the others
+        let blamed_thread = thread_list
+            .get_thread(pid as u32)
+            .expect("Couldn't find the blamed thread");
+        assert!(blamed_thread.raw.stack.memory.data_size > 0);
+
+        // The stacks are trimmed to whatever fits, and recorded as trimmed
+        assert!(total_limit_stack_size < total_normal_stack_size);
+        let soft_errors = read_minidump_soft_errors_or_panic(&dump);
+        let size_limit_errors = soft_errors
+            .as_array()
+            .unwrap()
+            .iter()
+            .find_map(|error| error.get("SizeLimitErrors"))
+            .expect("soft error list missing size limit errors");
+        assert!(size_limit_errors
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|error| error.get("StackTrimmed").is_some()));
     }
 
     child.kill().expect("Failed to kill process");
@@ -699,6 +710,794 @@ fn minidump_size_limit() {
     assert_eq!(status, Signal::SIGKILL as i32);
 }
 
+#[test]
+fn minidump_size_limit_strict() {
+    let num_of_threads = 10;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("write_dump_strict_limit")
+        .tempfile()
+        .unwrap();
+
+    // Neither the heap, the thread stacks nor the user stream fit in the limit
+    const USER_STREAM: u32 = 0x4747_0004;
+    let minidump_size_limit = 64 * 1024;
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .set_minidump_size_limit(minidump_size_limit)
+        .add_memory_capture_rule(MemoryCaptureRule {
+            name: Some("[heap]".into()),
+            max_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        })
+        .add_user_stream(UserStream::from_bytes(USER_STREAM, vec![0; 32 * 1024]));
+    let report = config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    // User streams have the lowest priority
+    let user_stream = report
+        .streams
+        .iter()
+        .find(|stream| stream.stream_type == USER_STREAM)
+        .unwrap();
+    assert_eq!(user_stream.status, StreamStatus::Skipped);
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let meta = std::fs::metadata(tmpfile.path()).expect("Couldn't get metadata for tempfile");
+    assert!(meta.len() <= minidump_size_limit);
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+    // The stacks of the other threads don't all fit, but the stack of the
+    // blamed thread has a higher priority
+    let blamed_thread = thread_list
+        .get_thread(pid as u32)
+        .expect("Couldn't find the blamed thread");
+    assert!(blamed_thread.raw.stack.memory.data_size > 0);
+    let _: MinidumpModuleList = dump.get_stream().expect("Couldn't find MinidumpModuleList");
+
+    // The trimmed data is reported in the MozSoftErrors stream
+    let soft_errors = read_minidump_soft_errors_or_panic(&dump);
+    assert!(
+        soft_errors
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|error| error.get("SizeLimitErrors").is_some()),
+        "soft error list missing size limit errors: {soft_errors:#?}"
+    );
+}
+
+#[test]
+fn minidump_size_limit_tiny() {
+    let num_of_threads = 10;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("write_dump_tiny_limit")
+        .tempfile()
+        .unwrap();
+
+    // The thread list alone takes up most of the limit
+    let minidump_size_limit = 16 * 1024;
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_minidump_size_limit(minidump_size_limit);
+    let report = config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let meta = std::fs::metadata(tmpfile.path()).expect("Couldn't get metadata for tempfile");
+    assert!(meta.len() <= minidump_size_limit);
+
+    // Every stream in the directory has a location, and the room kept for the
+    // soft errors holds the record of what was dropped
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    assert_eq!(
+        dump.header.stream_count as usize,
+        report
+            .streams
+            .iter()
+            .filter(|stream| stream.status == StreamStatus::Written)
+            .count()
+    );
+    assert!(dump
+        .all_streams()
+        .all(|stream| stream.location.data_size != 0 && stream.location.rva != 0));
+    let soft_errors = read_minidump_soft_errors_or_panic(&dump);
+    let size_limit_errors = soft_errors
+        .as_array()
+        .unwrap()
+        .iter()
+        .find_map(|error| error.get("SizeLimitErrors"))
+        .expect("soft error list missing size limit errors");
+    assert!(!size_limit_errors.as_array().unwrap().is_empty());
+}
+
+#[test]
+fn minidump_from_core_file() {
+    let core_dir = tempfile::Builder::new()
+        .prefix("core_file")
+        .tempdir()
+        .unwrap();
+    let num_of_threads = 3;
+    let mut child =
+        start_child_and_wait_for_threads_with_core_dumps(num_of_threads, core_dir.path());
+    let pid = child.id() as i32;
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), Signal::SIGSEGV)
+        .expect("Failed to send SIGSEGV");
+    let waitres = child.wait().expect("Failed to wait for child");
+    let status = waitres.signal().expect("Child did not die due to signal");
+    assert_eq!(status, Signal::SIGSEGV as i32);
+
+    // Whether and where a core file is written depends on the system's
+    // core_pattern, so there might be nothing to test
+    let Some(core_path) = std::fs::read_dir(core_dir.path())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("core"))
+        })
+    else {
+        eprintln!("no core file was written, check /proc/sys/kernel/core_pattern");
+        return;
+    };
+
+    let core_file = CoreFile::open(&core_path).expect("Failed to parse core file");
+    assert_eq!(core_file.process_id, pid);
+    assert_eq!(core_file.threads.len(), num_of_threads);
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("core_file")
+        .tempfile()
+        .unwrap();
+    MinidumpWriterConfig::from_core_file(core_file)
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+    let blamed_thread = thread_list
+        .get_thread(pid as u32)
+        .expect("Couldn't find the blamed thread");
+    assert!(blamed_thread.raw.stack.memory.data_size > 0);
+
+    let module_list: MinidumpModuleList =
+        dump.get_stream().expect("Couldn't find MinidumpModuleList");
+    assert!(module_list.main_module().is_some());
+
+    let exception: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+    assert_eq!(exception.raw.thread_id, pid as u32);
+    assert_eq!(
+        exception.raw.exception_record.exception_code,
+        Signal::SIGSEGV as u32
+    );
+}
+
+#[test]
+fn write_core_dump() {
+    let num_of_threads = 3;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut core_dump = tempfile::Builder::new()
+        .prefix("write_core_dump")
+        .tempfile()
+        .unwrap();
+    let report = MinidumpWriterConfig::new(pid, pid)
+        .write_core_dump(&mut core_dump)
+        .expect("Could not write core dump");
+    let exe = std::fs::read_link(format!("/proc/{pid}/exe")).unwrap();
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let soft_errors = &report.soft_errors;
+    assert!(soft_errors.is_empty(), "{soft_errors:#?}");
+    assert_eq!(report.size, core_dump.as_file().metadata().unwrap().len());
+
+    let core_file = CoreFile::open(core_dump.path()).expect("Failed to parse core dump");
+    assert_eq!(core_file.process_id, pid);
+    assert_eq!(core_file.threads.len(), num_of_threads);
+    // The blamed thread comes first
+    assert_eq!(core_file.threads[0].tid, pid);
+    assert!(!core_file.auxv.is_empty());
+    assert!(
+        core_file.files.iter().any(|file| file.path == exe),
+        "no mapping of {}",
+        exe.display()
+    );
+
+    // The stacks are included, so a minidump written from the core file has
+    // the same threads
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("write_core_dump")
+        .tempfile()
+        .unwrap();
+    MinidumpWriterConfig::from_core_file(core_file)
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+    for thread in &thread_list.threads {
+        assert!(thread.raw.stack.memory.data_size > 0);
+    }
+}
+
+#[test]
+fn non_invasive() {
+    let num_of_threads = 3;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("non_invasive")
+        .tempfile()
+        .unwrap();
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.non_invasive();
+    config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+    let _: MinidumpModuleList = dump.get_stream().expect("Couldn't find MinidumpModuleList");
+    let _: MinidumpMemoryInfoList = dump
+        .get_stream()
+        .expect("Couldn't find MinidumpMemoryInfoList");
+
+    // The threads' procfs state is recorded to mark the contexts as approximate
+    let soft_errors = read_minidump_soft_errors_or_panic(&dump);
+    let init_errors = soft_errors
+        .as_array()
+        .unwrap()
+        .iter()
+        .find_map(|error| error.get("InitErrors"))
+        .expect("no init errors");
+    let states = init_errors
+        .as_array()
+        .unwrap()
+        .iter()
+        .find_map(|error| error.get("ApproximateThreadContexts"))
+        .expect("thread contexts not marked as approximate");
+    assert_eq!(states.as_array().unwrap().len(), num_of_threads);
+}
+
+#[test]
+fn thread_attach_timeout() {
+    // One thread is stuck in `vfork` until its child exits
+    let mut child = start_child_and_return(&["spawn_vfork_wait"]);
+    wait_for_threads(&mut child, 2);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("thread_attach_timeout")
+        .tempfile()
+        .unwrap();
+
+    let start = Instant::now();
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_thread_attach_timeout(Duration::from_millis(100));
+    config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+    let elapsed = start.elapsed();
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    assert!(elapsed < Duration::from_secs(5), "took {elapsed:?}");
+
+    // The stuck thread is still written, with its procfs state
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), 2);
+
+    let soft_errors = read_minidump_soft_errors_or_panic(&dump);
+    let suspend_errors = soft_errors
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|error| error.get("InitErrors"))
+        .flat_map(|errors| errors.as_array().unwrap())
+        .find_map(|error| error.get("SuspendThreadsErrors"))
+        .expect("no errors suspending threads");
+    let state = suspend_errors
+        .as_array()
+        .unwrap()
+        .iter()
+        .find_map(|error| error.get("ThreadNotSuspended"))
+        .expect("stuck thread not recorded");
+    assert_eq!(state["state"], "D");
+    assert!(thread_list
+        .threads
+        .iter()
+        .any(|thread| json!(thread.raw.thread_id) == state["tid"]));
+}
+
+#[test]
+fn stop_strategies() {
+    let num_of_threads = 3;
+    // Freezing needs a cgroup of the child's own, which tests can't rely on
+    for (stop_strategy, name) in [
+        (StopStrategy::Signal, "Signal"),
+        (StopStrategy::PtraceInterrupt, "PtraceInterrupt"),
+        (StopStrategy::None, "None"),
+    ] {
+        let mut child = start_child_and_wait_for_threads(num_of_threads);
+        let pid = child.id() as i32;
+
+        let mut tmpfile = tempfile::Builder::new()
+            .prefix("stop_strategies")
+            .tempfile()
+            .unwrap();
+
+        let mut config = MinidumpWriterConfig::new(pid, pid);
+        config.set_stop_strategy(stop_strategy);
+        let report = config
+            .write(&mut tmpfile)
+            .expect("Could not write minidump");
+
+        // The process is running again, SIGCONT is delivered asynchronously
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+        let state = stat.rsplit(')').next().unwrap().split_whitespace().next();
+
+        child.kill().expect("Failed to kill process");
+        child.wait().expect("Failed to wait on killed process");
+
+        assert_ne!(state, Some("T"), "{name} left the process stopped");
+
+        let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+        let thread_list: MinidumpThreadList =
+            dump.get_stream().expect("Couldn't find MinidumpThreadList");
+        assert_eq!(thread_list.threads.len(), num_of_threads);
+
+        assert_eq!(report.stop_strategy, stop_strategy);
+        let recorded: serde_json::Value = serde_json::from_slice(
+            dump.get_raw_stream(STOP_STRATEGY_STREAM)
+                .expect("Couldn't find the stop strategy stream"),
+        )
+        .unwrap();
+        assert_eq!(recorded, json!(name));
+
+        // A successful dump has no soft errors
+        assert_eq!(read_minidump_soft_errors_or_panic(&dump), json!([]));
+    }
+}
+
+/// Records the state of the process when it is written
+struct ProcessStateStream {
+    pid: i32,
+    state: std::sync::Arc<std::sync::Mutex<String>>,
+}
+
+impl StreamWriter for ProcessStateStream {
+    fn stream_type(&self) -> u32 {
+        0x4747_0001
+    }
+
+    fn write(
+        &mut self,
+        _writer: &mut MinidumpWriter,
+        _buffer: &mut DumpBuf,
+        _soft_errors: &mut ErrorList<WriterError>,
+    ) -> std::result::Result<Option<MDRawDirectory>, WriterError> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid)).unwrap();
+        *self.state.lock().unwrap() = status
+            .lines()
+            .filter(|line| line.starts_with("State:") || line.starts_with("TracerPid:"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(None)
+    }
+}
+
+#[test]
+fn resumed_before_writing() {
+    let num_of_threads = 3;
+    // The full memory is read before the process is resumed too, but user
+    // stream callbacks need it to stay frozen
+    for (full_memory, callback) in [(false, false), (true, false), (false, true)] {
+        let mut child = start_child_and_wait_for_threads(num_of_threads);
+        let pid = child.id() as i32;
+
+        // The state of the process while the streams are written
+        let state = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let mut config = MinidumpWriterConfig::new(pid, pid);
+        config.stream_registry().push(ProcessStateStream {
+            pid,
+            state: state.clone(),
+        });
+        if full_memory {
+            config.full_memory();
+        }
+        if callback {
+            config.add_user_stream(UserStream::from_fn(0x4747_0002, |_, _| Ok(())));
+        }
+        let mut tmpfile = tempfile::Builder::new()
+            .prefix("resumed_before_writing")
+            .tempfile()
+            .unwrap();
+        let report = config
+            .write(&mut tmpfile)
+            .expect("Could not write minidump");
+
+        child.kill().expect("Failed to kill process");
+        child.wait().expect("Failed to wait on killed process");
+
+        let state = state.lock().unwrap();
+        let traced = !state.contains("TracerPid:\t0");
+        if callback {
+            assert!(traced, "{state}");
+            assert_eq!(report.paused_for, None);
+            assert_eq!(report.resume_deferred_by, Some(0x4747_0002));
+        } else {
+            assert!(!traced, "{state}");
+            assert!(report.paused_for.is_some());
+            assert_eq!(report.resume_deferred_by, None);
+        }
+
+        // The threads are written from what was captured while it was frozen
+        let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+        let thread_list: MinidumpThreadList =
+            dump.get_stream().expect("Couldn't find MinidumpThreadList");
+        assert_eq!(thread_list.threads.len(), num_of_threads);
+        for thread in &thread_list.threads {
+            assert!(thread.raw.stack.memory.data_size > 0);
+        }
+    }
+}
+
+fn pidfd_open(pid: u32) -> OwnedFd {
+    // SAFETY: `pidfd_open` has no memory arguments
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    assert!(fd >= 0, "pidfd_open failed: {}", Errno::last());
+    // SAFETY: the fd was just opened
+    unsafe { OwnedFd::from_raw_fd(fd as _) }
+}
+
+#[test]
+fn pidfd_identity() {
+    let mut child = start_child_and_wait_for_threads(1);
+    let mut other = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+    let other_pidfd = pidfd_open(other.id());
+    let pidfd = pidfd_open(child.id());
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_pidfd(pidfd_open(child.id()));
+    config
+        .write(&mut std::io::Cursor::new(Vec::new()))
+        .expect("Could not write minidump");
+
+    // A pidfd of a different process
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_pidfd(other_pidfd);
+    let result = config.write(&mut std::io::Cursor::new(Vec::new()));
+    assert!(
+        matches!(
+            result,
+            Err(WriterError::InitError(InitError::ProcessChanged(
+                ProcessChangedError(changed, _)
+            ))) if changed == pid
+        ),
+        "{result:?}"
+    );
+
+    // The process exited, and its pid may be reused
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+    other.kill().expect("Failed to kill process");
+    other.wait().expect("Failed to wait on killed process");
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_pidfd(pidfd);
+    let result = config.write(&mut std::io::Cursor::new(Vec::new()));
+    assert!(
+        matches!(
+            result,
+            Err(WriterError::InitError(InitError::ProcessChanged(_)))
+        ),
+        "{result:?}"
+    );
+}
+
+#[test]
+fn process_snapshot() {
+    let num_of_threads = 3;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut snapshot = MinidumpWriterConfig::new(pid, pid)
+        .snapshot()
+        .expect("Could not collect snapshot");
+
+    // The minidump is written after the process is gone
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    assert_eq!(snapshot.threads.len(), num_of_threads);
+    assert!(!snapshot.modules.is_empty());
+    for thread in &snapshot.threads {
+        let mut word = [0u8; std::mem::size_of::<usize>()];
+        assert_eq!(
+            snapshot.memory.read(thread.stack_pointer, &mut word),
+            word.len(),
+            "The stack of thread {} is missing",
+            thread.tid
+        );
+    }
+
+    snapshot
+        .proc_files
+        .insert("limits".into(), b"attached limits".to_vec());
+    let num_modules = snapshot.modules.len();
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("process_snapshot")
+        .tempfile()
+        .unwrap();
+    MinidumpWriterConfig::from_snapshot(snapshot)
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+    for thread in &thread_list.threads {
+        assert!(thread.raw.stack.memory.data_size > 0);
+    }
+
+    let module_list: MinidumpModuleList =
+        dump.get_stream().expect("Couldn't find MinidumpModuleList");
+    assert_eq!(module_list.iter().count(), num_modules);
+
+    assert_eq!(
+        dump.get_raw_stream(MozLinuxLimits as u32).unwrap(),
+        b"attached limits"
+    );
+    assert!(dump.get_raw_stream(LinuxMaps as u32).is_ok());
+    assert!(dump.get_raw_stream(HandleDataStream as u32).is_err());
+    // The link maps were read from the process while collecting the snapshot
+    assert!(dump.get_raw_stream(LinuxDsoDebug as u32).is_ok());
+}
+
+#[test]
+fn resilient() {
+    let num_of_threads = 3;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut snapshot = MinidumpWriterConfig::new(pid, pid)
+        .snapshot()
+        .expect("Could not collect snapshot");
+
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    // Without the memory of its stack, a thread can't be written
+    let skipped = snapshot.threads[1].tid;
+    let stack_pointer = snapshot.threads[1].stack_pointer;
+    let mut memory = MemoryRegions::default();
+    for (address, region) in snapshot.memory.iter() {
+        if !(address..address + region.len()).contains(&stack_pointer) {
+            memory.insert(address, region);
+        }
+    }
+    snapshot.memory = memory;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("resilient")
+        .tempfile()
+        .unwrap();
+    let mut config = MinidumpWriterConfig::from_snapshot(snapshot);
+    config.resilient().set_app_memory(vec![AppMemory {
+        ptr: 0,
+        length: 4096,
+    }]);
+    let report = config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+
+    let thread_list_errors = report
+        .soft_errors
+        .iter()
+        .find_map(|error| match error {
+            WriterError::WriteThreadListErrors(errors) => Some(errors),
+            _ => None,
+        })
+        .expect("The thread wasn't skipped");
+    assert!(matches!(
+        thread_list_errors.iter().next(),
+        Some(SectionThreadListError::ThreadSkipped(tid, _)) if *tid == skipped
+    ));
+    assert!(report
+        .soft_errors
+        .iter()
+        .any(|error| matches!(error, WriterError::WriteAppMemoryErrors(_))));
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads - 1);
+    assert!(thread_list.get_thread(skipped as u32).is_none());
+    for thread in &thread_list.threads {
+        assert!(thread.raw.stack.memory.data_size > 0);
+    }
+
+    let memory_list: MinidumpMemoryList =
+        dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    assert!(memory_list.memory_at_address(0).is_none());
+    assert!(memory_list
+        .memory_at_address(stack_pointer as u64)
+        .is_none());
+    let _: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+}
+
+#[test]
+fn write_current_process() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let _sleeper = std::thread::spawn(move || {
+        tx.send(nix::unistd::gettid().as_raw()).unwrap();
+        std::thread::park();
+    });
+    let sleeper_tid = rx.recv().unwrap();
+    let tid = nix::unistd::gettid().as_raw();
+    let marker = 0u8;
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    let mut config = MinidumpWriterConfig::current_process();
+    config.set_stop_strategy(StopStrategy::Signal);
+    let report = config
+        .write_current_process(&mut buffer)
+        .expect("Could not write minidump");
+
+    // The stop strategy is overridden, as the helper only interrupts the
+    // threads, which are resumed before the rest of the minidump is written
+    assert_eq!(report.stop_strategy, StopStrategy::PtraceInterrupt);
+    assert!(report.paused_for.is_some());
+
+    // The sleeper was stopped by the helper, rather than written with its
+    // procfs state
+    let soft_errors = serde_json::to_string(&report.soft_errors).unwrap();
+    assert!(
+        !soft_errors.contains(&format!("\"tid\":{sleeper_tid},")),
+        "{soft_errors}"
+    );
+
+    let dump = Minidump::read(buffer.into_inner()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    let tids: Vec<_> = thread_list
+        .threads
+        .iter()
+        .map(|t| t.raw.thread_id)
+        .collect();
+    assert!(tids.contains(&(tid as u32)));
+    assert!(tids.contains(&(sleeper_tid as u32)));
+
+    // The calling thread is blamed with the context captured before forking
+    let exception: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+    assert_eq!(exception.raw.thread_id, tid as u32);
+
+    // The blamed stack starts at the captured stack pointer, so it covers the
+    // frames of the caller
+    let marker_address = &marker as *const u8 as u64;
+    let stack = &thread_list.get_thread(tid as u32).unwrap().raw.stack;
+    assert!(stack.start_of_memory_range <= marker_address);
+    assert!(marker_address < stack.start_of_memory_range + stack.memory.data_size as u64);
+}
+
+#[test]
+fn crash_generation_server() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("server.sock");
+    let server = CrashGenerationServer::bind(&socket, dir.path()).expect("failed to bind");
+
+    let mut child = start_child_and_return(&["crash_client", socket.to_str().unwrap()]);
+    let pid = child.id() as i32;
+
+    let mut stream = server.accept().expect("failed to accept");
+    let crash_dump = server
+        .handle_request(&mut stream)
+        .expect("failed to write minidump")
+        .expect("client disconnected without crashing");
+    assert_eq!(crash_dump.process_id, pid);
+    assert_eq!(crash_dump.thread_id, pid);
+
+    // The client passes the signal on once the minidump is written
+    let status = child.wait().expect("Failed to wait for child");
+    assert_eq!(status.signal(), Some(Signal::SIGSEGV as i32));
+
+    let dump = Minidump::read_path(&crash_dump.path).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), 2);
+
+    let exception: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+    assert_eq!(exception.raw.thread_id, pid as u32);
+    assert_eq!(
+        exception.raw.exception_record.exception_code,
+        Signal::SIGSEGV as u32
+    );
+    assert_eq!(exception.raw.exception_record.exception_address, 0x10);
+
+    let info: MinidumpCrashpadInfo = dump.get_stream().expect("Couldn't find CrashpadInfo");
+    assert_eq!(info.simple_annotations["channel"], "nightly");
+}
+
+#[test]
+fn crash_catcher() {
+    let num_of_threads = 3;
+    let dir = tempfile::tempdir().unwrap();
+
+    // The catcher has to be the parent, so the helper is run directly rather
+    // than through `cargo run`
+    let path: String = if let Ok(p) = std::env::var("TEST_HELPER") {
+        p
+    } else {
+        std::env!("CARGO_BIN_EXE_test").into()
+    };
+    let mut command = Command::new(path);
+    command
+        .env("RUST_BACKTRACE", "1")
+        .arg("spawn_and_crash")
+        .arg(num_of_threads.to_string());
+
+    let mut catcher = CrashCatcher::spawn(&mut command, dir.path()).expect("failed to spawn");
+    let pid = catcher.process_id();
+    let outcome = catcher.run().expect("failed to run the target");
+
+    // The signal is passed on once the minidump is written
+    assert_eq!(outcome.status.signal(), Some(Signal::SIGSEGV as i32));
+    assert_eq!(outcome.crashes.len(), 1);
+    let crash = &outcome.crashes[0];
+    assert_eq!(crash.thread_id, pid);
+    assert_eq!(crash.signal, Signal::SIGSEGV as i32);
+    let path = crash.dump.as_ref().expect("failed to write minidump");
+
+    let dump = Minidump::read_path(path).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), num_of_threads);
+
+    let exception: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+    assert_eq!(exception.raw.thread_id, pid as u32);
+    assert_eq!(
+        exception.raw.exception_record.exception_code,
+        Signal::SIGSEGV as u32
+    );
+    assert_eq!(exception.raw.exception_record.exception_address, 0x10);
+}
+
 #[test]
 fn with_deleted_binary() {
     let num_of_threads = 1;
@@ -810,3 +1609,666 @@ fn memory_info_list_stream() {
     let list: MinidumpMemoryInfoList = dump.get_stream().expect("no memory info list");
     assert!(list.iter().count() > 1);
 }
+
+#[test]
+fn stream_registry() {
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("stream_registry")
+        .tempfile()
+        .unwrap();
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    let removed = config.stream_registry().remove(LinuxEnviron as u32);
+    assert!(removed.is_some());
+    let num_streams = config.stream_registry().len();
+
+    let report = config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    // Every registered stream, plus the soft errors stream, only those that
+    // were written are in the directory
+    assert_eq!(report.streams.len(), num_streams + 1);
+    let written = report
+        .streams
+        .iter()
+        .filter(|stream| stream.status == StreamStatus::Written)
+        .count();
+    assert_eq!(dump.header.stream_count as usize, written);
+    assert!(dump.all_streams().all(|stream| stream.stream_type != 0));
+    assert!(dump.get_raw_stream(LinuxEnviron as u32).is_err());
+    assert!(dump.get_raw_stream(LinuxCmdLine as u32).is_ok());
+    let _: MinidumpThreadList = dump.get_stream().expect("Couldn't find MinidumpThreadList");
+
+    // The config can be moved to another thread to be written there
+    fn assert_send<T: Send>(_: &T) {}
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    assert_send(&config);
+
+    // The memory list includes the stacks captured by the thread list, so it
+    // can't be written before it
+    let registry = config.stream_registry();
+    registry.remove(MemoryListStream as u32);
+    registry.insert_before(ThreadListStream as u32, BuiltinStream::MemoryList);
+    assert!(matches!(
+        registry.check_order(),
+        Err(WriterError::StreamOrder { stream, dependency })
+            if stream == MemoryListStream as u32 && dependency == ThreadListStream as u32
+    ));
+}
+
+#[test]
+fn user_streams() {
+    const BYTES_STREAM: u32 = 0x1234_0001;
+    const CALLBACK_STREAM: u32 = 0x1234_0002;
+    const FAILING_STREAM: u32 = 0x1234_0003;
+
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("user_streams")
+        .tempfile()
+        .unwrap();
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .add_user_stream(UserStream::from_bytes(BYTES_STREAM, b"app state".to_vec()))
+        .add_user_stream(UserStream::from_fn(CALLBACK_STREAM, |buffer, writer| {
+            // The main executable is always the first mapping, read its ELF magic
+            let mem = MemReader::new(writer.process_id);
+            let magic = mem.read_to_vec(
+                writer.mappings[0].start_address,
+                std::num::NonZeroUsize::new(4).unwrap(),
+            )?;
+            buffer.write_all(&magic);
+            Ok(())
+        }))
+        .add_user_stream(UserStream::from_fn(FAILING_STREAM, |buffer, _| {
+            buffer.write_all(b"partial");
+            Err("failed halfway".into())
+        }));
+
+    let report = config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    // What the failing callback wrote before failing is discarded
+    let failing = report
+        .streams
+        .iter()
+        .find(|stream| stream.stream_type == FAILING_STREAM)
+        .expect("Couldn't find the failing stream report");
+    assert_eq!(failing.status, StreamStatus::Failed);
+    assert_eq!(failing.size, 0);
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    assert_eq!(
+        dump.get_raw_stream(BYTES_STREAM)
+            .expect("Couldn't find bytes stream"),
+        b"app state"
+    );
+    assert_eq!(
+        dump.get_raw_stream(CALLBACK_STREAM)
+            .expect("Couldn't find callback stream"),
+        b"\x7fELF"
+    );
+}
+
+#[test]
+fn cancellation() {
+    const CANCEL_STREAM: u32 = 0x1234_0001;
+
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("cancellation")
+        .tempfile()
+        .unwrap();
+
+    // Cancel right after the thread list is written
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_cancellation_token(token);
+    config.stream_registry().insert_before(
+        ModuleListStream as u32,
+        UserStream::from_fn(CANCEL_STREAM, move |_, _| {
+            cancel.cancel();
+            Ok(())
+        }),
+    );
+    let report = config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let _: MinidumpThreadList = dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert!(dump.get_stream::<MinidumpModuleList>().is_err());
+    assert_soft_errors_in_minidump(&dump, &[json!({"Interrupted": "Cancelled"})]);
+    assert!(report
+        .streams
+        .iter()
+        .any(|stream| stream.stream_type == ModuleListStream as u32
+            && stream.status == StreamStatus::Skipped));
+
+    // A deadline that has already passed leaves out every stream, but the
+    // minidump is still valid
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("deadline")
+        .tempfile()
+        .unwrap();
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.set_deadline(std::time::Instant::now());
+    config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    assert!(dump.get_stream::<MinidumpThreadList>().is_err());
+    assert_soft_errors_in_minidump(&dump, &[json!({"Interrupted": "DeadlineExceeded"})]);
+}
+
+#[test]
+fn expired_deadline() {
+    let mut child = start_child_and_wait_for_threads(3);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("expired_deadline")
+        .tempfile()
+        .unwrap();
+
+    // The threads aren't suspended and no memory is read, even for the
+    // streams that are captured before the process is resumed
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .full_memory()
+        .add_memory_capture_rule(MemoryCaptureRule {
+            name: Some("[heap]".into()),
+            max_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        })
+        .capture_indirect_memory(IndirectMemory::default())
+        .set_deadline(std::time::Instant::now());
+    let report = config
+        .write(&mut tmpfile)
+        .expect("Could not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let memory64 = report
+        .streams
+        .iter()
+        .find(|stream| stream.stream_type == Memory64ListStream as u32)
+        .expect("Couldn't find the memory64 list stream report");
+    assert_eq!(memory64.status, StreamStatus::Skipped);
+    assert_eq!(memory64.size, 0);
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    assert!(dump.get_stream::<MinidumpThreadList>().is_err());
+    assert!(dump.get_stream::<MinidumpMemoryList>().is_err());
+    assert!(dump.get_raw_stream(Memory64ListStream as u32).is_err());
+    assert_soft_errors_in_minidump(&dump, &[json!({"Interrupted": "DeadlineExceeded"})]);
+}
+
+#[test]
+fn minidump_builder() {
+    const APP_STREAM: u32 = 0x1234_0001;
+    const STACK_ADDRESS: u64 = 0x7fff_0000;
+    const HEAP_ADDRESS: u64 = 0x60_0000;
+    const MODULE_ADDRESS: u64 = 0x40_0000;
+
+    let identifier = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE,
+        0xFF,
+    ];
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("minidump_builder")
+        .tempfile()
+        .unwrap();
+
+    let mut builder = MinidumpBuilder::new();
+    builder
+        .add_thread(
+            1,
+            RawContextCPU::default(),
+            STACK_ADDRESS,
+            vec![0xaa; 0x100],
+        )
+        .add_thread(
+            2,
+            RawContextCPU::default(),
+            STACK_ADDRESS + 0x1000,
+            vec![0xbb; 0x80],
+        )
+        .add_module(MODULE_ADDRESS, 0x1_0000, "/usr/bin/synthetic", &identifier)
+        .add_memory(HEAP_ADDRESS, b"heap memory".to_vec())
+        .set_exception(
+            2,
+            MDException {
+                exception_code: Signal::SIGSEGV as u32,
+                exception_address: HEAP_ADDRESS,
+                ..Default::default()
+            },
+        )
+        .add_stream(APP_STREAM, b"app state".to_vec());
+    let in_memory_buffer = builder
+        .write(&mut tmpfile)
+        .expect("could not write minidump");
+
+    assert_eq!(in_memory_buffer, std::fs::read(tmpfile.path()).unwrap());
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let _: MinidumpSystemInfo = dump.get_stream().expect("Couldn't find MinidumpSystemInfo");
+
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert_eq!(thread_list.threads.len(), 2);
+    let stack = &thread_list.get_thread(2).unwrap().raw.stack;
+    assert_eq!(stack.start_of_memory_range, STACK_ADDRESS + 0x1000);
+    assert_eq!(stack.memory.data_size, 0x80);
+
+    let module_list: MinidumpModuleList = dump
+        .get_stream()
+        .expect("Couldn't find stream MinidumpModuleList");
+    let module = module_list
+        .module_at_address(MODULE_ADDRESS + 0x10)
+        .expect("Couldn't find module");
+    assert_eq!(module.code_file(), "/usr/bin/synthetic");
+    assert_eq!(
+        module.debug_identifier(),
+        Some("33221100554477668899AABBCCDDEEFF0".parse().unwrap())
+    );
+
+    let memory_list: MinidumpMemoryList =
+        dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    let heap = memory_list
+        .memory_at_address(HEAP_ADDRESS)
+        .expect("Couldn't find heap memory");
+    assert_eq!(heap.bytes, b"heap memory");
+    let stack = memory_list
+        .memory_at_address(STACK_ADDRESS)
+        .expect("Couldn't find stack memory");
+    assert_eq!(stack.bytes, [0xaa; 0x100]);
+
+    let exception: MinidumpException = dump.get_stream().expect("Couldn't find MinidumpException");
+    assert_eq!(exception.raw.thread_id, 2);
+    assert_eq!(
+        exception.raw.exception_record.exception_code,
+        Signal::SIGSEGV as u32
+    );
+    assert_eq!(
+        exception.raw.thread_context.rva,
+        thread_list.get_thread(2).unwrap().raw.thread_context.rva
+    );
+
+    assert_eq!(
+        dump.get_raw_stream(APP_STREAM)
+            .expect("Couldn't find app stream"),
+        b"app state"
+    );
+
+    // The exception has to be in one of the threads
+    builder.set_exception(3, MDException::default());
+    assert!(matches!(
+        builder.write(&mut std::io::Cursor::new(Vec::new())),
+        Err(MinidumpBuilderError::UnknownExceptionThread(3))
+    ));
+}
+
+#[test]
+fn privacy_filters() {
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("privacy_filters")
+        .tempfile()
+        .unwrap();
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .set_environ_filter(EnvironFilter {
+            names: NameFilter::AllowList(vec!["PATH".into()]),
+            ..Default::default()
+        })
+        .set_cmdline_filter(CmdLineFilter {
+            redact_all: true,
+            ..Default::default()
+        });
+
+    config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+
+    let environ = dump
+        .get_raw_stream(LinuxEnviron as u32)
+        .expect("Couldn't find LinuxEnviron");
+    let environ = std::str::from_utf8(environ).expect("environ was not utf8");
+    // Set for every child by the test harness
+    assert!(environ.contains("RUST_BACKTRACE=<redacted>\0"));
+    assert!(!environ.contains("RUST_BACKTRACE=1"));
+
+    let cmd_line = dump
+        .get_raw_stream(LinuxCmdLine as u32)
+        .expect("Couldn't find LinuxCmdLine");
+    let cmd_line = std::str::from_utf8(cmd_line).expect("cmd line was not utf8");
+    assert!(cmd_line.ends_with("\0<redacted>\0"));
+    assert!(!cmd_line.contains("spawn_and_wait"));
+}
+
+#[test]
+fn annotations() {
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("annotations")
+        .tempfile()
+        .unwrap();
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .add_annotation("channel", "nightly")
+        .add_annotation("session", "1234")
+        .add_annotation_blob("feature_flags", vec![1, 0, 1, 1]);
+
+    // A blob for the shared library the test binary is linked against
+    let maps = std::fs::read_to_string(format!("/proc/{pid}/maps")).unwrap();
+    let libc_address = maps
+        .lines()
+        .find(|line| line.contains("/libc.so") || line.contains("/libc-"))
+        .and_then(|line| line.split('-').next())
+        .map(|start| usize::from_str_radix(start, 16).unwrap())
+        .expect("libc isn't mapped");
+    config.add_module_annotation_blob(libc_address, "libc_state", vec![2]);
+
+    config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let info: MinidumpCrashpadInfo = dump.get_stream().expect("Couldn't find CrashpadInfo");
+
+    assert_eq!(info.simple_annotations.len(), 2);
+    assert_eq!(info.simple_annotations["channel"], "nightly");
+    assert_eq!(info.simple_annotations["session"], "1234");
+
+    assert_eq!(info.module_list.len(), 2);
+    let main_module = &info.module_list[0];
+    assert_eq!(main_module.module_index, 0);
+    assert!(main_module.annotation_objects.contains_key("feature_flags"));
+
+    let modules: MinidumpModuleList = dump.get_stream().expect("Couldn't find ModuleList");
+    let libc_module = &info.module_list[1];
+    let libc = &modules.iter().nth(libc_module.module_index).unwrap();
+    assert!(libc.name.contains("libc"));
+    assert_eq!(libc_module.annotation_objects.len(), 1);
+    assert!(libc_module.annotation_objects.contains_key("libc_state"));
+}
+
+#[test]
+fn memory_capture_rules() {
+    let mut child = start_child_and_return(&["spawn_alloc_wait"]);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("memory_capture_rules")
+        .tempfile()
+        .unwrap();
+
+    let mut f = BufReader::new(child.stdout.as_mut().expect("Can't open stdout"));
+    let mut buf = String::new();
+    let _ = f
+        .read_line(&mut buf)
+        .expect("Couldn't read address provided by child");
+    let mut output = buf.split_whitespace();
+    // The allocation is small enough to be on the heap
+    let memory_addr = usize::from_str_radix(output.next().unwrap().trim_start_matches("0x"), 16)
+        .expect("unable to parse mmap_addr");
+    let memory_size: usize = output
+        .next()
+        .unwrap()
+        .parse()
+        .expect("unable to parse memory_size");
+
+    let heap = std::fs::read_to_string(format!("/proc/{pid}/maps"))
+        .expect("failed to read maps")
+        .lines()
+        .find(|line| line.ends_with("[heap]"))
+        .and_then(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            Some((
+                u64::from_str_radix(start, 16).ok()?,
+                u64::from_str_radix(end, 16).ok()?,
+            ))
+        })
+        .expect("Couldn't find the heap mapping");
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config
+        .add_memory_capture_rule(MemoryCaptureRule {
+            name: Some("[heap]".into()),
+            max_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        })
+        // Never selects anything, shared anonymous read/write/execute mappings
+        .add_memory_capture_rule(MemoryCaptureRule {
+            name: Some("".into()),
+            permissions: MMPermissions::all() - MMPermissions::PRIVATE,
+            max_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        });
+
+    config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let section: MinidumpMemoryList = dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    let region = section
+        .memory_at_address(memory_addr as u64)
+        .expect("Couldn't find memory region");
+
+    // The whole heap is captured
+    assert_eq!(region.base_address, heap.0);
+    assert_eq!(region.size, heap.1 - heap.0);
+
+    let offset = memory_addr - region.base_address as usize;
+    let values: Vec<u8> = (0..memory_size).map(|idx| (idx % 255) as u8).collect();
+    assert_eq!(&region.bytes[offset..offset + memory_size], values);
+}
+
+#[test]
+fn indirect_memory() {
+    let mut child = start_child_and_return(&["spawn_alloc_wait"]);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("indirect_memory")
+        .tempfile()
+        .unwrap();
+
+    let mut f = BufReader::new(child.stdout.as_mut().expect("Can't open stdout"));
+    let mut buf = String::new();
+    let _ = f
+        .read_line(&mut buf)
+        .expect("Couldn't read address provided by child");
+    let mut output = buf.split_whitespace();
+    // The pointer to the allocation is kept on the stack of the main thread
+    let memory_addr = usize::from_str_radix(output.next().unwrap().trim_start_matches("0x"), 16)
+        .expect("unable to parse mmap_addr");
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.capture_indirect_memory(IndirectMemory {
+        window_size: 256,
+        budget: 64 * 1024,
+    });
+
+    config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let section: MinidumpMemoryList = dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    let region = section
+        .memory_at_address(memory_addr as u64)
+        .expect("Couldn't find memory region");
+
+    // A window around the start of the allocation is captured, rather than
+    // the whole heap
+    let offset = memory_addr - region.base_address as usize;
+    let len = region.bytes.len() - offset;
+    assert!(region.size < 64 * 1024);
+    assert!(len >= 128);
+    let values: Vec<u8> = (0..128).map(|idx| (idx % 255) as u8).collect();
+    assert_eq!(&region.bytes[offset..offset + 128], values);
+
+    // No memory is captured twice, eg. a window that overlaps a stack
+    let mut ranges: Vec<_> = section
+        .iter()
+        .map(|memory| (memory.base_address, memory.base_address + memory.size))
+        .collect();
+    ranges.sort();
+    assert!(ranges.windows(2).all(|pair| pair[0].1 <= pair[1].0));
+}
+
+#[cfg(not(target_arch = "mips"))]
+#[test]
+fn register_memory() {
+    let mut child = start_child_and_return(&["spawn_alloc_wait"]);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("register_memory")
+        .tempfile()
+        .unwrap();
+
+    let mut f = BufReader::new(child.stdout.as_mut().expect("Can't open stdout"));
+    let mut buf = String::new();
+    let _ = f
+        .read_line(&mut buf)
+        .expect("Couldn't read address provided by child");
+    let mut output = buf.split_whitespace();
+    let memory_addr = usize::from_str_radix(output.next().unwrap().trim_start_matches("0x"), 16)
+        .expect("unable to parse mmap_addr");
+
+    let write_dump = |signo: i32, tmpfile: &mut tempfile::NamedTempFile| {
+        // Pretend the child crashed accessing the allocation
+        let mut crash_context = get_crash_context(pid);
+        crash_context.inner.siginfo.ssi_signo = signo as u32;
+        crash_context.inner.siginfo.ssi_addr = memory_addr as u64;
+
+        let mut config = MinidumpWriterConfig::new(pid, pid);
+        config
+            .set_crash_context(crash_context)
+            .capture_register_memory(RegisterMemory {
+                window_size: 64,
+                ..Default::default()
+            });
+
+        config.write(tmpfile).expect("cound not write minidump");
+        Minidump::read_path(tmpfile.path()).expect("failed to read minidump")
+    };
+
+    let dump = write_dump(libc::SIGSEGV, &mut tmpfile);
+    let section: MinidumpMemoryList = dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    let region = section
+        .memory_at_address(memory_addr as u64)
+        .expect("Couldn't find memory region");
+
+    // The window around the fault address
+    let offset = memory_addr - region.base_address as usize;
+    assert_eq!(region.bytes.len() - offset, 32);
+    let values: Vec<u8> = (0..32).map(|idx| (idx % 255) as u8).collect();
+    assert_eq!(&region.bytes[offset..], values);
+
+    // The address of a signal without a fault address is ignored
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("register_memory_abort")
+        .tempfile()
+        .unwrap();
+    let dump = write_dump(libc::SIGABRT, &mut tmpfile);
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    let section: MinidumpMemoryList = dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+    assert!(section.memory_at_address(memory_addr as u64).is_none());
+}
+
+#[test]
+fn full_memory() {
+    let mut child = start_child_and_return(&["spawn_alloc_wait"]);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("full_memory")
+        .tempfile()
+        .unwrap();
+
+    let mut f = BufReader::new(child.stdout.as_mut().expect("Can't open stdout"));
+    let mut buf = String::new();
+    let _ = f
+        .read_line(&mut buf)
+        .expect("Couldn't read address provided by child");
+    let mut output = buf.split_whitespace();
+    let memory_addr = usize::from_str_radix(output.next().unwrap().trim_start_matches("0x"), 16)
+        .expect("unable to parse mmap_addr");
+    let memory_size: usize = output
+        .next()
+        .unwrap()
+        .parse()
+        .expect("unable to parse memory_size");
+
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.full_memory();
+    let in_memory = config
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    // The memory is only written to the file
+    let file_len = tmpfile.as_file().metadata().unwrap().len();
+    assert!(file_len > in_memory.buffer.len() as u64);
+
+    let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
+    let memory: MinidumpMemory64List = dump
+        .get_stream()
+        .expect("Couldn't find MinidumpMemory64List");
+    let total: u64 = memory.iter().map(|region| region.size).sum();
+    assert_eq!(in_memory.buffer.len() as u64 + total, file_len);
+
+    let region = memory
+        .memory_at_address(memory_addr as u64)
+        .expect("Couldn't find memory region");
+    let offset = memory_addr - region.base_address as usize;
+    let values: Vec<u8> = (0..memory_size).map(|idx| (idx % 255) as u8).collect();
+    assert_eq!(&region.bytes[offset..offset + memory_size], values);
+
+    // The stacks are still written to the MemoryListStream
+    let _: MinidumpMemoryList = dump.get_stream().expect("Couldn't find MinidumpMemoryList");
+}
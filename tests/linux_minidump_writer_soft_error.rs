@@ -3,7 +3,11 @@
 use {
     common::*,
     minidump::Minidump,
-    minidump_writer::{minidump_writer::MinidumpWriterConfig, FailSpotName},
+    minidump_common::format::MINIDUMP_STREAM_TYPE::{self, *},
+    minidump_writer::{
+        minidump_writer::{errors::WriterError, report::StreamStatus, MinidumpWriterConfig},
+        FailSpotName,
+    },
     serde_json::json,
 };
 
@@ -90,3 +94,91 @@ fn soft_error_stream_content() {
     let dump = Minidump::read_path(tmpfile.path()).expect("failed to read minidump");
     assert_soft_errors_in_minidump(&dump, &expected_errors);
 }
+
+#[test]
+fn write_report() {
+    let mut child = start_child_and_wait_for_threads(1);
+    let pid = child.id() as i32;
+
+    let mut tmpfile = tempfile::Builder::new()
+        .prefix("write_report")
+        .tempfile()
+        .unwrap();
+
+    let mut fail_client = FailSpotName::testing_client();
+    fail_client.set_enabled(FailSpotName::CpuInfoFileOpen, true);
+
+    let report = MinidumpWriterConfig::new(pid, pid)
+        .write(&mut tmpfile)
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    assert!(report
+        .soft_errors
+        .iter()
+        .any(|error| matches!(error, WriterError::WriteSystemInfoErrors(_))));
+
+    let stream = |stream_type: MINIDUMP_STREAM_TYPE| {
+        report
+            .streams
+            .iter()
+            .find(|stream| stream.stream_type == stream_type as u32)
+            .unwrap_or_else(|| panic!("no report for stream {stream_type:?}"))
+    };
+
+    // The CPU information failing doesn't prevent writing the system info
+    let system_info = stream(SystemInfoStream);
+    assert_eq!(system_info.status, StreamStatus::Written);
+    assert_eq!(system_info.soft_errors, 1);
+
+    let thread_list = stream(ThreadListStream);
+    assert_eq!(thread_list.status, StreamStatus::Written);
+    assert_eq!(thread_list.soft_errors, 0);
+    assert!(thread_list.size > 0);
+
+    let soft_errors = report.streams.last().unwrap();
+    assert_eq!(soft_errors.stream_type, MozSoftErrors as u32);
+    assert_eq!(soft_errors.status, StreamStatus::Written);
+
+    // Everything but the header and the directory belongs to a stream
+    let file_size = std::fs::metadata(tmpfile.path()).unwrap().len();
+    assert_eq!(report.buffer.len() as u64, file_size);
+    assert!(report.size() < file_size);
+    assert!(report.size() > file_size - 1024);
+}
+
+#[test]
+fn uncaptured_threads() {
+    let num_of_threads = 2;
+    let mut child = start_child_and_wait_for_threads(num_of_threads);
+    let pid = child.id() as i32;
+
+    let mut fail_client = FailSpotName::testing_client();
+    fail_client.set_enabled(FailSpotName::CaptureThread, true);
+
+    // The process is resumed even though no thread context could be captured,
+    // and the threads are left out of the thread list
+    let mut config = MinidumpWriterConfig::new(pid, pid);
+    config.resilient();
+    let report = config
+        .write(&mut std::io::Cursor::new(Vec::new()))
+        .expect("cound not write minidump");
+    child.kill().expect("Failed to kill process");
+    child.wait().expect("Failed to wait on killed process");
+
+    assert!(report.paused_for.is_some());
+    assert_eq!(report.resume_deferred_by, None);
+
+    let soft_errors = serde_json::to_string(&report.soft_errors).unwrap();
+    assert_eq!(
+        soft_errors.matches("CaptureThreadFailed").count(),
+        num_of_threads,
+        "{soft_errors}"
+    );
+    assert_eq!(
+        soft_errors.matches("NotCaptured").count(),
+        num_of_threads,
+        "{soft_errors}"
+    );
+}
@@ -0,0 +1,116 @@
+//! Dumping the current process while another thread holds the allocator lock,
+//! in a binary of its own so that it can replace the global allocator
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use {
+    minidump::{Minidump, MinidumpThreadList},
+    minidump_writer::minidump_writer::MinidumpWriterConfig,
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    },
+};
+
+/// The system allocator behind a single lock, like the C allocator's arena
+/// locks
+struct LockedAllocator;
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Set once the helper has been forked, for the holder to take the lock
+static FORKED: AtomicBool = AtomicBool::new(false);
+
+fn lock() {
+    while LOCKED
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        // SAFETY: no arguments
+        unsafe { libc::sched_yield() };
+    }
+}
+
+fn unlock() {
+    LOCKED.store(false, Ordering::Release);
+}
+
+unsafe impl GlobalAlloc for LockedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        lock();
+        let ptr = System.alloc(layout);
+        unlock();
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        lock();
+        System.dealloc(ptr, layout);
+        unlock();
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: LockedAllocator = LockedAllocator;
+
+// Like the C library does for its allocator, the lock is taken while forking
+// and released in both processes
+extern "C" fn prepare_fork() {
+    lock();
+}
+
+extern "C" fn after_fork_in_parent() {
+    unlock();
+    FORKED.store(true, Ordering::Release);
+}
+
+extern "C" fn after_fork_in_child() {
+    unlock();
+}
+
+#[test]
+fn write_current_process_with_allocator_locked() {
+    // SAFETY: the handlers only touch atomics
+    unsafe {
+        libc::pthread_atfork(
+            Some(prepare_fork),
+            Some(after_fork_in_parent),
+            Some(after_fork_in_child),
+        )
+    };
+
+    let holder = std::thread::spawn(|| {
+        // Nothing is allocated here. The lock is held almost all the time for
+        // a while after forking, so it is held when the helper suspends the
+        // threads.
+        while !FORKED.load(Ordering::Acquire) {
+            // SAFETY: no arguments
+            unsafe { libc::sched_yield() };
+        }
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(500) {
+            lock();
+            std::thread::sleep(Duration::from_millis(5));
+            unlock();
+            // SAFETY: no arguments
+            unsafe { libc::sched_yield() };
+        }
+    });
+    // Dumping would block forever if the calling thread allocated while the
+    // holder is suspended, in which case the test is killed
+    // SAFETY: no pointers involved
+    unsafe { libc::alarm(60) };
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    let report = MinidumpWriterConfig::current_process()
+        .write_current_process(&mut buffer)
+        .expect("Could not write minidump");
+    // SAFETY: no pointers involved
+    unsafe { libc::alarm(0) };
+    holder.join().unwrap();
+
+    assert!(report.paused_for.is_some());
+    let dump = Minidump::read(buffer.into_inner()).expect("Failed to read minidump");
+    let thread_list: MinidumpThreadList =
+        dump.get_stream().expect("Couldn't find MinidumpThreadList");
+    assert!(thread_list.threads.len() >= 2);
+}